@@ -0,0 +1,65 @@
+use std::hint::black_box;
+
+use agora_http_parser::{Request, is_terminated};
+use criterion::{Criterion, criterion_group, criterion_main};
+
+/// Build a request with `count` headers, roughly what a browser with a pile of cookies sends
+fn large_request(count: usize) -> Vec<u8> {
+    let mut request = b"GET /some/fairly/long/path?with=query&params=1 HTTP/1.1\r\n".to_vec();
+    for i in 0..count {
+        request.extend_from_slice(
+            format!("x-header-{i}: some reasonably long header value number {i}\r\n").as_bytes(),
+        );
+    }
+    request.extend_from_slice(b"\r\n");
+    request
+}
+
+/// The byte by byte scan `is_terminated` used before switching to memchr
+fn naive_is_terminated(buf: &[u8]) -> bool {
+    buf.windows(4).any(|window| window == b"\r\n\r\n")
+}
+
+fn bench_is_terminated(c: &mut Criterion) {
+    let request = large_request(100);
+    // the worst case, the terminator is only found at the very end
+    let mut group = c.benchmark_group("is_terminated");
+    group.bench_function("naive", |b| {
+        b.iter(|| naive_is_terminated(black_box(&request)))
+    });
+    group.bench_function("memchr", |b| b.iter(|| is_terminated(black_box(&request))));
+    group.finish();
+}
+
+fn bench_parse_request(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_request");
+    for count in [10, 100] {
+        let request = large_request(count);
+        group.bench_function(format!("{count}_headers"), |b| {
+            b.iter(|| Request::parse(black_box(&request)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_header_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("header_lookup");
+    // below and above the number of headers stored inline
+    for count in [10, 100] {
+        let request = large_request(count);
+        let (request, _) = Request::parse(&request).unwrap();
+        let last = format!("x-header-{}", count - 1);
+        group.bench_function(format!("{count}_headers"), |b| {
+            b.iter(|| request.headers.get(black_box(&last)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_is_terminated,
+    bench_parse_request,
+    bench_header_lookup
+);
+criterion_main!(benches);
@@ -0,0 +1,147 @@
+//! Reading messages from tokio readers.
+//!
+//! These read exactly the message head from the reader, so the body can be read from the
+//! same reader afterwards according to the message's [`Body`](crate::Body).
+
+use std::io;
+
+use memchr::memmem;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::{HTTPParseError, Request, Response};
+
+/// The largest message head we will buffer before giving up
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Read and parse a request head.
+///
+/// Fails with [`io::ErrorKind::UnexpectedEof`] if the reader ends before the head does,
+/// [`io::ErrorKind::OutOfMemory`] if the head is larger than [`MAX_HEAD_SIZE`] and
+/// [`io::ErrorKind::InvalidData`] wrapping an [`HTTPParseError`] if it is malformed.
+pub async fn read_request(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Request> {
+    let head = read_head(reader).await?;
+    let (request, _) = Request::parse(&head).map_err(invalid_data)?;
+    Ok(request)
+}
+
+/// Read and parse a response head. Interim responses are returned like any other, so
+/// callers waiting for the final response should keep reading while
+/// [`Response::is_interim`] is true.
+///
+/// Fails in the same ways as [`read_request`].
+pub async fn read_response(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Response> {
+    let head = read_head(reader).await?;
+    let (response, _) = Response::parse(&head).map_err(invalid_data)?;
+    Ok(response)
+}
+
+/// Read up to and including the empty line ending a message head, leaving the rest in the reader
+async fn read_head(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Stream closed before the end of the message head",
+            ));
+        }
+
+        // only scan the new bytes, plus enough of the old ones to catch a terminator
+        // split over two reads
+        let scanned = head.len().saturating_sub(3);
+        let available = buf.len();
+        head.extend_from_slice(buf);
+
+        if let Some(position) = memmem::find(&head[scanned..], b"\r\n\r\n") {
+            let end = scanned + position + 4;
+            reader.consume(available - (head.len() - end));
+            head.truncate(end);
+            return Ok(head);
+        }
+        reader.consume(available);
+
+        if head.len() > MAX_HEAD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "Message head too large",
+            ));
+        }
+    }
+}
+
+fn invalid_data(e: HTTPParseError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use tokio::io::{AsyncReadExt, BufReader};
+
+    use crate::{Body, HTTPMethod};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let input =
+            b"POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n";
+        // a tiny buffer splits the head over many reads
+        let mut reader = BufReader::with_capacity(3, input.as_slice());
+
+        let request = read_request(&mut reader).await.unwrap();
+        assert_eq!(HTTPMethod::POST, request.method);
+        assert_eq!(Body::Sized(5), request.body);
+
+        let mut body = [0; 5];
+        reader.read_exact(&mut body).await.unwrap();
+        assert_eq!(b"hello", &body);
+
+        let request = read_request(&mut reader).await.unwrap();
+        assert_eq!(HTTPMethod::GET, request.method);
+        assert_eq!(
+            io::ErrorKind::UnexpectedEof,
+            read_request(&mut reader).await.unwrap_err().kind()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_response() {
+        let mut reader = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n".as_slice();
+
+        let response = read_response(&mut reader).await.unwrap();
+        assert!(response.is_interim());
+        let response = read_response(&mut reader).await.unwrap();
+        assert_eq!(Response::new(StatusCode::NO_CONTENT), response);
+        assert!(reader.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_invalid() {
+        let mut reader = b"BREW / HTTP/1.1\r\n\r\n".as_slice();
+        let e = read_request(&mut reader).await.unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, e.kind());
+        assert_eq!(
+            Some(&HTTPParseError::InvalidMethod),
+            e.get_ref().and_then(|e| e.downcast_ref())
+        );
+
+        let mut reader = b"GET / HTTP/1.1\r\n".as_slice();
+        assert_eq!(
+            io::ErrorKind::UnexpectedEof,
+            read_request(&mut reader).await.unwrap_err().kind()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_too_large() {
+        let mut input = b"GET / HTTP/1.1\r\n".to_vec();
+        input.extend(b"x-filler: a\r\n".repeat(MAX_HEAD_SIZE / 10));
+        let mut reader = input.as_slice();
+        assert_eq!(
+            io::ErrorKind::OutOfMemory,
+            read_request(&mut reader).await.unwrap_err().kind()
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! Credentials carried in `Authorization` and `Proxy-Authorization` headers (RFC 9110 section 11).
+
+use std::fmt::Display;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+use crate::{
+    HTTPParseError,
+    headers::{is_token, parse_quoted_string, quote},
+};
+
+/// Credentials of an `Authorization` header
+#[derive(Debug, PartialEq, Clone)]
+pub enum Authorization {
+    /// The `Basic` scheme (RFC 7617)
+    Basic { username: String, password: String },
+    /// The `Bearer` scheme (RFC 6750)
+    Bearer(String),
+    /// The `Digest` scheme (RFC 7616). Parameter names are lowercased, values are unquoted.
+    Digest(Vec<(String, String)>),
+    /// Any other scheme, with either a token68 or a list of parameters
+    Other {
+        scheme: String,
+        token: Option<String>,
+        params: Vec<(String, String)>,
+    },
+}
+
+impl Authorization {
+    pub fn basic(username: &str, password: &str) -> Self {
+        Self::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    pub fn bearer(token: &str) -> Self {
+        Self::Bearer(token.to_string())
+    }
+
+    /// Parse the value of an `Authorization` header.
+    /// Scheme names and parameter names are case-insensitive, tokens and values are not.
+    pub fn parse(value: &str) -> Result<Self, HTTPParseError> {
+        let value = value.trim();
+        let (scheme, credentials) = match value.split_once(' ') {
+            Some((scheme, credentials)) => (scheme, credentials.trim_start()),
+            None => (value, ""),
+        };
+        if !is_token(scheme) {
+            return Err(HTTPParseError::InvalidAuthorization);
+        }
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "basic" => parse_basic(credentials),
+            "bearer" if is_token68(credentials) => Ok(Self::bearer(credentials)),
+            "digest" if !credentials.is_empty() => Ok(Self::Digest(parse_params(credentials)?)),
+            "bearer" | "digest" => Err(HTTPParseError::InvalidAuthorization),
+            _ if credentials.is_empty() => Ok(Self::Other {
+                scheme: scheme.to_string(),
+                token: None,
+                params: Vec::new(),
+            }),
+            _ if is_token68(credentials) => Ok(Self::Other {
+                scheme: scheme.to_string(),
+                token: Some(credentials.to_string()),
+                params: Vec::new(),
+            }),
+            _ => Ok(Self::Other {
+                scheme: scheme.to_string(),
+                token: None,
+                params: parse_params(credentials)?,
+            }),
+        }
+    }
+
+    /// The authentication scheme, as it would be sent
+    pub fn scheme(&self) -> &str {
+        match self {
+            Authorization::Basic { .. } => "Basic",
+            Authorization::Bearer(_) => "Bearer",
+            Authorization::Digest(_) => "Digest",
+            Authorization::Other { scheme, .. } => scheme,
+        }
+    }
+
+    /// Look up an auth parameter by its case-insensitive name
+    pub fn param(&self, name: &str) -> Option<&str> {
+        let params = match self {
+            Authorization::Digest(params) | Authorization::Other { params, .. } => params,
+            _ => return None,
+        };
+        params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Decode `base64(username ":" password)`. The username can't contain a colon, the password can.
+fn parse_basic(credentials: &str) -> Result<Authorization, HTTPParseError> {
+    let decoded = STANDARD
+        .decode(credentials)
+        .map_err(|_| HTTPParseError::InvalidAuthorization)?;
+    let decoded = String::from_utf8(decoded).map_err(|_| HTTPParseError::InvalidAuthorization)?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or(HTTPParseError::InvalidAuthorization)?;
+
+    Ok(Authorization::basic(username, password))
+}
+
+/// Parse `#auth-param`, a comma separated list of `token BWS "=" BWS ( token / quoted-string )`
+fn parse_params(mut input: &str) -> Result<Vec<(String, String)>, HTTPParseError> {
+    let mut params = Vec::new();
+    loop {
+        // empty list elements are allowed and ignored (RFC 9110 section 5.6.1)
+        input = input.trim_start_matches([' ', '\t', ',']);
+        if input.is_empty() {
+            return Ok(params);
+        }
+
+        let (name, rest) = input
+            .split_once('=')
+            .ok_or(HTTPParseError::InvalidAuthorization)?;
+        let name = name.trim_end_matches([' ', '\t']);
+        if !is_token(name) {
+            return Err(HTTPParseError::InvalidAuthorization);
+        }
+
+        let rest = rest.trim_start_matches([' ', '\t']);
+        let (value, rest) = if rest.starts_with('"') {
+            parse_quoted_string(rest).ok_or(HTTPParseError::InvalidAuthorization)?
+        } else {
+            let end = rest.find([',', ' ', '\t']).unwrap_or(rest.len());
+            if !is_token(&rest[..end]) {
+                return Err(HTTPParseError::InvalidAuthorization);
+            }
+            (rest[..end].to_string(), &rest[end..])
+        };
+
+        input = rest.trim_start_matches([' ', '\t']);
+        if !input.is_empty() && !input.starts_with(',') {
+            return Err(HTTPParseError::InvalidAuthorization);
+        }
+        params.push((name.to_ascii_lowercase(), value));
+    }
+}
+
+/// `1*( ALPHA / DIGIT / "-" / "." / "_" / "~" / "+" / "/" ) *"="`
+fn is_token68(value: &str) -> bool {
+    let value = value.trim_end_matches('=');
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~+/".contains(&b))
+}
+
+fn write_params(
+    f: &mut std::fmt::Formatter<'_>,
+    params: &[(String, String)],
+    should_quote: impl Fn(&str, &str) -> bool,
+) -> std::fmt::Result {
+    for (i, (name, value)) in params.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        if should_quote(name, value) {
+            write!(f, "{name}={}", quote(value))?;
+        } else {
+            write!(f, "{name}={value}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Serializes to a header value
+impl Display for Authorization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Authorization::Basic { username, password } => {
+                write!(
+                    f,
+                    "Basic {}",
+                    STANDARD.encode(format!("{username}:{password}"))
+                )
+            }
+            Authorization::Bearer(token) => write!(f, "Bearer {token}"),
+            Authorization::Digest(params) => {
+                write!(f, "Digest ")?;
+                // RFC 7616 section 3.4 sends these as tokens and everything else quoted
+                write_params(f, params, |name, _| {
+                    !matches!(name, "algorithm" | "qop" | "nc")
+                })
+            }
+            Authorization::Other {
+                scheme,
+                token,
+                params,
+            } => {
+                write!(f, "{scheme}")?;
+                if let Some(token) = token {
+                    write!(f, " {token}")?;
+                } else if !params.is_empty() {
+                    write!(f, " ")?;
+                    write_params(f, params, |_, value| !is_token(value))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    // the example from RFC 7617 section 2
+    #[case("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==", "Aladdin", "open sesame")]
+    #[case("basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==", "Aladdin", "open sesame")]
+    #[case("Basic  dXNlcjpwYTpzczp3b3Jk", "user", "pa:ss:word")]
+    #[case("Basic dXNlcjo=", "user", "")]
+    fn test_parse_basic(#[case] input: &str, #[case] username: &str, #[case] password: &str) {
+        assert_eq!(
+            Ok(Authorization::basic(username, password)),
+            Authorization::parse(input)
+        );
+    }
+
+    #[rstest]
+    #[case("Basic")]
+    #[case("Basic !!!notbase64")]
+    // "no-colon" has no separator between the username and password
+    #[case("Basic bm8tY29sb24=")]
+    #[case("")]
+    fn test_parse_basic_invalid(#[case] input: &str) {
+        assert_eq!(
+            Err(HTTPParseError::InvalidAuthorization),
+            Authorization::parse(input)
+        );
+    }
+
+    #[test]
+    fn test_basic_roundtrip() {
+        let authorization = Authorization::basic("Aladdin", "open sesame");
+        assert_eq!(
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==",
+            authorization.to_string()
+        );
+        assert_eq!(
+            Ok(authorization.clone()),
+            Authorization::parse(&authorization.to_string())
+        );
+    }
+
+    #[rstest]
+    #[case("Bearer mF_9.B5f-4.1JqM", Authorization::bearer("mF_9.B5f-4.1JqM"))]
+    #[case("bearer abc/def+ghi==", Authorization::bearer("abc/def+ghi=="))]
+    #[case(
+        "Negotiate",
+        Authorization::Other { scheme: "Negotiate".to_string(), token: None, params: vec![] }
+    )]
+    #[case(
+        "Negotiate YIIBgwYGKwYBBQUC",
+        Authorization::Other {
+            scheme: "Negotiate".to_string(),
+            token: Some("YIIBgwYGKwYBBQUC".to_string()),
+            params: vec![],
+        }
+    )]
+    #[case(
+        "Custom Key=value , other = \"a, \\\"quoted\\\" value\"",
+        Authorization::Other {
+            scheme: "Custom".to_string(),
+            token: None,
+            params: vec![
+                ("key".to_string(), "value".to_string()),
+                ("other".to_string(), "a, \"quoted\" value".to_string()),
+            ],
+        }
+    )]
+    fn test_parse_schemes(#[case] input: &str, #[case] expected: Authorization) {
+        assert_eq!(Ok(expected), Authorization::parse(input));
+    }
+
+    #[test]
+    fn test_parse_digest() {
+        // the example from RFC 7616 section 3.9.1
+        let authorization = Authorization::parse(
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", uri=\"/dir/index.html\", \
+             algorithm=MD5, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", nc=00000001, \
+             cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, \
+             response=\"8ca523f5e9506fed4657c9700eebdbec\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+        )
+        .unwrap();
+
+        assert_eq!("Digest", authorization.scheme());
+        assert_eq!(Some("Mufasa"), authorization.param("username"));
+        assert_eq!(Some("MD5"), authorization.param("Algorithm"));
+        assert_eq!(Some("00000001"), authorization.param("nc"));
+        assert_eq!(
+            Some("8ca523f5e9506fed4657c9700eebdbec"),
+            authorization.param("response")
+        );
+        assert_eq!(None, authorization.param("missing"));
+        assert_eq!(
+            Ok(authorization.clone()),
+            Authorization::parse(&authorization.to_string())
+        );
+    }
+
+    #[rstest]
+    #[case("Bearer")]
+    #[case("Bearer two tokens")]
+    #[case("Bearer a=b")]
+    #[case("Digest")]
+    #[case("Digest username")]
+    #[case("Digest username=\"unterminated")]
+    #[case("Digest a=b c=d")]
+    #[case("Custom a=\"b\"c")]
+    #[case("Bad@Scheme token")]
+    fn test_parse_invalid(#[case] input: &str) {
+        assert_eq!(
+            Err(HTTPParseError::InvalidAuthorization),
+            Authorization::parse(input)
+        );
+    }
+
+    #[rstest]
+    #[case(Authorization::bearer("abc"), "Bearer abc")]
+    #[case(
+        Authorization::Digest(vec![
+            ("username".to_string(), "Mufasa".to_string()),
+            ("qop".to_string(), "auth".to_string()),
+        ]),
+        "Digest username=\"Mufasa\", qop=auth"
+    )]
+    #[case(
+        Authorization::Other {
+            scheme: "Custom".to_string(),
+            token: None,
+            params: vec![
+                ("a".to_string(), "token".to_string()),
+                ("b".to_string(), "needs \"quotes\"".to_string()),
+            ],
+        },
+        "Custom a=token, b=\"needs \\\"quotes\\\"\""
+    )]
+    fn test_serialize(#[case] authorization: Authorization, #[case] expected: &str) {
+        assert_eq!(expected, authorization.to_string());
+    }
+}
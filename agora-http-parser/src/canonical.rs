@@ -0,0 +1,171 @@
+//! A canonical form of requests for signing, modelled on the AWS SigV4 canonical request.
+//!
+//! Two requests that only differ in ways intermediaries are allowed to change (header case
+//! and order, percent encoding, dot segments, query parameter order, whitespace in header
+//! values) have the same canonical form.
+
+use crate::{HTTPParseError, Request, uri::remove_dot_segments};
+
+/// See [`Request::canonicalize`]
+pub(crate) fn canonicalize(request: &Request) -> Result<String, HTTPParseError> {
+    let uri = request.uri()?;
+    let path = match uri.path() {
+        "" => "/".to_string(),
+        path => normalize_component(&remove_dot_segments(path), b"/"),
+    };
+
+    let mut query = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                normalize_component(name, b""),
+                normalize_component(value, b""),
+            )
+        })
+        .collect::<Vec<_>>();
+    query.sort();
+    let query = query
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let mut headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| Ok((name.as_str(), canonical_value(value.to_str()?))))
+        .collect::<Result<Vec<_>, HTTPParseError>>()?;
+    // names are lowercase already
+    headers.sort();
+
+    let mut canonical = format!("{}\n{path}\n{query}\n", request.method.as_str());
+    for (name, value) in &headers {
+        canonical.push_str(&format!("{name}:{value}\n"));
+    }
+    canonical.push('\n');
+    canonical.push_str(
+        &headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";"),
+    );
+
+    Ok(canonical)
+}
+
+/// Trim a header value and collapse runs of whitespace into a single space
+fn canonical_value(value: &str) -> String {
+    value
+        .split([' ', '\t'])
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Percent encode everything but unreserved characters and `keep`, with uppercase hex digits.
+/// Existing escapes of unreserved characters are decoded, so `%7E` and `~` are the same.
+fn normalize_component(component: &str, keep: &[u8]) -> String {
+    let bytes = component.as_bytes();
+    let mut normalized = String::with_capacity(component.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let mut byte = bytes[i];
+        if byte == b'%'
+            && let Some(decoded) = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            byte = decoded;
+            i += 2;
+        } else if keep.contains(&byte) {
+            normalized.push(byte as char);
+            i += 1;
+            continue;
+        }
+
+        if is_unreserved(byte) {
+            normalized.push(byte as char);
+        } else {
+            normalized.push_str(&format!("%{byte:02X}"));
+        }
+        i += 1;
+    }
+    normalized
+}
+
+/// `ALPHA / DIGIT / "-" / "." / "_" / "~"` from RFC 3986 section 2.3
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-._~".contains(&byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("abc", b"", "abc")]
+    #[case("a b", b"", "a%20b")]
+    #[case("%7e%2f", b"", "~%2F")]
+    #[case("%2f/a", b"/", "%2F/a")]
+    #[case("a+b=c", b"", "a%2Bb%3Dc")]
+    #[case("100%", b"", "100%25")]
+    fn test_normalize_component(#[case] input: &str, #[case] keep: &[u8], #[case] expected: &str) {
+        assert_eq!(expected, normalize_component(input, keep));
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let (request, _) = Request::parse(
+            b"GET /a/./b/../c%7e?y=2&x=%31&x=0 HTTP/1.1\r\n\
+            Host: Example.com\r\n\
+            X-Spaced:   a   b\t c  \r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Ok(
+                "GET\n/a/c~\nx=0&x=1&y=2\nhost:Example.com\nx-spaced:a b c\n\nhost;x-spaced"
+                    .to_string()
+            ),
+            canonicalize(&request)
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_is_order_and_case_insensitive() {
+        let (a, _) = Request::parse(
+            b"POST /upload?b=1&a=2 HTTP/1.1\r\nHost: example.com\r\nContent-Type: text/plain\r\n\r\n",
+        )
+        .unwrap();
+        let (b, _) = Request::parse(
+            b"POST /upload?a=2&b=1 HTTP/1.1\r\ncontent-type: text/plain\r\nHOST: example.com\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(canonicalize(&a), canonicalize(&b));
+        assert_eq!(
+            Ok("POST\n/upload\na=2&b=1\ncontent-type:text/plain\nhost:example.com\n\ncontent-type;host"
+                .to_string()),
+            canonicalize(&a)
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_absolute_form() {
+        let (request, _) =
+            Request::parse(b"GET http://example.com HTTP/1.1\r\nHost: example.com\r\n\r\n")
+                .unwrap();
+        assert_eq!(
+            Ok("GET\n/\n\nhost:example.com\n\nhost".to_string()),
+            canonicalize(&request)
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! The chunked transfer coding (RFC 9112 section 7.1).
+
+use crate::{CRLF, HTTPParseError, Headers, parse_fields};
+
+/// Longest chunk size line or trailer section accepted, CRLFs included
+const MAX_LINE_LEN: usize = 8192;
+
+/// Find the length of the chunked body at the start of the buffer, including the chunk
+/// framing and any trailer section.
+/// Returns `None` if the buffer ends before the body does.
+pub fn chunked_body_len(buf: &[u8]) -> Result<Option<usize>, HTTPParseError> {
+    let mut decoder = ChunkedDecoder::default();
+    let len = decoder.decode(buf)?;
+    Ok(decoder.is_done().then_some(len))
+}
+
+/// Follows the framing of a chunked body as it arrives in pieces, to find where it ends
+/// without buffering it whole. Chunk data can contain anything, so the end is only
+/// known by reading each chunk size line and skipping the chunk it announces.
+#[derive(Debug, Default)]
+pub struct ChunkedDecoder {
+    state: State,
+    /// The partial chunk size or trailer line, when a piece ended in the middle of one
+    line: Vec<u8>,
+    /// The trailer lines read so far
+    section: Vec<u8>,
+    trailers: Headers,
+}
+
+#[derive(Debug, Default, PartialEq)]
+enum State {
+    #[default]
+    Size,
+    Data(u64),
+    DataEnd(usize),
+    Trailers,
+    Done,
+}
+
+impl ChunkedDecoder {
+    /// Take in the next piece of the message, returning how many of its bytes belong
+    /// to the body. Fewer than all of them means the body ended in this piece.
+    pub fn decode(&mut self, buf: &[u8]) -> Result<usize, HTTPParseError> {
+        self.take(buf, None)
+    }
+
+    /// Like [`ChunkedDecoder::decode`], also appending the data of the chunks in the
+    /// piece to `data`, for passing the body on without its chunked framing
+    pub fn decode_data(&mut self, buf: &[u8], data: &mut Vec<u8>) -> Result<usize, HTTPParseError> {
+        self.take(buf, Some(data))
+    }
+
+    fn take(
+        &mut self,
+        buf: &[u8],
+        mut data: Option<&mut Vec<u8>>,
+    ) -> Result<usize, HTTPParseError> {
+        let mut offset = 0;
+        while offset < buf.len() {
+            let rest = &buf[offset..];
+            match self.state {
+                State::Size | State::Trailers => {
+                    let Some(line_len) = memchr::memchr(b'\n', rest) else {
+                        self.push_line(rest)?;
+                        return Ok(buf.len());
+                    };
+                    self.push_line(&rest[..=line_len])?;
+                    offset += line_len + 1;
+                    self.end_line()?;
+                }
+                State::Data(size) => {
+                    let len = rest.len().min(usize::try_from(size).unwrap_or(usize::MAX));
+                    if let Some(data) = data.as_deref_mut() {
+                        data.extend_from_slice(&rest[..len]);
+                    }
+                    offset += len;
+                    self.state = match size - len as u64 {
+                        0 => State::DataEnd(0),
+                        size => State::Data(size),
+                    };
+                }
+                State::DataEnd(matched) => {
+                    if rest[0] != CRLF[matched] {
+                        return Err(HTTPParseError::InvalidChunkedBody);
+                    }
+                    offset += 1;
+                    self.state = match matched {
+                        0 => State::DataEnd(1),
+                        _ => State::Size,
+                    };
+                }
+                State::Done => break,
+            }
+        }
+        Ok(offset)
+    }
+
+    /// Whether the whole body, trailer section included, has been taken in
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// The trailer fields sent after the last chunk, empty until the body is done
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    fn push_line(&mut self, bytes: &[u8]) -> Result<(), HTTPParseError> {
+        if self.line.len() + bytes.len() > MAX_LINE_LEN {
+            return Err(HTTPParseError::InvalidChunkedBody);
+        }
+        self.line.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Act on the line just completed, which ends with its LF
+    fn end_line(&mut self) -> Result<(), HTTPParseError> {
+        let Some(line) = self.line.strip_suffix(CRLF) else {
+            return Err(HTTPParseError::InvalidChunkedBody);
+        };
+        self.state = match self.state {
+            State::Size => match parse_chunk_size(line)? {
+                0 => State::Trailers,
+                size => State::Data(size),
+            },
+            _ if line.is_empty() => {
+                self.section.extend_from_slice(CRLF);
+                let (fields, _) =
+                    parse_fields(&self.section).map_err(|_| HTTPParseError::InvalidChunkedBody)?;
+                self.trailers = Headers::from_fields(&fields);
+                State::Done
+            }
+            _ if !line.contains(&b':') || self.section.len() + self.line.len() > MAX_LINE_LEN => {
+                return Err(HTTPParseError::InvalidChunkedBody);
+            }
+            _ => {
+                self.section.extend_from_slice(&self.line);
+                State::Trailers
+            }
+        };
+        self.line.clear();
+        Ok(())
+    }
+}
+
+/// Parse a chunk size line without the CRLF, ignoring chunk extensions
+pub fn parse_chunk_size(line: &[u8]) -> Result<u64, HTTPParseError> {
+    let size = line
+        .split(|&b| b == b';')
+        .next()
+        .unwrap_or_default()
+        .trim_ascii_end();
+    if size.is_empty() || size.len() > 16 || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(HTTPParseError::InvalidChunkedBody);
+    }
+
+    // at most 16 hex digits always fit into a u64
+    Ok(size.iter().fold(0, |size, &digit| {
+        size << 4 | (digit as char).to_digit(16).unwrap() as u64
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(b"0\r\n\r\n", Ok(Some(5)))]
+    #[case(b"5\r\nhello\r\n0\r\n\r\nnext", Ok(Some(15)))]
+    #[case(b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\n\r\n", Ok(Some(32)))]
+    #[case(b"A\r\n0123456789\r\n0\r\nExpires: never\r\n\r\n", Ok(Some(36)))]
+    #[case(b"5\r\nhel", Ok(None))]
+    #[case(b"5\r\nhello\r\n0\r\n", Ok(None))]
+    #[case(b"5\r", Ok(None))]
+    #[case(b"x\r\n", Err(HTTPParseError::InvalidChunkedBody))]
+    #[case(b"\r\n", Err(HTTPParseError::InvalidChunkedBody))]
+    #[case(b"5\r\nhelloXX0\r\n\r\n", Err(HTTPParseError::InvalidChunkedBody))]
+    #[case(b"fffffffffffffffff\r\n", Err(HTTPParseError::InvalidChunkedBody))]
+    #[case(b"0\r\nnot a trailer\r\n\r\n", Err(HTTPParseError::InvalidChunkedBody))]
+    fn test_chunked_body_len(
+        #[case] input: &[u8],
+        #[case] expected: Result<Option<usize>, HTTPParseError>,
+    ) {
+        assert_eq!(expected, chunked_body_len(input));
+    }
+
+    #[rstest]
+    #[case(&["5\r\nhello\r\n0\r\n\r\n"], &[15])]
+    #[case(&["5\r", "\nhel", "lo\r", "\n0\r\n", "\r\nnext"], &[2, 4, 3, 4, 2])]
+    #[case(&["4\r\n\r\n\r\n\r\n0\r\n\r\n"], &[14])]
+    #[case(&["1A\r\n", "0123456789abcdefghijklmnop", "\r\n0\r\nx-sum: 1\r\n\r\nnext"], &[4, 26, 17])]
+    fn test_chunked_decoder(#[case] pieces: &[&str], #[case] expected: &[usize]) {
+        let mut decoder = ChunkedDecoder::default();
+        let decoded: Vec<usize> = pieces
+            .iter()
+            .map(|piece| decoder.decode(piece.as_bytes()).unwrap())
+            .collect();
+        assert_eq!(expected, decoded);
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn test_chunked_decoder_trailers() {
+        let mut decoder = ChunkedDecoder::default();
+        for piece in [
+            b"5\r\nhello\r\n0\r\ngrpc-status: 0\r\n".as_slice(),
+            b"grpc-message: ",
+            b"ok\r\n\r\n",
+        ] {
+            assert!(decoder.trailers().is_empty());
+            decoder.decode(piece).unwrap();
+        }
+        assert_eq!(
+            Headers::try_from([("grpc-status", "0"), ("grpc-message", "ok")]).unwrap(),
+            *decoder.trailers()
+        );
+    }
+
+    #[test]
+    fn test_chunked_decoder_data() {
+        let mut decoder = ChunkedDecoder::default();
+        let mut data = Vec::new();
+        for piece in [
+            b"5\r\nhel".as_slice(),
+            b"lo\r\n6\r\n",
+            b" world\r\n0\r\n\r\nGET",
+        ] {
+            decoder.decode_data(piece, &mut data).unwrap();
+        }
+        assert!(decoder.is_done());
+        assert_eq!(b"hello world", data.as_slice());
+    }
+
+    #[rstest]
+    #[case(b"5\r\nhello\n")]
+    #[case(b"5\nhello\r\n")]
+    #[case(&[b'a'; MAX_LINE_LEN + 1])]
+    #[case(b"0\r\nx y: z\r\n\r\n")]
+    fn test_chunked_decoder_invalid(#[case] input: &[u8]) {
+        assert_eq!(
+            Err(HTTPParseError::InvalidChunkedBody),
+            ChunkedDecoder::default().decode(input)
+        );
+    }
+
+    #[rstest]
+    #[case(b"0", Ok(0))]
+    #[case(b"1a", Ok(26))]
+    #[case(b"FF ; name=value", Ok(255))]
+    #[case(b"ffffffffffffffff", Ok(u64::MAX))]
+    #[case(b"-1", Err(HTTPParseError::InvalidChunkedBody))]
+    #[case(b"", Err(HTTPParseError::InvalidChunkedBody))]
+    fn test_parse_chunk_size(#[case] input: &[u8], #[case] expected: Result<u64, HTTPParseError>) {
+        assert_eq!(expected, parse_chunk_size(input));
+    }
+}
@@ -0,0 +1,354 @@
+//! The `Content-Disposition` header (RFC 6266), including `filename*` from RFC 8187.
+
+use std::fmt::Display;
+
+use crate::{
+    HTTPParseError,
+    headers::{is_token, parse_quoted_string, quote},
+};
+
+/// How the content should be presented
+#[derive(Debug, PartialEq, Clone)]
+pub enum DispositionType {
+    Inline,
+    Attachment,
+    /// A part of a `multipart/form-data` body (RFC 7578)
+    FormData,
+    /// Any other type, lowercased. Recipients should treat these as [`DispositionType::Attachment`].
+    Other(String),
+}
+
+impl DispositionType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            DispositionType::Inline => "inline",
+            DispositionType::Attachment => "attachment",
+            DispositionType::FormData => "form-data",
+            DispositionType::Other(name) => name,
+        }
+    }
+}
+
+/// A parsed or built `Content-Disposition` value
+#[derive(Debug, PartialEq, Clone)]
+pub struct ContentDisposition {
+    pub disposition: DispositionType,
+    /// The parameters with lowercased names. Values are unquoted, and extended parameters
+    /// like `filename*` are decoded.
+    pub params: Vec<(String, String)>,
+}
+
+impl ContentDisposition {
+    pub fn inline() -> Self {
+        Self::new(DispositionType::Inline)
+    }
+
+    pub fn attachment() -> Self {
+        Self::new(DispositionType::Attachment)
+    }
+
+    /// A form field with the given name
+    pub fn form_data(name: &str) -> Self {
+        Self::new(DispositionType::FormData).param("name", name)
+    }
+
+    fn new(disposition: DispositionType) -> Self {
+        Self {
+            disposition,
+            params: Vec::new(),
+        }
+    }
+
+    /// Set the file name, which can be any string.
+    /// Names that aren't printable ASCII are sent as `filename*` along with an ASCII
+    /// `filename` fallback for recipients that don't support it.
+    pub fn filename(mut self, filename: &str) -> Self {
+        let fallback = filename
+            .chars()
+            .map(|c| {
+                if c.is_ascii() && !c.is_ascii_control() {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        if fallback != filename {
+            self = self.param("filename*", filename);
+        }
+        self.param("filename", &fallback)
+    }
+
+    /// Add a parameter. Names ending with `*` are sent in the extended `UTF-8''` encoding.
+    pub fn param(mut self, name: &str, value: &str) -> Self {
+        self.params
+            .push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    /// Look up a parameter by its case-insensitive name
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The name of a form-data field
+    pub fn name(&self) -> Option<&str> {
+        self.get_param("name")
+    }
+
+    /// The suggested file name, preferring `filename*` over `filename`.
+    /// Any directory components are removed, so the name can't refer to another directory.
+    /// The name should still be treated as untrusted input.
+    pub fn get_filename(&self) -> Option<&str> {
+        self.get_param("filename*")
+            .or_else(|| self.get_param("filename"))
+            .map(|filename| filename.rsplit(['/', '\\']).next().unwrap_or(filename))
+    }
+
+    /// Parse the value of a `Content-Disposition` header
+    pub fn parse(value: &str) -> Result<Self, HTTPParseError> {
+        let value = value.trim_matches([' ', '\t']);
+        let end = value.find([';', ' ', '\t']).unwrap_or(value.len());
+        let disposition = &value[..end];
+        if !is_token(disposition) {
+            return Err(HTTPParseError::InvalidContentDisposition);
+        }
+
+        let mut parsed = Self::new(match disposition.to_ascii_lowercase().as_str() {
+            "inline" => DispositionType::Inline,
+            "attachment" => DispositionType::Attachment,
+            "form-data" => DispositionType::FormData,
+            other => DispositionType::Other(other.to_string()),
+        });
+
+        let mut input = &value[end..];
+        loop {
+            input = input.trim_start_matches([' ', '\t']);
+            if input.is_empty() {
+                return Ok(parsed);
+            }
+            let rest = input
+                .strip_prefix(';')
+                .ok_or(HTTPParseError::InvalidContentDisposition)?
+                .trim_start_matches([' ', '\t']);
+            // a trailing semicolon is common enough to tolerate
+            if rest.is_empty() {
+                return Ok(parsed);
+            }
+
+            let (name, rest) = rest
+                .split_once('=')
+                .ok_or(HTTPParseError::InvalidContentDisposition)?;
+            let name = name.trim_end_matches([' ', '\t']);
+            if !is_token(name) {
+                return Err(HTTPParseError::InvalidContentDisposition);
+            }
+
+            let rest = rest.trim_start_matches([' ', '\t']);
+            let (value, rest) = if rest.starts_with('"') {
+                parse_quoted_string(rest).ok_or(HTTPParseError::InvalidContentDisposition)?
+            } else {
+                let end = rest.find([';', ' ', '\t']).unwrap_or(rest.len());
+                if !is_token(&rest[..end]) {
+                    return Err(HTTPParseError::InvalidContentDisposition);
+                }
+                (rest[..end].to_string(), &rest[end..])
+            };
+
+            if name.ends_with('*') {
+                // values in charsets we can't decode are dropped so the plain parameter is used
+                if let Some(value) = decode_ext_value(&value)? {
+                    parsed = parsed.param(name, &value);
+                }
+            } else {
+                parsed = parsed.param(name, &value);
+            }
+            input = rest;
+        }
+    }
+}
+
+/// Decode `charset "'" [ language ] "'" value-chars` (RFC 8187 section 3.2).
+/// Returns `None` for charsets other than UTF-8 and ISO-8859-1.
+fn decode_ext_value(value: &str) -> Result<Option<String>, HTTPParseError> {
+    let mut parts = value.splitn(3, '\'');
+    let (Some(charset), Some(_language), Some(encoded)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(HTTPParseError::InvalidContentDisposition);
+    };
+
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut iter = encoded.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'%' => {
+                let hex = [
+                    iter.next()
+                        .ok_or(HTTPParseError::InvalidContentDisposition)?,
+                    iter.next()
+                        .ok_or(HTTPParseError::InvalidContentDisposition)?,
+                ];
+                let byte = str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or(HTTPParseError::InvalidContentDisposition)?;
+                bytes.push(byte);
+            }
+            b if is_attr_char(b) => bytes.push(b),
+            _ => return Err(HTTPParseError::InvalidContentDisposition),
+        }
+    }
+
+    if charset.eq_ignore_ascii_case("utf-8") {
+        String::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| HTTPParseError::InvalidContentDisposition)
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Ok(Some(bytes.into_iter().map(char::from).collect()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Encode a value as `UTF-8''value-chars`
+fn encode_ext_value(value: &str) -> String {
+    let mut encoded = String::from("UTF-8''");
+    for b in value.bytes() {
+        if is_attr_char(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+/// `attr-char` from RFC 8187, the characters that don't need percent encoding
+fn is_attr_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b)
+}
+
+/// Serializes to a header value
+impl Display for ContentDisposition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.disposition.as_str())?;
+        for (name, value) in &self.params {
+            if name.ends_with('*') {
+                write!(f, "; {name}={}", encode_ext_value(value))?;
+            } else if is_token(value) {
+                write!(f, "; {name}={value}")?;
+            } else {
+                // control characters would allow injecting headers, so never send them
+                let value = value.replace(|c: char| c.is_ascii_control(), "_");
+                write!(f, "; {name}={}", quote(&value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("inline", ContentDisposition::inline())]
+    #[case(
+        "attachment; filename=genome.jpeg",
+        ContentDisposition::attachment().param("filename", "genome.jpeg")
+    )]
+    #[case(
+        r#"Attachment ; FileName = "my \"file\".txt" ;"#,
+        ContentDisposition::attachment().param("filename", "my \"file\".txt")
+    )]
+    #[case(
+        r#"form-data; name="field1"; filename="a;b.txt""#,
+        ContentDisposition::form_data("field1").param("filename", "a;b.txt")
+    )]
+    #[case(
+        "attachment; filename*=UTF-8''%e2%82%ac%20rates",
+        ContentDisposition::attachment().param("filename*", "€ rates")
+    )]
+    #[case(
+        "attachment; filename*=iso-8859-1'en'%A3%20rates",
+        ContentDisposition::attachment().param("filename*", "£ rates")
+    )]
+    #[case(
+        "attachment; filename=fallback; filename*=koi8-r''%c1",
+        ContentDisposition::attachment().param("filename", "fallback")
+    )]
+    #[case(
+        "x-custom",
+        ContentDisposition::new(DispositionType::Other("x-custom".to_string()))
+    )]
+    fn test_parse(#[case] input: &str, #[case] expected: ContentDisposition) {
+        assert_eq!(Ok(expected), ContentDisposition::parse(input));
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("attach ment")]
+    #[case("attachment filename=a")]
+    #[case("attachment; filename")]
+    #[case(r#"attachment; filename="unterminated"#)]
+    #[case("attachment; filename*=no-quotes")]
+    #[case("attachment; filename*=UTF-8''%zz")]
+    #[case("attachment; filename*=UTF-8''%ff")]
+    #[case("attachment; filename*=UTF-8''a b")]
+    fn test_parse_invalid(#[case] input: &str) {
+        assert_eq!(
+            Err(HTTPParseError::InvalidContentDisposition),
+            ContentDisposition::parse(input)
+        );
+    }
+
+    #[rstest]
+    #[case(
+        r#"attachment; filename="a.txt"; filename*=UTF-8''b.txt"#,
+        Some("b.txt")
+    )]
+    #[case(r#"attachment; filename="../../etc/passwd""#, Some("passwd"))]
+    #[case(r#"attachment; filename="C:\\temp\\x.exe""#, Some("x.exe"))]
+    #[case("inline", None)]
+    fn test_get_filename(#[case] input: &str, #[case] expected: Option<&str>) {
+        assert_eq!(
+            expected,
+            ContentDisposition::parse(input).unwrap().get_filename()
+        );
+    }
+
+    #[rstest]
+    #[case(
+        ContentDisposition::attachment().filename("report.pdf"),
+        "attachment; filename=report.pdf"
+    )]
+    #[case(
+        ContentDisposition::attachment().filename("my report.pdf"),
+        r#"attachment; filename="my report.pdf""#
+    )]
+    #[case(
+        ContentDisposition::attachment().filename("€ \"rates\".pdf"),
+        r#"attachment; filename*=UTF-8''%E2%82%AC%20%22rates%22.pdf; filename="_ \"rates\".pdf""#
+    )]
+    #[case(
+        ContentDisposition::form_data("upload").param("filename", "evil\r\nSet-Cookie: x"),
+        r#"form-data; name=upload; filename="evil__Set-Cookie: x""#
+    )]
+    fn test_display(#[case] disposition: ContentDisposition, #[case] expected: &str) {
+        assert_eq!(expected, disposition.to_string());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let disposition = ContentDisposition::attachment().filename("naïve résumé.txt");
+        let parsed = ContentDisposition::parse(&disposition.to_string()).unwrap();
+        assert_eq!(disposition, parsed);
+        assert_eq!(Some("naïve résumé.txt"), parsed.get_filename());
+    }
+}
@@ -0,0 +1,250 @@
+//! Content codings and their negotiation with `Accept-Encoding` (RFC 9110 sections 8.4 and 12.5.3).
+
+use std::fmt::Display;
+
+use crate::{HTTPParseError, headers::is_token};
+
+/// A content coding from the Content-Encoding or Accept-Encoding header
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ContentCoding {
+    Gzip,
+    Br,
+    Deflate,
+    Zstd,
+    Identity,
+    /// A coding we don't know, lowercased
+    Unknown(String),
+}
+
+impl ContentCoding {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Br => "br",
+            ContentCoding::Deflate => "deflate",
+            ContentCoding::Zstd => "zstd",
+            ContentCoding::Identity => "identity",
+            ContentCoding::Unknown(name) => name,
+        }
+    }
+}
+
+impl From<&str> for ContentCoding {
+    fn from(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => ContentCoding::Gzip,
+            "br" => ContentCoding::Br,
+            "deflate" => ContentCoding::Deflate,
+            "zstd" => ContentCoding::Zstd,
+            "identity" => ContentCoding::Identity,
+            name => ContentCoding::Unknown(name.to_string()),
+        }
+    }
+}
+
+impl Display for ContentCoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Parse a Content-Encoding value into the ordered list of codings applied to the body
+pub fn parse_content_encoding(value: &str) -> Result<Vec<ContentCoding>, HTTPParseError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .map(|coding| {
+            if is_token(coding) {
+                Ok(ContentCoding::from(coding))
+            } else {
+                Err(HTTPParseError::InvalidEncoding)
+            }
+        })
+        .collect()
+}
+
+/// The codings a client is willing to accept, parsed from an `Accept-Encoding` header.
+///
+/// Quality values are kept in thousandths, so `q=0.5` is `500`, which avoids comparing floats.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct AcceptEncoding {
+    codings: Vec<(ContentCoding, u16)>,
+    /// The quality of the `*` wildcard, if it was present
+    any: Option<u16>,
+}
+
+impl AcceptEncoding {
+    /// Parse the value of an `Accept-Encoding` header.
+    /// An empty value is valid and means that only `identity` is acceptable.
+    pub fn parse(value: &str) -> Result<Self, HTTPParseError> {
+        let mut accept = Self::default();
+        for item in value.split(',').map(str::trim) {
+            // empty list elements are allowed and ignored (RFC 9110 section 5.6.1)
+            if item.is_empty() {
+                continue;
+            }
+
+            let mut parts = item.split(';').map(str::trim);
+            let coding = parts.next().unwrap_or_default();
+            if !is_token(coding) {
+                return Err(HTTPParseError::InvalidEncoding);
+            }
+
+            let mut quality = 1000;
+            for param in parts {
+                let Some((name, value)) = param.split_once('=') else {
+                    return Err(HTTPParseError::InvalidEncoding);
+                };
+                if name.trim_end().eq_ignore_ascii_case("q") {
+                    quality = parse_qvalue(value.trim_start())?;
+                }
+            }
+
+            if coding == "*" {
+                accept.any = Some(quality);
+            } else {
+                accept.codings.push((ContentCoding::from(coding), quality));
+            }
+        }
+
+        Ok(accept)
+    }
+
+    /// The quality of a coding, from 0 (not acceptable) to 1000 (preferred).
+    /// `identity` is acceptable unless it is explicitly excluded, either directly or by `*;q=0`.
+    pub fn quality(&self, coding: &ContentCoding) -> u16 {
+        if let Some((_, quality)) = self.codings.iter().find(|(c, _)| c == coding) {
+            return *quality;
+        }
+
+        match (self.any, coding) {
+            (Some(quality), _) => quality,
+            (None, ContentCoding::Identity) => 1,
+            (None, _) => 0,
+        }
+    }
+
+    /// Whether the client accepts this coding
+    pub fn accepts(&self, coding: &ContentCoding) -> bool {
+        self.quality(coding) > 0
+    }
+
+    /// Pick the coding to apply from the ones the server supports, listed in order of the
+    /// server's preference which is used to break ties.
+    /// Returns `None` if none of them are acceptable, in which case the server should respond
+    /// with `406 Not Acceptable` or send the body without a coding anyway.
+    pub fn preferred<'a>(&self, available: &'a [ContentCoding]) -> Option<&'a ContentCoding> {
+        available
+            .iter()
+            .map(|coding| (coding, self.quality(coding)))
+            .filter(|(_, quality)| *quality > 0)
+            // max_by_key returns the last maximum, so reverse to prefer earlier entries
+            .rev()
+            .max_by_key(|(_, quality)| *quality)
+            .map(|(coding, _)| coding)
+    }
+}
+
+/// Parse a quality value, which has at most three decimal places and is between 0 and 1
+fn parse_qvalue(value: &str) -> Result<u16, HTTPParseError> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(HTTPParseError::InvalidEncoding);
+    }
+
+    let fraction = format!("{fraction:0<3}").parse::<u16>().unwrap_or_default();
+    match whole {
+        "0" => Ok(fraction),
+        "1" if fraction == 0 => Ok(1000),
+        _ => Err(HTTPParseError::InvalidEncoding),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("gzip", Ok(vec![ContentCoding::Gzip]))]
+    #[case("deflate, X-GZIP", Ok(vec![ContentCoding::Deflate, ContentCoding::Gzip]))]
+    #[case("br,,zstd", Ok(vec![ContentCoding::Br, ContentCoding::Zstd]))]
+    #[case("aes128gcm", Ok(vec![ContentCoding::Unknown("aes128gcm".to_string())]))]
+    #[case("", Ok(vec![]))]
+    #[case("gzip;q=1", Err(HTTPParseError::InvalidEncoding))]
+    fn test_parse_content_encoding(
+        #[case] input: &str,
+        #[case] expected: Result<Vec<ContentCoding>, HTTPParseError>,
+    ) {
+        assert_eq!(expected, parse_content_encoding(input));
+    }
+
+    #[rstest]
+    #[case("0", Ok(0))]
+    #[case("0.5", Ok(500))]
+    #[case("0.25", Ok(250))]
+    #[case("0.001", Ok(1))]
+    #[case("1", Ok(1000))]
+    #[case("1.000", Ok(1000))]
+    #[case("1.5", Err(HTTPParseError::InvalidEncoding))]
+    #[case("0.1234", Err(HTTPParseError::InvalidEncoding))]
+    #[case("-0", Err(HTTPParseError::InvalidEncoding))]
+    #[case("", Err(HTTPParseError::InvalidEncoding))]
+    fn test_parse_qvalue(#[case] input: &str, #[case] expected: Result<u16, HTTPParseError>) {
+        assert_eq!(expected, parse_qvalue(input));
+    }
+
+    #[rstest]
+    #[case("gzip, deflate;q=0.5", ContentCoding::Gzip, 1000)]
+    #[case("gzip, deflate;q=0.5", ContentCoding::Deflate, 500)]
+    #[case("gzip, deflate;q=0.5", ContentCoding::Br, 0)]
+    #[case("gzip, deflate;q=0.5", ContentCoding::Identity, 1)]
+    #[case("GZIP;Q=0.8", ContentCoding::Gzip, 800)]
+    #[case("*;q=0.3, br", ContentCoding::Zstd, 300)]
+    #[case("*;q=0", ContentCoding::Identity, 0)]
+    #[case("identity;q=0, *", ContentCoding::Identity, 0)]
+    #[case("", ContentCoding::Identity, 1)]
+    #[case("", ContentCoding::Gzip, 0)]
+    fn test_quality(#[case] input: &str, #[case] coding: ContentCoding, #[case] expected: u16) {
+        assert_eq!(
+            expected,
+            AcceptEncoding::parse(input).unwrap().quality(&coding)
+        );
+    }
+
+    #[rstest]
+    #[case("gzip;q=2")]
+    #[case("gzip;q")]
+    #[case("gz ip")]
+    #[case("gzip;q=0.5;")]
+    fn test_parse_accept_encoding_invalid(#[case] input: &str) {
+        assert_eq!(
+            Err(HTTPParseError::InvalidEncoding),
+            AcceptEncoding::parse(input)
+        );
+    }
+
+    #[rstest]
+    #[case("gzip, br", Some(ContentCoding::Br))]
+    #[case("gzip, br;q=0.9", Some(ContentCoding::Gzip))]
+    #[case("deflate", None)]
+    #[case("*", Some(ContentCoding::Zstd))]
+    #[case("*, zstd;q=0", Some(ContentCoding::Br))]
+    #[case("", None)]
+    fn test_preferred(#[case] input: &str, #[case] expected: Option<ContentCoding>) {
+        let available = [ContentCoding::Zstd, ContentCoding::Br, ContentCoding::Gzip];
+        assert_eq!(
+            expected.as_ref(),
+            AcceptEncoding::parse(input).unwrap().preferred(&available)
+        );
+    }
+
+    #[test]
+    fn test_preferred_falls_back_to_identity() {
+        let available = [ContentCoding::Gzip, ContentCoding::Identity];
+        let accept = AcceptEncoding::parse("br").unwrap();
+        assert_eq!(Some(&ContentCoding::Identity), accept.preferred(&available));
+    }
+}
@@ -0,0 +1,576 @@
+//! Generators of messages for fuzzing and property tests, enabled by the `fuzzing` feature.
+//!
+//! [`Request`], [`Response`] and [`Headers`] implement [`arbitrary::Arbitrary`] for use with
+//! cargo-fuzz, and proptest's `Arbitrary` so `any::<Request>()` works in property tests.
+//! Generated messages are always valid, and parse back to the same message after being
+//! serialized.
+//!
+//! Parsers mostly go wrong on input that is *almost* valid, so [`NearValidRequest`],
+//! [`NearValidResponse`], [`near_valid_request`] and [`near_valid_response`] take a valid
+//! message and break it the way real clients, servers and attackers do: conflicting
+//! framing, obsolete line folding, bare LFs, stray whitespace and so on.
+
+use arbitrary::{Arbitrary, Unstructured};
+use http::StatusCode;
+use memchr::{memchr, memmem};
+use proptest::{
+    arbitrary::any,
+    collection, prop_oneof, sample,
+    strategy::{BoxedStrategy, Just, Strategy},
+};
+
+use crate::{
+    Body, CRLF, HTTPMethod, HTTPVersion, HeaderName, HeaderValue, Headers, Request, Response,
+    StandardHeader, headers_as_fields,
+};
+
+const METHODS: [HTTPMethod; 9] = [
+    HTTPMethod::GET,
+    HTTPMethod::POST,
+    HTTPMethod::PUT,
+    HTTPMethod::PATCH,
+    HTTPMethod::DELETE,
+    HTTPMethod::HEAD,
+    HTTPMethod::CONNECT,
+    HTTPMethod::OPTIONS,
+    HTTPMethod::TRACE,
+];
+
+const VERSIONS: [HTTPVersion; 4] = [
+    HTTPVersion::HTTP1_0,
+    HTTPVersion::HTTP1_1,
+    HTTPVersion::HTTP2,
+    HTTPVersion::HTTP3,
+];
+
+/// The versions a message can have on the wire in HTTP/1.x syntax
+const WIRE_VERSIONS: [HTTPVersion; 2] = [HTTPVersion::HTTP1_0, HTTPVersion::HTTP1_1];
+
+/// `tchar` from RFC 9110 section 5.6.2
+const TOKEN_CHARS: &[u8] =
+    b"!#$%&'*+-.^_`|~0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// `pchar` from RFC 3986 without percent encoding, plus the `/` and `?` delimiters
+const PATH_CHARS: &[u8] =
+    b"!$&'()*+,-./:;=?@_~0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// Letters, digits, `-` and `.`, which make up host names
+const HOST_CHARS: &[u8] = b"-.0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// The longest generated custom header names, paths and host names
+const MAX_LEN: usize = 40;
+
+impl<'a> Arbitrary<'a> for HTTPMethod {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        u.choose(&METHODS).copied()
+    }
+}
+
+impl<'a> Arbitrary<'a> for HTTPVersion {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        u.choose(&VERSIONS).copied()
+    }
+}
+
+impl<'a> Arbitrary<'a> for Body {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(match u.int_in_range(0..=3)? {
+            0 => Body::Empty,
+            1 => Body::Sized(u.arbitrary()?),
+            2 => Body::Chunked,
+            _ => Body::UntilClose,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for HeaderName {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        // mostly well-known names, since those are the ones with a meaning to get wrong
+        if u.ratio(3u8, 4u8)? {
+            Ok((*u.choose(&StandardHeader::ALL)?).into())
+        } else {
+            let len = u.int_in_range(1..=MAX_LEN)?;
+            let name = (0..len)
+                .map(|_| u.choose(TOKEN_CHARS).map(|&c| c as char))
+                .collect::<arbitrary::Result<String>>()?;
+            Ok(HeaderName::from_valid(&name))
+        }
+    }
+}
+
+impl<'a> Arbitrary<'a> for HeaderValue {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let value = Vec::<u8>::arbitrary(u)?
+            .into_iter()
+            .filter(|&b| is_value_byte(b))
+            .collect::<Vec<_>>();
+        Ok(value_from_bytes(&value))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Headers {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut headers = Headers::new();
+        for field in u.arbitrary_iter::<(HeaderName, HeaderValue)>()? {
+            let (name, value) = field?;
+            headers.insert(name, value);
+        }
+        Ok(headers)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Request {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let method = u.arbitrary()?;
+        // CONNECT targets the authority of the destination rather than a path
+        let target = match method {
+            HTTPMethod::CONNECT => {
+                let len = u.int_in_range(1..=MAX_LEN)?;
+                let host = (0..len)
+                    .map(|_| u.choose(HOST_CHARS).copied())
+                    .collect::<arbitrary::Result<Vec<_>>>()?;
+                authority_form(&host, u.arbitrary()?)
+            }
+            _ => {
+                let len = u.int_in_range(0..=MAX_LEN)?;
+                let path = (0..len)
+                    .map(|_| u.choose(PATH_CHARS).copied())
+                    .collect::<arbitrary::Result<Vec<_>>>()?;
+                origin_form(&path)
+            }
+        };
+        let version = *u.choose(&WIRE_VERSIONS)?;
+        let headers = u.arbitrary()?;
+        let body = u.arbitrary()?;
+        Ok(build_request(method, target, version, headers, body))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Response {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let status = u.int_in_range(100..=599)?;
+        let version = *u.choose(&WIRE_VERSIONS)?;
+        let headers = u.arbitrary()?;
+        let body = u.arbitrary()?;
+        Ok(build_response(status, version, headers, body))
+    }
+}
+
+/// The bytes of a valid request broken in one or more ways
+#[derive(Debug, Clone)]
+pub struct NearValidRequest(pub Vec<u8>);
+
+/// The bytes of a valid response broken in one or more ways
+#[derive(Debug, Clone)]
+pub struct NearValidResponse(pub Vec<u8>);
+
+impl<'a> Arbitrary<'a> for NearValidRequest {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut message = Request::arbitrary(u)?.into_bytes();
+        mutate(u, &mut message)?;
+        Ok(Self(message))
+    }
+}
+
+impl<'a> Arbitrary<'a> for NearValidResponse {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut message = Response::arbitrary(u)?.into_bytes();
+        mutate(u, &mut message)?;
+        Ok(Self(message))
+    }
+}
+
+/// Apply at least one mutation to the message
+fn mutate(u: &mut Unstructured, message: &mut Vec<u8>) -> arbitrary::Result<()> {
+    Mutation::arbitrary(u)?.apply(message);
+    for mutation in u.arbitrary_iter::<Mutation>()? {
+        mutation?.apply(message);
+    }
+    Ok(())
+}
+
+/// A way to break an otherwise valid message
+#[derive(Debug, Clone, Copy, Arbitrary)]
+enum Mutation {
+    /// Two Content-Length headers that disagree
+    ConflictingContentLength,
+    /// Both Content-Length and Transfer-Encoding, the classic request smuggling vector
+    LengthAndChunked,
+    /// A transfer coding after chunked
+    ChunkedNotLast,
+    /// A Content-Length that doesn't fit in 64 bits
+    OverflowingContentLength,
+    /// A Content-Length with a sign, which some integer parsers accept
+    SignedContentLength,
+    /// A header value continued on the next line (obs-fold)
+    ObsFold,
+    /// Whitespace between a header name and the colon
+    SpaceBeforeColon,
+    /// A header line without a colon
+    MissingColon,
+    /// A header name containing this byte, which is usually not allowed there
+    NameByte(u8),
+    /// A header value containing this byte, which is usually not allowed there
+    ValueByte(u8),
+    /// A line ending in a bare LF
+    BareLf,
+    /// The start line lowercased
+    LowercaseStartLine,
+    /// Two spaces between the first two parts of the start line
+    DoubleSpace,
+    /// The message cut short at this offset, wrapped around its length
+    Truncate(usize),
+    /// The byte at this offset, wrapped around the message length, replaced by another
+    Replace(usize, u8),
+}
+
+impl Mutation {
+    fn apply(self, message: &mut Vec<u8>) {
+        match self {
+            Mutation::ConflictingContentLength => {
+                insert_line(message, b"Content-Length: 1\r\nContent-Length: 2\r\n")
+            }
+            Mutation::LengthAndChunked => insert_line(
+                message,
+                b"Content-Length: 5\r\nTransfer-Encoding: chunked\r\n",
+            ),
+            Mutation::ChunkedNotLast => {
+                insert_line(message, b"Transfer-Encoding: chunked, gzip\r\n")
+            }
+            Mutation::OverflowingContentLength => {
+                insert_line(message, b"Content-Length: 18446744073709551616\r\n")
+            }
+            Mutation::SignedContentLength => insert_line(message, b"Content-Length: +5\r\n"),
+            Mutation::ObsFold => insert_line(message, b"X-Folded: a\r\n b\r\n"),
+            Mutation::SpaceBeforeColon => insert_line(message, b"Content-Length : 0\r\n"),
+            Mutation::MissingColon => insert_line(message, b"X-Missing-Colon\r\n"),
+            Mutation::NameByte(b) => insert_line(message, &[b"X-", &[b][..], b": v\r\n"].concat()),
+            Mutation::ValueByte(b) => {
+                insert_line(message, &[b"X-Value: ", &[b][..], b"\r\n"].concat())
+            }
+            Mutation::BareLf => {
+                if let Some(i) = memmem::find(message, CRLF) {
+                    message.remove(i);
+                }
+            }
+            Mutation::LowercaseStartLine => {
+                let end = start_line_end(message);
+                message[..end].make_ascii_lowercase();
+            }
+            Mutation::DoubleSpace => {
+                if let Some(i) = memchr(b' ', message) {
+                    message.insert(i, b' ');
+                }
+            }
+            Mutation::Truncate(at) => message.truncate(at % (message.len() + 1)),
+            Mutation::Replace(at, b) => {
+                if !message.is_empty() {
+                    let len = message.len();
+                    message[at % len] = b;
+                }
+            }
+        }
+    }
+}
+
+/// Insert header lines right after the start line
+fn insert_line(message: &mut Vec<u8>, line: &[u8]) {
+    let at = start_line_end(message);
+    message.splice(at..at, line.iter().copied());
+}
+
+/// The offset just past the first line break, or the end if there is none
+fn start_line_end(message: &[u8]) -> usize {
+    memchr(b'\n', message).map_or(message.len(), |i| i + 1)
+}
+
+/// Any octet but control characters, other than horizontal tab
+fn is_value_byte(b: u8) -> bool {
+    !b.is_ascii_control() || b == b'\t'
+}
+
+fn value_from_bytes(value: &[u8]) -> HeaderValue {
+    // whitespace around values isn't part of them, so it would be lost when parsing
+    HeaderValue::try_from(value.trim_ascii()).expect("control characters were filtered out")
+}
+
+/// Build a request whose framing headers agree with the body
+fn build_request(
+    method: HTTPMethod,
+    target: String,
+    version: HTTPVersion,
+    mut headers: Headers,
+    body: Body,
+) -> Request {
+    set_framing(&mut headers, body);
+    Request {
+        path: target,
+        method,
+        body: Body::for_request(headers_as_fields(&headers)).expect("framing headers are valid"),
+        headers,
+        version,
+    }
+}
+
+/// The target of a request for the path, which is made of `PATH_CHARS`
+fn origin_form(path: &[u8]) -> String {
+    format!("/{}", String::from_utf8_lossy(path))
+}
+
+/// The target of a CONNECT request for the host, which is made of `HOST_CHARS`
+fn authority_form(host: &[u8], port: u16) -> String {
+    format!("{}:{port}", String::from_utf8_lossy(host))
+}
+
+/// Build a response whose framing headers agree with the body
+fn build_response(status: u16, version: HTTPVersion, mut headers: Headers, body: Body) -> Response {
+    let status = StatusCode::from_u16(status).expect("status is between 100 and 599");
+    set_framing(&mut headers, body);
+    Response {
+        status,
+        version,
+        body: Body::for_response(status, headers_as_fields(&headers))
+            .expect("framing headers are valid"),
+        headers,
+    }
+}
+
+/// Replace any framing headers with ones for the body
+fn set_framing(headers: &mut Headers, body: Body) {
+    headers.remove("content-length");
+    headers.remove("transfer-encoding");
+    match body {
+        Body::Sized(length) => headers.insert(
+            StandardHeader::ContentLength.into(),
+            HeaderValue::try_from(length.to_string()).expect("digits are a valid value"),
+        ),
+        Body::Chunked => headers.insert(
+            StandardHeader::TransferEncoding.into(),
+            HeaderValue::from_static("chunked"),
+        ),
+        Body::Empty | Body::UntilClose => None,
+    };
+}
+
+pub fn method() -> impl Strategy<Value = HTTPMethod> {
+    sample::select(&METHODS[..])
+}
+
+pub fn header_name() -> impl Strategy<Value = HeaderName> {
+    prop_oneof![
+        3 => sample::select(&StandardHeader::ALL[..]).prop_map(HeaderName::from),
+        1 => collection::vec(sample::select(TOKEN_CHARS), 1..=MAX_LEN).prop_map(|name| {
+            HeaderName::from_valid(str::from_utf8(&name).expect("tokens are ASCII"))
+        }),
+    ]
+}
+
+pub fn header_value() -> impl Strategy<Value = HeaderValue> {
+    collection::vec(
+        any::<u8>().prop_filter("control characters aren't allowed", |&b| is_value_byte(b)),
+        0..MAX_LEN,
+    )
+    .prop_map(|value| value_from_bytes(&value))
+}
+
+pub fn headers() -> impl Strategy<Value = Headers> {
+    collection::vec((header_name(), header_value()), 0..20).prop_map(|fields| {
+        let mut headers = Headers::new();
+        for (name, value) in fields {
+            headers.insert(name, value);
+        }
+        headers
+    })
+}
+
+fn body() -> impl Strategy<Value = Body> {
+    prop_oneof![
+        Just(Body::Empty),
+        any::<u64>().prop_map(Body::Sized),
+        Just(Body::Chunked),
+        Just(Body::UntilClose),
+    ]
+}
+
+/// A target fitting the method: the authority of the destination for CONNECT, a path
+/// otherwise
+fn target(method: HTTPMethod) -> BoxedStrategy<String> {
+    match method {
+        HTTPMethod::CONNECT => (
+            collection::vec(sample::select(HOST_CHARS), 1..=MAX_LEN),
+            any::<u16>(),
+        )
+            .prop_map(|(host, port)| authority_form(&host, port))
+            .boxed(),
+        _ => collection::vec(sample::select(PATH_CHARS), 0..=MAX_LEN)
+            .prop_map(|path| origin_form(&path))
+            .boxed(),
+    }
+}
+
+pub fn request() -> impl Strategy<Value = Request> {
+    (
+        method().prop_flat_map(|method| (Just(method), target(method))),
+        sample::select(&WIRE_VERSIONS[..]),
+        headers(),
+        body(),
+    )
+        .prop_map(|((method, target), version, headers, body)| {
+            build_request(method, target, version, headers, body)
+        })
+}
+
+pub fn response() -> impl Strategy<Value = Response> {
+    (
+        100..=599u16,
+        sample::select(&WIRE_VERSIONS[..]),
+        headers(),
+        body(),
+    )
+        .prop_map(|(status, version, headers, body)| build_response(status, version, headers, body))
+}
+
+fn mutation() -> impl Strategy<Value = Mutation> {
+    prop_oneof![
+        Just(Mutation::ConflictingContentLength),
+        Just(Mutation::LengthAndChunked),
+        Just(Mutation::ChunkedNotLast),
+        Just(Mutation::OverflowingContentLength),
+        Just(Mutation::SignedContentLength),
+        Just(Mutation::ObsFold),
+        Just(Mutation::SpaceBeforeColon),
+        Just(Mutation::MissingColon),
+        any::<u8>().prop_map(Mutation::NameByte),
+        any::<u8>().prop_map(Mutation::ValueByte),
+        Just(Mutation::BareLf),
+        Just(Mutation::LowercaseStartLine),
+        Just(Mutation::DoubleSpace),
+        any::<usize>().prop_map(Mutation::Truncate),
+        any::<(usize, u8)>().prop_map(|(at, b)| Mutation::Replace(at, b)),
+    ]
+}
+
+/// The bytes of a valid request broken in one or more ways
+pub fn near_valid_request() -> impl Strategy<Value = Vec<u8>> {
+    (request(), collection::vec(mutation(), 1..4)).prop_map(|(request, mutations)| {
+        let mut message = request.into_bytes();
+        mutations
+            .into_iter()
+            .for_each(|mutation| mutation.apply(&mut message));
+        message
+    })
+}
+
+/// The bytes of a valid response broken in one or more ways
+pub fn near_valid_response() -> impl Strategy<Value = Vec<u8>> {
+    (response(), collection::vec(mutation(), 1..4)).prop_map(|(response, mutations)| {
+        let mut message = response.into_bytes();
+        mutations
+            .into_iter()
+            .for_each(|mutation| mutation.apply(&mut message));
+        message
+    })
+}
+
+impl proptest::arbitrary::Arbitrary for Headers {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        headers().boxed()
+    }
+}
+
+impl proptest::arbitrary::Arbitrary for Request {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        request().boxed()
+    }
+}
+
+impl proptest::arbitrary::Arbitrary for Response {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        response().boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use proptest::{prop_assert_eq, proptest};
+
+    use crate::{RawRequest, RawResponse};
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_request_round_trip(request in request()) {
+            let bytes = request.into_bytes();
+            prop_assert_eq!(Ok((request, &b""[..])), Request::parse(&bytes));
+        }
+
+        #[test]
+        fn test_response_round_trip(response in response()) {
+            let bytes = response.into_bytes();
+            prop_assert_eq!(Ok((response, &b""[..])), Response::parse(&bytes));
+        }
+
+        #[test]
+        fn test_near_valid_request(bytes in near_valid_request()) {
+            // any result is fine as long as nothing panics
+            let _ = Request::parse(&bytes);
+            let _ = RawRequest::parse(Bytes::from(bytes.clone()));
+            Request::parse_many(&bytes).for_each(drop);
+        }
+
+        #[test]
+        fn test_near_valid_response(bytes in near_valid_response()) {
+            let _ = Response::parse(&bytes);
+            let _ = Response::parse_final(&bytes);
+            let _ = RawResponse::parse(Bytes::from(bytes));
+        }
+    }
+
+    /// Deterministic noise so the arbitrary tests are reproducible
+    fn noise(len: usize) -> Vec<u8> {
+        (0..len as u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_arbitrary_round_trip() {
+        let data = noise(1 << 16);
+        let mut u = Unstructured::new(&data);
+        while !u.is_empty() {
+            let request = Request::arbitrary(&mut u).unwrap();
+            let bytes = request.into_bytes();
+            assert_eq!(Ok((request, &b""[..])), Request::parse(&bytes));
+
+            let response = Response::arbitrary(&mut u).unwrap();
+            let bytes = response.into_bytes();
+            assert_eq!(Ok((response, &b""[..])), Response::parse(&bytes));
+        }
+    }
+
+    #[test]
+    fn test_arbitrary_near_valid() {
+        let data = noise(1 << 16);
+        let mut u = Unstructured::new(&data);
+        let mut rejected = 0;
+        while !u.is_empty() {
+            let NearValidRequest(bytes) = NearValidRequest::arbitrary(&mut u).unwrap();
+            rejected += usize::from(Request::parse(&bytes).is_err());
+            let NearValidResponse(bytes) = NearValidResponse::arbitrary(&mut u).unwrap();
+            let _ = Response::parse(&bytes);
+        }
+        // the mutations should mostly produce invalid messages
+        assert!(rejected > 0);
+    }
+}
@@ -0,0 +1,807 @@
+//! HTTP/2 frame layer (RFC 9113 section 4 and 6).
+//!
+//! This module only deals with framing: splitting a byte stream into frames and
+//! serializing frames back into bytes. Header blocks carried by HEADERS and
+//! CONTINUATION frames are left compressed.
+
+use std::fmt::Display;
+
+/// The client connection preface that must open every HTTP/2 connection
+pub const CONNECTION_PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Every frame starts with a fixed 9 byte header
+pub const FRAME_HEADER_LEN: usize = 9;
+
+/// The max frame size every endpoint must accept until told otherwise by SETTINGS
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+pub const MAX_ALLOWED_FRAME_SIZE: u32 = 16_777_215;
+/// The flow control window of every stream and of the connection until changed
+pub const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+pub const MAX_WINDOW_SIZE: u32 = 2_147_483_647;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_RST_STREAM: u8 = 0x3;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_PING: u8 = 0x6;
+const FRAME_GOAWAY: u8 = 0x7;
+const FRAME_WINDOW_UPDATE: u8 = 0x8;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+    /// The buffer does not contain a full frame yet
+    Incomplete,
+    /// The connection did not start with [`CONNECTION_PREFACE`]
+    InvalidPreface,
+    /// The frame length is larger than allowed or invalid for the frame type
+    FrameSize,
+    /// The frame violates the protocol, e.g. a DATA frame on stream 0
+    Protocol,
+    /// A window size or increment is out of range
+    FlowControl,
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                FrameError::Incomplete => "Frame is not complete",
+                FrameError::InvalidPreface => "Invalid HTTP/2 connection preface",
+                FrameError::FrameSize => "Invalid HTTP/2 frame size",
+                FrameError::Protocol => "HTTP/2 protocol error",
+                FrameError::FlowControl => "HTTP/2 flow control error",
+            }
+        )
+    }
+}
+
+impl FrameError {
+    /// The error code that should be sent in a GOAWAY for this error
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            FrameError::Incomplete | FrameError::InvalidPreface | FrameError::Protocol => {
+                ErrorCode::ProtocolError
+            }
+            FrameError::FrameSize => ErrorCode::FrameSizeError,
+            FrameError::FlowControl => ErrorCode::FlowControlError,
+        }
+    }
+}
+
+/// Error codes used by RST_STREAM and GOAWAY frames
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorCode {
+    NoError,
+    ProtocolError,
+    InternalError,
+    FlowControlError,
+    SettingsTimeout,
+    StreamClosed,
+    FrameSizeError,
+    RefusedStream,
+    Cancel,
+    CompressionError,
+    ConnectError,
+    EnhanceYourCalm,
+    InadequateSecurity,
+    HTTP1_1Required,
+    Unknown(u32),
+}
+
+impl From<u32> for ErrorCode {
+    fn from(value: u32) -> Self {
+        match value {
+            0x0 => ErrorCode::NoError,
+            0x1 => ErrorCode::ProtocolError,
+            0x2 => ErrorCode::InternalError,
+            0x3 => ErrorCode::FlowControlError,
+            0x4 => ErrorCode::SettingsTimeout,
+            0x5 => ErrorCode::StreamClosed,
+            0x6 => ErrorCode::FrameSizeError,
+            0x7 => ErrorCode::RefusedStream,
+            0x8 => ErrorCode::Cancel,
+            0x9 => ErrorCode::CompressionError,
+            0xa => ErrorCode::ConnectError,
+            0xb => ErrorCode::EnhanceYourCalm,
+            0xc => ErrorCode::InadequateSecurity,
+            0xd => ErrorCode::HTTP1_1Required,
+            code => ErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(value: ErrorCode) -> Self {
+        match value {
+            ErrorCode::NoError => 0x0,
+            ErrorCode::ProtocolError => 0x1,
+            ErrorCode::InternalError => 0x2,
+            ErrorCode::FlowControlError => 0x3,
+            ErrorCode::SettingsTimeout => 0x4,
+            ErrorCode::StreamClosed => 0x5,
+            ErrorCode::FrameSizeError => 0x6,
+            ErrorCode::RefusedStream => 0x7,
+            ErrorCode::Cancel => 0x8,
+            ErrorCode::CompressionError => 0x9,
+            ErrorCode::ConnectError => 0xa,
+            ErrorCode::EnhanceYourCalm => 0xb,
+            ErrorCode::InadequateSecurity => 0xc,
+            ErrorCode::HTTP1_1Required => 0xd,
+            ErrorCode::Unknown(code) => code,
+        }
+    }
+}
+
+/// Identifiers of the parameters carried by a SETTINGS frame
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SettingId {
+    HeaderTableSize,
+    EnablePush,
+    MaxConcurrentStreams,
+    InitialWindowSize,
+    MaxFrameSize,
+    MaxHeaderListSize,
+    /// Unknown settings must be ignored, but we keep them around so they round trip
+    Unknown(u16),
+}
+
+impl From<u16> for SettingId {
+    fn from(value: u16) -> Self {
+        match value {
+            0x1 => SettingId::HeaderTableSize,
+            0x2 => SettingId::EnablePush,
+            0x3 => SettingId::MaxConcurrentStreams,
+            0x4 => SettingId::InitialWindowSize,
+            0x5 => SettingId::MaxFrameSize,
+            0x6 => SettingId::MaxHeaderListSize,
+            id => SettingId::Unknown(id),
+        }
+    }
+}
+
+impl From<SettingId> for u16 {
+    fn from(value: SettingId) -> Self {
+        match value {
+            SettingId::HeaderTableSize => 0x1,
+            SettingId::EnablePush => 0x2,
+            SettingId::MaxConcurrentStreams => 0x3,
+            SettingId::InitialWindowSize => 0x4,
+            SettingId::MaxFrameSize => 0x5,
+            SettingId::MaxHeaderListSize => 0x6,
+            SettingId::Unknown(id) => id,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Setting {
+    pub id: SettingId,
+    pub value: u32,
+}
+
+impl Setting {
+    fn validate(&self) -> Result<(), FrameError> {
+        match self.id {
+            SettingId::EnablePush if self.value > 1 => Err(FrameError::Protocol),
+            SettingId::InitialWindowSize if self.value > MAX_WINDOW_SIZE => {
+                Err(FrameError::FlowControl)
+            }
+            SettingId::MaxFrameSize
+                if !(DEFAULT_MAX_FRAME_SIZE..=MAX_ALLOWED_FRAME_SIZE).contains(&self.value) =>
+            {
+                Err(FrameError::Protocol)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Stream dependency information carried by HEADERS frames with the PRIORITY flag
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Priority {
+    pub exclusive: bool,
+    pub dependency: u32,
+    pub weight: u8,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Frame<'a> {
+    Data {
+        stream_id: u32,
+        end_stream: bool,
+        data: &'a [u8],
+    },
+    Headers {
+        stream_id: u32,
+        end_stream: bool,
+        end_headers: bool,
+        priority: Option<Priority>,
+        /// The HPACK encoded header block fragment
+        header_block: &'a [u8],
+    },
+    RstStream {
+        stream_id: u32,
+        error_code: ErrorCode,
+    },
+    Settings {
+        ack: bool,
+        settings: Vec<Setting>,
+    },
+    Ping {
+        ack: bool,
+        data: [u8; 8],
+    },
+    GoAway {
+        last_stream_id: u32,
+        error_code: ErrorCode,
+        debug_data: &'a [u8],
+    },
+    WindowUpdate {
+        stream_id: u32,
+        increment: u32,
+    },
+    Continuation {
+        stream_id: u32,
+        end_headers: bool,
+        header_block: &'a [u8],
+    },
+    /// Frame types we don't interpret (PRIORITY, PUSH_PROMISE, extensions).
+    /// These are passed up so the caller can decide to ignore them.
+    Unknown {
+        frame_type: u8,
+        flags: u8,
+        stream_id: u32,
+        payload: &'a [u8],
+    },
+}
+
+/// The fixed 9 byte header in front of every frame
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FrameHeader {
+    pub length: u32,
+    pub frame_type: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+}
+
+impl FrameHeader {
+    /// Parse the frame header from the buffer and return the remaining bytes
+    pub fn parse(buf: &[u8]) -> Result<(Self, &[u8]), FrameError> {
+        if buf.len() < FRAME_HEADER_LEN {
+            return Err(FrameError::Incomplete);
+        }
+
+        let length = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+        let frame_type = buf[3];
+        let flags = buf[4];
+        // the most significant bit is reserved and must be ignored
+        let stream_id = read_u32(&buf[5..9]) & 0x7fff_ffff;
+
+        Ok((
+            Self {
+                length,
+                frame_type,
+                flags,
+                stream_id,
+            },
+            &buf[FRAME_HEADER_LEN..],
+        ))
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.length.to_be_bytes()[1..]);
+        buf.push(self.frame_type);
+        buf.push(self.flags);
+        buf.extend_from_slice(&(self.stream_id & 0x7fff_ffff).to_be_bytes());
+    }
+}
+
+/// Check that the buffer starts with the client connection preface.
+/// Returns the bytes following the preface.
+pub fn parse_preface(buf: &[u8]) -> Result<&[u8], FrameError> {
+    if buf.len() < CONNECTION_PREFACE.len() {
+        return if CONNECTION_PREFACE.starts_with(buf) {
+            Err(FrameError::Incomplete)
+        } else {
+            Err(FrameError::InvalidPreface)
+        };
+    }
+
+    if &buf[..CONNECTION_PREFACE.len()] != CONNECTION_PREFACE {
+        return Err(FrameError::InvalidPreface);
+    }
+
+    Ok(&buf[CONNECTION_PREFACE.len()..])
+}
+
+impl<'a> Frame<'a> {
+    /// Parse a single frame from the buffer and return the remaining bytes.
+    /// Frames longer than `max_frame_size` are rejected with [`FrameError::FrameSize`].
+    pub fn parse(buf: &'a [u8], max_frame_size: u32) -> Result<(Self, &'a [u8]), FrameError> {
+        let (header, buf) = FrameHeader::parse(buf)?;
+
+        if header.length > max_frame_size {
+            return Err(FrameError::FrameSize);
+        }
+
+        let length = header.length as usize;
+        if buf.len() < length {
+            return Err(FrameError::Incomplete);
+        }
+
+        let (payload, rest) = buf.split_at(length);
+        Ok((Self::parse_payload(header, payload)?, rest))
+    }
+
+    fn parse_payload(header: FrameHeader, payload: &'a [u8]) -> Result<Self, FrameError> {
+        let FrameHeader {
+            frame_type,
+            flags,
+            stream_id,
+            ..
+        } = header;
+
+        let frame = match frame_type {
+            FRAME_DATA => {
+                require_stream(stream_id)?;
+                Frame::Data {
+                    stream_id,
+                    end_stream: flags & FLAG_END_STREAM != 0,
+                    data: strip_padding(flags, payload)?,
+                }
+            }
+            FRAME_HEADERS => {
+                require_stream(stream_id)?;
+                let mut block = strip_padding(flags, payload)?;
+                let mut priority = None;
+                if flags & FLAG_PRIORITY != 0 {
+                    if block.len() < 5 {
+                        return Err(FrameError::FrameSize);
+                    }
+                    let dependency = read_u32(&block[..4]);
+                    priority = Some(Priority {
+                        exclusive: dependency & 0x8000_0000 != 0,
+                        dependency: dependency & 0x7fff_ffff,
+                        weight: block[4],
+                    });
+                    block = &block[5..];
+                }
+
+                Frame::Headers {
+                    stream_id,
+                    end_stream: flags & FLAG_END_STREAM != 0,
+                    end_headers: flags & FLAG_END_HEADERS != 0,
+                    priority,
+                    header_block: block,
+                }
+            }
+            FRAME_RST_STREAM => {
+                require_stream(stream_id)?;
+                if payload.len() != 4 {
+                    return Err(FrameError::FrameSize);
+                }
+                Frame::RstStream {
+                    stream_id,
+                    error_code: read_u32(payload).into(),
+                }
+            }
+            FRAME_SETTINGS => {
+                require_connection(stream_id)?;
+                let ack = flags & FLAG_ACK != 0;
+                if (ack && !payload.is_empty()) || !payload.len().is_multiple_of(6) {
+                    return Err(FrameError::FrameSize);
+                }
+
+                let settings = payload
+                    .chunks_exact(6)
+                    .map(|chunk| {
+                        let setting = Setting {
+                            id: u16::from_be_bytes([chunk[0], chunk[1]]).into(),
+                            value: read_u32(&chunk[2..]),
+                        };
+                        setting.validate().map(|_| setting)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Frame::Settings { ack, settings }
+            }
+            FRAME_PING => {
+                require_connection(stream_id)?;
+                let data = payload.try_into().map_err(|_| FrameError::FrameSize)?;
+                Frame::Ping {
+                    ack: flags & FLAG_ACK != 0,
+                    data,
+                }
+            }
+            FRAME_GOAWAY => {
+                require_connection(stream_id)?;
+                if payload.len() < 8 {
+                    return Err(FrameError::FrameSize);
+                }
+                Frame::GoAway {
+                    last_stream_id: read_u32(&payload[..4]) & 0x7fff_ffff,
+                    error_code: read_u32(&payload[4..8]).into(),
+                    debug_data: &payload[8..],
+                }
+            }
+            FRAME_WINDOW_UPDATE => {
+                if payload.len() != 4 {
+                    return Err(FrameError::FrameSize);
+                }
+                let increment = read_u32(payload) & 0x7fff_ffff;
+                if increment == 0 {
+                    return Err(FrameError::Protocol);
+                }
+                Frame::WindowUpdate {
+                    stream_id,
+                    increment,
+                }
+            }
+            FRAME_CONTINUATION => {
+                require_stream(stream_id)?;
+                Frame::Continuation {
+                    stream_id,
+                    end_headers: flags & FLAG_END_HEADERS != 0,
+                    header_block: payload,
+                }
+            }
+            frame_type => Frame::Unknown {
+                frame_type,
+                flags,
+                stream_id,
+                payload,
+            },
+        };
+
+        Ok(frame)
+    }
+
+    /// The stream this frame belongs to, 0 for connection level frames
+    pub fn stream_id(&self) -> u32 {
+        match self {
+            Frame::Data { stream_id, .. }
+            | Frame::Headers { stream_id, .. }
+            | Frame::RstStream { stream_id, .. }
+            | Frame::WindowUpdate { stream_id, .. }
+            | Frame::Continuation { stream_id, .. }
+            | Frame::Unknown { stream_id, .. } => *stream_id,
+            Frame::Settings { .. } | Frame::Ping { .. } | Frame::GoAway { .. } => 0,
+        }
+    }
+
+    /// Serialize the frame and append it to the buffer.
+    /// Frames are never padded when serialized.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        // reserve space for the frame header, we fill it in once we know the payload length
+        buf.extend_from_slice(&[0; FRAME_HEADER_LEN]);
+
+        let (frame_type, flags) = match self {
+            Frame::Data {
+                end_stream, data, ..
+            } => {
+                buf.extend_from_slice(data);
+                (FRAME_DATA, flag(*end_stream, FLAG_END_STREAM))
+            }
+            Frame::Headers {
+                end_stream,
+                end_headers,
+                priority,
+                header_block,
+                ..
+            } => {
+                if let Some(priority) = priority {
+                    let exclusive = if priority.exclusive { 0x8000_0000 } else { 0 };
+                    buf.extend_from_slice(&(priority.dependency | exclusive).to_be_bytes());
+                    buf.push(priority.weight);
+                }
+                buf.extend_from_slice(header_block);
+                (
+                    FRAME_HEADERS,
+                    flag(*end_stream, FLAG_END_STREAM)
+                        | flag(*end_headers, FLAG_END_HEADERS)
+                        | flag(priority.is_some(), FLAG_PRIORITY),
+                )
+            }
+            Frame::RstStream { error_code, .. } => {
+                buf.extend_from_slice(&u32::from(*error_code).to_be_bytes());
+                (FRAME_RST_STREAM, 0)
+            }
+            Frame::Settings { ack, settings } => {
+                for setting in settings {
+                    buf.extend_from_slice(&u16::from(setting.id).to_be_bytes());
+                    buf.extend_from_slice(&setting.value.to_be_bytes());
+                }
+                (FRAME_SETTINGS, flag(*ack, FLAG_ACK))
+            }
+            Frame::Ping { ack, data } => {
+                buf.extend_from_slice(data);
+                (FRAME_PING, flag(*ack, FLAG_ACK))
+            }
+            Frame::GoAway {
+                last_stream_id,
+                error_code,
+                debug_data,
+            } => {
+                buf.extend_from_slice(&last_stream_id.to_be_bytes());
+                buf.extend_from_slice(&u32::from(*error_code).to_be_bytes());
+                buf.extend_from_slice(debug_data);
+                (FRAME_GOAWAY, 0)
+            }
+            Frame::WindowUpdate { increment, .. } => {
+                buf.extend_from_slice(&increment.to_be_bytes());
+                (FRAME_WINDOW_UPDATE, 0)
+            }
+            Frame::Continuation {
+                end_headers,
+                header_block,
+                ..
+            } => {
+                buf.extend_from_slice(header_block);
+                (FRAME_CONTINUATION, flag(*end_headers, FLAG_END_HEADERS))
+            }
+            Frame::Unknown {
+                frame_type,
+                flags,
+                payload,
+                ..
+            } => {
+                buf.extend_from_slice(payload);
+                (*frame_type, *flags)
+            }
+        };
+
+        let header = FrameHeader {
+            length: (buf.len() - start - FRAME_HEADER_LEN) as u32,
+            frame_type,
+            flags,
+            stream_id: self.stream_id(),
+        };
+        let mut header_bytes = Vec::with_capacity(FRAME_HEADER_LEN);
+        header.encode(&mut header_bytes);
+        buf[start..start + FRAME_HEADER_LEN].copy_from_slice(&header_bytes);
+    }
+}
+
+fn flag(set: bool, flag: u8) -> u8 {
+    if set { flag } else { 0 }
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+fn require_stream(stream_id: u32) -> Result<(), FrameError> {
+    if stream_id == 0 {
+        return Err(FrameError::Protocol);
+    }
+    Ok(())
+}
+
+fn require_connection(stream_id: u32) -> Result<(), FrameError> {
+    if stream_id != 0 {
+        return Err(FrameError::Protocol);
+    }
+    Ok(())
+}
+
+/// Remove the pad length byte and trailing padding if the PADDED flag is set
+fn strip_padding(flags: u8, payload: &[u8]) -> Result<&[u8], FrameError> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+
+    let Some((&pad_length, payload)) = payload.split_first() else {
+        return Err(FrameError::FrameSize);
+    };
+
+    // padding that is as long as the payload is a protocol error
+    if pad_length as usize > payload.len() {
+        return Err(FrameError::Protocol);
+    }
+
+    Ok(&payload[..payload.len() - pad_length as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\nrest", Ok(b"rest".as_slice()))]
+    #[case(b"PRI * HTTP/2.0\r\n", Err(FrameError::Incomplete))]
+    #[case(b"GET / HTTP/1.1\r\n\r\n", Err(FrameError::InvalidPreface))]
+    #[case(
+        b"GET / HTTP/1.1\r\nHost: some-longer-host\r\n\r\n",
+        Err(FrameError::InvalidPreface)
+    )]
+    fn test_parse_preface(#[case] input: &[u8], #[case] expected: Result<&[u8], FrameError>) {
+        assert_eq!(expected, parse_preface(input));
+    }
+
+    #[rstest]
+    #[case(
+        b"\x00\x00\x05\x00\x01\x00\x00\x00\x01hello",
+        Ok((Frame::Data { stream_id: 1, end_stream: true, data: b"hello" }, b"".as_slice()))
+    )]
+    #[case(
+        b"\x00\x00\x08\x00\x08\x00\x00\x00\x03\x02hello\x00\x00rest",
+        Ok((Frame::Data { stream_id: 3, end_stream: false, data: b"hello" }, b"rest".as_slice()))
+    )]
+    #[case(
+        b"\x00\x00\x03\x01\x04\x00\x00\x00\x01\x82\x86\x84",
+        Ok((
+            Frame::Headers {
+                stream_id: 1,
+                end_stream: false,
+                end_headers: true,
+                priority: None,
+                header_block: b"\x82\x86\x84"
+            },
+            b"".as_slice()
+        ))
+    )]
+    #[case(
+        b"\x00\x00\x06\x01\x25\x00\x00\x00\x03\x80\x00\x00\x01\x0f\x82",
+        Ok((
+            Frame::Headers {
+                stream_id: 3,
+                end_stream: true,
+                end_headers: true,
+                priority: Some(Priority { exclusive: true, dependency: 1, weight: 15 }),
+                header_block: b"\x82"
+            },
+            b"".as_slice()
+        ))
+    )]
+    #[case(
+        b"\x00\x00\x04\x03\x00\x00\x00\x00\x01\x00\x00\x00\x08",
+        Ok((Frame::RstStream { stream_id: 1, error_code: ErrorCode::Cancel }, b"".as_slice()))
+    )]
+    #[case(
+        b"\x00\x00\x0c\x04\x00\x00\x00\x00\x00\x00\x03\x00\x00\x00\x64\x00\x04\x00\x01\x00\x00",
+        Ok((
+            Frame::Settings {
+                ack: false,
+                settings: vec![
+                    Setting { id: SettingId::MaxConcurrentStreams, value: 100 },
+                    Setting { id: SettingId::InitialWindowSize, value: 65536 },
+                ]
+            },
+            b"".as_slice()
+        ))
+    )]
+    #[case(
+        b"\x00\x00\x00\x04\x01\x00\x00\x00\x00",
+        Ok((Frame::Settings { ack: true, settings: vec![] }, b"".as_slice()))
+    )]
+    #[case(
+        b"\x00\x00\x08\x06\x01\x00\x00\x00\x00abcdefgh",
+        Ok((Frame::Ping { ack: true, data: *b"abcdefgh" }, b"".as_slice()))
+    )]
+    #[case(
+        b"\x00\x00\x0a\x07\x00\x00\x00\x00\x00\x00\x00\x00\x05\x00\x00\x00\x00hi",
+        Ok((
+            Frame::GoAway { last_stream_id: 5, error_code: ErrorCode::NoError, debug_data: b"hi" },
+            b"".as_slice()
+        ))
+    )]
+    #[case(
+        b"\x00\x00\x04\x08\x00\x00\x00\x00\x00\x00\x00\x10\x00",
+        Ok((Frame::WindowUpdate { stream_id: 0, increment: 4096 }, b"".as_slice()))
+    )]
+    #[case(
+        b"\x00\x00\x02\x09\x04\x00\x00\x00\x01\x82\x86",
+        Ok((
+            Frame::Continuation { stream_id: 1, end_headers: true, header_block: b"\x82\x86" },
+            b"".as_slice()
+        ))
+    )]
+    #[case(
+        b"\x00\x00\x01\x02\x00\x00\x00\x00\x01\x00",
+        Ok((
+            Frame::Unknown { frame_type: 2, flags: 0, stream_id: 1, payload: b"\x00" },
+            b"".as_slice()
+        ))
+    )]
+    #[case(b"\x00\x00\x05\x00\x01\x00\x00", Err(FrameError::Incomplete))]
+    #[case(
+        b"\x00\x00\x05\x00\x01\x00\x00\x00\x01hel",
+        Err(FrameError::Incomplete)
+    )]
+    #[case(
+        b"\x00\x00\x05\x00\x01\x00\x00\x00\x00hello",
+        Err(FrameError::Protocol)
+    )]
+    #[case(b"\x00\x40\x01\x00\x01\x00\x00\x00\x01", Err(FrameError::FrameSize))]
+    #[case(
+        b"\x00\x00\x02\x00\x08\x00\x00\x00\x01\x05a",
+        Err(FrameError::Protocol)
+    )]
+    #[case(
+        b"\x00\x00\x03\x03\x00\x00\x00\x00\x01\x00\x00\x08",
+        Err(FrameError::FrameSize)
+    )]
+    #[case(
+        b"\x00\x00\x05\x04\x00\x00\x00\x00\x00\x00\x03\x00\x00\x00",
+        Err(FrameError::FrameSize)
+    )]
+    #[case(
+        b"\x00\x00\x06\x04\x01\x00\x00\x00\x00\x00\x03\x00\x00\x00\x64",
+        Err(FrameError::FrameSize)
+    )]
+    #[case(
+        b"\x00\x00\x06\x04\x00\x00\x00\x00\x01\x00\x03\x00\x00\x00\x64",
+        Err(FrameError::Protocol)
+    )]
+    #[case(
+        b"\x00\x00\x06\x04\x00\x00\x00\x00\x00\x00\x02\x00\x00\x00\x02",
+        Err(FrameError::Protocol)
+    )]
+    #[case(
+        b"\x00\x00\x06\x04\x00\x00\x00\x00\x00\x00\x04\x80\x00\x00\x00",
+        Err(FrameError::FlowControl)
+    )]
+    #[case(
+        b"\x00\x00\x06\x04\x00\x00\x00\x00\x00\x00\x05\x00\x00\x00\x10",
+        Err(FrameError::Protocol)
+    )]
+    #[case(
+        b"\x00\x00\x04\x08\x00\x00\x00\x00\x00\x00\x00\x00\x00",
+        Err(FrameError::Protocol)
+    )]
+    #[case(
+        b"\x00\x00\x07\x06\x00\x00\x00\x00\x00abcdefg",
+        Err(FrameError::FrameSize)
+    )]
+    fn test_parse_frame(
+        #[case] input: &[u8],
+        #[case] expected: Result<(Frame, &[u8]), FrameError>,
+    ) {
+        assert_eq!(expected, Frame::parse(input, DEFAULT_MAX_FRAME_SIZE));
+    }
+
+    #[rstest]
+    #[case(Frame::Data { stream_id: 1, end_stream: true, data: b"hello" })]
+    #[case(Frame::Headers {
+        stream_id: 3,
+        end_stream: false,
+        end_headers: true,
+        priority: Some(Priority { exclusive: false, dependency: 1, weight: 200 }),
+        header_block: b"\x82\x86\x84",
+    })]
+    #[case(Frame::RstStream { stream_id: 7, error_code: ErrorCode::Unknown(0xff) })]
+    #[case(Frame::Settings {
+        ack: false,
+        settings: vec![
+            Setting { id: SettingId::HeaderTableSize, value: 0 },
+            Setting { id: SettingId::Unknown(0x99), value: 1 },
+        ],
+    })]
+    #[case(Frame::Settings { ack: true, settings: vec![] })]
+    #[case(Frame::Ping { ack: false, data: [1, 2, 3, 4, 5, 6, 7, 8] })]
+    #[case(Frame::GoAway { last_stream_id: 9, error_code: ErrorCode::EnhanceYourCalm, debug_data: b"slow down" })]
+    #[case(Frame::WindowUpdate { stream_id: 1, increment: MAX_WINDOW_SIZE })]
+    #[case(Frame::Continuation { stream_id: 1, end_headers: false, header_block: b"\x82" })]
+    fn test_encode_roundtrip(#[case] frame: Frame) {
+        let mut buf = Vec::new();
+        frame.encode(&mut buf);
+        buf.extend_from_slice(b"next");
+
+        assert_eq!(
+            Ok((frame, b"next".as_slice())),
+            Frame::parse(&buf, DEFAULT_MAX_FRAME_SIZE)
+        );
+    }
+}
@@ -0,0 +1,1068 @@
+//! Header names, values and an insertion ordered header map.
+
+use std::{
+    borrow::{Borrow, Cow},
+    collections::HashMap,
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+use smallvec::SmallVec;
+
+use crate::HTTPParseError;
+
+/// A well-known header name. Parsed names that match one of these are stored as the variant
+/// instead of an allocated string, and compare as enums.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StandardHeader {
+    Accept,
+    AcceptEncoding,
+    AcceptLanguage,
+    AcceptRanges,
+    AccessControlAllowOrigin,
+    Age,
+    Allow,
+    Authorization,
+    CacheControl,
+    Connection,
+    ContentDisposition,
+    ContentEncoding,
+    ContentLanguage,
+    ContentLength,
+    ContentLocation,
+    ContentRange,
+    ContentType,
+    Cookie,
+    Date,
+    Etag,
+    Expect,
+    Expires,
+    Forwarded,
+    Host,
+    IfMatch,
+    IfModifiedSince,
+    IfNoneMatch,
+    IfRange,
+    IfUnmodifiedSince,
+    KeepAlive,
+    LastModified,
+    Link,
+    Location,
+    Origin,
+    Pragma,
+    ProxyAuthenticate,
+    ProxyAuthorization,
+    Range,
+    Referer,
+    RetryAfter,
+    Server,
+    SetCookie,
+    StrictTransportSecurity,
+    TE,
+    Trailer,
+    TransferEncoding,
+    Upgrade,
+    UserAgent,
+    Vary,
+    Via,
+    WwwAuthenticate,
+    XForwardedFor,
+    XForwardedHost,
+    XForwardedProto,
+    XRequestId,
+}
+
+impl StandardHeader {
+    pub(crate) const ALL: [StandardHeader; 55] = [
+        StandardHeader::Accept,
+        StandardHeader::AcceptEncoding,
+        StandardHeader::AcceptLanguage,
+        StandardHeader::AcceptRanges,
+        StandardHeader::AccessControlAllowOrigin,
+        StandardHeader::Age,
+        StandardHeader::Allow,
+        StandardHeader::Authorization,
+        StandardHeader::CacheControl,
+        StandardHeader::Connection,
+        StandardHeader::ContentDisposition,
+        StandardHeader::ContentEncoding,
+        StandardHeader::ContentLanguage,
+        StandardHeader::ContentLength,
+        StandardHeader::ContentLocation,
+        StandardHeader::ContentRange,
+        StandardHeader::ContentType,
+        StandardHeader::Cookie,
+        StandardHeader::Date,
+        StandardHeader::Etag,
+        StandardHeader::Expect,
+        StandardHeader::Expires,
+        StandardHeader::Forwarded,
+        StandardHeader::Host,
+        StandardHeader::IfMatch,
+        StandardHeader::IfModifiedSince,
+        StandardHeader::IfNoneMatch,
+        StandardHeader::IfRange,
+        StandardHeader::IfUnmodifiedSince,
+        StandardHeader::KeepAlive,
+        StandardHeader::LastModified,
+        StandardHeader::Link,
+        StandardHeader::Location,
+        StandardHeader::Origin,
+        StandardHeader::Pragma,
+        StandardHeader::ProxyAuthenticate,
+        StandardHeader::ProxyAuthorization,
+        StandardHeader::Range,
+        StandardHeader::Referer,
+        StandardHeader::RetryAfter,
+        StandardHeader::Server,
+        StandardHeader::SetCookie,
+        StandardHeader::StrictTransportSecurity,
+        StandardHeader::TE,
+        StandardHeader::Trailer,
+        StandardHeader::TransferEncoding,
+        StandardHeader::Upgrade,
+        StandardHeader::UserAgent,
+        StandardHeader::Vary,
+        StandardHeader::Via,
+        StandardHeader::WwwAuthenticate,
+        StandardHeader::XForwardedFor,
+        StandardHeader::XForwardedHost,
+        StandardHeader::XForwardedProto,
+        StandardHeader::XRequestId,
+    ];
+
+    /// The lowercased name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StandardHeader::Accept => "accept",
+            StandardHeader::AcceptEncoding => "accept-encoding",
+            StandardHeader::AcceptLanguage => "accept-language",
+            StandardHeader::AcceptRanges => "accept-ranges",
+            StandardHeader::AccessControlAllowOrigin => "access-control-allow-origin",
+            StandardHeader::Age => "age",
+            StandardHeader::Allow => "allow",
+            StandardHeader::Authorization => "authorization",
+            StandardHeader::CacheControl => "cache-control",
+            StandardHeader::Connection => "connection",
+            StandardHeader::ContentDisposition => "content-disposition",
+            StandardHeader::ContentEncoding => "content-encoding",
+            StandardHeader::ContentLanguage => "content-language",
+            StandardHeader::ContentLength => "content-length",
+            StandardHeader::ContentLocation => "content-location",
+            StandardHeader::ContentRange => "content-range",
+            StandardHeader::ContentType => "content-type",
+            StandardHeader::Cookie => "cookie",
+            StandardHeader::Date => "date",
+            StandardHeader::Etag => "etag",
+            StandardHeader::Expect => "expect",
+            StandardHeader::Expires => "expires",
+            StandardHeader::Forwarded => "forwarded",
+            StandardHeader::Host => "host",
+            StandardHeader::IfMatch => "if-match",
+            StandardHeader::IfModifiedSince => "if-modified-since",
+            StandardHeader::IfNoneMatch => "if-none-match",
+            StandardHeader::IfRange => "if-range",
+            StandardHeader::IfUnmodifiedSince => "if-unmodified-since",
+            StandardHeader::KeepAlive => "keep-alive",
+            StandardHeader::LastModified => "last-modified",
+            StandardHeader::Link => "link",
+            StandardHeader::Location => "location",
+            StandardHeader::Origin => "origin",
+            StandardHeader::Pragma => "pragma",
+            StandardHeader::ProxyAuthenticate => "proxy-authenticate",
+            StandardHeader::ProxyAuthorization => "proxy-authorization",
+            StandardHeader::Range => "range",
+            StandardHeader::Referer => "referer",
+            StandardHeader::RetryAfter => "retry-after",
+            StandardHeader::Server => "server",
+            StandardHeader::SetCookie => "set-cookie",
+            StandardHeader::StrictTransportSecurity => "strict-transport-security",
+            StandardHeader::TE => "te",
+            StandardHeader::Trailer => "trailer",
+            StandardHeader::TransferEncoding => "transfer-encoding",
+            StandardHeader::Upgrade => "upgrade",
+            StandardHeader::UserAgent => "user-agent",
+            StandardHeader::Vary => "vary",
+            StandardHeader::Via => "via",
+            StandardHeader::WwwAuthenticate => "www-authenticate",
+            StandardHeader::XForwardedFor => "x-forwarded-for",
+            StandardHeader::XForwardedHost => "x-forwarded-host",
+            StandardHeader::XForwardedProto => "x-forwarded-proto",
+            StandardHeader::XRequestId => "x-request-id",
+        }
+    }
+
+    /// Look up a name case-insensitively
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL.into_iter().find(|header| {
+            let standard = header.as_str();
+            standard.len() == name.len() && standard.as_bytes().eq_ignore_ascii_case(name)
+        })
+    }
+}
+
+impl Display for StandardHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A header field name, validated as a token (RFC 9110 section 5.1) and stored lowercased.
+/// HTTP/2 pseudo-header names like `:path` are allowed as well.
+#[derive(Clone, PartialEq, Eq)]
+pub struct HeaderName(Repr);
+
+/// Names are only ever stored as [`Repr::Custom`] if they aren't a [`StandardHeader`], so the
+/// derived comparison agrees with comparing the names as strings
+#[derive(Clone, PartialEq, Eq)]
+enum Repr {
+    Standard(StandardHeader),
+    Custom(Box<str>),
+}
+
+impl HeaderName {
+    /// Build a name from a constant. Panics if the name is invalid.
+    pub fn from_static(name: &'static str) -> Self {
+        Self::try_from(name).expect("invalid header name")
+    }
+
+    /// Build a name that was already validated, interning it if it is a standard one
+    pub(crate) fn from_valid(name: &str) -> Self {
+        match StandardHeader::from_name(name.as_bytes()) {
+            Some(standard) => Self(Repr::Standard(standard)),
+            None => Self(Repr::Custom(name.to_ascii_lowercase().into_boxed_str())),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Repr::Standard(standard) => standard.as_str(),
+            Repr::Custom(name) => name,
+        }
+    }
+
+    /// The well-known header this is, if any
+    pub fn standard(&self) -> Option<StandardHeader> {
+        match self.0 {
+            Repr::Standard(standard) => Some(standard),
+            Repr::Custom(_) => None,
+        }
+    }
+
+    /// Whether this is an HTTP/2 pseudo-header like `:method`
+    pub fn is_pseudo(&self) -> bool {
+        self.as_str().starts_with(':')
+    }
+}
+
+impl From<StandardHeader> for HeaderName {
+    fn from(standard: StandardHeader) -> Self {
+        Self(Repr::Standard(standard))
+    }
+}
+
+impl TryFrom<&str> for HeaderName {
+    type Error = HTTPParseError;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        if !is_valid_name(name) {
+            return Err(HTTPParseError::InvalidHeader);
+        }
+        Ok(Self::from_valid(name))
+    }
+}
+
+impl TryFrom<String> for HeaderName {
+    type Error = HTTPParseError;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Self::try_from(name.as_str())
+    }
+}
+
+/// A header field value. Control characters other than horizontal tab are rejected
+/// (RFC 9110 section 5.5), so a value can never smuggle in a CRLF and start a new header.
+///
+/// Values are stored as bytes since they aren't required to be UTF-8, and some servers still
+/// send latin-1. Use [`HeaderValue::to_str`] when the value must be text, or
+/// [`HeaderValue::to_str_lossy`] for logging and display.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct HeaderValue(Vec<u8>);
+
+impl HeaderValue {
+    /// Build a value from a constant. Panics if the value is invalid.
+    pub fn from_static(value: &'static str) -> Self {
+        Self::try_from(value).expect("invalid header value")
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The value as text, or an error if it isn't valid UTF-8
+    pub fn to_str(&self) -> Result<&str, HTTPParseError> {
+        str::from_utf8(&self.0).map_err(|_| HTTPParseError::InvalidHeader)
+    }
+
+    /// The value as text, with any invalid UTF-8 replaced by `U+FFFD`
+    pub fn to_str_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    /// Add another element to the value, separated by `separator`
+    fn push(&mut self, separator: &str, value: &HeaderValue) {
+        self.0.extend_from_slice(separator.as_bytes());
+        self.0.extend_from_slice(&value.0);
+    }
+}
+
+impl TryFrom<&str> for HeaderValue {
+    type Error = HTTPParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_bytes())
+    }
+}
+
+impl TryFrom<String> for HeaderValue {
+    type Error = HTTPParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.into_bytes())
+    }
+}
+
+impl TryFrom<&[u8]> for HeaderValue {
+    type Error = HTTPParseError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::try_from(value.to_vec())
+    }
+}
+
+/// Numbers are always valid values, like for `content-length`
+impl From<u64> for HeaderValue {
+    fn from(value: u64) -> Self {
+        Self(value.to_string().into_bytes())
+    }
+}
+
+impl TryFrom<Vec<u8>> for HeaderValue {
+    type Error = HTTPParseError;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        if !is_valid_value(&value) {
+            return Err(HTTPParseError::InvalidHeader);
+        }
+        Ok(Self(value))
+    }
+}
+
+impl Debug for HeaderValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.to_str_lossy())
+    }
+}
+
+impl Display for HeaderValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_str_lossy())
+    }
+}
+
+impl PartialEq<str> for HeaderValue {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for HeaderValue {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl Deref for HeaderName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Hashes the name as a string, so that names can be looked up by `&str` in a map
+impl Hash for HeaderName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl Borrow<str> for HeaderName {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Debug for HeaderName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl Display for HeaderName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl PartialEq<str> for HeaderName {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for HeaderName {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<StandardHeader> for HeaderName {
+    fn eq(&self, other: &StandardHeader) -> bool {
+        self.standard() == Some(*other)
+    }
+}
+
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    is_token(name.strip_prefix(':').unwrap_or(name))
+}
+
+/// Any octet but control characters, which includes obs-text (0x80-0xFF)
+pub(crate) fn is_valid_value(value: &[u8]) -> bool {
+    !value.iter().any(|&b| b.is_ascii_control() && b != b'\t')
+}
+
+/// Set-Cookie can't be combined like other repeated fields, its values having commas
+/// of their own (RFC 9110 section 5.3)
+fn is_set_cookie(name: &str) -> bool {
+    name.eq_ignore_ascii_case(StandardHeader::SetCookie.as_str())
+}
+
+/// `1*tchar` from RFC 9110 section 5.6.2
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Parse a quoted string starting at the opening quote, returning the unescaped contents and
+/// whatever follows the closing quote
+pub(crate) fn parse_quoted_string(input: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = input.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &input[i + 1..])),
+            '\\' => value.push(chars.next()?.1),
+            c => value.push(c),
+        }
+    }
+
+    None
+}
+
+/// Serialize a value as a quoted string, escaping quotes and backslashes
+pub(crate) fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Fields that are always hop-by-hop, regardless of the Connection header
+const HOP_BY_HOP: [&str; 6] = [
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+/// Header fields of a message, kept in the order they were received or inserted so that
+/// serializing a message is deterministic.
+///
+/// Each name appears at most once, repeated fields are combined into a single value.
+/// Set-Cookie is the exception, since its values can't be combined (RFC 9110 section
+/// 5.3): each of its fields keeps an entry of its own. Names are compared
+/// case-insensitively.
+///
+/// Up to [`INLINE_HEADERS`] fields are stored inline without allocating, and looked up with a
+/// linear scan which beats hashing at that size. Larger sets spill to the heap and keep an
+/// index from names to positions.
+#[derive(Clone, Default)]
+pub struct Headers {
+    entries: SmallVec<[(HeaderName, HeaderValue); INLINE_HEADERS]>,
+    /// Only built once there are more than [`INLINE_HEADERS`] fields
+    index: Option<HashMap<HeaderName, usize>>,
+}
+
+/// The number of fields stored without allocating, enough for most messages
+pub const INLINE_HEADERS: usize = 16;
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: SmallVec::with_capacity(capacity),
+            index: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        match &self.index {
+            // names in the index are lowercase, so the name we look for must be as well
+            Some(index) if name.bytes().any(|b| b.is_ascii_uppercase()) => {
+                index.get(name.to_ascii_lowercase().as_str()).copied()
+            }
+            Some(index) => index.get(name).copied(),
+            None => self
+                .entries
+                .iter()
+                .position(|(key, _)| key.eq_ignore_ascii_case(name)),
+        }
+    }
+
+    /// Add a field whose name isn't present yet, or another Set-Cookie field
+    fn push(&mut self, name: HeaderName, value: HeaderValue) {
+        if let Some(index) = &mut self.index {
+            index.entry(name.clone()).or_insert(self.entries.len());
+        }
+        self.entries.push((name, value));
+        if self.index.is_none() && self.entries.len() > INLINE_HEADERS {
+            self.reindex();
+        }
+    }
+
+    /// Rebuild the index after positions changed. Names point to their first entry.
+    fn reindex(&mut self) {
+        self.index = (self.entries.len() > INLINE_HEADERS).then(|| {
+            let mut index = HashMap::with_capacity(self.entries.len());
+            for (i, (name, _)) in self.entries.iter().enumerate() {
+                index.entry(name.clone()).or_insert(i);
+            }
+            index
+        });
+    }
+
+    /// Remove the entries of a name after its first one, which only Set-Cookie has
+    fn remove_repeated(&mut self, name: &str) {
+        if !is_set_cookie(name) {
+            return;
+        }
+        let len = self.entries.len();
+        let mut seen = false;
+        self.entries.retain(|(key, _)| {
+            let repeated = seen && key.eq_ignore_ascii_case(name);
+            seen |= key.eq_ignore_ascii_case(name);
+            !repeated
+        });
+        if self.entries.len() != len {
+            self.reindex();
+        }
+    }
+
+    /// The value of a field, the first one for Set-Cookie
+    pub fn get(&self, name: &str) -> Option<&HeaderValue> {
+        self.position(name).map(|i| &self.entries[i].1)
+    }
+
+    /// The values of a field in order, of which only Set-Cookie can have more than one
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a HeaderValue> {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    /// Set a header, returning the previous value.
+    /// A header that already exists keeps its position, and its other values are removed.
+    pub fn insert(&mut self, name: HeaderName, value: HeaderValue) -> Option<HeaderValue> {
+        match self.position(&name) {
+            Some(i) => {
+                let previous = std::mem::replace(&mut self.entries[i].1, value);
+                self.remove_repeated(name.as_str());
+                Some(previous)
+            }
+            None => {
+                self.push(name, value);
+                None
+            }
+        }
+    }
+
+    /// Add a value to a header, combining it with any existing value into a comma separated
+    /// list (RFC 9110 section 5.3). Set-Cookie values get an entry of their own instead.
+    pub fn append(&mut self, name: HeaderName, value: HeaderValue) {
+        self.append_with_separator(name, value, ", ");
+    }
+
+    /// Like [`Headers::append`], but with a custom separator for fields like Cookie
+    pub(crate) fn append_with_separator(
+        &mut self,
+        name: HeaderName,
+        value: HeaderValue,
+        separator: &str,
+    ) {
+        match self.position(&name) {
+            Some(_) if is_set_cookie(&name) => self.push(name, value),
+            Some(i) => self.entries[i].1.push(separator, &value),
+            None => self.push(name, value),
+        }
+    }
+
+    /// Remove a header, returning its value, the first one for Set-Cookie
+    pub fn remove(&mut self, name: &str) -> Option<HeaderValue> {
+        let i = self.position(name)?;
+        let (_, value) = self.entries.remove(i);
+        if is_set_cookie(name) {
+            self.entries
+                .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        }
+        if self.index.is_some() {
+            self.reindex();
+        }
+        Some(value)
+    }
+
+    /// Build headers from fields that were already validated while parsing,
+    /// combining repeated fields but Set-Cookie (RFC 9110 section 5.3)
+    pub(crate) fn from_fields(fields: &[(&str, &[u8])]) -> Self {
+        let mut headers = Headers::with_capacity(fields.len());
+        for (name, value) in fields {
+            headers.append(HeaderName::from_valid(name), HeaderValue(value.to_vec()));
+        }
+        headers
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+
+    /// Remove the hop-by-hop fields that only apply to a single connection, which a proxy
+    /// must not forward (RFC 9110 section 7.6.1).
+    /// This removes Connection and every field it nominates, Keep-Alive, Transfer-Encoding,
+    /// TE, Trailer, Upgrade and any `Proxy-*` field.
+    pub fn strip_hop_by_hop(&mut self) {
+        let nominated = self
+            .get("connection")
+            .map(|connection| {
+                connection
+                    .as_bytes()
+                    .split(|&b| b == b',')
+                    .map(|name| name.trim_ascii().to_ascii_lowercase())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        self.entries.retain(|(name, _)| {
+            !HOP_BY_HOP.contains(&name.as_str())
+                && !name.starts_with("proxy-")
+                && !nominated
+                    .iter()
+                    .any(|nominated| nominated == name.as_bytes())
+        });
+        self.reindex();
+    }
+
+    /// Sort the headers by name, for a canonical order that doesn't depend on how the
+    /// message was built. Useful for signing, snapshots and cache keys.
+    pub fn sort(&mut self) {
+        // names are already lowercased, so this is a case-insensitive sort
+        self.entries
+            .sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        self.reindex();
+    }
+}
+
+impl Debug for Headers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Headers are equal if they have the same fields, regardless of their order but for
+/// the values of Set-Cookie
+impl PartialEq for Headers {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(name, _)| self.get_all(name.as_str()).eq(other.get_all(name.as_str())))
+    }
+}
+
+impl<'a> IntoIterator for &'a Headers {
+    type Item = (&'a HeaderName, &'a HeaderValue);
+    type IntoIter = std::iter::Map<
+        std::slice::Iter<'a, (HeaderName, HeaderValue)>,
+        fn(&'a (HeaderName, HeaderValue)) -> (&'a HeaderName, &'a HeaderValue),
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+}
+
+impl IntoIterator for Headers {
+    type Item = (HeaderName, HeaderValue);
+    type IntoIter = smallvec::IntoIter<[(HeaderName, HeaderValue); INLINE_HEADERS]>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl FromIterator<(HeaderName, HeaderValue)> for Headers {
+    fn from_iter<T: IntoIterator<Item = (HeaderName, HeaderValue)>>(iter: T) -> Self {
+        let mut headers = Headers::new();
+        headers.extend(iter);
+        headers
+    }
+}
+
+impl Extend<(HeaderName, HeaderValue)> for Headers {
+    fn extend<T: IntoIterator<Item = (HeaderName, HeaderValue)>>(&mut self, iter: T) {
+        for (name, value) in iter {
+            self.insert(name, value);
+        }
+    }
+}
+
+impl<const N: usize> From<[(HeaderName, HeaderValue); N]> for Headers {
+    fn from(entries: [(HeaderName, HeaderValue); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+/// Build headers from name and value pairs, validating each of them
+impl<'a, const N: usize> TryFrom<[(&'a str, &'a str); N]> for Headers {
+    type Error = HTTPParseError;
+
+    fn try_from(entries: [(&'a str, &'a str); N]) -> Result<Self, Self::Error> {
+        entries
+            .into_iter()
+            .map(|(name, value)| Ok((HeaderName::try_from(name)?, HeaderValue::try_from(value)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("content-type", Ok("content-type"))]
+    #[case("X-Custom_Header.1", Ok("x-custom_header.1"))]
+    #[case(":path", Ok(":path"))]
+    #[case("", Err(HTTPParseError::InvalidHeader))]
+    #[case("bad name", Err(HTTPParseError::InvalidHeader))]
+    #[case("bad\r\nname", Err(HTTPParseError::InvalidHeader))]
+    #[case("x:y", Err(HTTPParseError::InvalidHeader))]
+    #[case(":", Err(HTTPParseError::InvalidHeader))]
+    fn test_header_name(#[case] input: &str, #[case] expected: Result<&str, HTTPParseError>) {
+        assert_eq!(
+            expected.map(str::to_string),
+            HeaderName::try_from(input).map(|name| name.as_str().to_string())
+        );
+    }
+
+    #[rstest]
+    #[case("text/html; charset=utf-8", true)]
+    #[case("a\tb", true)]
+    #[case("", true)]
+    #[case("caf\u{e9}", true)]
+    #[case("value\r\nset-cookie: injected=1", false)]
+    #[case("value\n", false)]
+    #[case("nul\0", false)]
+    #[case("del\x7f", false)]
+    fn test_header_value(#[case] input: &str, #[case] valid: bool) {
+        assert_eq!(valid, HeaderValue::try_from(input).is_ok());
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let mut headers = Headers::new();
+        headers.insert(
+            HeaderName::from_static("host"),
+            HeaderValue::from_static("example.com"),
+        );
+        headers.insert(
+            HeaderName::from_static("accept"),
+            HeaderValue::from_static("*/*"),
+        );
+        headers.insert(
+            HeaderName::from_static("content-type"),
+            HeaderValue::from_static("text/plain"),
+        );
+        // replacing a value keeps the original position
+        assert_eq!(
+            Some(HeaderValue::from_static("example.com")),
+            headers.insert(
+                HeaderName::from_static("Host"),
+                HeaderValue::from_static("example.org")
+            )
+        );
+
+        assert_eq!(
+            vec!["host", "accept", "content-type"],
+            headers
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some("example.org"),
+            headers.get("HOST").and_then(|value| value.to_str().ok())
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut headers =
+            Headers::try_from([("x-b", "2"), ("Content-Type", "text/plain"), ("x-a", "1")])
+                .unwrap();
+        headers.sort();
+        assert_eq!(
+            vec!["content-type", "x-a", "x-b"],
+            headers
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_append_and_remove() {
+        let mut headers = Headers::new();
+        headers.append(
+            HeaderName::from_static("accept"),
+            HeaderValue::from_static("text/html"),
+        );
+        headers.append(
+            HeaderName::from_static("Accept"),
+            HeaderValue::from_static("application/json"),
+        );
+        assert_eq!(
+            Some("text/html, application/json"),
+            headers.get("accept").and_then(|value| value.to_str().ok())
+        );
+
+        assert_eq!(
+            Some(HeaderValue::from_static("text/html, application/json")),
+            headers.remove("ACCEPT")
+        );
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_set_cookie_kept_apart() {
+        let set_cookie = HeaderName::from_static("set-cookie");
+        let mut headers = Headers::new();
+        for cookie in ["a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT", "b=2"] {
+            headers.append(set_cookie.clone(), HeaderValue::from_static(cookie));
+        }
+        assert_eq!(2, headers.len());
+        assert_eq!(
+            vec!["a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT", "b=2"],
+            headers
+                .get_all("Set-Cookie")
+                .map(|value| value.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_ne!(
+            headers,
+            Headers::try_from([("set-cookie", "b=2"), ("set-cookie", "a=1")]).unwrap()
+        );
+
+        // setting the field replaces all of its values
+        headers.insert(set_cookie.clone(), HeaderValue::from_static("c=3"));
+        assert_eq!(1, headers.len());
+        headers.append(set_cookie, HeaderValue::from_static("d=4"));
+        assert_eq!(
+            Some(HeaderValue::from_static("c=3")),
+            headers.remove("set-cookie")
+        );
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_eq_ignores_order() {
+        assert_eq!(
+            Headers::try_from([("a", "1"), ("b", "2")]),
+            Headers::try_from([("b", "2"), ("a", "1")])
+        );
+        assert_ne!(
+            Headers::try_from([("a", "1"), ("b", "2")]),
+            Headers::try_from([("a", "1"), ("b", "3")])
+        );
+    }
+
+    #[test]
+    fn test_strip_hop_by_hop() {
+        let mut headers = Headers::try_from([
+            ("Host", "example.com"),
+            ("Connection", "keep-alive, X-Internal,, close"),
+            ("X-Internal", "secret"),
+            ("Keep-Alive", "timeout=5"),
+            ("Transfer-Encoding", "chunked"),
+            ("TE", "trailers"),
+            ("Trailer", "Expires"),
+            ("Upgrade", "websocket"),
+            ("Proxy-Authorization", "Basic Zm9vOmJhcg=="),
+            ("Proxy-Connection", "keep-alive"),
+            ("Content-Type", "text/plain"),
+        ])
+        .unwrap();
+        headers.strip_hop_by_hop();
+
+        assert_eq!(
+            Headers::try_from([("Host", "example.com"), ("Content-Type", "text/plain")]).unwrap(),
+            headers
+        );
+    }
+
+    #[rstest]
+    #[case("Host", Some(StandardHeader::Host))]
+    #[case("CONTENT-LENGTH", Some(StandardHeader::ContentLength))]
+    #[case("te", Some(StandardHeader::TE))]
+    #[case("x-custom", None)]
+    #[case("hos", None)]
+    fn test_standard_header(#[case] name: &str, #[case] expected: Option<StandardHeader>) {
+        let name = HeaderName::try_from(name).unwrap();
+        assert_eq!(expected, name.standard());
+        assert_eq!(name.to_ascii_lowercase(), name.as_str());
+    }
+
+    #[test]
+    fn test_standard_header_names() {
+        for standard in StandardHeader::ALL {
+            assert!(is_token(standard.as_str()));
+            assert_eq!(standard.as_str().to_ascii_lowercase(), standard.as_str());
+            assert_eq!(
+                Some(standard),
+                StandardHeader::from_name(standard.as_str().as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn test_interned_names_compare_equal() {
+        let parsed = HeaderName::try_from("Content-Type").unwrap();
+        assert_eq!(HeaderName::from(StandardHeader::ContentType), parsed);
+        assert_eq!(parsed, StandardHeader::ContentType);
+        assert_eq!(parsed, "content-type");
+        assert_eq!("\"content-type\"", format!("{parsed:?}"));
+
+        let headers = Headers::try_from([("Content-Type", "text/plain")]).unwrap();
+        assert_eq!(
+            Some(StandardHeader::ContentType),
+            headers.iter().next().and_then(|(name, _)| name.standard())
+        );
+    }
+
+    #[test]
+    fn test_small_headers_stay_inline() {
+        let headers = (0..INLINE_HEADERS)
+            .map(|i| {
+                (
+                    HeaderName::try_from(format!("x-header-{i}")).unwrap(),
+                    HeaderValue::from_static("value"),
+                )
+            })
+            .collect::<Headers>();
+        assert!(!headers.entries.spilled());
+        assert!(headers.index.is_none());
+    }
+
+    #[test]
+    fn test_large_headers_index() {
+        let mut headers = Headers::new();
+        for i in 0..40 {
+            headers.insert(
+                HeaderName::try_from(format!("x-header-{i}")).unwrap(),
+                HeaderValue::try_from(i.to_string()).unwrap(),
+            );
+        }
+        assert!(headers.index.is_some());
+        assert_eq!(
+            Some(&HeaderValue::from_static("25")),
+            headers.get("X-Header-25")
+        );
+
+        headers.append(
+            HeaderName::from_static("x-header-3"),
+            HeaderValue::from_static("again"),
+        );
+        assert_eq!(40, headers.len());
+        assert_eq!(
+            Some(&HeaderValue::from_static("3, again")),
+            headers.get("x-header-3")
+        );
+
+        // removing shifts the positions of everything after it
+        assert_eq!(
+            Some(HeaderValue::from_static("10")),
+            headers.remove("x-header-10")
+        );
+        assert_eq!(None, headers.get("x-header-10"));
+        assert_eq!(
+            Some(&HeaderValue::from_static("39")),
+            headers.get("x-header-39")
+        );
+
+        headers.sort();
+        assert_eq!(
+            Some(&HeaderValue::from_static("0")),
+            headers.get("x-header-0")
+        );
+        assert_eq!(
+            Some("x-header-0"),
+            headers.iter().next().map(|(name, _)| name.as_str())
+        );
+
+        // shrinking back down drops the index
+        for i in 11..40 {
+            headers.remove(&format!("x-header-{i}"));
+        }
+        assert!(headers.index.is_none());
+        assert_eq!(
+            Some(&HeaderValue::from_static("5")),
+            headers.get("x-header-5")
+        );
+    }
+}
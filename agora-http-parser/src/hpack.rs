@@ -0,0 +1,754 @@
+//! HPACK header compression for HTTP/2 (RFC 7541).
+//!
+//! The [`Decoder`] turns the header block fragments carried by HEADERS and
+//! CONTINUATION frames back into [`Headers`], and the [`Encoder`] does the reverse.
+//! Both sides keep a dynamic table, so a single encoder/decoder must be used for
+//! every header block on a connection in the order the blocks are sent.
+
+mod huffman;
+
+use std::{collections::VecDeque, fmt::Display};
+
+use crate::{HeaderName, HeaderValue, Headers};
+
+/// The default SETTINGS_HEADER_TABLE_SIZE
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Each dynamic table entry costs the length of its name and value plus this overhead
+const ENTRY_OVERHEAD: usize = 32;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Headers that should never be added to a compression table, to avoid
+/// leaking secrets through compression side channels (RFC 7541 section 7.1)
+pub(crate) const SENSITIVE_HEADERS: [&str; 3] =
+    ["authorization", "proxy-authorization", "set-cookie"];
+
+#[derive(Debug, PartialEq)]
+pub enum HpackError {
+    /// The header block ended in the middle of a representation
+    Incomplete,
+    /// An index refers to an entry in neither the static nor the dynamic table
+    InvalidIndex,
+    /// An integer does not fit into a usize
+    IntegerOverflow,
+    /// A huffman encoded string literal is malformed
+    InvalidHuffman,
+    /// A header name or value is not valid UTF-8
+    InvalidUtf8,
+    /// A dynamic table size update exceeds the allowed maximum or is misplaced
+    InvalidTableSizeUpdate,
+    /// A decoded header name or value contains characters that aren't allowed
+    InvalidField,
+    /// The decoded header fields are larger than the decoder takes
+    HeaderListTooLarge,
+}
+
+impl Display for HpackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                HpackError::Incomplete => "Header block is not complete",
+                HpackError::InvalidIndex => "Invalid header table index",
+                HpackError::IntegerOverflow => "Integer overflow in header block",
+                HpackError::InvalidHuffman => "Invalid huffman encoded string",
+                HpackError::InvalidUtf8 => "Header field is not valid UTF-8",
+                HpackError::InvalidTableSizeUpdate => "Invalid dynamic table size update",
+                HpackError::InvalidField => "Invalid header field",
+                HpackError::HeaderListTooLarge => "Header list is too large",
+            }
+        )
+    }
+}
+
+/// The dynamic table, newest entries are at the front
+#[derive(Debug)]
+struct DynamicTable {
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    /// Look up an entry using the combined static and dynamic index space
+    fn get(&self, index: usize) -> Result<(&str, &str), HpackError> {
+        match index {
+            0 => Err(HpackError::InvalidIndex),
+            1..=61 => Ok(STATIC_TABLE[index - 1]),
+            _ => self
+                .entries
+                .get(index - STATIC_TABLE.len() - 1)
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .ok_or(HpackError::InvalidIndex),
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let entry_size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.evict(self.max_size.saturating_sub(entry_size));
+
+        // an entry larger than the table empties the table and is not inserted
+        if entry_size <= self.max_size {
+            self.size += entry_size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(max_size);
+    }
+
+    /// Evict the oldest entries until the table size is at most `target`
+    fn evict(&mut self, target: usize) {
+        while self.size > target {
+            let Some((name, value)) = self.entries.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+
+    /// Find the best index for a header.
+    /// Returns the index and whether the value matched as well as the name.
+    fn find(&self, name: &str, value: &str) -> Option<(usize, bool)> {
+        let dynamic = self
+            .entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()));
+
+        let mut name_match = None;
+        for (i, (entry_name, entry_value)) in
+            STATIC_TABLE.iter().copied().chain(dynamic).enumerate()
+        {
+            if entry_name == name {
+                if entry_value == value {
+                    return Some((i + 1, true));
+                }
+                name_match.get_or_insert(i + 1);
+            }
+        }
+
+        name_match.map(|index| (index, false))
+    }
+}
+
+#[derive(Debug)]
+pub struct Decoder {
+    table: DynamicTable,
+    /// The largest table size the encoder is allowed to pick, which is our SETTINGS_HEADER_TABLE_SIZE
+    max_allowed_size: usize,
+    /// The largest header list decoded from a block, which is our SETTINGS_MAX_HEADER_LIST_SIZE
+    max_header_list_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl Decoder {
+    pub fn new(max_table_size: usize) -> Self {
+        Self {
+            table: DynamicTable::new(max_table_size),
+            max_allowed_size: max_table_size,
+            max_header_list_size: usize::MAX,
+        }
+    }
+
+    /// Update the max table size once our SETTINGS_HEADER_TABLE_SIZE has been acknowledged
+    pub fn set_max_table_size(&mut self, max_table_size: usize) {
+        self.max_allowed_size = max_table_size;
+        if self.table.max_size > max_table_size {
+            self.table.set_max_size(max_table_size);
+        }
+    }
+
+    /// Limit the size of the header lists decoded, counted like the entries of the
+    /// table are. A few bytes of indexes could otherwise stand for megabytes of fields.
+    pub fn set_max_header_list_size(&mut self, max_header_list_size: usize) {
+        self.max_header_list_size = max_header_list_size;
+    }
+
+    /// Decode a complete header block into the list of header fields in the order
+    /// they were encoded. Pseudo-header fields (`:method`, `:path`, ...) are included.
+    pub fn decode(&mut self, mut block: &[u8]) -> Result<Vec<(String, String)>, HpackError> {
+        let mut fields = Vec::new();
+        let max_header_list_size = self.max_header_list_size;
+        let mut list_size = 0;
+        let mut count = |name: &str, value: &str| {
+            list_size += name.len() + value.len() + ENTRY_OVERHEAD;
+            match list_size > max_header_list_size {
+                true => Err(HpackError::HeaderListTooLarge),
+                false => Ok(()),
+            }
+        };
+
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                // Indexed header field
+                let (index, rest) = decode_integer(block, 7)?;
+                let (name, value) = self.table.get(index)?;
+                count(name, value)?;
+                fields.push((name.to_string(), value.to_string()));
+                block = rest;
+            } else if first & 0x40 != 0 {
+                // Literal header field with incremental indexing
+                let (name, value, rest) = self.decode_literal(block, 6)?;
+                count(&name, &value)?;
+                self.table.insert(name.clone(), value.clone());
+                fields.push((name, value));
+                block = rest;
+            } else if first & 0x20 != 0 {
+                // Dynamic table size updates are only allowed at the start of a block
+                if !fields.is_empty() {
+                    return Err(HpackError::InvalidTableSizeUpdate);
+                }
+                let (size, rest) = decode_integer(block, 5)?;
+                if size > self.max_allowed_size {
+                    return Err(HpackError::InvalidTableSizeUpdate);
+                }
+                self.table.set_max_size(size);
+                block = rest;
+            } else {
+                // Literal header field without indexing (0000) or never indexed (0001)
+                let (name, value, rest) = self.decode_literal(block, 4)?;
+                count(&name, &value)?;
+                fields.push((name, value));
+                block = rest;
+            }
+        }
+
+        Ok(fields)
+    }
+
+    /// Decode a complete header block into [`Headers`].
+    /// Repeated fields are combined, with cookies joined by `; ` as required by RFC 9113,
+    /// but Set-Cookie fields are kept apart.
+    pub fn decode_headers(&mut self, block: &[u8]) -> Result<Headers, HpackError> {
+        let mut headers = Headers::new();
+        for (name, value) in self.decode(block)? {
+            let separator = if name == "cookie" { "; " } else { ", " };
+            let name = HeaderName::try_from(name).map_err(|_| HpackError::InvalidField)?;
+            let value = HeaderValue::try_from(value).map_err(|_| HpackError::InvalidField)?;
+            headers.append_with_separator(name, value, separator);
+        }
+
+        Ok(headers)
+    }
+
+    fn decode_literal<'a>(
+        &self,
+        block: &'a [u8],
+        prefix: u8,
+    ) -> Result<(String, String, &'a [u8]), HpackError> {
+        let (index, block) = decode_integer(block, prefix)?;
+        let (name, block) = if index == 0 {
+            decode_string(block, 7)?
+        } else {
+            (self.table.get(index)?.0.to_string(), block)
+        };
+        let (value, block) = decode_string(block, 7)?;
+
+        Ok((name, value, block))
+    }
+}
+
+#[derive(Debug)]
+pub struct Encoder {
+    table: DynamicTable,
+    /// Table size changes we still need to signal at the start of the next block
+    pending_size_update: Option<usize>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl Encoder {
+    pub fn new(max_table_size: usize) -> Self {
+        Self {
+            table: DynamicTable::new(max_table_size),
+            pending_size_update: None,
+        }
+    }
+
+    /// Apply the peer's SETTINGS_HEADER_TABLE_SIZE
+    pub fn set_max_table_size(&mut self, max_table_size: usize) {
+        self.table.set_max_size(max_table_size);
+        self.pending_size_update = Some(max_table_size);
+    }
+
+    /// Encode the header fields into a header block and append it to the buffer.
+    /// Names are expected to already be lowercase, and pseudo-headers must come first.
+    pub fn encode<'h>(
+        &mut self,
+        fields: impl IntoIterator<Item = (&'h str, &'h str)>,
+        buf: &mut Vec<u8>,
+    ) {
+        self.encode_size_update(buf);
+        for (name, value) in fields {
+            self.encode_field(name, value, buf);
+        }
+    }
+
+    /// Encode [`Headers`], emitting pseudo-headers before regular fields
+    pub fn encode_headers(&mut self, headers: &Headers, buf: &mut Vec<u8>) {
+        self.encode_size_update(buf);
+
+        let pseudo = headers.iter().filter(|(name, _)| name.is_pseudo());
+        let regular = headers.iter().filter(|(name, _)| !name.is_pseudo());
+        for (name, value) in pseudo.chain(regular) {
+            match value.to_str() {
+                Ok(value) => self.encode_field(name, value, buf),
+                // the dynamic table only holds text, so values that aren't UTF-8 are sent as
+                // literals without indexing
+                Err(_) => {
+                    let name_index = self.table.find(name, "").map_or(0, |(index, _)| index);
+                    encode_integer(name_index, 4, 0x00, buf);
+                    if name_index == 0 {
+                        encode_string(name.as_bytes(), 7, 0, buf);
+                    }
+                    encode_string(value.as_bytes(), 7, 0, buf);
+                }
+            }
+        }
+    }
+
+    fn encode_size_update(&mut self, buf: &mut Vec<u8>) {
+        if let Some(size) = self.pending_size_update.take() {
+            encode_integer(size, 5, 0x20, buf);
+        }
+    }
+
+    fn encode_field(&mut self, name: &str, value: &str, buf: &mut Vec<u8>) {
+        let sensitive = SENSITIVE_HEADERS.contains(&name);
+        match self.table.find(name, value) {
+            Some((index, true)) if !sensitive => encode_integer(index, 7, 0x80, buf),
+            found => {
+                let name_index = found.map_or(0, |(index, _)| index);
+                if sensitive {
+                    // never indexed
+                    encode_integer(name_index, 4, 0x10, buf);
+                } else {
+                    // incremental indexing
+                    encode_integer(name_index, 6, 0x40, buf);
+                }
+
+                if name_index == 0 {
+                    encode_string(name.as_bytes(), 7, 0, buf);
+                }
+                encode_string(value.as_bytes(), 7, 0, buf);
+
+                if !sensitive {
+                    self.table.insert(name.to_string(), value.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Decode an integer with an N-bit prefix (RFC 7541 section 5.1)
+pub(crate) fn decode_integer(buf: &[u8], prefix: u8) -> Result<(usize, &[u8]), HpackError> {
+    let Some((&first, mut buf)) = buf.split_first() else {
+        return Err(HpackError::Incomplete);
+    };
+
+    let max_prefix = (1usize << prefix) - 1;
+    let mut value = first as usize & max_prefix;
+    if value < max_prefix {
+        return Ok((value, buf));
+    }
+
+    let mut shift = 0;
+    loop {
+        let Some((&byte, rest)) = buf.split_first() else {
+            return Err(HpackError::Incomplete);
+        };
+        buf = rest;
+
+        if shift > 28 {
+            return Err(HpackError::IntegerOverflow);
+        }
+        value = value
+            .checked_add(((byte & 0x7f) as usize) << shift)
+            .ok_or(HpackError::IntegerOverflow)?;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok((value, buf));
+        }
+    }
+}
+
+/// Encode an integer with an N-bit prefix, `flags` are the bits above the prefix
+pub(crate) fn encode_integer(value: usize, prefix: u8, flags: u8, buf: &mut Vec<u8>) {
+    let max_prefix = (1usize << prefix) - 1;
+    if value < max_prefix {
+        buf.push(flags | value as u8);
+        return;
+    }
+
+    buf.push(flags | max_prefix as u8);
+    let mut value = value - max_prefix;
+    while value >= 0x80 {
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Decode a string literal (RFC 7541 section 5.2) whose length has an N-bit prefix, with the
+/// huffman flag in the bit above it. HPACK always uses a 7-bit prefix, QPACK also uses 3 bits.
+pub(crate) fn decode_string(buf: &[u8], prefix: u8) -> Result<(String, &[u8]), HpackError> {
+    let huffman = buf.first().is_some_and(|byte| byte & (1 << prefix) != 0);
+    let (length, buf) = decode_integer(buf, prefix)?;
+    if buf.len() < length {
+        return Err(HpackError::Incomplete);
+    }
+
+    let (data, rest) = buf.split_at(length);
+    let data = if huffman {
+        huffman::decode(data).ok_or(HpackError::InvalidHuffman)?
+    } else {
+        data.to_vec()
+    };
+
+    Ok((
+        String::from_utf8(data).map_err(|_| HpackError::InvalidUtf8)?,
+        rest,
+    ))
+}
+
+/// Encode a string literal with an N-bit length prefix, using huffman coding when it is
+/// shorter. `flags` are the bits above the huffman flag.
+pub(crate) fn encode_string(data: &[u8], prefix: u8, flags: u8, buf: &mut Vec<u8>) {
+    let huffman_len = huffman::encoded_len(data);
+    if huffman_len < data.len() {
+        encode_integer(huffman_len, prefix, flags | (1 << prefix), buf);
+        huffman::encode(data, buf);
+    } else {
+        encode_integer(data.len(), prefix, flags, buf);
+        buf.extend_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[rstest]
+    #[case(10, 5, b"\x0a")]
+    #[case(1337, 5, b"\x1f\x9a\x0a")]
+    #[case(42, 8, b"\x2a")]
+    #[case(31, 5, b"\x1f\x00")]
+    fn test_integer(#[case] value: usize, #[case] prefix: u8, #[case] encoded: &[u8]) {
+        let mut buf = Vec::new();
+        encode_integer(value, prefix, 0, &mut buf);
+        assert_eq!(encoded, buf);
+        assert_eq!(Ok((value, b"".as_slice())), decode_integer(encoded, prefix));
+    }
+
+    #[rstest]
+    #[case(b"", Err(HpackError::Incomplete))]
+    #[case(b"\x1f\x9a", Err(HpackError::Incomplete))]
+    #[case(
+        b"\x1f\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01",
+        Err(HpackError::IntegerOverflow)
+    )]
+    fn test_integer_invalid(
+        #[case] encoded: &[u8],
+        #[case] expected: Result<(usize, &[u8]), HpackError>,
+    ) {
+        assert_eq!(expected, decode_integer(encoded, 5));
+    }
+
+    // RFC 7541 Appendix C.3, requests without huffman coding sharing one dynamic table
+    #[test]
+    fn test_decode_requests() {
+        let mut decoder = Decoder::default();
+
+        let first = b"\x82\x86\x84\x41\x0fwww.example.com";
+        assert_eq!(
+            Ok(fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])),
+            decoder.decode(first)
+        );
+
+        let second = b"\x82\x86\x84\xbe\x58\x08no-cache";
+        assert_eq!(
+            Ok(fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])),
+            decoder.decode(second)
+        );
+
+        let third = b"\x82\x87\x85\xbf\x40\x0acustom-key\x0ccustom-value";
+        assert_eq!(
+            Ok(fields(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ])),
+            decoder.decode(third)
+        );
+        assert_eq!(164, decoder.table.size);
+    }
+
+    // RFC 7541 Appendix C.4, the same requests with huffman coding
+    #[test]
+    fn test_decode_requests_huffman() {
+        let mut decoder = Decoder::default();
+
+        let first = b"\x82\x86\x84\x41\x8c\xf1\xe3\xc2\xe5\xf2\x3a\x6b\xa0\xab\x90\xf4\xff";
+        assert_eq!(
+            Ok(Headers::try_from([
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+            .unwrap()),
+            decoder.decode_headers(first)
+        );
+
+        let second = b"\x82\x86\x84\xbe\x58\x86\xa8\xeb\x10\x64\x9c\xbf";
+        assert_eq!(
+            Some(&HeaderValue::from_static("no-cache")),
+            decoder.decode_headers(second).unwrap().get("cache-control")
+        );
+    }
+
+    // RFC 7541 Appendix C.5, responses with a 256 byte table causing evictions
+    #[test]
+    fn test_decode_responses_with_eviction() {
+        let mut decoder = Decoder::new(256);
+
+        let first = b"\x48\x03\x33\x30\x32\x58\x07\x70\x72\x69\x76\x61\x74\x65\x61\x1d\x4d\x6f\x6e\x2c\x20\x32\x31\x20\x4f\x63\x74\x20\x32\x30\x31\x33\x20\x32\x30\x3a\x31\x33\x3a\x32\x31\x20\x47\x4d\x54\x6e\x17\x68\x74\x74\x70\x73\x3a\x2f\x2f\x77\x77\x77\x2e\x65\x78\x61\x6d\x70\x6c\x65\x2e\x63\x6f\x6d";
+        decoder.decode(first).unwrap();
+        assert_eq!(222, decoder.table.size);
+
+        let second = b"\x48\x03\x33\x30\x37\xc1\xc0\xbf";
+        assert_eq!(
+            Ok(fields(&[
+                (":status", "307"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                ("location", "https://www.example.com"),
+            ])),
+            decoder.decode(second)
+        );
+        assert_eq!(222, decoder.table.size);
+    }
+
+    #[rstest]
+    #[case(b"\xc0", HpackError::InvalidIndex)]
+    #[case(b"\x80", HpackError::InvalidIndex)]
+    #[case(b"\x41\x0fwww", HpackError::Incomplete)]
+    #[case(b"\x82\x3f\xe1\x1f", HpackError::InvalidTableSizeUpdate)]
+    #[case(b"\x3f\xe2\x1f", HpackError::InvalidTableSizeUpdate)]
+    #[case(b"\x40\x81\xff\x00", HpackError::InvalidHuffman)]
+    #[case(b"\x40\x01\xff\x00", HpackError::InvalidUtf8)]
+    fn test_decode_invalid(#[case] block: &[u8], #[case] expected: HpackError) {
+        assert_eq!(Err(expected), Decoder::default().decode(block));
+    }
+
+    #[test]
+    fn test_decode_max_header_list_size() {
+        let mut decoder = Decoder::default();
+        decoder.set_max_header_list_size(100);
+        // 1 + 30 + 32 bytes, indexed as the newest entry of the table
+        let mut block = b"\x40\x01x\x1e".to_vec();
+        block.extend_from_slice(&[b'a'; 30]);
+        assert_eq!(1, decoder.decode(&block).unwrap().len());
+
+        // the same field twice is over the limit, which a single byte refers to
+        assert_eq!(
+            Err(HpackError::HeaderListTooLarge),
+            decoder.decode(b"\xbe\xbe")
+        );
+        assert_eq!(
+            Ok(fields(&[("x", &"a".repeat(30))])),
+            decoder.decode(b"\xbe")
+        );
+    }
+
+    #[test]
+    fn test_decode_headers_combines() {
+        let mut block = Vec::new();
+        Encoder::default().encode(
+            [
+                ("cookie", "a=1"),
+                ("set-cookie", "b=2; Expires=Wed, 21 Oct 2015 07:28:00 GMT"),
+                ("cookie", "c=3"),
+                ("set-cookie", "d=4"),
+            ],
+            &mut block,
+        );
+        let headers = Decoder::default().decode_headers(&block).unwrap();
+        assert_eq!(
+            Some(&HeaderValue::from_static("a=1; c=3")),
+            headers.get("cookie")
+        );
+        assert_eq!(
+            vec!["b=2; Expires=Wed, 21 Oct 2015 07:28:00 GMT", "d=4"],
+            headers
+                .get_all("set-cookie")
+                .map(|value| value.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let mut encoder = Encoder::default();
+        let mut decoder = Decoder::default();
+
+        let requests = [
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("authorization", "Bearer secret"),
+            ]),
+            fields(&[
+                (":method", "POST"),
+                (":scheme", "https"),
+                (":path", "/api/upload"),
+                (":authority", "www.example.com"),
+                ("authorization", "Bearer secret"),
+                ("x-custom", "value"),
+            ]),
+        ];
+
+        for (i, request) in requests.iter().enumerate() {
+            if i == 1 {
+                encoder.set_max_table_size(128);
+                decoder.set_max_table_size(128);
+            }
+
+            let mut block = Vec::new();
+            encoder.encode(
+                request
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+                &mut block,
+            );
+            assert_eq!(Ok(request.clone()), decoder.decode(&block));
+        }
+
+        // sensitive headers never make it into the table
+        assert!(
+            encoder
+                .table
+                .find("authorization", "Bearer secret")
+                .is_some_and(|(_, full)| !full)
+        );
+    }
+
+    #[test]
+    fn test_encode_reuses_table() {
+        let mut encoder = Encoder::default();
+        let mut block = Vec::new();
+        encoder.encode([("x-custom", "value")], &mut block);
+
+        let mut second = Vec::new();
+        encoder.encode([("x-custom", "value")], &mut second);
+        assert_eq!(b"\xbe".as_slice(), second);
+    }
+}
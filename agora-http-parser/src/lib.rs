@@ -1,20 +1,54 @@
+#[cfg(feature = "tokio")]
+pub mod async_read;
+pub mod auth;
+mod canonical;
+pub mod chunked;
+pub mod disposition;
+pub mod encoding;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod h2;
+pub mod headers;
+pub mod hpack;
+pub mod link;
+pub mod pipeline;
+pub mod qpack;
+pub mod raw;
+pub mod uri;
+pub mod via;
+
 use std::{
-    collections::HashMap,
     fmt::{Debug, Display},
+    io,
+    str::FromStr,
+    sync::LazyLock,
 };
 
+pub use auth::Authorization;
+use bytes::BufMut;
+pub use disposition::ContentDisposition;
+pub use encoding::{AcceptEncoding, ContentCoding};
+pub use headers::{HeaderName, HeaderValue, Headers, StandardHeader};
 use http::StatusCode;
+pub use link::Link;
+use memchr::{memchr, memmem};
+pub use pipeline::MessageIter;
+pub use raw::{RawRequest, RawResponse};
+pub use uri::Uri;
+use uri::is_valid_authority;
+pub use via::Via;
 
 const CRLF: &[u8; 2] = b"\r\n";
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum HTTPVersion {
+    HTTP1_0,
     HTTP1_1,
     HTTP2,
     HTTP3,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum HTTPMethod {
     GET,
     POST,
@@ -28,12 +62,13 @@ pub enum HTTPMethod {
     TRACE,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Request {
     pub path: String,
     pub method: HTTPMethod,
     pub headers: Headers,
     pub version: HTTPVersion,
+    pub body: Body,
 }
 
 #[derive(Debug, PartialEq)]
@@ -44,9 +79,181 @@ pub enum HTTPParseError {
     InvalidHeader,
     InvalidPath,
     InvalidStatusCode,
+    InvalidContentLength,
+    InvalidTransferEncoding,
+    ConflictingFraming,
+    InvalidHost,
+    InvalidUri,
+    InvalidAuthorization,
+    InvalidChunkedBody,
+    InvalidQuery,
+    InvalidEncoding,
+    InvalidLink,
+    InvalidContentDisposition,
+    InvalidVia,
+}
+
+/// How the body following a message head should be read
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Body {
+    /// There is no body
+    Empty,
+    /// The body is exactly this many bytes long
+    Sized(u64),
+    /// The body uses the chunked transfer coding
+    Chunked,
+    /// The body is delimited by the connection closing
+    UntilClose,
+}
+
+impl Body {
+    /// Apply the framing rules of RFC 9112 section 6.3 shared by requests and responses.
+    /// Returns `None` if the header fields don't say anything about the body.
+    fn from_fields<'f>(
+        fields: impl IntoIterator<Item = (&'f str, &'f [u8])>,
+    ) -> Result<Option<Self>, HTTPParseError> {
+        let mut transfer_encoding = Vec::new();
+        let mut content_length = Vec::new();
+        for (name, value) in fields {
+            if name.eq_ignore_ascii_case("transfer-encoding") {
+                transfer_encoding.push(
+                    str::from_utf8(value).map_err(|_| HTTPParseError::InvalidTransferEncoding)?,
+                );
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length
+                    .push(str::from_utf8(value).map_err(|_| HTTPParseError::InvalidContentLength)?);
+            }
+        }
+
+        // Transfer-Encoding would win, but a message with both is a classic smuggling
+        // vector so we refuse to guess which one the next hop will use.
+        if !transfer_encoding.is_empty() && !content_length.is_empty() {
+            return Err(HTTPParseError::ConflictingFraming);
+        }
+
+        if !transfer_encoding.is_empty() {
+            let codings = parse_transfer_encoding(&transfer_encoding.join(", "))?;
+            if codings
+                .iter()
+                .any(|coding| matches!(coding, Coding::Unknown(_)))
+            {
+                return Err(HTTPParseError::InvalidTransferEncoding);
+            }
+
+            return Ok(Some(if codings.last() == Some(&Coding::Chunked) {
+                Body::Chunked
+            } else {
+                Body::UntilClose
+            }));
+        }
+
+        if content_length.is_empty() {
+            return Ok(None);
+        }
+        match parse_content_length(&content_length.join(", "))? {
+            0 => Ok(Some(Body::Empty)),
+            length => Ok(Some(Body::Sized(length))),
+        }
+    }
+
+    /// Framing of a request, which has no body unless it says otherwise
+    fn for_request<'f>(
+        fields: impl IntoIterator<Item = (&'f str, &'f [u8])>,
+    ) -> Result<Self, HTTPParseError> {
+        match Body::from_fields(fields)? {
+            // the length of a request body can't be determined without chunked
+            Some(Body::UntilClose) => Err(HTTPParseError::InvalidTransferEncoding),
+            Some(body) => Ok(body),
+            None => Ok(Body::Empty),
+        }
+    }
+
+    /// Framing of a response, which is read until the connection closes unless it says otherwise
+    fn for_response<'f>(
+        status: StatusCode,
+        fields: impl IntoIterator<Item = (&'f str, &'f [u8])>,
+    ) -> Result<Self, HTTPParseError> {
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            return Ok(Body::Empty);
+        }
+
+        Ok(Body::from_fields(fields)?.unwrap_or(Body::UntilClose))
+    }
+}
+
+/// A transfer coding from the Transfer-Encoding header
+#[derive(Debug, PartialEq, Clone)]
+pub enum Coding {
+    Chunked,
+    Gzip,
+    Deflate,
+    Compress,
+    Identity,
+    /// A coding we don't know, lowercased and without parameters
+    Unknown(String),
+}
+
+impl From<&str> for Coding {
+    fn from(value: &str) -> Self {
+        // parameters are never meaningful for the codings we know, so drop them
+        let name = value.split(';').next().unwrap_or_default().trim();
+        match name.to_ascii_lowercase().as_str() {
+            "chunked" => Coding::Chunked,
+            "gzip" | "x-gzip" => Coding::Gzip,
+            "deflate" => Coding::Deflate,
+            "compress" | "x-compress" => Coding::Compress,
+            "identity" => Coding::Identity,
+            name => Coding::Unknown(name.to_string()),
+        }
+    }
+}
+
+/// Parse a Transfer-Encoding value into the ordered list of codings applied to the body.
+/// Chunked may only be applied once, and only as the final coding.
+pub fn parse_transfer_encoding(value: &str) -> Result<Vec<Coding>, HTTPParseError> {
+    let codings = value
+        .split(',')
+        .map(str::trim)
+        // empty list elements are allowed and ignored (RFC 9110 section 5.6.1)
+        .filter(|coding| !coding.is_empty())
+        .map(Coding::from)
+        .collect::<Vec<_>>();
+
+    let chunked = codings.iter().position(|coding| *coding == Coding::Chunked);
+    if codings.is_empty() || chunked.is_some_and(|i| i != codings.len() - 1) {
+        return Err(HTTPParseError::InvalidTransferEncoding);
+    }
+
+    Ok(codings)
 }
 
-pub type Headers = HashMap<String, String>;
+/// Parse a Content-Length value.
+/// A list of identical lengths (e.g. `5, 5`) is accepted as a single length, anything else
+/// that isn't purely digits or doesn't fit into a u64 is rejected. We are strict here since
+/// disagreeing with the next hop about the length of a message leads to request smuggling.
+pub fn parse_content_length(value: &str) -> Result<u64, HTTPParseError> {
+    let mut length = None;
+    for item in value.split(',') {
+        let item = item.trim_matches([' ', '\t']);
+        // checking for digits also rejects a leading `+` or `-` which `parse` would accept
+        if item.is_empty() || !item.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(HTTPParseError::InvalidContentLength);
+        }
+
+        let item: u64 = item
+            .parse()
+            .map_err(|_| HTTPParseError::InvalidContentLength)?;
+        if length.is_some_and(|length| length != item) {
+            return Err(HTTPParseError::InvalidContentLength);
+        }
+        length = Some(item);
+    }
+
+    length.ok_or(HTTPParseError::InvalidContentLength)
+}
 
 impl Display for HTTPParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -60,21 +267,70 @@ impl Display for HTTPParseError {
                 HTTPParseError::InvalidHeader => "Invalid HTTP headers",
                 HTTPParseError::InvalidPath => "Invalid HTTP path",
                 HTTPParseError::InvalidStatusCode => "Invalid HTTP status code",
+                HTTPParseError::InvalidContentLength => "Invalid Content-Length",
+                HTTPParseError::InvalidTransferEncoding => "Invalid Transfer-Encoding",
+                HTTPParseError::InvalidHost => "Missing or invalid Host",
+                HTTPParseError::InvalidUri => "Invalid URI",
+                HTTPParseError::InvalidAuthorization => "Invalid Authorization",
+                HTTPParseError::InvalidChunkedBody => "Invalid chunked body",
+                HTTPParseError::InvalidQuery => "Invalid query string",
+                HTTPParseError::InvalidEncoding => "Invalid content coding",
+                HTTPParseError::InvalidLink => "Invalid link",
+                HTTPParseError::InvalidContentDisposition => "Invalid content disposition",
+                HTTPParseError::InvalidVia => "Invalid via",
+                HTTPParseError::ConflictingFraming => {
+                    "Message has both Content-Length and Transfer-Encoding"
+                }
             }
         )
     }
 }
 
+impl std::error::Error for HTTPParseError {}
+
 impl Display for HTTPVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "HTTP/{}",
-            match self {
-                HTTPVersion::HTTP1_1 => "1.1",
-                HTTPVersion::HTTP2 => "2",
-                HTTPVersion::HTTP3 => "3",
-            }
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl HTTPVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HTTPVersion::HTTP1_0 => "HTTP/1.0",
+            HTTPVersion::HTTP1_1 => "HTTP/1.1",
+            HTTPVersion::HTTP2 => "HTTP/2",
+            HTTPVersion::HTTP3 => "HTTP/3",
+        }
+    }
+}
+
+impl HTTPMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HTTPMethod::GET => "GET",
+            HTTPMethod::POST => "POST",
+            HTTPMethod::PUT => "PUT",
+            HTTPMethod::PATCH => "PATCH",
+            HTTPMethod::DELETE => "DELETE",
+            HTTPMethod::HEAD => "HEAD",
+            HTTPMethod::CONNECT => "CONNECT",
+            HTTPMethod::OPTIONS => "OPTIONS",
+            HTTPMethod::TRACE => "TRACE",
+        }
+    }
+
+    /// Whether sending the request several times has the same effect as sending it
+    /// once, which makes it safe to retry
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            HTTPMethod::GET
+                | HTTPMethod::HEAD
+                | HTTPMethod::OPTIONS
+                | HTTPMethod::TRACE
+                | HTTPMethod::PUT
+                | HTTPMethod::DELETE
         )
     }
 }
@@ -105,54 +361,237 @@ impl Display for Response {
 impl<'a> Request {
     /// Parse the buffer into a [`Request`]
     pub fn parse(buf: &'a [u8]) -> Result<(Self, &'a [u8]), HTTPParseError> {
-        let (path, method, version, buf) = Self::parse_request_line(buf)?;
-        let (headers, buf) = parse_headers(buf)?;
+        let (head, buf) = RequestHead::parse(buf)?;
 
         Ok((
             Self {
-                path: path.to_string(),
-                method,
-                headers,
-                version,
+                path: head.path.to_string(),
+                method: head.method,
+                headers: Headers::from_fields(&head.fields),
+                version: head.version,
+                body: head.body,
             },
             buf,
         ))
     }
 
-    /// Parse the buffer for the HTTP start line from the start to the first CRLF
-    /// Returns the path, method, and version, and remainging bytes in this exact order
-    fn parse_request_line(
-        buf: &'a [u8],
-    ) -> Result<(&'a str, HTTPMethod, HTTPVersion, &'a [u8]), HTTPParseError> {
-        let (method, buf) = Self::parse_method(buf)?;
-        let (path, buf) = parse_path(buf)?;
-        let (version, buf) = Self::parse_version(buf)?;
+    /// Iterate over pipelined requests at the start of the buffer.
+    /// See [`MessageIter`] for how incomplete messages are handled.
+    pub fn parse_many(buf: &'a [u8]) -> MessageIter<'a> {
+        MessageIter::new(buf)
+    }
 
-        Ok((path, method, version, buf))
+    /// Parse the request target into a [`Uri`]
+    pub fn uri(&self) -> Result<Uri, HTTPParseError> {
+        Uri::parse(&self.path)
     }
 
-    /// Parse the http method from the buffer and return the remaining bytes
-    fn parse_method(buf: &[u8]) -> Result<(HTTPMethod, &[u8]), HTTPParseError> {
-        let method = parse_until_space(buf);
-        Ok((method.try_into()?, &buf[method.len() + 1..]))
+    /// Look up a query parameter and parse it into `T`.
+    /// Returns `None` if the parameter isn't present, and uses the first value if it is
+    /// repeated.
+    pub fn query_param<T: FromStr>(&self, name: &str) -> Result<Option<T>, HTTPParseError> {
+        self.uri()?
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.parse().map_err(|_| HTTPParseError::InvalidQuery))
+            .transpose()
     }
 
-    /// Parse the http version from the buffer and return the remainging bytes
-    fn parse_version(buf: &[u8]) -> Result<(HTTPVersion, &[u8]), HTTPParseError> {
-        let version = parse_until_crlf(buf);
+    /// Deserialize the whole query string into `T`.
+    /// A request without a query deserializes from an empty query.
+    #[cfg(feature = "serde")]
+    pub fn query_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, HTTPParseError> {
+        serde_urlencoded::from_str(self.uri()?.query().unwrap_or_default())
+            .map_err(|_| HTTPParseError::InvalidQuery)
+    }
 
-        // + 2 here to skip over CRLF
-        Ok((version.try_into()?, &buf[version.len() + 2..]))
+    /// A deterministic canonical form of the request for computing signatures, modelled on
+    /// the AWS SigV4 canonical request. Each part is on its own line:
+    ///
+    /// ```text
+    /// GET
+    /// /a%20b/c
+    /// x=1&y=2
+    /// content-type:text/plain
+    /// host:example.com
+    ///
+    /// content-type;host
+    /// ```
+    ///
+    /// That is the method, the path without dot segments, the query sorted by name and value,
+    /// one `name:value` line per header sorted by name with its value's whitespace collapsed,
+    /// an empty line and the signed header names. Paths and query components are percent
+    /// encoded the same way whatever the client sent. The body isn't included, sign a hash
+    /// of it separately if needed.
+    ///
+    /// Fails if the target can't be parsed or a header value isn't valid UTF-8.
+    pub fn canonicalize(&self) -> Result<String, HTTPParseError> {
+        canonical::canonicalize(self)
     }
 
-    pub fn into_bytes(&self) -> Vec<u8> {
-        let mut request = format!("{:?} {} {}\r\n", self.method, self.path, self.version);
-        for (key, value) in &self.headers {
-            request.push_str(&format!("{}: {}\r\n", key, value));
+    /// The authority (`host[:port]`) this request is addressed to.
+    ///
+    /// This is taken from the request target when it is in absolute-form, otherwise from the
+    /// Host header. Repeated Host headers are combined while parsing, so they are rejected
+    /// here along with a missing or malformed Host.
+    pub fn host(&self) -> Result<&str, HTTPParseError> {
+        // a Host header is still required, but the target takes precedence (RFC 9112 section 3.2.2)
+        if let Some(authority) = absolute_form_authority(&self.path) {
+            return if is_valid_authority(authority) {
+                Ok(authority)
+            } else {
+                Err(HTTPParseError::InvalidHost)
+            };
+        }
+
+        match self.headers.get("host").map(HeaderValue::to_str) {
+            Some(Ok(host)) if is_valid_authority(host) => Ok(host),
+            _ => Err(HTTPParseError::InvalidHost),
         }
-        request.push_str("\r\n");
+    }
+
+    /// The credentials in the Authorization header, if there is one
+    pub fn authorization(&self) -> Result<Option<Authorization>, HTTPParseError> {
+        self.headers
+            .get("authorization")
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| HTTPParseError::InvalidAuthorization)
+                    .and_then(Authorization::parse)
+            })
+            .transpose()
+    }
+
+    /// The transfer codings applied to the body, if there is a Transfer-Encoding header
+    pub fn transfer_encoding(&self) -> Result<Option<Vec<Coding>>, HTTPParseError> {
+        self.headers
+            .get("transfer-encoding")
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| HTTPParseError::InvalidTransferEncoding)
+                    .and_then(parse_transfer_encoding)
+            })
+            .transpose()
+    }
+
+    /// The codings the client accepts for the response body.
+    /// Returns `None` if there is no Accept-Encoding header, meaning any coding is acceptable.
+    pub fn accept_encoding(&self) -> Result<Option<AcceptEncoding>, HTTPParseError> {
+        self.headers
+            .get("accept-encoding")
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| HTTPParseError::InvalidEncoding)
+                    .and_then(AcceptEncoding::parse)
+            })
+            .transpose()
+    }
+
+    /// The content codings applied to the body, if there is a Content-Encoding header
+    pub fn content_encoding(&self) -> Result<Option<Vec<ContentCoding>>, HTTPParseError> {
+        content_encoding(&self.headers)
+    }
+
+    /// The intermediaries listed in the Via header, in the order they forwarded the message
+    pub fn via(&self) -> Result<Vec<Via>, HTTPParseError> {
+        via(&self.headers)
+    }
+
+    /// Identify ourselves in the Via header before forwarding, using the version this
+    /// message was received with
+    pub fn append_via(&mut self, received_by: &str) {
+        via::append_via(&mut self.headers, &Via::new(self.version, received_by));
+    }
+
+    /// Whether the client sent `Expect: 100-continue` and is waiting for a
+    /// [`Response::continue_`] before sending the body.
+    /// The expectation is ignored for HTTP/1.0 requests (RFC 9110 section 10.1.1).
+    pub fn expects_continue(&self) -> bool {
+        self.version != HTTPVersion::HTTP1_0
+            && self.headers.get("expect").is_some_and(|expect| {
+                expect
+                    .as_bytes()
+                    .trim_ascii()
+                    .eq_ignore_ascii_case(b"100-continue")
+            })
+    }
 
-        request.into_bytes()
+    /// Whether the client asks to switch the connection over to the WebSocket protocol
+    /// (RFC 6455 section 4.1). Only HTTP/1.1 connections can be upgraded.
+    pub fn upgrades_to_websocket(&self) -> bool {
+        self.upgrades_to("websocket")
+    }
+
+    /// Whether the client asks to switch the connection over to cleartext HTTP/2, with
+    /// its settings in `HTTP2-Settings` (RFC 7540 section 3.2)
+    pub fn upgrades_to_h2c(&self) -> bool {
+        self.upgrades_to("h2c")
+            && has_connection_token(&self.headers, "http2-settings")
+            && self.headers.contains_key("http2-settings")
+    }
+
+    fn upgrades_to(&self, protocol: &str) -> bool {
+        self.version == HTTPVersion::HTTP1_1
+            && has_connection_token(&self.headers, "upgrade")
+            && self.headers.get("upgrade").is_some_and(|upgrade| {
+                upgrade.as_bytes().split(|&b| b == b',').any(|offered| {
+                    let name = offered.split(|&b| b == b'/').next().unwrap_or_default();
+                    name.trim_ascii().eq_ignore_ascii_case(protocol.as_bytes())
+                })
+            })
+    }
+
+    /// Whether the client wants the connection to stay open after this request
+    pub fn wants_keep_alive(&self) -> bool {
+        wants_keep_alive(&self.version, &self.headers)
+    }
+
+    /// Determine how the body of this request is framed.
+    /// A request without a Content-Length or Transfer-Encoding has no body.
+    pub fn framing(&self) -> Result<Body, HTTPParseError> {
+        Body::for_request(headers_as_fields(&self.headers))
+    }
+
+    pub fn into_bytes(&self) -> Vec<u8> {
+        let mut request = Vec::with_capacity(self.head_len());
+        self.write_to_buf(&mut request);
+        request
+    }
+
+    /// Serialize the request head into the writer
+    pub fn write_to(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        self.write_head(|bytes| writer.write_all(bytes))
+    }
+
+    /// Serialize the request head into the buffer.
+    /// Panics if the buffer can't grow to fit the head, like [`BufMut::put_slice`].
+    pub fn write_to_buf(&self, buf: &mut impl BufMut) {
+        let _ = self.write_head(|bytes| {
+            buf.put_slice(bytes);
+            Ok(())
+        });
+    }
+
+    fn write_head(&self, mut put: impl FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+        put(self.method.as_str().as_bytes())?;
+        put(b" ")?;
+        put(self.path.as_bytes())?;
+        put(b" ")?;
+        put(self.version.as_str().as_bytes())?;
+        put(CRLF)?;
+        write_headers(&self.headers, put)
+    }
+
+    /// The length of the serialized request head
+    fn head_len(&self) -> usize {
+        self.method.as_str().len()
+            + self.path.len()
+            + self.version.as_str().len()
+            + 4
+            + headers_len(&self.headers)
     }
 }
 
@@ -161,6 +600,7 @@ pub struct Response {
     status: StatusCode,
     version: HTTPVersion,
     headers: Headers,
+    body: Body,
 }
 
 impl<'a> Response {
@@ -168,37 +608,308 @@ impl<'a> Response {
         Self {
             status,
             version: HTTPVersion::HTTP1_1, // Hardcode to HTTP/1.1
-            headers: HashMap::new(),
+            headers: Headers::new(),
+            body: Body::Empty,
         }
     }
 
+    /// The interim `100 Continue` response telling a client to go ahead and send the body
+    pub fn continue_() -> Self {
+        Self::new(StatusCode::CONTINUE)
+    }
+
     pub fn parse(buf: &'a [u8]) -> Result<(Self, &'a [u8]), HTTPParseError> {
-        let (version, status, buf) = Self::parse_status_line(buf)?;
-        let (headers, buf) = parse_headers(buf)?;
+        let (head, buf) = ResponseHead::parse(buf)?;
 
         Ok((
             Self {
-                status,
-                version,
-                headers,
+                status: head.status,
+                version: head.version,
+                headers: Headers::from_fields(&head.fields),
+                body: head.body,
             },
             buf,
         ))
     }
 
+    /// Parse responses until the final one, skipping over any interim (1xx) responses.
+    /// The interim responses are returned as well, in the order they were received,
+    /// since a proxy still needs to forward them.
+    pub fn parse_final(buf: &'a [u8]) -> Result<(Vec<Self>, Self, &'a [u8]), HTTPParseError> {
+        let mut interim = Vec::new();
+        let mut buf = buf;
+        loop {
+            let (response, remaining) = Self::parse(buf)?;
+            if !response.is_interim() {
+                return Ok((interim, response, remaining));
+            }
+            interim.push(response);
+            buf = remaining;
+        }
+    }
+
+    /// Whether this is an interim response like `100 Continue` or `103 Early Hints`, which is
+    /// followed by another response to the same request.
+    /// `101 Switching Protocols` is informational but final, since the connection stops being
+    /// HTTP/1.1 after it.
+    pub fn is_interim(&self) -> bool {
+        self.status.is_informational() && self.status != StatusCode::SWITCHING_PROTOCOLS
+    }
+
+    /// The transfer codings applied to the body, if there is a Transfer-Encoding header
+    pub fn transfer_encoding(&self) -> Result<Option<Vec<Coding>>, HTTPParseError> {
+        self.headers
+            .get("transfer-encoding")
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| HTTPParseError::InvalidTransferEncoding)
+                    .and_then(parse_transfer_encoding)
+            })
+            .transpose()
+    }
+
+    /// The content codings applied to the body, if there is a Content-Encoding header
+    pub fn content_encoding(&self) -> Result<Option<Vec<ContentCoding>>, HTTPParseError> {
+        content_encoding(&self.headers)
+    }
+
+    /// The links in the Link header, empty if there is none
+    pub fn links(&self) -> Result<Vec<Link>, HTTPParseError> {
+        match self.headers.get("link") {
+            Some(value) => value
+                .to_str()
+                .map_err(|_| HTTPParseError::InvalidLink)
+                .and_then(Link::parse_all),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The Content-Disposition header, if there is one
+    pub fn content_disposition(&self) -> Result<Option<ContentDisposition>, HTTPParseError> {
+        self.headers
+            .get("content-disposition")
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| HTTPParseError::InvalidContentDisposition)
+                    .and_then(ContentDisposition::parse)
+            })
+            .transpose()
+    }
+
+    /// The intermediaries listed in the Via header, in the order they forwarded the message
+    pub fn via(&self) -> Result<Vec<Via>, HTTPParseError> {
+        via(&self.headers)
+    }
+
+    /// Identify ourselves in the Via header before forwarding, using the version this
+    /// message was received with
+    pub fn append_via(&mut self, received_by: &str) {
+        via::append_via(&mut self.headers, &Via::new(self.version, received_by));
+    }
+
+    /// Whether this is a redirect that points somewhere else with a Location header:
+    /// 301, 302, 303, 307 or 308.
+    /// 300 Multiple Choices and 304 Not Modified are 3xx as well, but not redirects to follow.
+    pub fn is_redirect(&self) -> bool {
+        matches!(
+            self.status,
+            StatusCode::MOVED_PERMANENTLY
+                | StatusCode::FOUND
+                | StatusCode::SEE_OTHER
+                | StatusCode::TEMPORARY_REDIRECT
+                | StatusCode::PERMANENT_REDIRECT
+        )
+    }
+
+    /// The Location header as sent, which may be a relative reference
+    pub fn location(&self) -> Result<Option<Uri>, HTTPParseError> {
+        self.headers
+            .get("location")
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| HTTPParseError::InvalidUri)
+                    .and_then(Uri::parse)
+            })
+            .transpose()
+    }
+
+    /// The Location header resolved against the target of the request this responds to.
+    /// Origin-form targets take their authority from the Host header, but the scheme isn't
+    /// known from the request alone, so the result has none unless the Location had one.
+    pub fn resolve_location(&self, request: &Request) -> Result<Option<Uri>, HTTPParseError> {
+        let Some(location) = self.location()? else {
+            return Ok(None);
+        };
+
+        let mut base = request.uri()?;
+        if base.authority().is_none() {
+            base.set_authority(Some(request.host()?))?;
+        }
+        Ok(Some(base.resolve(&location)))
+    }
+
+    /// Whether the connection can stay open after this response.
+    /// A body delimited by the connection closing always ends the connection.
+    pub fn wants_keep_alive(&self) -> bool {
+        self.body != Body::UntilClose && wants_keep_alive(&self.version, &self.headers)
+    }
+
+    /// Determine how the body of this response is framed.
+    /// Note that responses to HEAD requests never have a body, which can't be
+    /// known from the response alone.
+    pub fn framing(&self) -> Result<Body, HTTPParseError> {
+        Body::for_response(self.status, headers_as_fields(&self.headers))
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
     pub fn get_headers(&'a self) -> &'a Headers {
         &self.headers
     }
 
-    fn parse_status_line(
-        buf: &'a [u8],
-    ) -> Result<(HTTPVersion, StatusCode, &'a [u8]), HTTPParseError> {
+    pub fn get_headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    pub fn body(&self) -> Body {
+        self.body
+    }
+
+    /// Override the framing found in the head, like for a response to a HEAD request,
+    /// which never has a body whatever its head says (RFC 9110 section 9.3.2)
+    pub fn set_body(&mut self, body: Body) {
+        self.body = body;
+    }
+
+    pub fn header(&mut self, name: HeaderName, value: HeaderValue) {
+        self.headers.insert(name, value);
+    }
+
+    pub fn get_header(&self, key: &str) -> Option<&HeaderValue> {
+        self.headers.get(key)
+    }
+
+    pub fn into_bytes(&self) -> Vec<u8> {
+        let mut response = Vec::with_capacity(self.head_len());
+        self.write_to_buf(&mut response);
+        response
+    }
+
+    /// Serialize the response head into the writer
+    pub fn write_to(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        self.write_head(|bytes| writer.write_all(bytes))
+    }
+
+    /// Serialize the response head into the buffer.
+    /// Panics if the buffer can't grow to fit the head, like [`BufMut::put_slice`].
+    pub fn write_to_buf(&self, buf: &mut impl BufMut) {
+        let _ = self.write_head(|bytes| {
+            buf.put_slice(bytes);
+            Ok(())
+        });
+    }
+
+    fn write_head(&self, mut put: impl FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+        put(self.version.as_str().as_bytes())?;
+        put(b" ")?;
+        put(self.status.as_str().as_bytes())?;
+        put(b" ")?;
+        put(self.reason().as_bytes())?;
+        put(CRLF)?;
+        write_headers(&self.headers, put)
+    }
+
+    fn reason(&self) -> &'static str {
+        self.status.canonical_reason().unwrap_or("Unknown Reason")
+    }
+
+    /// The length of the serialized response head
+    fn head_len(&self) -> usize {
+        self.version.as_str().len() + 3 + self.reason().len() + 4 + headers_len(&self.headers)
+    }
+}
+
+/// Header fields as `(name, value)` slices, in the order they were received
+pub(crate) type Fields<'a> = Vec<(&'a str, &'a [u8])>;
+
+/// The parts of a request head as slices of the buffer it was parsed from.
+/// Both the owned [`Request`] and the [`raw::RawRequest`] are built from this.
+pub(crate) struct RequestHead<'a> {
+    pub(crate) method: HTTPMethod,
+    pub(crate) path: &'a str,
+    pub(crate) version: HTTPVersion,
+    pub(crate) fields: Fields<'a>,
+    pub(crate) body: Body,
+}
+
+impl<'a> RequestHead<'a> {
+    pub(crate) fn parse(buf: &'a [u8]) -> Result<(Self, &'a [u8]), HTTPParseError> {
+        let (method, buf) = Self::parse_method(buf)?;
+        let (path, buf) = parse_path(buf, &method)?;
         let (version, buf) = Self::parse_version(buf)?;
-        let (status, buf) = Self::parse_status(buf)?;
+        let (fields, buf) = parse_fields(buf)?;
+        let body = Body::for_request(fields.iter().copied())?;
 
+        Ok((
+            Self {
+                method,
+                path,
+                version,
+                fields,
+                body,
+            },
+            buf,
+        ))
+    }
+
+    /// Parse the http method from the buffer and return the remaining bytes
+    fn parse_method(buf: &[u8]) -> Result<(HTTPMethod, &[u8]), HTTPParseError> {
+        let method = parse_until_space(buf);
+        Ok((method.try_into()?, &buf[method.len() + 1..]))
+    }
+
+    /// Parse the http version from the buffer and return the remainging bytes
+    fn parse_version(buf: &[u8]) -> Result<(HTTPVersion, &[u8]), HTTPParseError> {
+        let version = parse_until_crlf(buf);
+
+        // + 2 here to skip over CRLF
+        Ok((version.try_into()?, &buf[version.len() + 2..]))
+    }
+}
+
+/// The parts of a response head as slices of the buffer it was parsed from
+pub(crate) struct ResponseHead<'a> {
+    pub(crate) status: StatusCode,
+    pub(crate) version: HTTPVersion,
+    pub(crate) fields: Fields<'a>,
+    pub(crate) body: Body,
+}
+
+impl<'a> ResponseHead<'a> {
+    pub(crate) fn parse(buf: &'a [u8]) -> Result<(Self, &'a [u8]), HTTPParseError> {
+        let (version, buf) = Self::parse_version(buf)?;
+        let (status, buf) = Self::parse_status(buf)?;
         let reason = parse_until_crlf(buf);
+        let buf = buf
+            .get(reason.len() + 2..)
+            .ok_or(HTTPParseError::UnterminatedHeader)?;
+        let (fields, buf) = parse_fields(buf)?;
+        let body = Body::for_response(status, fields.iter().copied())?;
 
-        Ok((version, status, &buf[reason.len() + 2..]))
+        Ok((
+            Self {
+                status,
+                version,
+                fields,
+                body,
+            },
+            buf,
+        ))
     }
 
     /// Parse the http version from the buffer and return the remainging bytes
@@ -215,121 +926,259 @@ impl<'a> Response {
             &buf[status.len() + 1..],
         ))
     }
+}
 
-    pub fn header(&mut self, key: &str, value: &str) {
-        self.headers.insert(key.to_lowercase(), value.to_string());
+/// Whether the Connection header contains the token, compared case-insensitively
+fn via(headers: &Headers) -> Result<Vec<Via>, HTTPParseError> {
+    match headers.get("via") {
+        Some(value) => value
+            .to_str()
+            .map_err(|_| HTTPParseError::InvalidVia)
+            .and_then(Via::parse_all),
+        None => Ok(Vec::new()),
     }
+}
 
-    pub fn get_header(&self, key: &str) -> Option<&String> {
-        self.headers.get(key)
-    }
+fn content_encoding(headers: &Headers) -> Result<Option<Vec<ContentCoding>>, HTTPParseError> {
+    headers
+        .get("content-encoding")
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| HTTPParseError::InvalidEncoding)
+                .and_then(encoding::parse_content_encoding)
+        })
+        .transpose()
+}
 
-    pub fn into_bytes(&self) -> Vec<u8> {
-        let mut response = format!(
-            "{} {} {}\r\n",
-            self.version,
-            self.status.as_u16(),
-            self.status.canonical_reason().unwrap_or("Unknown Reason")
-        );
+pub fn has_connection_token(headers: &Headers, token: &str) -> bool {
+    headers.get("connection").is_some_and(|connection| {
+        connection
+            .as_bytes()
+            .split(|&b| b == b',')
+            .any(|value| value.trim_ascii().eq_ignore_ascii_case(token.as_bytes()))
+    })
+}
 
-        for (key, value) in &self.headers {
-            response.push_str(&format!("{}: {}\r\n", key, value));
-        }
-        response.push_str("\r\n");
+/// Combine the persistence default of the version with the Connection header (RFC 9112 section 9.3)
+fn wants_keep_alive(version: &HTTPVersion, headers: &Headers) -> bool {
+    if has_connection_token(headers, "close") {
+        return false;
+    }
+
+    match version {
+        HTTPVersion::HTTP1_0 => has_connection_token(headers, "keep-alive"),
+        HTTPVersion::HTTP1_1 => true,
+        // connection management is done by the protocol itself
+        HTTPVersion::HTTP2 | HTTPVersion::HTTP3 => true,
+    }
+}
 
-        response.into_bytes()
+/// Write each header line followed by the empty line ending the head
+fn write_headers(
+    headers: &Headers,
+    mut put: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    for (key, value) in headers {
+        put(key.as_bytes())?;
+        put(b": ")?;
+        put(value.as_bytes())?;
+        put(CRLF)?;
     }
+    put(CRLF)
 }
 
-/// Parse the path from the buffer and return the remaining bytes
-fn parse_path(buf: &[u8]) -> Result<(&str, &[u8]), HTTPParseError> {
+fn headers_len(headers: &Headers) -> usize {
+    headers
+        .iter()
+        .map(|(key, value)| key.len() + value.len() + 4)
+        .sum::<usize>()
+        + 2
+}
+
+/// Parse the path from the buffer and return the remaining bytes. CONNECT requests
+/// take the `host:port` of the authority-form instead (RFC 9112 section 3.2.3).
+fn parse_path<'b>(
+    buf: &'b [u8],
+    method: &HTTPMethod,
+) -> Result<(&'b str, &'b [u8]), HTTPParseError> {
     let Ok(path) = str::from_utf8(parse_until_space(buf)) else {
         return Err(HTTPParseError::InvalidPath);
     };
 
     // will need a path validator here
-    if path.is_empty() || !path.starts_with("/") {
+    let valid = match method {
+        HTTPMethod::CONNECT => Uri::from_authority(path).is_ok_and(|uri| uri.port().is_some()),
+        _ => path.starts_with("/") || absolute_form_authority(path).is_some(),
+    };
+    if !valid {
         return Err(HTTPParseError::InvalidPath);
     }
 
     Ok((path, &buf[path.len() + 1..]))
 }
 
+/// If the request target is in absolute-form (`http://host/path`), return its authority
+fn absolute_form_authority(target: &str) -> Option<&str> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
 /// Parse the headers from the buffer and
 /// Return the headers and remaining bytes
-fn parse_headers(mut buf: &[u8]) -> Result<(Headers, &[u8]), HTTPParseError> {
-    let mut headers = HashMap::new();
+#[cfg(test)]
+fn parse_headers(buf: &[u8]) -> Result<(Headers, &[u8]), HTTPParseError> {
+    let (fields, buf) = parse_fields(buf)?;
+    Ok((Headers::from_fields(&fields), buf))
+}
 
-    // buf is the start of the current line
-    // loop will stop when we either find a crlf at the start of the line indicating the end,
-    // or we don't have a crlf terminator at all
-    while buf.len() >= 2 && &buf[..2] != CRLF {
-        let (key, value, rest) = parse_header(buf)?;
+/// Parse and validate the header fields at the start of the buffer, in the order they appear.
+/// Returns the fields and the remaining bytes.
+fn parse_fields(buf: &[u8]) -> Result<(Fields<'_>, &[u8]), HTTPParseError> {
+    let mut fields = Fields::new();
+    let mut content_length = None;
+
+    let mut iter = parse_headers_iter(buf);
+    for header in &mut iter {
+        let (name, value) = header?;
+        if !headers::is_valid_name(name) || !headers::is_valid_value(value) {
+            return Err(HTTPParseError::InvalidHeader);
+        }
 
-        headers.insert(key.to_lowercase(), value.to_string());
-        buf = rest;
+        // repeated Content-Length headers are only tolerated if they all agree
+        if name.eq_ignore_ascii_case("content-length") {
+            let length = str::from_utf8(value)
+                .map_err(|_| HTTPParseError::InvalidContentLength)
+                .and_then(parse_content_length)?;
+            if content_length.is_some_and(|existing| existing != length) {
+                return Err(HTTPParseError::InvalidContentLength);
+            }
+            content_length = Some(length);
+        }
+
+        fields.push((name, value));
     }
 
-    // loop terminated because we don't have a crlf terminator
-    if buf.len() < 2 {
-        return Err(HTTPParseError::UnterminatedHeader);
+    // the iterator only finishes without an error once it reached the end of the headers
+    Ok((fields, iter.remaining().unwrap_or_default()))
+}
+
+/// View headers as the `(name, value)` fields the framing rules work on
+fn headers_as_fields(headers: &Headers) -> impl Iterator<Item = (&str, &[u8])> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_bytes()))
+}
+
+/// Lazily parse the header block at the start of the buffer.
+///
+/// Yields the `(name, value)` pairs as slices of the buffer in the order they appear,
+/// without lowercasing names or combining repeated headers. Values are bytes since they
+/// aren't required to be UTF-8. This is meant for single pass
+/// consumers which don't need the allocations of [`Headers`].
+pub fn parse_headers_iter(buf: &[u8]) -> HeadersIter<'_> {
+    HeadersIter {
+        buf,
+        finished: false,
     }
+}
 
-    // otherwise the current line starts with crlf, so we've reached the end of the headers
+/// Iterator returned by [`parse_headers_iter`]
+#[derive(Debug, Clone)]
+pub struct HeadersIter<'a> {
+    buf: &'a [u8],
+    finished: bool,
+}
 
-    Ok((headers, &buf[2..]))
+impl<'a> HeadersIter<'a> {
+    /// The bytes following the header block.
+    /// Returns `None` until the iterator has reached the end of the headers.
+    pub fn remaining(&self) -> Option<&'a [u8]> {
+        (self.finished && self.buf.starts_with(CRLF)).then(|| &self.buf[2..])
+    }
 }
 
-fn parse_header(buf: &[u8]) -> Result<(&str, &str, &[u8]), HTTPParseError> {
-    let mut separator_index = None;
-    for i in 0..buf.len() - 1 {
-        if buf[i] == b':' && separator_index.is_none() {
-            separator_index = Some(i);
+impl<'a> Iterator for HeadersIter<'a> {
+    type Item = Result<(&'a str, &'a [u8]), HTTPParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
         }
 
-        if &buf[i..i + 2] == CRLF {
-            if let Some(separator_index) = separator_index {
-                return Ok((
-                    str::from_utf8(&buf[..separator_index])
-                        .map_err(|_| HTTPParseError::InvalidHeader)?,
-                    str::from_utf8(&buf[separator_index + 1..i])
-                        .map_err(|_| HTTPParseError::InvalidHeader)?
-                        .trim(),
-                    &buf[i + 2..],
-                ));
-            } else {
-                return Err(HTTPParseError::UnterminatedHeader);
+        // buf is the start of the current line, a line starting with crlf marks the end
+        if self.buf.starts_with(CRLF) {
+            self.finished = true;
+            return None;
+        }
+
+        // we don't have a crlf terminator at all
+        if self.buf.len() < 2 {
+            self.finished = true;
+            return Some(Err(HTTPParseError::UnterminatedHeader));
+        }
+
+        match parse_header(self.buf) {
+            Ok((key, value, rest)) => {
+                self.buf = rest;
+                Some(Ok((key, value)))
+            }
+            Err(e) => {
+                // stop at the first error, there is no way to resync
+                self.finished = true;
+                self.buf = b"";
+                Some(Err(e))
             }
         }
     }
+}
+
+fn parse_header(buf: &[u8]) -> Result<(&str, &[u8], &[u8]), HTTPParseError> {
+    let Some(end) = memmem::find(buf, CRLF) else {
+        return Err(HTTPParseError::UnterminatedHeader);
+    };
+    let line = &buf[..end];
 
-    Err(HTTPParseError::UnterminatedHeader)
+    let Some(separator_index) = memchr(b':', line) else {
+        return Err(HTTPParseError::UnterminatedHeader);
+    };
+
+    Ok((
+        str::from_utf8(&line[..separator_index]).map_err(|_| HTTPParseError::InvalidHeader)?,
+        line[separator_index + 1..].trim_ascii(),
+        &buf[end + 2..],
+    ))
 }
 
 fn parse_until_space(buf: &[u8]) -> &[u8] {
-    for i in 0..buf.len() {
-        if buf[i] == b' ' {
-            return &buf[..i];
-        }
-    }
-
     // if we couldn't find a space, return empty string
-    b""
+    memchr(b' ', buf).map_or(b"", |i| &buf[..i])
 }
 
 fn parse_until_crlf(buf: &[u8]) -> &[u8] {
-    for i in 0..buf.len() {
-        if i < buf.len() - 1 && &buf[i..i + 2] == CRLF {
-            return &buf[..i];
-        }
-    }
-
-    b""
+    memmem::find(buf, CRLF).map_or(b"", |i| &buf[..i])
 }
 
+static TERMINATOR: LazyLock<memmem::Finder<'static>> =
+    LazyLock::new(|| memmem::Finder::new(b"\r\n\r\n"));
+
 // Return whether the HTTP Header is terminated
 pub fn is_terminated(buf: &[u8]) -> bool {
-    buf.windows(4).any(|window| window == b"\r\n\r\n")
+    TERMINATOR.find(buf).is_some()
+}
+
+/// Return whether the HTTP Header is terminated, given that the first `scanned` bytes
+/// of the buffer were already checked and did not contain the terminator.
+/// This lets callers reading into a growing buffer avoid rescanning it on every read.
+pub fn is_terminated_from(buf: &[u8], scanned: usize) -> bool {
+    // the terminator could have been split between the old and new bytes,
+    // so back up enough to see the 3 bytes before the new data.
+    is_terminated(&buf[scanned.saturating_sub(3).min(buf.len())..])
 }
 
 impl TryFrom<&[u8]> for HTTPMethod {
@@ -356,6 +1205,7 @@ impl TryFrom<&[u8]> for HTTPVersion {
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         match value {
+            b"HTTP/1.0" => Ok(HTTPVersion::HTTP1_0),
             b"HTTP/1.1" => Ok(HTTPVersion::HTTP1_1),
             b"HTTP/2" => Ok(HTTPVersion::HTTP2),
             b"HTTP/3" => Ok(HTTPVersion::HTTP3),
@@ -417,21 +1267,41 @@ mod tests {
         #[case] input: &[u8],
         #[case] expected: Result<(HTTPMethod, &[u8]), HTTPParseError>,
     ) {
-        assert_eq!(expected, Request::parse_method(input));
+        assert_eq!(expected, RequestHead::parse_method(input));
     }
 
     #[rstest]
     #[case(b"/ HTTP/1.1\r\n\r\n", Ok(("/", b"HTTP/1.1\r\n\r\n".as_slice())))]
     #[case(b"/api HTTP/1.1\r\n\r\n", Ok(("/api", b"HTTP/1.1\r\n\r\n".as_slice())))]
     #[case(b"/stuff-with-dashes HTTP/1.1\r\n\r\n", Ok(("/stuff-with-dashes", b"HTTP/1.1\r\n\r\n".as_slice())))]
+    #[case(
+        b"http://example.com/api HTTP/1.1\r\n\r\n",
+        Ok(("http://example.com/api", b"HTTP/1.1\r\n\r\n".as_slice()))
+    )]
     #[case(b"not-a-path HTTP/1.1\r\n\r\n", Err(HTTPParseError::InvalidPath))]
+    #[case(
+        b"ftp://example.com/ HTTP/1.1\r\n\r\n",
+        Err(HTTPParseError::InvalidPath)
+    )]
     #[case(b" HTTP/1.1\r\n\r\n", Err(HTTPParseError::InvalidPath))]
     #[case(b"HTTP/1.1\r\n\r\n", Err(HTTPParseError::InvalidPath))]
     fn test_parse_path(
         #[case] input: &[u8],
         #[case] expected: Result<(&str, &[u8]), HTTPParseError>,
     ) {
-        assert_eq!(expected, parse_path(input));
+        assert_eq!(expected, parse_path(input, &HTTPMethod::GET));
+    }
+
+    #[rstest]
+    #[case(b"example.com:443 HTTP/1.1\r\n\r\n", Ok(("example.com:443", b"HTTP/1.1\r\n\r\n".as_slice())))]
+    #[case(b"[::1]:8443 HTTP/1.1\r\n\r\n", Ok(("[::1]:8443", b"HTTP/1.1\r\n\r\n".as_slice())))]
+    #[case(b"example.com HTTP/1.1\r\n\r\n", Err(HTTPParseError::InvalidPath))]
+    #[case(b"/ HTTP/1.1\r\n\r\n", Err(HTTPParseError::InvalidPath))]
+    fn test_parse_connect_target(
+        #[case] input: &[u8],
+        #[case] expected: Result<(&str, &[u8]), HTTPParseError>,
+    ) {
+        assert_eq!(expected, parse_path(input, &HTTPMethod::CONNECT));
     }
 
     #[rstest]
@@ -440,6 +1310,7 @@ mod tests {
         b"HTTP/1.1\r\nConnection: close\r\n\r\n", 
         Ok((HTTPVersion::HTTP1_1, b"Connection: close\r\n\r\n".as_slice())))
     ]
+    #[case(b"HTTP/1.0\r\n\r\n", Ok((HTTPVersion::HTTP1_0, b"\r\n".as_slice())))]
     #[case(b"HTTP/2\r\n\r\n", Ok((HTTPVersion::HTTP2, b"\r\n".as_slice())))]
     #[case(b"HTTP/3\r\n\r\n", Ok((HTTPVersion::HTTP3, b"\r\n".as_slice())))]
     #[case(b"HTTP/100\r\n\r\n", Err(HTTPParseError::InvalidVersion))]
@@ -450,39 +1321,46 @@ mod tests {
         #[case] input: &[u8],
         #[case] expected: Result<(HTTPVersion, &[u8]), HTTPParseError>,
     ) {
-        assert_eq!(expected, Request::parse_version(input));
+        assert_eq!(expected, RequestHead::parse_version(input));
     }
 
     #[rstest]
     #[case(
         b"Host: test\r\nConnection: keep-alive\r\nAccept: text/html\r\n\r\n",
-        Ok((HashMap::from([
-            ("host".to_string(), "test".to_string()),
-            ("connection".to_string(), "keep-alive".to_string()),
-            ("accept".to_string(), "text/html".to_string()),
-        ]),
+        Ok((Headers::try_from([
+            ("host", "test"),
+            ("connection", "keep-alive"),
+            ("accept", "text/html"),
+        ]).unwrap(),
         b"".as_slice()))
     )]
     #[case(
         b"Host: test:3000\r\nConnection: keep-alive\r\nAccept: text/html\r\nreferer:http://localhost:8080/proxy/\r\n\r\n",
-        Ok((HashMap::from([
-            ("host".to_string(), "test:3000".to_string()),
-            ("connection".to_string(), "keep-alive".to_string()),
-            ("accept".to_string(), "text/html".to_string()),
-            ("referer".to_string(), "http://localhost:8080/proxy/".to_string()),
-        ]),
+        Ok((Headers::try_from([
+            ("host", "test:3000"),
+            ("connection", "keep-alive"),
+            ("accept", "text/html"),
+            ("referer", "http://localhost:8080/proxy/"),
+        ]).unwrap(),
         b"".as_slice()))
     )]
     #[case(
         b"Host:test\r\nConnection:keep-alive\r\nAccept:text/html\r\n\r\n",
-        Ok((HashMap::from([
-            ("host".to_string(), "test".to_string()),
-            ("connection".to_string(), "keep-alive".to_string()),
-            ("accept".to_string(), "text/html".to_string()),
-        ]),
+        Ok((Headers::try_from([
+            ("host", "test"),
+            ("connection", "keep-alive"),
+            ("accept", "text/html"),
+        ]).unwrap(),
+        b"".as_slice()))
+    )]
+    #[case(
+        b"Accept: text/html\r\naccept: application/json\r\n\r\n",
+        Ok((Headers::try_from([
+            ("accept", "text/html, application/json"),
+        ]).unwrap(),
         b"".as_slice()))
     )]
-    #[case(b"\r\n", Ok((HashMap::from([]), b"".as_slice())))]
+    #[case(b"\r\n", Ok((Headers::new(), b"".as_slice())))]
     #[case(
         b"Host: test\r\nConnection: keep-alive\r\nAccept: text/html\r\n",
         Err(HTTPParseError::UnterminatedHeader)
@@ -490,6 +1368,10 @@ mod tests {
     #[case(b"Host: test", Err(HTTPParseError::UnterminatedHeader))]
     #[case(b"", Err(HTTPParseError::UnterminatedHeader))]
     #[case(b"Connection\r\n", Err(HTTPParseError::UnterminatedHeader))]
+    #[case(b"Bad Name: test\r\n\r\n", Err(HTTPParseError::InvalidHeader))]
+    // whitespace between the name and colon must be rejected (RFC 9112 section 5.1)
+    #[case(b"Host : test\r\n\r\n", Err(HTTPParseError::InvalidHeader))]
+    #[case(b"Host: te\x00st\r\n\r\n", Err(HTTPParseError::InvalidHeader))]
     fn test_parse_headers(
         #[case] input: &[u8],
         #[case] expected: Result<(Headers, &[u8]), HTTPParseError>,
@@ -504,8 +1386,9 @@ mod tests {
             Request {
                 path: "/".to_string(),
                 method: HTTPMethod::GET,
-                headers: Headers::from([("host".to_string(), "test".to_string())]),
-                version: HTTPVersion::HTTP1_1
+                headers: Headers::try_from([("host", "test")]).unwrap(),
+                version: HTTPVersion::HTTP1_1,
+                body: Body::Empty,
             },
             b"Hello World".as_slice()))
     )]
@@ -535,7 +1418,9 @@ mod tests {
             Response {
                 status: StatusCode::OK,
                 version: HTTPVersion::HTTP1_1,
-                headers: HashMap::from([("host".to_string(), "test".to_string())]) },
+                headers: Headers::try_from([("host", "test")]).unwrap(),
+                body: Body::UntilClose,
+            },
             b"Hello World".as_slice()))
     )]
     #[case(
@@ -544,10 +1429,642 @@ mod tests {
     )]
     #[case(b"HTTP/1.11 200 OK\r\n\r\n", Err(HTTPParseError::InvalidVersion))]
     #[case(b"HTTP/1.1 99 WHAT\r\n\r\n", Err(HTTPParseError::InvalidStatusCode))]
+    // the status line ends before its CRLF, found by test_near_valid_response
+    #[case(b"HTTP/1.0 103 ", Err(HTTPParseError::UnterminatedHeader))]
+    #[case(b"HTTP/1.1 200 OK", Err(HTTPParseError::UnterminatedHeader))]
     fn test_parse_response(
         #[case] input: &[u8],
         #[case] expected: Result<(Response, &[u8]), HTTPParseError>,
     ) {
         assert_eq!(expected, Response::parse(input));
     }
+
+    #[rstest]
+    #[case(b"GET / HTTP/1.1\r\n\r\n", Ok(Body::Empty))]
+    #[case(b"POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n", Ok(Body::Empty))]
+    #[case(b"POST / HTTP/1.1\r\nContent-Length: 42\r\n\r\n", Ok(Body::Sized(42)))]
+    #[case(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+        Ok(Body::Chunked)
+    )]
+    #[case(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, Chunked\r\n\r\n",
+        Ok(Body::Chunked)
+    )]
+    #[case(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+        Err(HTTPParseError::InvalidTransferEncoding)
+    )]
+    #[case(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+        Err(HTTPParseError::InvalidTransferEncoding)
+    )]
+    #[case(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, chunked\r\n\r\n",
+        Err(HTTPParseError::InvalidTransferEncoding)
+    )]
+    #[case(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: magic, chunked\r\n\r\n",
+        Err(HTTPParseError::InvalidTransferEncoding)
+    )]
+    #[case(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n",
+        Err(HTTPParseError::ConflictingFraming)
+    )]
+    #[case(
+        b"POST / HTTP/1.1\r\nContent-Length: lots\r\n\r\n",
+        Err(HTTPParseError::InvalidContentLength)
+    )]
+    #[case(
+        b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\n",
+        Ok(Body::Sized(5))
+    )]
+    #[case(
+        b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n",
+        Err(HTTPParseError::InvalidContentLength)
+    )]
+    fn test_request_body(#[case] input: &[u8], #[case] expected: Result<Body, HTTPParseError>) {
+        assert_eq!(
+            expected,
+            Request::parse(input).map(|(request, _)| request.body)
+        );
+    }
+
+    #[rstest]
+    #[case(b"HTTP/1.1 200 OK\r\n\r\n", Ok(Body::UntilClose))]
+    #[case(b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\n", Ok(Body::Sized(12)))]
+    #[case(
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n",
+        Ok(Body::Chunked)
+    )]
+    #[case(
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\r\n",
+        Ok(Body::UntilClose)
+    )]
+    #[case(
+        b"HTTP/1.1 204 No Content\r\nContent-Length: 12\r\n\r\n",
+        Ok(Body::Empty)
+    )]
+    #[case(
+        b"HTTP/1.1 304 Not Modified\r\nContent-Length: 12\r\n\r\n",
+        Ok(Body::Empty)
+    )]
+    #[case(b"HTTP/1.1 100 Continue\r\n\r\n", Ok(Body::Empty))]
+    #[case(
+        b"HTTP/1.1 200 OK\r\nContent-Length: -1\r\n\r\n",
+        Err(HTTPParseError::InvalidContentLength)
+    )]
+    fn test_response_body(#[case] input: &[u8], #[case] expected: Result<Body, HTTPParseError>) {
+        assert_eq!(
+            expected,
+            Response::parse(input).map(|(response, _)| response.body())
+        );
+    }
+
+    #[rstest]
+    #[case("0", Ok(0))]
+    #[case("42", Ok(42))]
+    #[case("007", Ok(7))]
+    #[case("5, 5", Ok(5))]
+    #[case("5,5 ,5", Ok(5))]
+    #[case("18446744073709551615", Ok(u64::MAX))]
+    #[case("", Err(HTTPParseError::InvalidContentLength))]
+    #[case("+5", Err(HTTPParseError::InvalidContentLength))]
+    #[case("-5", Err(HTTPParseError::InvalidContentLength))]
+    #[case("5a", Err(HTTPParseError::InvalidContentLength))]
+    #[case("0x10", Err(HTTPParseError::InvalidContentLength))]
+    #[case("1 2", Err(HTTPParseError::InvalidContentLength))]
+    #[case("5, 6", Err(HTTPParseError::InvalidContentLength))]
+    #[case("5,", Err(HTTPParseError::InvalidContentLength))]
+    #[case("18446744073709551616", Err(HTTPParseError::InvalidContentLength))]
+    fn test_parse_content_length(
+        #[case] input: &str,
+        #[case] expected: Result<u64, HTTPParseError>,
+    ) {
+        assert_eq!(expected, parse_content_length(input));
+    }
+
+    #[rstest]
+    #[case(b"GET / HTTP/1.1\r\n\r\n", 0, true)]
+    #[case(b"GET / HTTP/1.1\r\n\r\n", 16, true)]
+    #[case(b"GET / HTTP/1.1\r\n\r\n", 18, false)]
+    #[case(b"GET / HTTP/1.1\r\n\r\n", 100, false)]
+    #[case(b"GET / HTTP/1.1\r\n\r", 0, false)]
+    fn test_is_terminated_from(
+        #[case] input: &[u8],
+        #[case] scanned: usize,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(expected, is_terminated_from(input, scanned));
+    }
+
+    #[test]
+    fn test_parse_headers_iter() {
+        let mut iter = parse_headers_iter(b"Host: test\r\nX-Tag: a\r\nx-tag:b\r\n\r\nbody");
+        assert_eq!(None, iter.remaining());
+        assert_eq!(Some(Ok(("Host", b"test".as_slice()))), iter.next());
+        assert_eq!(Some(Ok(("X-Tag", b"a".as_slice()))), iter.next());
+        assert_eq!(Some(Ok(("x-tag", b"b".as_slice()))), iter.next());
+        assert_eq!(None, iter.next());
+        assert_eq!(Some(b"body".as_slice()), iter.remaining());
+    }
+
+    #[rstest]
+    #[case(b"Host: test\r\n")]
+    #[case(b"Host: test")]
+    #[case(b"Connection\r\n\r\n")]
+    fn test_parse_headers_iter_invalid(#[case] input: &[u8]) {
+        let mut iter = parse_headers_iter(input);
+        assert!(iter.by_ref().any(|header| header.is_err()));
+        assert_eq!(None, iter.next());
+        assert_eq!(None, iter.remaining());
+    }
+
+    #[test]
+    fn test_write_request() {
+        let request = Request {
+            path: "/api".to_string(),
+            method: HTTPMethod::POST,
+            headers: Headers::try_from([("host", "test")]).unwrap(),
+            version: HTTPVersion::HTTP1_1,
+            body: Body::Empty,
+        };
+        let expected = b"POST /api HTTP/1.1\r\nhost: test\r\n\r\n";
+
+        let mut written = Vec::new();
+        request.write_to(&mut written).unwrap();
+        assert_eq!(expected.as_slice(), written);
+
+        let mut buf = bytes::BytesMut::new();
+        request.write_to_buf(&mut buf);
+        assert_eq!(expected.as_slice(), buf);
+
+        let bytes = request.into_bytes();
+        assert_eq!(expected.as_slice(), bytes);
+        assert_eq!(bytes.len(), bytes.capacity());
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut response = Response::new(StatusCode::NOT_FOUND);
+        response.header(
+            HeaderName::from_static("connection"),
+            HeaderValue::from_static("close"),
+        );
+        let expected = b"HTTP/1.1 404 Not Found\r\nconnection: close\r\n\r\n";
+
+        let mut written = Vec::new();
+        response.write_to(&mut written).unwrap();
+        assert_eq!(expected.as_slice(), written);
+
+        let bytes = response.into_bytes();
+        assert_eq!(expected.as_slice(), bytes);
+        assert_eq!(bytes.len(), bytes.capacity());
+    }
+
+    #[rstest]
+    #[case(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n", Ok("example.com"))]
+    #[case(
+        b"GET / HTTP/1.1\r\nHost: example.com:8080\r\n\r\n",
+        Ok("example.com:8080")
+    )]
+    #[case(b"GET / HTTP/1.1\r\nHost: 127.0.0.1:80\r\n\r\n", Ok("127.0.0.1:80"))]
+    #[case(b"GET / HTTP/1.1\r\nHost: [::1]:3000\r\n\r\n", Ok("[::1]:3000"))]
+    #[case(
+        b"GET http://target.com/api HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        Ok("target.com")
+    )]
+    #[case(b"GET https://target.com?q HTTP/1.1\r\n\r\n", Ok("target.com"))]
+    #[case(b"GET / HTTP/1.1\r\n\r\n", Err(HTTPParseError::InvalidHost))]
+    #[case(b"GET / HTTP/1.1\r\nHost: \r\n\r\n", Err(HTTPParseError::InvalidHost))]
+    #[case(
+        b"GET / HTTP/1.1\r\nHost: a.com\r\nHost: b.com\r\n\r\n",
+        Err(HTTPParseError::InvalidHost)
+    )]
+    #[case(
+        b"GET / HTTP/1.1\r\nHost: a.com:port\r\n\r\n",
+        Err(HTTPParseError::InvalidHost)
+    )]
+    #[case(
+        b"GET / HTTP/1.1\r\nHost: a.com/path\r\n\r\n",
+        Err(HTTPParseError::InvalidHost)
+    )]
+    #[case(
+        b"GET / HTTP/1.1\r\nHost: [::1\r\n\r\n",
+        Err(HTTPParseError::InvalidHost)
+    )]
+    #[case(
+        b"GET / HTTP/1.1\r\nHost: user@a.com\r\n\r\n",
+        Err(HTTPParseError::InvalidHost)
+    )]
+    fn test_host(#[case] input: &[u8], #[case] expected: Result<&str, HTTPParseError>) {
+        let (request, _) = Request::parse(input).unwrap();
+        assert_eq!(expected, request.host());
+    }
+
+    #[rstest]
+    #[case("chunked", Ok(vec![Coding::Chunked]))]
+    #[case("gzip, chunked", Ok(vec![Coding::Gzip, Coding::Chunked]))]
+    #[case("X-GZIP ,  Chunked", Ok(vec![Coding::Gzip, Coding::Chunked]))]
+    #[case("gzip,,deflate", Ok(vec![Coding::Gzip, Coding::Deflate]))]
+    #[case("identity", Ok(vec![Coding::Identity]))]
+    #[case("br;q=1, chunked", Ok(vec![Coding::Unknown("br".to_string()), Coding::Chunked]))]
+    #[case("chunked, gzip", Err(HTTPParseError::InvalidTransferEncoding))]
+    #[case("chunked, chunked", Err(HTTPParseError::InvalidTransferEncoding))]
+    #[case(" , ", Err(HTTPParseError::InvalidTransferEncoding))]
+    fn test_parse_transfer_encoding(
+        #[case] input: &str,
+        #[case] expected: Result<Vec<Coding>, HTTPParseError>,
+    ) {
+        assert_eq!(expected, parse_transfer_encoding(input));
+    }
+
+    #[rstest]
+    #[case(b"GET / HTTP/1.1\r\n\r\n", true)]
+    #[case(b"GET / HTTP/1.1\r\nConnection: keep-alive\r\n\r\n", true)]
+    #[case(b"GET / HTTP/1.1\r\nConnection: Close\r\n\r\n", false)]
+    #[case(b"GET / HTTP/1.1\r\nConnection: upgrade, close\r\n\r\n", false)]
+    #[case(b"GET / HTTP/1.0\r\n\r\n", false)]
+    #[case(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n", true)]
+    #[case(b"GET / HTTP/1.0\r\nConnection: keep-alive, close\r\n\r\n", false)]
+    fn test_request_wants_keep_alive(#[case] input: &[u8], #[case] expected: bool) {
+        assert_eq!(
+            expected,
+            Request::parse(input).unwrap().0.wants_keep_alive()
+        );
+    }
+
+    #[rstest]
+    #[case(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n", true)]
+    #[case(b"HTTP/1.1 200 OK\r\n\r\n", false)]
+    #[case(b"HTTP/1.1 204 No Content\r\n\r\n", true)]
+    #[case(
+        b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+        false
+    )]
+    #[case(b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n", false)]
+    #[case(
+        b"HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nContent-Length: 0\r\n\r\n",
+        true
+    )]
+    fn test_response_wants_keep_alive(#[case] input: &[u8], #[case] expected: bool) {
+        assert_eq!(
+            expected,
+            Response::parse(input).unwrap().0.wants_keep_alive()
+        );
+    }
+
+    #[test]
+    fn test_response_set_body() {
+        // answering a HEAD request, the response ends with its head
+        let (mut response, _) = Response::parse(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        assert_eq!(Body::UntilClose, response.body());
+        response.set_body(Body::Empty);
+        assert_eq!(Body::Empty, response.body());
+        assert!(response.wants_keep_alive());
+    }
+
+    #[rstest]
+    #[case(b"POST / HTTP/1.1\r\nExpect: 100-continue\r\n\r\n", true)]
+    #[case(b"POST / HTTP/1.1\r\nExpect: 100-Continue\r\n\r\n", true)]
+    #[case(b"POST / HTTP/1.1\r\n\r\n", false)]
+    #[case(b"POST / HTTP/1.1\r\nExpect: something-else\r\n\r\n", false)]
+    #[case(b"POST / HTTP/1.0\r\nExpect: 100-continue\r\n\r\n", false)]
+    fn test_expects_continue(#[case] input: &[u8], #[case] expected: bool) {
+        assert_eq!(
+            expected,
+            Request::parse(input).unwrap().0.expects_continue()
+        );
+    }
+
+    #[rstest]
+    #[case(
+        b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n",
+        true
+    )]
+    #[case(
+        b"GET / HTTP/1.1\r\nUpgrade: h2c, WebSocket\r\nConnection: keep-alive, upgrade\r\n\r\n",
+        true
+    )]
+    #[case(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\n\r\n", false)]
+    #[case(
+        b"GET / HTTP/1.1\r\nUpgrade: h2c\r\nConnection: Upgrade\r\n\r\n",
+        false
+    )]
+    #[case(
+        b"GET / HTTP/1.0\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n",
+        false
+    )]
+    fn test_upgrades_to_websocket(#[case] input: &[u8], #[case] expected: bool) {
+        assert_eq!(
+            expected,
+            Request::parse(input).unwrap().0.upgrades_to_websocket()
+        );
+    }
+
+    #[rstest]
+    #[case(
+        b"GET / HTTP/1.1\r\nUpgrade: h2c\r\nConnection: Upgrade, HTTP2-Settings\r\nHTTP2-Settings: AAMAAABkAAQAoAAAAAIAAAAA\r\n\r\n",
+        true
+    )]
+    #[case(
+        b"GET / HTTP/1.1\r\nUpgrade: h2c\r\nConnection: Upgrade\r\nHTTP2-Settings: AAMAAABkAAQAoAAAAAIAAAAA\r\n\r\n",
+        false
+    )]
+    #[case(
+        b"GET / HTTP/1.1\r\nUpgrade: h2c\r\nConnection: Upgrade, HTTP2-Settings\r\n\r\n",
+        false
+    )]
+    fn test_upgrades_to_h2c(#[case] input: &[u8], #[case] expected: bool) {
+        assert_eq!(expected, Request::parse(input).unwrap().0.upgrades_to_h2c());
+    }
+
+    #[test]
+    fn test_continue_response() {
+        assert_eq!(
+            b"HTTP/1.1 100 Continue\r\n\r\n".as_slice(),
+            Response::continue_().into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_request_authorization() {
+        let (request, _) =
+            Request::parse(b"GET / HTTP/1.1\r\nAuthorization: Basic dXNlcjpwYXNz\r\n\r\n").unwrap();
+        assert_eq!(
+            Ok(Some(Authorization::basic("user", "pass"))),
+            request.authorization()
+        );
+
+        let (request, _) = Request::parse(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(Ok(None), request.authorization());
+    }
+
+    #[rstest]
+    #[case(StatusCode::CONTINUE, true)]
+    #[case(StatusCode::from_u16(103).unwrap(), true)]
+    #[case(StatusCode::SWITCHING_PROTOCOLS, false)]
+    #[case(StatusCode::OK, false)]
+    fn test_is_interim(#[case] status: StatusCode, #[case] expected: bool) {
+        assert_eq!(expected, Response::new(status).is_interim());
+    }
+
+    #[test]
+    fn test_parse_final() {
+        let input = b"HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n\
+            HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+
+        let (interim, response, remaining) = Response::parse_final(input).unwrap();
+        assert_eq!(
+            vec![StatusCode::CONTINUE, StatusCode::from_u16(103).unwrap()],
+            interim.iter().map(|r| r.status).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(&HeaderValue::from_static("</style.css>; rel=preload")),
+            interim[1].get_header("link")
+        );
+        assert_eq!(StatusCode::OK, response.status);
+        assert_eq!(Body::Sized(2), response.body());
+        assert_eq!(b"ok", remaining);
+
+        // the final response hasn't arrived yet
+        assert_eq!(
+            Err(HTTPParseError::UnterminatedHeader),
+            Response::parse_final(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Len")
+        );
+    }
+
+    #[test]
+    fn test_into_bytes_keeps_header_order() {
+        let input =
+            b"GET / HTTP/1.1\r\nhost: example.com\r\nx-b: 2\r\naccept: */*\r\nx-a: 1\r\n\r\n";
+        let (mut request, _) = Request::parse(input).unwrap();
+        assert_eq!(input.as_slice(), request.into_bytes());
+
+        request.headers.sort();
+        assert_eq!(
+            b"GET / HTTP/1.1\r\naccept: */*\r\nhost: example.com\r\nx-a: 1\r\nx-b: 2\r\n\r\n"
+                .as_slice(),
+            request.into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_into_bytes_keeps_set_cookie_apart() {
+        let input =
+            b"HTTP/1.1 200 OK\r\nset-cookie: a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT\r\n\
+                      vary: accept\r\nset-cookie: b=2\r\nvary: cookie\r\ncontent-length: 0\r\n\r\n";
+        let (response, _) = Response::parse(input).unwrap();
+        assert_eq!(
+            b"HTTP/1.1 200 OK\r\nset-cookie: a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT\r\n\
+              vary: accept, cookie\r\nset-cookie: b=2\r\ncontent-length: 0\r\n\r\n"
+                .as_slice(),
+            response.into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_opaque_header_value() {
+        // "café" in latin-1, which isn't valid UTF-8
+        let input = b"HTTP/1.1 200 OK\r\nx-name: caf\xe9\r\ncontent-length: 0\r\n\r\n";
+        let (response, _) = Response::parse(input).unwrap();
+
+        let value = response.get_header("x-name").unwrap();
+        assert_eq!(b"caf\xe9", value.as_bytes());
+        assert_eq!(Err(HTTPParseError::InvalidHeader), value.to_str());
+        assert_eq!("caf\u{fffd}", value.to_str_lossy());
+
+        // the original bytes are passed through untouched
+        assert_eq!(input.as_slice(), response.into_bytes());
+    }
+
+    #[rstest]
+    #[case(
+        b"GET / HTTP/1.1\r\ntransfer-encoding: chunked\xff\r\n\r\n",
+        HTTPParseError::InvalidTransferEncoding
+    )]
+    #[case(
+        b"GET / HTTP/1.1\r\ncontent-length: 1\xff\r\n\r\n",
+        HTTPParseError::InvalidContentLength
+    )]
+    fn test_opaque_framing_headers(#[case] input: &[u8], #[case] expected: HTTPParseError) {
+        assert_eq!(Err(expected), Request::parse(input).map(|_| ()));
+    }
+
+    #[rstest]
+    #[case(b"GET /items?page=3&limit=10 HTTP/1.1\r\n\r\n", "page", Ok(Some(3)))]
+    #[case(b"GET /items?page=3&page=4 HTTP/1.1\r\n\r\n", "page", Ok(Some(3)))]
+    #[case(b"GET /items?limit=10 HTTP/1.1\r\n\r\n", "page", Ok(None))]
+    #[case(b"GET /items HTTP/1.1\r\n\r\n", "page", Ok(None))]
+    #[case(
+        b"GET /items?page=three HTTP/1.1\r\n\r\n",
+        "page",
+        Err(HTTPParseError::InvalidQuery)
+    )]
+    fn test_query_param(
+        #[case] input: &[u8],
+        #[case] name: &str,
+        #[case] expected: Result<Option<u32>, HTTPParseError>,
+    ) {
+        let (request, _) = Request::parse(input).unwrap();
+        assert_eq!(expected, request.query_param::<u32>(name));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_query_as() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Search {
+            q: String,
+            page: Option<u32>,
+        }
+
+        let (request, _) =
+            Request::parse(b"GET /search?q=rust+lang&page=2 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(
+            Ok(Search {
+                q: "rust lang".to_string(),
+                page: Some(2)
+            }),
+            request.query_as::<Search>()
+        );
+
+        let (request, _) = Request::parse(b"GET /search HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(
+            Err(HTTPParseError::InvalidQuery),
+            request.query_as::<Search>()
+        );
+    }
+
+    #[test]
+    fn test_content_negotiation() {
+        let (request, _) = Request::parse(
+            b"GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0.8, br\r\nContent-Encoding: gzip\r\n\r\n",
+        )
+        .unwrap();
+        let accept = request.accept_encoding().unwrap().unwrap();
+        assert_eq!(
+            Some(&ContentCoding::Br),
+            accept.preferred(&[ContentCoding::Gzip, ContentCoding::Br])
+        );
+        assert_eq!(
+            Ok(Some(vec![ContentCoding::Gzip])),
+            request.content_encoding()
+        );
+
+        let (request, _) = Request::parse(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(Ok(None), request.accept_encoding());
+
+        let mut response = Response::new(StatusCode::OK);
+        response.header(
+            HeaderName::from_static("content-encoding"),
+            HeaderValue::from_static("zstd"),
+        );
+        assert_eq!(
+            Ok(Some(vec![ContentCoding::Zstd])),
+            response.content_encoding()
+        );
+    }
+
+    #[test]
+    fn test_response_links() {
+        let mut response = Response::new(StatusCode::from_u16(103).unwrap());
+        assert_eq!(Ok(vec![]), response.links());
+
+        let link = Link::new("/style.css").rel("preload").param("as", "style");
+        response.header(
+            HeaderName::from_static("link"),
+            HeaderValue::try_from(link.to_string()).unwrap(),
+        );
+        assert_eq!(Ok(vec![link]), response.links());
+    }
+
+    #[test]
+    fn test_response_content_disposition() {
+        let mut response = Response::new(StatusCode::OK);
+        assert_eq!(Ok(None), response.content_disposition());
+
+        let disposition = ContentDisposition::attachment().filename("données.csv");
+        response.header(
+            HeaderName::from_static("content-disposition"),
+            HeaderValue::try_from(disposition.to_string()).unwrap(),
+        );
+        assert_eq!(Ok(Some(disposition)), response.content_disposition());
+    }
+
+    #[test]
+    fn test_append_via() {
+        let (mut request, _) =
+            Request::parse(b"GET / HTTP/1.0\r\nVia: 1.1 cdn (edge)\r\n\r\n").unwrap();
+        request.append_via("agora");
+        assert_eq!(
+            Ok(vec![
+                Via::new(HTTPVersion::HTTP1_1, "cdn").comment("edge"),
+                Via::new(HTTPVersion::HTTP1_0, "agora"),
+            ]),
+            request.via()
+        );
+
+        let mut response = Response::new(StatusCode::OK);
+        assert_eq!(Ok(vec![]), response.via());
+        response.append_via("agora");
+        assert_eq!(
+            Some(&HeaderValue::from_static("1.1 agora")),
+            response.get_header("via")
+        );
+    }
+
+    #[rstest]
+    #[case(301, true)]
+    #[case(302, true)]
+    #[case(303, true)]
+    #[case(307, true)]
+    #[case(308, true)]
+    #[case(300, false)]
+    #[case(304, false)]
+    #[case(200, false)]
+    fn test_is_redirect(#[case] status: u16, #[case] expected: bool) {
+        let response = Response::new(StatusCode::from_u16(status).unwrap());
+        assert_eq!(expected, response.is_redirect());
+    }
+
+    #[rstest]
+    #[case(
+        "GET /a/b HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        "../c?d",
+        "//example.com/c?d"
+    )]
+    #[case(
+        "GET /a/b HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        "https://other.com/",
+        "https://other.com/"
+    )]
+    #[case(
+        "GET http://example.com/a/b HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        "c",
+        "http://example.com/a/c"
+    )]
+    fn test_resolve_location(
+        #[case] request: &str,
+        #[case] location: &str,
+        #[case] expected: &str,
+    ) {
+        let (request, _) = Request::parse(request.as_bytes()).unwrap();
+        let mut response = Response::new(StatusCode::FOUND);
+        assert_eq!(Ok(None), response.resolve_location(&request));
+
+        response.header(
+            HeaderName::from_static("location"),
+            HeaderValue::try_from(location).unwrap(),
+        );
+        assert_eq!(Ok(Some(Uri::parse(location).unwrap())), response.location());
+        assert_eq!(
+            Some(expected.to_string()),
+            response
+                .resolve_location(&request)
+                .unwrap()
+                .map(|uri| uri.to_string())
+        );
+    }
 }
@@ -0,0 +1,230 @@
+//! Typed links from the `Link` header (RFC 8288).
+
+use std::fmt::Display;
+
+use crate::{
+    HTTPParseError,
+    headers::{is_token, parse_quoted_string, quote},
+};
+
+/// A single link of a `Link` header, like `<https://example.com/?page=2>; rel="next"`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Link {
+    /// The URI reference between the angle brackets, not resolved against the request URI
+    pub target: String,
+    /// The relation types, from the space separated `rel` parameter
+    pub rel: Vec<String>,
+    /// Every other target attribute. Names are lowercased, values are unquoted and a
+    /// parameter without a value has an empty one.
+    pub params: Vec<(String, String)>,
+}
+
+impl Link {
+    pub fn new(target: &str) -> Self {
+        Self {
+            target: target.to_string(),
+            rel: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Add a relation type
+    pub fn rel(mut self, rel: &str) -> Self {
+        self.rel.push(rel.to_string());
+        self
+    }
+
+    /// Add a target attribute like `as` or `type`
+    pub fn param(mut self, name: &str, value: &str) -> Self {
+        self.params
+            .push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    /// Whether the link has this relation type, compared case-insensitively
+    pub fn has_rel(&self, rel: &str) -> bool {
+        self.rel.iter().any(|r| r.eq_ignore_ascii_case(rel))
+    }
+
+    /// Look up a target attribute by its case-insensitive name
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parse the value of a `Link` header into its links
+    pub fn parse_all(mut input: &str) -> Result<Vec<Self>, HTTPParseError> {
+        let mut links = Vec::new();
+        loop {
+            // empty list elements are allowed and ignored (RFC 9110 section 5.6.1)
+            input = input.trim_start_matches([' ', '\t', ',']);
+            if input.is_empty() {
+                return Ok(links);
+            }
+
+            let (target, rest) = input
+                .strip_prefix('<')
+                .and_then(|rest| rest.split_once('>'))
+                .ok_or(HTTPParseError::InvalidLink)?;
+            if target.contains([' ', '\t', '<']) {
+                return Err(HTTPParseError::InvalidLink);
+            }
+
+            let (link, rest) = Self::parse_params(Self::new(target), rest)?;
+            if !rest.is_empty() && !rest.starts_with(',') {
+                return Err(HTTPParseError::InvalidLink);
+            }
+            links.push(link);
+            input = rest;
+        }
+    }
+
+    /// Parse `*( OWS ";" OWS link-param )` following the target
+    fn parse_params(mut link: Self, mut input: &str) -> Result<(Self, &str), HTTPParseError> {
+        let mut seen_rel = false;
+        loop {
+            input = input.trim_start_matches([' ', '\t']);
+            let Some(rest) = input.strip_prefix(';') else {
+                return Ok((link, input));
+            };
+
+            let rest = rest.trim_start_matches([' ', '\t']);
+            let end = rest.find(['=', ';', ',', ' ', '\t']).unwrap_or(rest.len());
+            let name = &rest[..end];
+            if !is_token(name) {
+                return Err(HTTPParseError::InvalidLink);
+            }
+
+            let rest = rest[end..].trim_start_matches([' ', '\t']);
+            let (value, rest) = match rest.strip_prefix('=') {
+                Some(rest) => {
+                    let rest = rest.trim_start_matches([' ', '\t']);
+                    if rest.starts_with('"') {
+                        parse_quoted_string(rest).ok_or(HTTPParseError::InvalidLink)?
+                    } else {
+                        let end = rest.find([';', ',', ' ', '\t']).unwrap_or(rest.len());
+                        if !is_token(&rest[..end]) {
+                            return Err(HTTPParseError::InvalidLink);
+                        }
+                        (rest[..end].to_string(), &rest[end..])
+                    }
+                }
+                None => (String::new(), rest),
+            };
+
+            if name.eq_ignore_ascii_case("rel") {
+                // occurrences after the first must be ignored (RFC 8288 section 3.3)
+                if !seen_rel {
+                    link.rel = value.split_ascii_whitespace().map(String::from).collect();
+                    seen_rel = true;
+                }
+            } else {
+                link = link.param(name, &value);
+            }
+            input = rest;
+        }
+    }
+}
+
+/// Serializes to a header value
+impl Display for Link {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{}>", self.target)?;
+        if !self.rel.is_empty() {
+            let rel = self.rel.join(" ");
+            if is_token(&rel) {
+                write!(f, "; rel={rel}")?;
+            } else {
+                write!(f, "; rel={}", quote(&rel))?;
+            }
+        }
+        for (name, value) in &self.params {
+            if value.is_empty() {
+                write!(f, "; {name}")?;
+            } else if is_token(value) {
+                write!(f, "; {name}={value}")?;
+            } else {
+                write!(f, "; {name}={}", quote(value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_pagination() {
+        let links = Link::parse_all(
+            r#"<https://api.example.com/items?page=2>; rel="next", <https://api.example.com/items?page=9>; rel=last"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Link::new("https://api.example.com/items?page=2").rel("next"),
+                Link::new("https://api.example.com/items?page=9").rel("last"),
+            ],
+            links
+        );
+        assert!(links[1].has_rel("LAST"));
+    }
+
+    #[rstest]
+    #[case(
+        "</style.css>; rel=preload; as=style",
+        Link::new("/style.css").rel("preload").param("as", "style")
+    )]
+    #[case(
+        "</font.woff2> ; rel=\"preload\" ; as=font ; crossorigin",
+        Link::new("/font.woff2").rel("preload").param("as", "font").param("crossorigin", "")
+    )]
+    #[case(
+        r#"<http://example.com/>; rel="start http://example.net/relation/other""#,
+        Link::new("http://example.com/").rel("start").rel("http://example.net/relation/other")
+    )]
+    #[case(
+        r#"</a,b>; Title="x, \"y\""; rel=first; rel=ignored"#,
+        Link::new("/a,b").rel("first").param("title", "x, \"y\"")
+    )]
+    #[case("<>", Link::new(""))]
+    fn test_parse(#[case] input: &str, #[case] expected: Link) {
+        assert_eq!(Ok(vec![expected]), Link::parse_all(input));
+    }
+
+    #[rstest]
+    #[case("/no-brackets; rel=next")]
+    #[case("</unterminated; rel=next")]
+    #[case("</a b>")]
+    #[case("</a>; rel=\"next")]
+    #[case("</a>; =next")]
+    #[case("</a>; rel=a b")]
+    #[case("</a> </b>")]
+    fn test_parse_invalid(#[case] input: &str) {
+        assert_eq!(Err(HTTPParseError::InvalidLink), Link::parse_all(input));
+    }
+
+    #[rstest]
+    #[case(Link::new("/a").rel("next"), "</a>; rel=next")]
+    #[case(Link::new("/a").rel("preload").rel("prefetch"), r#"</a>; rel="preload prefetch""#)]
+    #[case(
+        Link::new("/a").param("title", "say \"hi\"").param("crossorigin", ""),
+        r#"</a>; title="say \"hi\""; crossorigin"#
+    )]
+    fn test_display(#[case] link: Link, #[case] expected: &str) {
+        assert_eq!(expected, link.to_string());
+        assert_eq!(Ok(vec![link]), Link::parse_all(expected));
+    }
+
+    #[test]
+    fn test_get_param() {
+        let link = Link::new("/a").param("Type", "text/css");
+        assert_eq!(Some("text/css"), link.get_param("type"));
+        assert_eq!(None, link.get_param("as"));
+    }
+}
@@ -0,0 +1,127 @@
+//! Parsing several pipelined requests out of a single buffer.
+
+use crate::{Body, HTTPParseError, Request, chunked::chunked_body_len, is_terminated};
+
+/// Iterator over the requests at the start of a buffer, created by [`Request::parse_many`].
+///
+/// Each item is a request along with the raw bytes of its body, still in their transfer
+/// coding. Iteration stops at the first request that isn't completely in the buffer, which
+/// can then be found with [`MessageIter::remaining`] once more data has been read.
+/// A malformed request is yielded as an error and ends the iteration since there is no way
+/// to tell where the next request would start.
+pub struct MessageIter<'a> {
+    buf: &'a [u8],
+    finished: bool,
+}
+
+impl<'a> MessageIter<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            finished: false,
+        }
+    }
+
+    /// The bytes that haven't been parsed into a complete request yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    fn body_len(body: Body, buf: &[u8]) -> Result<Option<usize>, HTTPParseError> {
+        match body {
+            Body::Empty => Ok(Some(0)),
+            Body::Sized(length) => Ok(usize::try_from(length)
+                .ok()
+                .filter(|&length| length <= buf.len())),
+            Body::Chunked => chunked_body_len(buf),
+            // requests are never delimited by the connection closing
+            Body::UntilClose => Err(HTTPParseError::InvalidTransferEncoding),
+        }
+    }
+}
+
+impl<'a> Iterator for MessageIter<'a> {
+    type Item = Result<(Request, &'a [u8]), HTTPParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished || !is_terminated(self.buf) {
+            return None;
+        }
+
+        let result = Request::parse(self.buf).and_then(|(request, rest)| {
+            Ok(Self::body_len(request.body, rest)?.map(|len| (request, rest, len)))
+        });
+
+        match result {
+            Ok(Some((request, rest, len))) => {
+                self.buf = &rest[len..];
+                Some(Ok((request, &rest[..len])))
+            }
+            // the body hasn't been fully received yet
+            Ok(None) => None,
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HTTPMethod;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_many() {
+        let buf = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\n\
+            POST /b HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+            POST /c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n\
+            GET /d HTTP/1.1\r\nHost:";
+
+        let mut iter = Request::parse_many(buf);
+        let messages = iter
+            .by_ref()
+            .map(|message| {
+                let (request, body) = message.unwrap();
+                (request.method, request.path, body)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                (HTTPMethod::GET, "/a".to_string(), b"".as_slice()),
+                (HTTPMethod::POST, "/b".to_string(), b"hello".as_slice()),
+                (
+                    HTTPMethod::POST,
+                    "/c".to_string(),
+                    b"2\r\nhi\r\n0\r\n\r\n".as_slice()
+                ),
+            ],
+            messages
+        );
+        assert_eq!(b"GET /d HTTP/1.1\r\nHost:".as_slice(), iter.remaining());
+    }
+
+    #[test]
+    fn test_parse_many_incomplete_body() {
+        let buf = b"GET / HTTP/1.1\r\n\r\nPOST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort";
+        let mut iter = Request::parse_many(buf);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().is_none());
+        assert_eq!(
+            b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort".as_slice(),
+            iter.remaining()
+        );
+    }
+
+    #[test]
+    fn test_parse_many_stops_at_error() {
+        let buf = b"GET / HTTP/1.1\r\n\r\nBREW / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let mut iter = Request::parse_many(buf);
+        assert!(iter.next().unwrap().is_ok());
+        assert_eq!(Some(Err(HTTPParseError::InvalidMethod)), iter.next());
+        assert_eq!(None, iter.next());
+    }
+}
@@ -0,0 +1,446 @@
+//! QPACK field compression for HTTP/3 (RFC 9204), limited to the static table.
+//!
+//! Without a dynamic table there is no encoder or decoder stream and no state to keep, so
+//! encoding and decoding are plain functions. Field sections that reference the dynamic table
+//! are rejected, which peers won't send as long as we advertise a
+//! SETTINGS_QPACK_MAX_TABLE_CAPACITY of 0 (the default).
+
+use std::fmt::Display;
+
+use crate::{
+    HeaderName, HeaderValue, Headers,
+    hpack::{
+        HpackError, SENSITIVE_HEADERS, decode_integer, decode_string, encode_integer, encode_string,
+    },
+};
+
+/// The static table from RFC 9204 Appendix A. Unlike HPACK, indices start at 0.
+const STATIC_TABLE: [(&str, &str); 99] = [
+    (":authority", ""),
+    (":path", "/"),
+    ("age", "0"),
+    ("content-disposition", ""),
+    ("content-length", "0"),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("referer", ""),
+    ("set-cookie", ""),
+    (":method", "CONNECT"),
+    (":method", "DELETE"),
+    (":method", "GET"),
+    (":method", "HEAD"),
+    (":method", "OPTIONS"),
+    (":method", "POST"),
+    (":method", "PUT"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "103"),
+    (":status", "200"),
+    (":status", "304"),
+    (":status", "404"),
+    (":status", "503"),
+    ("accept", "*/*"),
+    ("accept", "application/dns-message"),
+    ("accept-encoding", "gzip, deflate, br"),
+    ("accept-ranges", "bytes"),
+    ("access-control-allow-headers", "cache-control"),
+    ("access-control-allow-headers", "content-type"),
+    ("access-control-allow-origin", "*"),
+    ("cache-control", "max-age=0"),
+    ("cache-control", "max-age=2592000"),
+    ("cache-control", "max-age=604800"),
+    ("cache-control", "no-cache"),
+    ("cache-control", "no-store"),
+    ("cache-control", "public, max-age=31536000"),
+    ("content-encoding", "br"),
+    ("content-encoding", "gzip"),
+    ("content-type", "application/dns-message"),
+    ("content-type", "application/javascript"),
+    ("content-type", "application/json"),
+    ("content-type", "application/x-www-form-urlencoded"),
+    ("content-type", "image/gif"),
+    ("content-type", "image/jpeg"),
+    ("content-type", "image/png"),
+    ("content-type", "text/css"),
+    ("content-type", "text/html; charset=utf-8"),
+    ("content-type", "text/plain"),
+    ("content-type", "text/plain;charset=utf-8"),
+    ("range", "bytes=0-"),
+    ("strict-transport-security", "max-age=31536000"),
+    (
+        "strict-transport-security",
+        "max-age=31536000; includesubdomains",
+    ),
+    (
+        "strict-transport-security",
+        "max-age=31536000; includesubdomains; preload",
+    ),
+    ("vary", "accept-encoding"),
+    ("vary", "origin"),
+    ("x-content-type-options", "nosniff"),
+    ("x-xss-protection", "1; mode=block"),
+    (":status", "100"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "302"),
+    (":status", "400"),
+    (":status", "403"),
+    (":status", "421"),
+    (":status", "425"),
+    (":status", "500"),
+    ("accept-language", ""),
+    ("access-control-allow-credentials", "FALSE"),
+    ("access-control-allow-credentials", "TRUE"),
+    ("access-control-allow-headers", "*"),
+    ("access-control-allow-methods", "get"),
+    ("access-control-allow-methods", "get, post, options"),
+    ("access-control-allow-methods", "options"),
+    ("access-control-expose-headers", "content-length"),
+    ("access-control-request-headers", "content-type"),
+    ("access-control-request-method", "get"),
+    ("access-control-request-method", "post"),
+    ("alt-svc", "clear"),
+    ("authorization", ""),
+    (
+        "content-security-policy",
+        "script-src 'none'; object-src 'none'; base-uri 'none'",
+    ),
+    ("early-data", "1"),
+    ("expect-ct", ""),
+    ("forwarded", ""),
+    ("if-range", ""),
+    ("origin", ""),
+    ("purpose", "prefetch"),
+    ("server", ""),
+    ("timing-allow-origin", "*"),
+    ("upgrade-insecure-requests", "1"),
+    ("user-agent", ""),
+    ("x-forwarded-for", ""),
+    ("x-frame-options", "deny"),
+    ("x-frame-options", "sameorigin"),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum QpackError {
+    /// The field section ended in the middle of a representation
+    Incomplete,
+    /// An index refers to an entry outside of the static table
+    InvalidIndex,
+    /// An integer does not fit into a usize
+    IntegerOverflow,
+    /// A huffman encoded string literal is malformed
+    InvalidHuffman,
+    /// A field name or value is not valid UTF-8
+    InvalidUtf8,
+    /// The field section references the dynamic table, which we don't support
+    DynamicTableReference,
+    /// A decoded field name or value contains characters that aren't allowed
+    InvalidField,
+    /// The decoded fields are larger than the decoder takes
+    FieldSectionTooLarge,
+}
+
+impl Display for QpackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                QpackError::Incomplete => "Field section is not complete",
+                QpackError::InvalidIndex => "Invalid static table index",
+                QpackError::IntegerOverflow => "Integer overflow in field section",
+                QpackError::InvalidHuffman => "Invalid huffman encoded string",
+                QpackError::InvalidUtf8 => "Field is not valid UTF-8",
+                QpackError::DynamicTableReference => "Field section references the dynamic table",
+                QpackError::InvalidField => "Invalid field",
+                QpackError::FieldSectionTooLarge => "Field section is too large",
+            }
+        )
+    }
+}
+
+/// The primitives are shared with HPACK, so their errors translate directly
+impl From<HpackError> for QpackError {
+    fn from(e: HpackError) -> Self {
+        match e {
+            HpackError::Incomplete => QpackError::Incomplete,
+            HpackError::InvalidIndex => QpackError::InvalidIndex,
+            HpackError::IntegerOverflow => QpackError::IntegerOverflow,
+            HpackError::InvalidHuffman => QpackError::InvalidHuffman,
+            HpackError::InvalidUtf8 => QpackError::InvalidUtf8,
+            HpackError::InvalidTableSizeUpdate => QpackError::DynamicTableReference,
+            HpackError::InvalidField => QpackError::InvalidField,
+            HpackError::HeaderListTooLarge => QpackError::FieldSectionTooLarge,
+        }
+    }
+}
+
+fn static_entry(index: usize) -> Result<(&'static str, &'static str), QpackError> {
+    STATIC_TABLE
+        .get(index)
+        .copied()
+        .ok_or(QpackError::InvalidIndex)
+}
+
+/// Find a field in the static table, returning its index and whether the value matched too
+fn find_static(name: &str, value: &str) -> Option<(usize, bool)> {
+    let mut name_match = None;
+    for (index, (entry_name, entry_value)) in STATIC_TABLE.iter().enumerate() {
+        if *entry_name == name {
+            if *entry_value == value {
+                return Some((index, true));
+            }
+            name_match.get_or_insert((index, false));
+        }
+    }
+    name_match
+}
+
+/// Decode a complete field section into the list of fields in the order they were encoded.
+/// Pseudo-header fields (`:method`, `:path`, ...) are included.
+pub fn decode(block: &[u8]) -> Result<Vec<(String, String)>, QpackError> {
+    // the prefix holds the Required Insert Count followed by the sign bit and Delta Base,
+    // the base is meaningless when nothing refers to the dynamic table
+    let (required_insert_count, block) = decode_integer(block, 8)?;
+    if required_insert_count != 0 {
+        return Err(QpackError::DynamicTableReference);
+    }
+    let (_, mut block) = decode_integer(block, 7)?;
+
+    let mut fields = Vec::new();
+    while let Some(&first) = block.first() {
+        if first & 0x80 != 0 {
+            // Indexed field line, T is set for the static table
+            if first & 0x40 == 0 {
+                return Err(QpackError::DynamicTableReference);
+            }
+            let (index, rest) = decode_integer(block, 6)?;
+            let (name, value) = static_entry(index)?;
+            fields.push((name.to_string(), value.to_string()));
+            block = rest;
+        } else if first & 0x40 != 0 {
+            // Literal field line with name reference
+            if first & 0x10 == 0 {
+                return Err(QpackError::DynamicTableReference);
+            }
+            let (index, rest) = decode_integer(block, 4)?;
+            let (name, _) = static_entry(index)?;
+            let (value, rest) = decode_string(rest, 7)?;
+            fields.push((name.to_string(), value));
+            block = rest;
+        } else if first & 0x20 != 0 {
+            // Literal field line with literal name
+            let (name, rest) = decode_string(block, 3)?;
+            let (value, rest) = decode_string(rest, 7)?;
+            fields.push((name, value));
+            block = rest;
+        } else {
+            // Indexed field line with post-base index (0001) or literal field line with
+            // post-base name reference (0000)
+            return Err(QpackError::DynamicTableReference);
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Decode a complete field section into [`Headers`].
+/// Repeated fields are combined, with cookies joined by `; ` as required by RFC 9114,
+/// but Set-Cookie fields are kept apart.
+pub fn decode_headers(block: &[u8]) -> Result<Headers, QpackError> {
+    let mut headers = Headers::new();
+    for (name, value) in decode(block)? {
+        let separator = if name == "cookie" { "; " } else { ", " };
+        let name = HeaderName::try_from(name).map_err(|_| QpackError::InvalidField)?;
+        let value = HeaderValue::try_from(value).map_err(|_| QpackError::InvalidField)?;
+        headers.append_with_separator(name, value, separator);
+    }
+
+    Ok(headers)
+}
+
+/// Encode the fields into a field section and append it to the buffer.
+/// Names are expected to already be lowercase, and pseudo-headers must come first.
+pub fn encode<'h>(fields: impl IntoIterator<Item = (&'h str, &'h [u8])>, buf: &mut Vec<u8>) {
+    // Required Insert Count and Delta Base are both 0 without a dynamic table
+    buf.extend_from_slice(&[0, 0]);
+    for (name, value) in fields {
+        encode_field(name, value, buf);
+    }
+}
+
+/// Encode [`Headers`], emitting pseudo-headers before regular fields
+pub fn encode_headers(headers: &Headers, buf: &mut Vec<u8>) {
+    let pseudo = headers.iter().filter(|(name, _)| name.is_pseudo());
+    let regular = headers.iter().filter(|(name, _)| !name.is_pseudo());
+    encode(
+        pseudo
+            .chain(regular)
+            .map(|(name, value)| (name.as_str(), value.as_bytes())),
+        buf,
+    );
+}
+
+fn encode_field(name: &str, value: &[u8], buf: &mut Vec<u8>) {
+    // the N bit asks intermediaries to never add sensitive fields to a dynamic table
+    let sensitive = SENSITIVE_HEADERS.contains(&name);
+    let found = str::from_utf8(value)
+        .ok()
+        .and_then(|value| find_static(name, value));
+
+    match found {
+        Some((index, true)) => encode_integer(index, 6, 0xc0, buf),
+        Some((index, false)) => {
+            let flags = if sensitive { 0x70 } else { 0x50 };
+            encode_integer(index, 4, flags, buf);
+            encode_string(value, 7, 0, buf);
+        }
+        None => {
+            let flags = if sensitive { 0x30 } else { 0x20 };
+            encode_string(name.as_bytes(), 3, flags, buf);
+            encode_string(value, 7, 0, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_rfc_example() {
+        // RFC 9204 Appendix B.1, a literal field line with a static name reference
+        let block = b"\x00\x00\x51\x0b/index.html";
+        assert_eq!(Ok(fields(&[(":path", "/index.html")])), decode(block));
+    }
+
+    #[test]
+    fn test_decode_representations() {
+        let block = [
+            b"\x00\x00".as_slice(),
+            // indexed field line, static index 17 (:method: GET)
+            b"\xd1",
+            // indexed field line, static index 23 (:scheme: https)
+            b"\xd7",
+            // literal with static name reference 0 (:authority), never indexed
+            b"\x70\x0bexample.com",
+            // literal with literal name, huffman encoded value
+            b"\x27\x03custom-key\x89\x25\xa8\x49\xe9\x5b\xb8\xe8\xb4\xbf",
+        ]
+        .concat();
+
+        assert_eq!(
+            Ok(fields(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":authority", "example.com"),
+                ("custom-key", "custom-value"),
+            ])),
+            decode(&block)
+        );
+    }
+
+    #[rstest]
+    // Required Insert Count isn't 0
+    #[case(b"\x02\x00\xd1", QpackError::DynamicTableReference)]
+    // indexed field line referencing the dynamic table
+    #[case(b"\x00\x00\x81", QpackError::DynamicTableReference)]
+    // literal with a dynamic name reference
+    #[case(b"\x00\x00\x41\x01a", QpackError::DynamicTableReference)]
+    // post-base index
+    #[case(b"\x00\x00\x10", QpackError::DynamicTableReference)]
+    #[case(b"\x00\x00\xff\x24", QpackError::InvalidIndex)]
+    #[case(b"\x00\x00\x51\x0b/index", QpackError::Incomplete)]
+    #[case(b"\x00", QpackError::Incomplete)]
+    #[case(b"\x00\x00\x51\x01\xff", QpackError::InvalidUtf8)]
+    fn test_decode_invalid(#[case] block: &[u8], #[case] expected: QpackError) {
+        assert_eq!(Err(expected), decode(block));
+    }
+
+    #[test]
+    fn test_encode_uses_static_table() {
+        let mut buf = Vec::new();
+        encode(
+            [
+                (":method", b"GET".as_slice()),
+                (":path", b"/index.html"),
+                ("authorization", b"secret"),
+                ("x-custom", b"1"),
+            ],
+            &mut buf,
+        );
+
+        // prefix, indexed :method GET, then a literal with the :path name reference
+        assert_eq!([0x00, 0x00, 0xd1, 0x51], buf[..4]);
+        // authorization (static index 84) is referenced by name with the N bit set
+        assert!(buf.windows(2).any(|window| window == [0x7f, 0x45]));
+        assert_eq!(
+            Ok(fields(&[
+                (":method", "GET"),
+                (":path", "/index.html"),
+                ("authorization", "secret"),
+                ("x-custom", "1"),
+            ])),
+            decode(&buf)
+        );
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let headers = Headers::try_from([
+            ("content-type", "text/html; charset=utf-8"),
+            (":status", "200"),
+            ("cache-control", "private"),
+            ("x-request-id", "6c3b0a7e"),
+            ("set-cookie", "a=b"),
+        ])
+        .unwrap();
+
+        let mut buf = Vec::new();
+        encode_headers(&headers, &mut buf);
+        let decoded = decode(&buf).unwrap();
+
+        // pseudo-headers are moved to the front
+        assert_eq!(":status", decoded[0].0);
+        assert_eq!(headers, decode_headers(&buf).unwrap());
+    }
+
+    #[test]
+    fn test_decode_headers_combines_cookies() {
+        let mut buf = Vec::new();
+        encode(
+            [
+                ("cookie", b"a=1".as_slice()),
+                ("cookie", b"b=2"),
+                ("accept", b"text/html"),
+                ("accept", b"*/*"),
+            ],
+            &mut buf,
+        );
+
+        let headers = decode_headers(&buf).unwrap();
+        assert_eq!(
+            Some(&HeaderValue::from_static("a=1; b=2")),
+            headers.get("cookie")
+        );
+        assert_eq!(
+            Some(&HeaderValue::from_static("text/html, */*")),
+            headers.get("accept")
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Parsing from [`Bytes`] without copying the message head.
+//!
+//! The path and header fields of a [`RawRequest`] or [`RawResponse`] are reference counted
+//! slices of the buffer they were parsed from, so they are cheap to clone and can be sent to
+//! other tasks. Use [`RawRequest::into_request`] to get an owned [`Request`] when needed.
+
+use bytes::Bytes;
+use http::StatusCode;
+
+use crate::{
+    Body, HTTPMethod, HTTPParseError, HTTPVersion, Headers, Request, RequestHead, Response,
+    ResponseHead,
+};
+
+/// A parsed request whose path and headers point into the parsed buffer
+#[derive(Debug, PartialEq, Clone)]
+pub struct RawRequest {
+    pub method: HTTPMethod,
+    /// The request target, guaranteed to be valid UTF-8
+    pub path: Bytes,
+    pub version: HTTPVersion,
+    /// Header fields in the order they were received. Repeated fields are not combined
+    /// and names keep their original case.
+    pub headers: Vec<(Bytes, Bytes)>,
+    pub body: Body,
+}
+
+/// A parsed response whose headers point into the parsed buffer
+#[derive(Debug, PartialEq, Clone)]
+pub struct RawResponse {
+    pub status: StatusCode,
+    pub version: HTTPVersion,
+    /// Header fields in the order they were received. Repeated fields are not combined
+    /// and names keep their original case.
+    pub headers: Vec<(Bytes, Bytes)>,
+    pub body: Body,
+}
+
+impl RawRequest {
+    /// Parse the request at the start of the buffer, returning it and the remaining bytes
+    pub fn parse(buf: Bytes) -> Result<(Self, Bytes), HTTPParseError> {
+        let (head, remaining) = RequestHead::parse(&buf)?;
+
+        let request = Self {
+            method: head.method,
+            path: buf.slice_ref(head.path.as_bytes()),
+            version: head.version,
+            headers: slice_fields(&buf, &head.fields),
+            body: head.body,
+        };
+        let remaining = buf.slice(buf.len() - remaining.len()..);
+
+        Ok((request, remaining))
+    }
+
+    /// The value of the first header with this name, compared case-insensitively
+    pub fn get_header(&self, name: &str) -> Option<&Bytes> {
+        get_field(&self.headers, name)
+    }
+
+    /// Copy into an owned [`Request`]
+    pub fn into_request(self) -> Request {
+        Request {
+            // the path was validated as UTF-8 while parsing
+            path: String::from_utf8_lossy(&self.path).into_owned(),
+            method: self.method,
+            headers: headers_from_raw(&self.headers),
+            version: self.version,
+            body: self.body,
+        }
+    }
+}
+
+impl RawResponse {
+    /// Parse the response at the start of the buffer, returning it and the remaining bytes
+    pub fn parse(buf: Bytes) -> Result<(Self, Bytes), HTTPParseError> {
+        let (head, remaining) = ResponseHead::parse(&buf)?;
+
+        let response = Self {
+            status: head.status,
+            version: head.version,
+            headers: slice_fields(&buf, &head.fields),
+            body: head.body,
+        };
+        let remaining = buf.slice(buf.len() - remaining.len()..);
+
+        Ok((response, remaining))
+    }
+
+    /// The value of the first header with this name, compared case-insensitively
+    pub fn get_header(&self, name: &str) -> Option<&Bytes> {
+        get_field(&self.headers, name)
+    }
+
+    /// Copy into an owned [`Response`]
+    pub fn into_response(self) -> Response {
+        Response {
+            status: self.status,
+            version: self.version,
+            headers: headers_from_raw(&self.headers),
+            body: self.body,
+        }
+    }
+}
+
+fn slice_fields(buf: &Bytes, fields: &[(&str, &[u8])]) -> Vec<(Bytes, Bytes)> {
+    fields
+        .iter()
+        .map(|(name, value)| (buf.slice_ref(name.as_bytes()), buf.slice_ref(value)))
+        .collect()
+}
+
+fn get_field<'a>(fields: &'a [(Bytes, Bytes)], name: &str) -> Option<&'a Bytes> {
+    fields
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name.as_bytes()))
+        .map(|(_, value)| value)
+}
+
+fn headers_from_raw(fields: &[(Bytes, Bytes)]) -> Headers {
+    // the fields were validated while parsing, and names are always UTF-8
+    let fields = fields
+        .iter()
+        .filter_map(|(name, value)| Some((str::from_utf8(name).ok()?, value.as_ref())))
+        .collect::<Vec<_>>();
+    Headers::from_fields(&fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_raw_request() {
+        let buf = Bytes::from_static(
+            b"POST /upload HTTP/1.1\r\nHost: example.com\r\nX-Tag: a\r\nx-tag: b\r\nContent-Length: 4\r\n\r\nbody",
+        );
+        let (request, remaining) = RawRequest::parse(buf.clone()).unwrap();
+
+        assert_eq!(HTTPMethod::POST, request.method);
+        assert_eq!(b"/upload".as_slice(), request.path);
+        assert_eq!(Body::Sized(4), request.body);
+        assert_eq!(
+            vec![
+                (Bytes::from("Host"), Bytes::from("example.com")),
+                (Bytes::from("X-Tag"), Bytes::from("a")),
+                (Bytes::from("x-tag"), Bytes::from("b")),
+                (Bytes::from("Content-Length"), Bytes::from("4")),
+            ],
+            request.headers
+        );
+        assert_eq!(Some(&Bytes::from("a")), request.get_header("x-tag"));
+        assert_eq!(b"body".as_slice(), remaining);
+
+        // no copies were made, everything points into the original buffer
+        let range = buf.as_ptr_range();
+        assert!(range.contains(&request.path.as_ptr()));
+        assert!(range.contains(&request.headers[0].1.as_ptr()));
+
+        let owned = request.into_request();
+        assert_eq!(Request::parse(&buf).unwrap().0, owned);
+        assert_eq!(
+            Some("a, b"),
+            owned
+                .headers
+                .get("x-tag")
+                .and_then(|value| value.to_str().ok())
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_response() {
+        let buf = Bytes::from_static(b"HTTP/1.1 204 No Content\r\nServer: agora\r\n\r\n");
+        let (response, remaining) = RawResponse::parse(buf.clone()).unwrap();
+
+        assert_eq!(StatusCode::NO_CONTENT, response.status);
+        assert_eq!(Body::Empty, response.body);
+        assert_eq!(Some(&Bytes::from("agora")), response.get_header("SERVER"));
+        assert!(remaining.is_empty());
+        assert_eq!(Response::parse(&buf).unwrap().0, response.into_response());
+    }
+
+    #[test]
+    fn test_parse_raw_invalid() {
+        assert_eq!(
+            Err(HTTPParseError::ConflictingFraming),
+            RawRequest::parse(Bytes::from_static(
+                b"POST / HTTP/1.1\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n"
+            ))
+        );
+    }
+}
@@ -0,0 +1,510 @@
+//! URI references as used by request targets and Location headers (RFC 3986).
+
+use std::fmt::Display;
+
+use crate::HTTPParseError;
+
+/// A parsed URI reference, split into its components.
+///
+/// Both absolute URIs (`https://example.com/a?b`) and relative references (`/a?b`, `../a`)
+/// are supported, so absent components are `None` rather than defaulted.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Uri {
+    scheme: Option<String>,
+    authority: Option<String>,
+    path: String,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+
+impl Uri {
+    /// Parse a URI reference
+    pub fn parse(uri: &str) -> Result<Self, HTTPParseError> {
+        if uri
+            .bytes()
+            .any(|b| b.is_ascii_control() || b == b' ' || !b.is_ascii())
+        {
+            return Err(HTTPParseError::InvalidUri);
+        }
+
+        let (rest, fragment) = match uri.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment.to_string())),
+            None => (uri, None),
+        };
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query.to_string())),
+            None => (rest, None),
+        };
+
+        // a scheme is only present if the colon comes before the first slash
+        let (scheme, rest) = match rest.split_once(':') {
+            Some((scheme, rest)) if is_valid_scheme(scheme) && !scheme.contains('/') => {
+                (Some(scheme.to_ascii_lowercase()), rest)
+            }
+            _ => (None, rest),
+        };
+
+        let (authority, path) = match rest.strip_prefix("//") {
+            Some(rest) => {
+                let end = rest.find('/').unwrap_or(rest.len());
+                let authority = &rest[..end];
+                if !is_valid_authority(authority) {
+                    return Err(HTTPParseError::InvalidUri);
+                }
+                (Some(authority.to_string()), &rest[end..])
+            }
+            None => (None, rest),
+        };
+
+        Ok(Self {
+            scheme,
+            authority,
+            path: path.to_string(),
+            query,
+            fragment,
+        })
+    }
+
+    /// Build a URI from just an authority, as in the authority-form targets of CONNECT requests
+    pub fn from_authority(authority: &str) -> Result<Self, HTTPParseError> {
+        if !is_valid_authority(authority) {
+            return Err(HTTPParseError::InvalidUri);
+        }
+
+        Ok(Self {
+            authority: Some(authority.to_string()),
+            ..Default::default()
+        })
+    }
+
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    /// The `host[:port]` component
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_deref()
+    }
+
+    /// The host without the port. IPv6 literals keep their brackets.
+    pub fn host(&self) -> Option<&str> {
+        self.authority
+            .as_deref()
+            .map(|authority| split_port(authority).0)
+    }
+
+    /// The explicit port, if any
+    pub fn port(&self) -> Option<u16> {
+        self.authority
+            .as_deref()
+            .and_then(|authority| split_port(authority).1)
+            .and_then(|port| port.parse().ok())
+    }
+
+    /// The explicit port, falling back to the default port of the scheme
+    pub fn port_or_default(&self) -> Option<u16> {
+        self.port().or(match self.scheme.as_deref() {
+            Some("http") | Some("ws") => Some(80),
+            Some("https") | Some("wss") => Some(443),
+            _ => None,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    /// The path and query, which is what goes into an origin-form request target.
+    /// An empty path is normalized to `/`.
+    pub fn path_and_query(&self) -> String {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        match &self.query {
+            Some(query) => format!("{path}?{query}"),
+            None => path.to_string(),
+        }
+    }
+
+    /// The decoded `name=value` pairs of an `application/x-www-form-urlencoded` query.
+    /// Pairs that don't decode to valid UTF-8 are skipped.
+    pub fn query_pairs(&self) -> impl Iterator<Item = (String, String)> {
+        self.query
+            .as_deref()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Some((form_decode(name)?, form_decode(value)?))
+            })
+    }
+
+    /// Whether this URI has a scheme, and so isn't a relative reference
+    pub fn is_absolute(&self) -> bool {
+        self.scheme.is_some()
+    }
+
+    /// Resolve a reference against this URI as its base (RFC 3986 section 5.2.2), e.g. a
+    /// relative Location header against the request target. Dot segments are removed from
+    /// the resulting path.
+    pub fn resolve(&self, reference: &Uri) -> Uri {
+        let mut target = reference.clone();
+        if reference.scheme.is_some() {
+            target.path = remove_dot_segments(&reference.path);
+            return target;
+        }
+
+        target.scheme = self.scheme.clone();
+        if reference.authority.is_some() {
+            target.path = remove_dot_segments(&reference.path);
+            return target;
+        }
+
+        target.authority = self.authority.clone();
+        if reference.path.is_empty() {
+            target.path = self.path.clone();
+            if reference.query.is_none() {
+                target.query = self.query.clone();
+            }
+        } else if reference.path.starts_with('/') {
+            target.path = remove_dot_segments(&reference.path);
+        } else {
+            target.path = remove_dot_segments(&self.merge(&reference.path));
+        }
+        target
+    }
+
+    /// Append a relative path to the directory of this URI's path (RFC 3986 section 5.2.3)
+    fn merge(&self, path: &str) -> String {
+        if self.authority.is_some() && self.path.is_empty() {
+            return format!("/{path}");
+        }
+        match self.path.rfind('/') {
+            Some(i) => format!("{}{path}", &self.path[..=i]),
+            None => path.to_string(),
+        }
+    }
+
+    pub fn set_scheme(&mut self, scheme: Option<&str>) {
+        self.scheme = scheme.map(|scheme| scheme.to_ascii_lowercase());
+    }
+
+    pub fn set_authority(&mut self, authority: Option<&str>) -> Result<(), HTTPParseError> {
+        if authority.is_some_and(|authority| !is_valid_authority(authority)) {
+            return Err(HTTPParseError::InvalidUri);
+        }
+        self.authority = authority.map(str::to_string);
+        Ok(())
+    }
+
+    pub fn set_path(&mut self, path: &str) {
+        self.path = path.to_string();
+    }
+
+    pub fn set_query(&mut self, query: Option<&str>) {
+        self.query = query.map(str::to_string);
+    }
+
+    pub fn set_fragment(&mut self, fragment: Option<&str>) {
+        self.fragment = fragment.map(str::to_string);
+    }
+}
+
+impl Display for Uri {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{scheme}:")?;
+        }
+        if let Some(authority) = &self.authority {
+            write!(f, "//{authority}")?;
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{query}")?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{fragment}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Interpret and remove `.` and `..` segments from a path (RFC 3986 section 5.2.4)
+pub(crate) fn remove_dot_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut segments = path.split('/').peekable();
+    if absolute {
+        segments.next();
+    }
+
+    let mut output = Vec::new();
+    while let Some(segment) = segments.next() {
+        let last = segments.peek().is_none();
+        match segment {
+            "." => {}
+            ".." => {
+                output.pop();
+            }
+            segment => {
+                output.push(segment);
+                continue;
+            }
+        }
+        // a trailing dot segment still refers to a directory
+        if last {
+            output.push("");
+        }
+    }
+
+    let path = output.join("/");
+    if absolute { format!("/{path}") } else { path }
+}
+
+/// Decode a form urlencoded component, where `+` is a space and `%XX` a percent encoded byte
+fn form_decode(component: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(component.len());
+    let mut bytes = component.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                decoded.push(u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => decoded.push(b),
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn is_valid_scheme(scheme: &str) -> bool {
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b))
+}
+
+/// Split an authority into the host and port
+fn split_port(authority: &str) -> (&str, Option<&str>) {
+    if authority.starts_with('[') {
+        return match authority.split_once("]:") {
+            Some((host, port)) => (&authority[..host.len() + 1], Some(port)),
+            None => (authority, None),
+        };
+    }
+
+    match authority.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (authority, None),
+    }
+}
+
+/// Validate `host [ ":" port ]` as described in RFC 3986 section 3.2.
+/// Userinfo is not allowed since it's deprecated for http(s) URIs.
+pub(crate) fn is_valid_authority(authority: &str) -> bool {
+    let (host, port) = split_port(authority);
+
+    let valid_host = if let Some(address) = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+    {
+        address.parse::<std::net::Ipv6Addr>().is_ok()
+    } else {
+        !host.is_empty()
+            && host
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._~%!$&'()*+,;=".contains(&b))
+    };
+    let valid_port = port.is_none_or(|port| port.bytes().all(|b| b.is_ascii_digit()));
+
+    valid_host && valid_port
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(
+        "https://example.com:8443/api/v1?page=2#top",
+        Some("https"),
+        Some("example.com:8443"),
+        "/api/v1",
+        Some("page=2"),
+        Some("top")
+    )]
+    #[case(
+        "HTTP://example.com",
+        Some("http"),
+        Some("example.com"),
+        "",
+        None,
+        None
+    )]
+    #[case("/api?", None, None, "/api", Some(""), None)]
+    #[case("/", None, None, "/", None, None)]
+    #[case("*", None, None, "*", None, None)]
+    #[case("../other", None, None, "../other", None, None)]
+    #[case("//cdn.example.com/x", None, Some("cdn.example.com"), "/x", None, None)]
+    #[case("/a:b", None, None, "/a:b", None, None)]
+    #[case(
+        "mailto:someone@example.com",
+        Some("mailto"),
+        None,
+        "someone@example.com",
+        None,
+        None
+    )]
+    fn test_parse_uri(
+        #[case] input: &str,
+        #[case] scheme: Option<&str>,
+        #[case] authority: Option<&str>,
+        #[case] path: &str,
+        #[case] query: Option<&str>,
+        #[case] fragment: Option<&str>,
+    ) {
+        let uri = Uri::parse(input).unwrap();
+        assert_eq!(scheme, uri.scheme());
+        assert_eq!(authority, uri.authority());
+        assert_eq!(path, uri.path());
+        assert_eq!(query, uri.query());
+        assert_eq!(fragment, uri.fragment());
+    }
+
+    #[rstest]
+    #[case("/with space")]
+    #[case("/with\r\nnewline")]
+    #[case("http://user@example.com/")]
+    #[case("http://example.com:http/")]
+    #[case("http:///path")]
+    #[case("http://[::1/")]
+    fn test_parse_uri_invalid(#[case] input: &str) {
+        assert_eq!(Err(HTTPParseError::InvalidUri), Uri::parse(input));
+    }
+
+    #[rstest]
+    #[case("https://example.com:8443/api/v1?page=2#top")]
+    #[case("/api?q")]
+    #[case("//cdn.example.com/x")]
+    #[case("http://[::1]:80/")]
+    fn test_uri_roundtrip(#[case] input: &str) {
+        assert_eq!(input, Uri::parse(input).unwrap().to_string());
+    }
+
+    #[rstest]
+    #[case("http://example.com", Some("example.com"), None, Some(80))]
+    #[case(
+        "https://example.com:8443",
+        Some("example.com"),
+        Some(8443),
+        Some(8443)
+    )]
+    #[case("https://[::1]:8443", Some("[::1]"), Some(8443), Some(8443))]
+    #[case("wss://[::1]", Some("[::1]"), None, Some(443))]
+    #[case("/path", None, None, None)]
+    fn test_host_and_port(
+        #[case] input: &str,
+        #[case] host: Option<&str>,
+        #[case] port: Option<u16>,
+        #[case] port_or_default: Option<u16>,
+    ) {
+        let uri = Uri::parse(input).unwrap();
+        assert_eq!(host, uri.host());
+        assert_eq!(port, uri.port());
+        assert_eq!(port_or_default, uri.port_or_default());
+    }
+
+    #[test]
+    fn test_set_components() {
+        let mut uri = Uri::parse("/old/path?x=1").unwrap();
+        uri.set_scheme(Some("HTTPS"));
+        uri.set_authority(Some("example.com")).unwrap();
+        uri.set_path("/new/path");
+        uri.set_query(None);
+        assert_eq!("https://example.com/new/path", uri.to_string());
+        assert_eq!("/new/path", uri.path_and_query());
+
+        assert_eq!(
+            Err(HTTPParseError::InvalidUri),
+            uri.set_authority(Some("bad host"))
+        );
+    }
+
+    #[rstest]
+    #[case("/search?q=rust+lang&page=2", vec![("q", "rust lang"), ("page", "2")])]
+    #[case("/?a=%26%3D&b", vec![("a", "&="), ("b", "")])]
+    #[case("/?caf%C3%A9=1&&x=y", vec![("café", "1"), ("x", "y")])]
+    // invalid escapes are skipped
+    #[case("/?bad=%zz&ok=1&bad2=%e9", vec![("ok", "1")])]
+    #[case("/", vec![])]
+    fn test_query_pairs(#[case] input: &str, #[case] expected: Vec<(&str, &str)>) {
+        let uri = Uri::parse(input).unwrap();
+        assert_eq!(
+            expected,
+            uri.query_pairs()
+                .collect::<Vec<_>>()
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[rstest]
+    // the examples from RFC 3986 section 5.4
+    #[case("g:h", "g:h")]
+    #[case("g", "http://a/b/c/g")]
+    #[case("./g", "http://a/b/c/g")]
+    #[case("g/", "http://a/b/c/g/")]
+    #[case("/g", "http://a/g")]
+    #[case("//g", "http://g")]
+    #[case("?y", "http://a/b/c/d;p?y")]
+    #[case("g?y", "http://a/b/c/g?y")]
+    #[case("#s", "http://a/b/c/d;p?q#s")]
+    #[case("g?y#s", "http://a/b/c/g?y#s")]
+    #[case(";x", "http://a/b/c/;x")]
+    #[case("", "http://a/b/c/d;p?q")]
+    #[case(".", "http://a/b/c/")]
+    #[case("./", "http://a/b/c/")]
+    #[case("..", "http://a/b/")]
+    #[case("../g", "http://a/b/g")]
+    #[case("../..", "http://a/")]
+    #[case("../../g", "http://a/g")]
+    #[case("../../../g", "http://a/g")]
+    #[case("/./g", "http://a/g")]
+    #[case("/../g", "http://a/g")]
+    #[case("g.", "http://a/b/c/g.")]
+    #[case("..g", "http://a/b/c/..g")]
+    #[case("./../g", "http://a/b/g")]
+    #[case("g/./h", "http://a/b/c/g/h")]
+    #[case("g/../h", "http://a/b/c/h")]
+    #[case("g;x=1/./y", "http://a/b/c/g;x=1/y")]
+    #[case("g;x=1/../y", "http://a/b/c/y")]
+    fn test_resolve(#[case] reference: &str, #[case] expected: &str) {
+        let base = Uri::parse("http://a/b/c/d;p?q").unwrap();
+        let reference = Uri::parse(reference).unwrap();
+        assert_eq!(expected, base.resolve(&reference).to_string());
+    }
+
+    #[test]
+    fn test_resolve_against_empty_path() {
+        let base = Uri::parse("https://example.com").unwrap();
+        let reference = Uri::parse("login").unwrap();
+        assert_eq!(
+            "https://example.com/login",
+            base.resolve(&reference).to_string()
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! The `Via` header (RFC 9110 section 7.6.3), listing the intermediaries a message went through.
+
+use std::fmt::Display;
+
+use crate::{
+    HTTPParseError, HTTPVersion, HeaderName, HeaderValue, Headers, headers::is_token,
+    uri::is_valid_authority,
+};
+
+/// One hop of a `Via` header, like `1.1 proxy.example.com (agora)`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Via {
+    /// The protocol name, which is omitted when it is HTTP
+    pub protocol: Option<String>,
+    /// The protocol version the hop received the message with, e.g. `1.1`
+    pub version: String,
+    /// The host and optional port of the recipient, or a pseudonym hiding it
+    pub received_by: String,
+    /// The comment without its parentheses
+    pub comment: Option<String>,
+}
+
+impl Via {
+    /// An HTTP hop received with this version
+    pub fn new(version: HTTPVersion, received_by: &str) -> Self {
+        let version = match version {
+            HTTPVersion::HTTP1_0 => "1.0",
+            HTTPVersion::HTTP1_1 => "1.1",
+            HTTPVersion::HTTP2 => "2",
+            HTTPVersion::HTTP3 => "3",
+        };
+        Self {
+            protocol: None,
+            version: version.to_string(),
+            received_by: received_by.to_string(),
+            comment: None,
+        }
+    }
+
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    /// Parse the value of a `Via` header into its hops, in the order they were added
+    pub fn parse_all(mut input: &str) -> Result<Vec<Self>, HTTPParseError> {
+        let mut hops = Vec::new();
+        loop {
+            // empty list elements are allowed and ignored (RFC 9110 section 5.6.1)
+            input = input.trim_start_matches([' ', '\t', ',']);
+            if input.is_empty() {
+                return Ok(hops);
+            }
+
+            let (received_protocol, rest) = split_word(input);
+            let (protocol, version) = match received_protocol.split_once('/') {
+                Some((protocol, version)) => (Some(protocol), version),
+                None => (None, received_protocol),
+            };
+            if protocol.is_some_and(|protocol| !is_token(protocol)) || !is_token(version) {
+                return Err(HTTPParseError::InvalidVia);
+            }
+
+            let (received_by, rest) = split_word(rest.trim_start_matches([' ', '\t']));
+            if !is_token(received_by) && !is_valid_authority(received_by) {
+                return Err(HTTPParseError::InvalidVia);
+            }
+
+            let rest = rest.trim_start_matches([' ', '\t']);
+            let (comment, rest) = if rest.starts_with('(') {
+                let (comment, rest) = parse_comment(rest).ok_or(HTTPParseError::InvalidVia)?;
+                (Some(comment), rest.trim_start_matches([' ', '\t']))
+            } else {
+                (None, rest)
+            };
+            if !rest.is_empty() && !rest.starts_with(',') {
+                return Err(HTTPParseError::InvalidVia);
+            }
+
+            hops.push(Self {
+                protocol: protocol.map(String::from),
+                version: version.to_string(),
+                received_by: received_by.to_string(),
+                comment,
+            });
+            input = rest;
+        }
+    }
+}
+
+/// Split off everything up to the next whitespace or comma
+fn split_word(input: &str) -> (&str, &str) {
+    input.split_at(input.find([' ', '\t', ',']).unwrap_or(input.len()))
+}
+
+/// Parse a possibly nested comment starting at the opening parenthesis, returning its contents
+/// without the outer parentheses and whatever follows it
+fn parse_comment(input: &str) -> Option<(String, &str)> {
+    let mut comment = String::new();
+    let mut depth = 0;
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '(' => {
+                depth += 1;
+                if depth == 1 {
+                    continue;
+                }
+            }
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((comment, &input[i + 1..]));
+                }
+            }
+            '\\' => {
+                comment.push(chars.next()?.1);
+                continue;
+            }
+            _ => {}
+        }
+        comment.push(c);
+    }
+
+    None
+}
+
+/// Serializes to a header value
+impl Display for Via {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(protocol) = &self.protocol {
+            write!(f, "{protocol}/")?;
+        }
+        write!(f, "{} {}", self.version, self.received_by)?;
+        if let Some(comment) = &self.comment {
+            // only parentheses need escaping, and nested comments aren't kept when parsing
+            let escaped = comment
+                .replace('\\', "\\\\")
+                .replace('(', "\\(")
+                .replace(')', "\\)");
+            write!(f, " ({escaped})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Add a hop to the Via header, after any existing ones
+pub fn append_via(headers: &mut Headers, via: &Via) {
+    let value = HeaderValue::try_from(via.to_string().replace(|c: char| c.is_control(), ""))
+        .expect("control characters were removed");
+    headers.append(HeaderName::from_static("via"), value);
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("1.1 proxy", vec![Via::new(HTTPVersion::HTTP1_1, "proxy")])]
+    #[case(
+        "1.0 fred, 1.1 p.example.net",
+        vec![Via::new(HTTPVersion::HTTP1_0, "fred"), Via::new(HTTPVersion::HTTP1_1, "p.example.net")]
+    )]
+    #[case(
+        "HTTP/2 [::1]:8080 (agora (nested) \\) x),,2 cdn",
+        vec![
+            Via {
+                protocol: Some("HTTP".to_string()),
+                version: "2".to_string(),
+                received_by: "[::1]:8080".to_string(),
+                comment: Some("agora (nested) ) x".to_string()),
+            },
+            Via::new(HTTPVersion::HTTP2, "cdn"),
+        ]
+    )]
+    #[case("", vec![])]
+    fn test_parse(#[case] input: &str, #[case] expected: Vec<Via>) {
+        assert_eq!(Ok(expected), Via::parse_all(input));
+    }
+
+    #[rstest]
+    #[case("1.1")]
+    #[case("1.1 proxy extra")]
+    #[case("1.1 proxy (unterminated")]
+    #[case("/1.1 proxy")]
+    #[case("1.1 [::1")]
+    fn test_parse_invalid(#[case] input: &str) {
+        assert_eq!(Err(HTTPParseError::InvalidVia), Via::parse_all(input));
+    }
+
+    #[rstest]
+    #[case(Via::new(HTTPVersion::HTTP1_1, "agora"), "1.1 agora")]
+    #[case(
+        Via::new(HTTPVersion::HTTP1_0, "proxy:8080").comment("agora (beta)"),
+        "1.0 proxy:8080 (agora \\(beta\\))"
+    )]
+    fn test_display(#[case] via: Via, #[case] expected: &str) {
+        assert_eq!(expected, via.to_string());
+        assert_eq!(Ok(vec![via]), Via::parse_all(expected));
+    }
+
+    #[test]
+    fn test_append_via() {
+        let mut headers = Headers::new();
+        append_via(&mut headers, &Via::new(HTTPVersion::HTTP1_0, "fred"));
+        append_via(
+            &mut headers,
+            &Via::new(HTTPVersion::HTTP1_1, "agora").comment("a\r\nb"),
+        );
+        assert_eq!(
+            Some(&HeaderValue::from_static("1.0 fred, 1.1 agora (ab)")),
+            headers.get("via")
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use agora_proxy::{
+    config::{ProxyEntry, Route, ServerConfig},
+    routing::PathPattern,
+    server::Server,
+};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+};
+
+const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\ncontent-length: 12\r\n\r\nHello World!";
+
+/// A browser-like request, with enough headers to be realistic
+const REQUEST: &[u8] = b"GET /index.html HTTP/1.1\r\n\
+    Host: example.com\r\n\
+    User-Agent: Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0\r\n\
+    Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\n\
+    Accept-Language: en-US,en;q=0.5\r\n\
+    Accept-Encoding: gzip, deflate, br, zstd\r\n\
+    Connection: keep-alive\r\n\
+    Cookie: session=0123456789abcdef; theme=dark\r\n\
+    Upgrade-Insecure-Requests: 1\r\n\
+    Cache-Control: max-age=0\r\n\r\n";
+
+/// Start an upstream that answers every connection with a fixed response, and a proxy in
+/// front of it. Returns the address of the proxy.
+async fn start_proxy() -> std::net::SocketAddr {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf).await.unwrap();
+                stream.write_all(RESPONSE).await.unwrap();
+            });
+        }
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut config = ServerConfig::default();
+        config.routes.push(Route::new(
+            PathPattern::prefix("/"),
+            ProxyEntry {
+                addr: upstream_addr.to_string().into(),
+                strip_prefix: false,
+                ..Default::default()
+            },
+        ));
+        Server::new(config).serve(listener).await.unwrap();
+    });
+
+    proxy_addr
+}
+
+fn bench_proxy_request(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let proxy_addr = runtime.block_on(start_proxy());
+
+    let mut group = c.benchmark_group("proxy");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("request", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+            stream.write_all(REQUEST).await.unwrap();
+            // the proxy closes the connection once it sees no more requests are coming
+            stream.shutdown().await.unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+            response
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_proxy_request);
+criterion_main!(benches);
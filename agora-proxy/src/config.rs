@@ -0,0 +1,2406 @@
+//! The server config and the files it is loaded from.
+//!
+//! A config is made of sections: the `listeners` to accept connections on, the `routes`
+//! and `virtual_hosts` requests are matched against, the named `upstreams` routes can
+//! proxy to, the `limits` on connections, and the `logging` setup.
+
+mod check;
+mod env;
+mod include;
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use agora_http_parser::{
+    HTTPMethod, HTTPVersion, HeaderName, HeaderValue, Headers, Request, Response, Uri, Via, h2,
+};
+use clap::ValueEnum;
+use http::StatusCode;
+use ipnet::IpNet;
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{
+        MapAccess, SeqAccess, Visitor,
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+    },
+};
+use serde_json::Value;
+
+use crate::{
+    forwarded::ForwardedHeaders,
+    routing::{PathMatch, PathPattern, Predicate, Rewrite, Router},
+    security_headers::SecurityHeaders,
+    static_files::StaticFiles,
+    tls,
+    transform::HeaderTransform,
+};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Addresses to accept connections on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<Listener>,
+    /// Routes for requests that don't match a virtual host
+    #[serde(
+        default,
+        alias = "reverse_proxy_mapping",
+        deserialize_with = "deserialize_routes"
+    )]
+    pub routes: Vec<Route>,
+    /// Mapping of host name to the routes for that host.
+    /// Names are either exact, like `example.com`, or wildcards like `*.example.com` which
+    /// match any subdomain but not the domain itself.
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        deserialize_with = "deserialize_virtual_hosts"
+    )]
+    pub virtual_hosts: HashMap<String, Vec<Route>>,
+    /// Upstreams routes can refer to by name instead of repeating their address
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub upstreams: HashMap<String, Upstream>,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub logging: Logging,
+    /// Name the proxy adds to the `Via` header of the messages it forwards, `agora` if
+    /// unset. Requests already naming it came back around in a loop, so proxies
+    /// forwarding to each other need different names.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub via: Option<String>,
+    /// Networks of the proxies in front of this one, like `10.0.0.0/8`. Their
+    /// `X-Forwarded-For` and `Forwarded` headers tell the client behind them, while
+    /// those of anyone else are replaced.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_proxies: Vec<IpNet>,
+    #[serde(default)]
+    pub http2: Http2,
+    /// Tunnels clients may open with `CONNECT`, which are refused unless set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_proxy: Option<ForwardProxy>,
+    /// Security headers added to the responses of routes without their own
+    #[serde(default)]
+    pub security_headers: SecurityHeaders,
+}
+
+/// An address to accept connections on
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Listener {
+    /// Address to bind, like `0.0.0.0:8080`
+    pub address: String,
+    /// Connections start with a PROXY protocol header from the load balancer in front
+    /// of the proxy, telling the client it passed on. Connections without one are
+    /// closed.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// Forward the UDP datagrams received on the address instead of serving HTTP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp: Option<UdpForward>,
+    /// Pass TLS connections through to the server they name, without terminating
+    /// them, instead of serving HTTP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_passthrough: Option<TlsPassthrough>,
+    /// Serve HTTPS, terminating TLS with the certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<ListenerTls>,
+    /// Answer requests with a redirect to the same URL over HTTPS instead of routing
+    /// them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub https_redirect: Option<HttpsRedirect>,
+}
+
+impl Listener {
+    /// Whether the listener serves HTTP rather than forwarding what it receives
+    pub fn is_http(&self) -> bool {
+        self.udp.is_none() && self.tls_passthrough.is_none()
+    }
+}
+
+/// How a cleartext listener sends clients over to HTTPS
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpsRedirect {
+    /// 301 (Moved Permanently), or 308 (Permanent Redirect) for clients to keep the
+    /// method and body of requests other than GET
+    pub status: u16,
+    /// The port HTTPS is served on, when not 443
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Route requests for ACME HTTP-01 challenges, under
+    /// `/.well-known/acme-challenge/`, instead of redirecting them
+    pub exclude_acme_challenges: bool,
+}
+
+impl Default for HttpsRedirect {
+    fn default() -> Self {
+        Self {
+            status: 301,
+            port: None,
+            exclude_acme_challenges: false,
+        }
+    }
+}
+
+impl HttpsRedirect {
+    /// Whether the request is routed rather than redirected
+    pub fn excludes(&self, request: &Request) -> bool {
+        self.exclude_acme_challenges
+            && Uri::parse(&request.path)
+                .is_ok_and(|target| target.path().starts_with("/.well-known/acme-challenge/"))
+    }
+
+    /// The HTTPS URL of the request, on the same host with the same path and query.
+    /// `None` if the request doesn't tell its host.
+    pub fn location(&self, request: &Request) -> Option<String> {
+        let host = request_host(request)?;
+        let port = match self.port {
+            Some(port) if port != 443 => format!(":{port}"),
+            _ => String::new(),
+        };
+        let path = Uri::parse(&request.path)
+            .map(|target| target.path_and_query())
+            .ok()
+            .filter(|path| path.starts_with('/'))
+            .unwrap_or_else(|| String::from("/"));
+        Some(format!("https://{host}{port}{path}"))
+    }
+
+    /// The response sending the client to the location
+    pub fn response(&self, location: &str) -> Vec<u8> {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::MOVED_PERMANENTLY);
+        let mut response = Response::new(status);
+        if let Ok(location) = HeaderValue::try_from(location) {
+            response.header(HeaderName::from_static("location"), location);
+        }
+        response.header(HeaderName::from_static("content-length"), 0.into());
+        response.header(
+            HeaderName::from_static("connection"),
+            HeaderValue::from_static("close"),
+        );
+        response.into_bytes()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !matches!(self.status, 301 | 308) {
+            return Err(String::from("status: must be 301 or 308"));
+        }
+        if self.port == Some(0) {
+            return Err(String::from("port: must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// The certificate a listener serving HTTPS presents, and the TLS it allows clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerTls {
+    /// PEM file of the certificate chain, starting with the certificate of the proxy
+    pub cert: PathBuf,
+    /// PEM file of the private key of `cert`
+    pub key: PathBuf,
+    /// The oldest version clients may use
+    pub min_version: TlsVersion,
+    /// The newest version clients may use
+    pub max_version: TlsVersion,
+    /// Names of the cipher suites clients may use, most preferred first, like
+    /// `TLS13_AES_256_GCM_SHA384` or `TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256`. All the
+    /// suites of the versions allowed by default.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cipher_suites: Vec<String>,
+    /// Names of the key exchange groups clients may use, most preferred first, out of
+    /// `X25519`, `secp256r1` and `secp384r1`. All of them by default.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub curves: Vec<String>,
+    /// The protocols offered to clients in ALPN, most preferred first. Clients
+    /// offering none of them are refused, and those not using ALPN speak HTTP/1.1.
+    pub alpn: Vec<Alpn>,
+    /// How clients authenticate with certificates, for mutual TLS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_auth: Option<ClientAuth>,
+}
+
+impl Default for ListenerTls {
+    fn default() -> Self {
+        Self {
+            cert: PathBuf::new(),
+            key: PathBuf::new(),
+            min_version: TlsVersion::Tls12,
+            max_version: TlsVersion::Tls13,
+            cipher_suites: Vec::new(),
+            curves: Vec::new(),
+            alpn: vec![Alpn::H2, Alpn::Http1],
+            client_auth: None,
+        }
+    }
+}
+
+/// The certificates clients of a listener authenticate with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientAuth {
+    /// PEM file of the certificates to verify those of clients against
+    pub ca: PathBuf,
+    /// Whether clients without a certificate are refused. Those with one have it
+    /// verified either way.
+    pub required: bool,
+}
+
+impl Default for ClientAuth {
+    fn default() -> Self {
+        Self {
+            ca: PathBuf::new(),
+            required: true,
+        }
+    }
+}
+
+/// A version of TLS. Older ones have known weaknesses and aren't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// A protocol a listener serving HTTPS can speak, as negotiated with ALPN (RFC 7301)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Alpn {
+    #[serde(rename = "http/1.1")]
+    Http1,
+    #[serde(rename = "h2")]
+    H2,
+    /// TLS-ALPN-01 challenges of ACME (RFC 8737), which are done with the handshake
+    #[serde(rename = "acme-tls/1")]
+    AcmeTls,
+}
+
+impl Alpn {
+    /// The protocol ID sent in the ALPN extension
+    pub fn id(self) -> &'static [u8] {
+        match self {
+            Alpn::Http1 => b"http/1.1",
+            Alpn::H2 => b"h2",
+            Alpn::AcmeTls => b"acme-tls/1",
+        }
+    }
+}
+
+/// Where TLS connections go, by the server name of their ClientHello
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsPassthrough {
+    /// Addresses by server name, which can be a wildcard like `*.example.com`
+    /// matching any subdomain. Names match whatever their case.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub servers: HashMap<String, String>,
+    /// Address for connections naming none of the servers, or no server at all.
+    /// They are refused without it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+impl TlsPassthrough {
+    /// The address connections asking for the server name go to. Names match before
+    /// wildcards, and longer wildcards before shorter ones.
+    pub fn server(&self, name: Option<&str>) -> Option<&str> {
+        let matched = name.and_then(|name| {
+            self.servers
+                .iter()
+                .filter(|(pattern, _)| host_matches(pattern, name))
+                .max_by_key(|(pattern, _)| (!pattern.starts_with("*."), pattern.len()))
+        });
+        matched
+            .map(|(_, addr)| addr)
+            .or(self.default.as_ref())
+            .map(String::as_str)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.servers.is_empty() && self.default.is_none() {
+            return Err(String::from(
+                "servers: at least one server is required without a `default`",
+            ));
+        }
+        if let Some((name, _)) = self.servers.iter().find(|(_, addr)| addr.is_empty()) {
+            return Err(format!("servers.{name}: address is required"));
+        }
+        if self.default.as_ref().is_some_and(String::is_empty) {
+            return Err(String::from("default: must not be empty"));
+        }
+        Ok(())
+    }
+}
+
+/// Where a UDP listener forwards datagrams, and how long it remembers the clients
+/// sending them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UdpForward {
+    /// Address datagrams are forwarded to, like `10.0.0.53:53`
+    pub addr: String,
+    /// Seconds after which a client sending nothing, and being sent nothing back, is
+    /// forgotten
+    pub idle_timeout: u64,
+    /// The most clients forwarded for at once. Datagrams of others are dropped.
+    pub max_sessions: usize,
+}
+
+impl Default for UdpForward {
+    fn default() -> Self {
+        Self {
+            addr: String::new(),
+            idle_timeout: 60,
+            max_sessions: 10_000,
+        }
+    }
+}
+
+impl UdpForward {
+    fn validate(&self) -> Result<(), String> {
+        if self.addr.is_empty() {
+            return Err(String::from("addr: is required"));
+        }
+        if self.idle_timeout == 0 {
+            return Err(String::from("idle_timeout: must be at least 1"));
+        }
+        if self.max_sessions == 0 {
+            return Err(String::from("max_sessions: must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// A named upstream
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Upstream {
+    /// The backends of the upstream
+    #[serde(default, skip_serializing_if = "Addrs::is_empty")]
+    pub addr: Addrs,
+    /// How requests are spread over the backends
+    #[serde(default)]
+    pub balance: Balance,
+    /// Requests sent to each backend in the background, to stop picking backends that
+    /// are down
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    /// When to stop picking backends for a while after requests to them fail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outlier_detection: Option<OutlierDetection>,
+    /// How connections to the backends are kept open for later requests
+    #[serde(default)]
+    pub keepalive: KeepAlive,
+    /// Speak HTTP/2 to the backends over cleartext, sending many requests at once over
+    /// each connection. `https://` backends are still spoken to over HTTP/1.1.
+    #[serde(default)]
+    pub http2: bool,
+    /// Start each connection to the backends with a PROXY protocol version 2 header
+    /// telling the client, for backends that read it. Connections are then only used
+    /// for requests of that client, and not kept open for later ones.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// The service in the Consul catalog whose passing instances are the backends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consul: Option<Consul>,
+    /// A file listing more backends as `address weight` lines, read again whenever it
+    /// changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// How `https://` backends are connected to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<UpstreamTls>,
+    /// A proxy the connections to the backends go through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ParentProxy>,
+}
+
+/// A proxy connections to backends go through, written as
+/// `{ "http": { "addr": "proxy.internal:3128" } }` or
+/// `{ "socks5": { "addr": "127.0.0.1:1080" } }`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParentProxy {
+    /// An HTTP proxy, asked to open a tunnel to each backend with `CONNECT`
+    Http(ProxyServer),
+    /// A SOCKS5 proxy, like the dynamic forward of an SSH connection or Tor
+    Socks5(ProxyServer),
+}
+
+/// The address of a parent proxy, along with the credentials it asks for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyServer {
+    pub addr: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// How connections to `https://` backends are secured
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamTls {
+    /// PEM file of the certificates to verify backends against, instead of the ones
+    /// trusted by the system
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca: Option<PathBuf>,
+    /// Whether the certificates of backends are verified. Turning it off lets anyone
+    /// between the proxy and the backends read and change the traffic.
+    pub verify: bool,
+    /// The name sent to backends in SNI and their certificates are verified for,
+    /// instead of the host of their address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    /// PEM file of the certificate chain the proxy authenticates itself to backends
+    /// with, for backends requiring mutual TLS. Needs `key`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert: Option<PathBuf>,
+    /// PEM file of the private key of `cert`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+}
+
+impl Default for UpstreamTls {
+    fn default() -> Self {
+        Self {
+            ca: None,
+            verify: true,
+            server_name: None,
+            cert: None,
+            key: None,
+        }
+    }
+}
+
+impl From<Addrs> for Upstream {
+    fn from(addr: Addrs) -> Self {
+        Self {
+            addr,
+            ..Default::default()
+        }
+    }
+}
+
+/// How backends are checked, with times in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthCheck {
+    /// Path requested from the backends, which are healthy if they respond with a
+    /// `2xx` or `3xx` status
+    pub path: String,
+    /// Time between checks of a backend
+    pub interval: u64,
+    /// Time allowed for a check, after which it counts as failed
+    pub timeout: u64,
+    /// Checks in a row a backend that is down has to pass to be picked again
+    pub healthy_threshold: u32,
+    /// Checks in a row a backend has to fail to stop being picked
+    pub unhealthy_threshold: u32,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            path: String::from("/"),
+            interval: 10,
+            timeout: 2,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+/// How many connections to each backend are kept open once their response is done,
+/// and for how long, in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeepAlive {
+    /// Idle connections kept per backend, the oldest being closed to make room for
+    /// newer ones. Zero opens a connection for each request.
+    pub max_idle: usize,
+    /// Time after which an idle connection is closed instead of used
+    pub idle_timeout: u64,
+    /// Requests after which a connection is closed, for backends that keep state per
+    /// connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u32>,
+    /// Time after which a connection is closed instead of used again, so new ones pick
+    /// up changes like backends rotating behind a load balancer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lifetime: Option<u64>,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            max_idle: 32,
+            idle_timeout: 60,
+            max_requests: None,
+            max_lifetime: None,
+        }
+    }
+}
+
+/// A service in the Consul catalog
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Consul {
+    /// Address of the HTTP API of the Consul agent
+    pub addr: String,
+    /// Name of the service
+    pub service: String,
+    /// Tags an instance has to have all of to be a backend
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Datacenter of the service, the one of the agent if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datacenter: Option<String>,
+    /// ACL token to read the catalog with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl Default for Consul {
+    fn default() -> Self {
+        Self {
+            addr: String::from("127.0.0.1:8500"),
+            service: String::new(),
+            tags: Vec::new(),
+            datacenter: None,
+            token: None,
+        }
+    }
+}
+
+impl Consul {
+    fn validate(&self) -> Result<(), String> {
+        if self.service.is_empty() {
+            return Err(String::from("service: is required"));
+        }
+        // the name goes into the path of queries as is
+        if !self
+            .service
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            return Err(String::from(
+                "service: can only have letters, digits, `-`, `_`, and `.`",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ParentProxy {
+    fn validate(&self) -> Result<(), String> {
+        let (kind, server) = match self {
+            ParentProxy::Http(server) => ("http", server),
+            ParentProxy::Socks5(server) => ("socks5", server),
+        };
+        if server.addr.is_empty() {
+            return Err(format!("{kind}.addr: is required"));
+        }
+        if server.password.is_some() && server.username.is_none() {
+            return Err(format!("{kind}.password: requires `username`"));
+        }
+        // SOCKS5 sends the length of each in a byte
+        if let ParentProxy::Socks5(server) = self {
+            for (name, value) in [
+                ("username", &server.username),
+                ("password", &server.password),
+            ] {
+                if value.as_ref().is_some_and(|value| value.len() > 255) {
+                    return Err(format!("socks5.{name}: must be at most 255 bytes"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl KeepAlive {
+    fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("idle_timeout", Some(self.idle_timeout)),
+            ("max_requests", self.max_requests.map(u64::from)),
+            ("max_lifetime", self.max_lifetime),
+        ] {
+            if value == Some(0) {
+                return Err(format!("{name}: must be at least 1"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// When backends are ejected for failing requests, with times in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutlierDetection {
+    /// Requests failing in a row that eject a backend, by failing to connect, timing
+    /// out, or getting a `5xx` response
+    pub consecutive_failures: u32,
+    /// How long a backend is ejected the first time. Each ejection in a row doubles it.
+    pub base_ejection_time: u64,
+    /// The longest a backend is ejected for
+    pub max_ejection_time: u64,
+}
+
+impl Default for OutlierDetection {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            base_ejection_time: 30,
+            max_ejection_time: 300,
+        }
+    }
+}
+
+impl OutlierDetection {
+    fn validate(&self) -> Result<(), String> {
+        if self.consecutive_failures == 0 {
+            return Err(String::from("consecutive_failures: must be at least 1"));
+        }
+        if self.base_ejection_time == 0 {
+            return Err(String::from("base_ejection_time: must be at least 1"));
+        }
+        if self.max_ejection_time < self.base_ejection_time {
+            return Err(String::from(
+                "max_ejection_time: must be at least `base_ejection_time`",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl HealthCheck {
+    fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("interval", self.interval),
+            ("timeout", self.timeout),
+            ("healthy_threshold", self.healthy_threshold.into()),
+            ("unhealthy_threshold", self.unhealthy_threshold.into()),
+        ] {
+            if value == 0 {
+                return Err(format!("{name}: must be at least 1"));
+            }
+        }
+        if !self.path.starts_with('/') {
+            return Err(String::from("path: must start with `/`"));
+        }
+        Ok(())
+    }
+}
+
+/// The policies for picking the backend of a pool for a request, written as
+/// `"round_robin"`, `"least_conn"`, `"p2c"`, or `{ "hash": ... }`
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Balance {
+    /// Each backend in turn
+    #[default]
+    RoundRobin,
+    /// The backend with the fewest requests in flight, which keeps slow backends from
+    /// piling up requests
+    LeastConn,
+    /// The cheaper of two random backends, by their average latency and requests in
+    /// flight, which steers requests away from slow backends
+    P2c,
+    /// The backend the key hashes to, so requests with the same key go to the same
+    /// backend. Requests without the key take turns.
+    Hash(HashKey),
+}
+
+/// What requests are hashed by, written as `"ip"`, `{ "header": "x-user-id" }`, or
+/// `{ "cookie": "session" }`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKey {
+    /// The IP address of the client
+    Ip,
+    /// The value of a request header
+    Header(String),
+    /// The value of a cookie
+    Cookie(String),
+}
+
+/// The addresses of the backends requests are proxied to, written as a single address
+/// or a list of them
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Addrs(pub Vec<String>);
+
+impl Addrs {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+impl From<String> for Addrs {
+    fn from(addr: String) -> Self {
+        Self(vec![addr])
+    }
+}
+
+impl From<&str> for Addrs {
+    fn from(addr: &str) -> Self {
+        Self(vec![addr.to_string()])
+    }
+}
+
+impl std::fmt::Display for Addrs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0.join(", "))
+    }
+}
+
+/// A single address is written as a string, like in configs from before pools
+impl Serialize for Addrs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.as_slice() {
+            [addr] => serializer.serialize_str(addr),
+            addrs => addrs.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Addrs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AddrsVisitor;
+
+        impl<'de> Visitor<'de> for AddrsVisitor {
+            type Value = Addrs;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an address, or a list of addresses")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, addr: &str) -> Result<Self::Value, E> {
+                // an empty address is the same as none, which routes serving files use
+                Ok(if addr.is_empty() {
+                    Addrs::default()
+                } else {
+                    Addrs::from(addr)
+                })
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(Addrs)
+            }
+        }
+
+        deserializer.deserialize_any(AddrsVisitor)
+    }
+}
+
+/// Limits on how long connections can take, with times in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Time allowed for reading the head of a request from the client
+    pub request_timeout: u64,
+    /// Time allowed for each of sending the request upstream and relaying the response
+    pub upstream_timeout: u64,
+    /// Time a client connection is kept open waiting for its next request. Zero closes
+    /// connections after their first response.
+    pub keepalive_timeout: u64,
+    /// Requests handled on a client connection before it is closed
+    pub keepalive_requests: u32,
+    /// Time a tunnel, like a WebSocket, is kept open without bytes going either way.
+    /// Zero keeps idle tunnels open.
+    pub tunnel_idle_timeout: u64,
+    /// Time a tunnel is kept open at most
+    pub tunnel_max_duration: Option<u64>,
+    /// Bytes passed through a tunnel, both ways together, before it is closed
+    pub tunnel_max_bytes: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            request_timeout: 30,
+            upstream_timeout: 30,
+            keepalive_timeout: 60,
+            keepalive_requests: 1000,
+            tunnel_idle_timeout: 600,
+            tunnel_max_duration: None,
+            tunnel_max_bytes: None,
+        }
+    }
+}
+
+/// HTTP/2 connections from clients, which either start with the HTTP/2 preface or
+/// upgrade from HTTP/1.1 with `Upgrade: h2c`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Http2 {
+    /// Whether clients can speak HTTP/2 to the proxy
+    pub enabled: bool,
+    /// Streams a client may have open at once on a connection
+    pub max_concurrent_streams: u32,
+    /// Bytes of a request body a client may send ahead on each stream, before the
+    /// proxy passed them on. All streams of a connection together get as many.
+    pub initial_window_size: u32,
+    /// The largest frame the proxy accepts, in bytes
+    pub max_frame_size: u32,
+}
+
+impl Default for Http2 {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_concurrent_streams: 100,
+            initial_window_size: 1 << 20,
+            max_frame_size: h2::DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+/// Tunnels clients open with `CONNECT` to the destinations allowed, which makes the
+/// proxy a forward proxy for them as well
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForwardProxy {
+    /// Hosts tunnels may go to: names like `api.github.com`, wildcards like
+    /// `*.github.com` matching any subdomain, or networks like `10.0.0.0/8` matching
+    /// addresses. `*` allows any host.
+    pub allow: Vec<String>,
+    /// Ports tunnels may go to
+    pub ports: Vec<u16>,
+}
+
+impl Default for ForwardProxy {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            ports: vec![443],
+        }
+    }
+}
+
+impl ForwardProxy {
+    /// Whether a tunnel may go to the host and port. Names are allowed whatever they
+    /// resolve to, networks only match addresses given as such.
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        if !self.ports.contains(&port) {
+            return false;
+        }
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        let ip = host.parse::<IpAddr>().ok();
+        self.allow.iter().any(|allowed| {
+            allowed == "*"
+                || match allowed.parse::<IpNet>() {
+                    Ok(network) => ip.is_some_and(|ip| network.contains(&ip)),
+                    Err(_) => host_matches(allowed, host),
+                }
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.allow.is_empty() {
+            return Err(String::from("allow: at least one host is required"));
+        }
+        if self.ports.is_empty() {
+            return Err(String::from("ports: at least one port is required"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    /// The most verbose level logged
+    pub level: LogLevel,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<LogLevel> for tracing::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Trace => tracing::Level::TRACE,
+            LogLevel::Debug => tracing::Level::DEBUG,
+            LogLevel::Info => tracing::Level::INFO,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Error => tracing::Level::ERROR,
+        }
+    }
+}
+
+/// A path pattern and where to proxy the requests matching it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub path: PathPattern,
+    /// Routes with a higher priority are tried first
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(flatten)]
+    pub entry: ProxyEntry,
+}
+
+impl Route {
+    pub fn new(path: PathPattern, entry: ProxyEntry) -> Self {
+        Self {
+            path,
+            priority: 0,
+            entry,
+        }
+    }
+}
+
+/// Routes are written as a list, or as a mapping of path pattern to proxy entry
+enum RouteList {
+    List(Vec<Route>),
+    Mapping(HashMap<PathPattern, ProxyEntry>),
+}
+
+/// Picks the form by the type of the value rather than trying each form in turn, so the
+/// error for an invalid route is about the route itself
+impl<'de> Deserialize<'de> for RouteList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RouteListVisitor;
+
+        impl<'de> Visitor<'de> for RouteListVisitor {
+            type Value = RouteList;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a list of routes, or a mapping of path patterns to routes")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(RouteList::List)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                HashMap::deserialize(MapAccessDeserializer::new(map)).map(RouteList::Mapping)
+            }
+        }
+
+        deserializer.deserialize_any(RouteListVisitor)
+    }
+}
+
+impl From<RouteList> for Vec<Route> {
+    fn from(routes: RouteList) -> Self {
+        match routes {
+            RouteList::List(routes) => routes,
+            RouteList::Mapping(mapping) => {
+                let mut routes: Vec<_> = mapping
+                    .into_iter()
+                    .map(|(path, entry)| Route::new(path, entry))
+                    .collect();
+                // a mapping has no order of its own, so regular expressions would be
+                // tried in an arbitrary order otherwise
+                routes.sort_by(|a, b| a.path.as_str().cmp(b.path.as_str()));
+                routes
+            }
+        }
+    }
+}
+
+fn deserialize_routes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Route>, D::Error> {
+    RouteList::deserialize(deserializer).map(Vec::from)
+}
+
+fn deserialize_virtual_hosts<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, Vec<Route>>, D::Error> {
+    let virtual_hosts = HashMap::<String, RouteList>::deserialize(deserializer)?;
+    Ok(virtual_hosts
+        .into_iter()
+        .map(|(name, routes)| (name, routes.into()))
+        .collect())
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProxyEntry {
+    /// The backends to proxy to, picked between for each request
+    #[serde(default, skip_serializing_if = "Addrs::is_empty")]
+    pub addr: Addrs,
+    /// Name of the upstream in the `upstreams` section to proxy to, instead of `addr`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    pub strip_prefix: bool,
+    /// Conditions on request headers which must all hold for the route to match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<Predicate>,
+    /// Conditions on query parameters which must all hold for the route to match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub query: Vec<Predicate>,
+    /// Rewrite of the path, applied after the prefix is stripped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rewrite: Option<Rewrite>,
+    /// The Host header sent upstream
+    #[serde(default)]
+    pub host: HostHeader,
+    /// Changes to the headers of requests sent upstream
+    #[serde(default, skip_serializing_if = "HeaderTransform::is_empty")]
+    pub request_headers: HeaderTransform,
+    /// Changes to the headers of responses sent to the client
+    #[serde(default, skip_serializing_if = "HeaderTransform::is_empty")]
+    pub response_headers: HeaderTransform,
+    /// Address of an upstream sent a copy of each request, whose responses are discarded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<String>,
+    /// Files served instead of proxying to an upstream, in which case `addr` is unused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub static_files: Option<StaticFiles>,
+    /// While set, requests get a `503 Service Unavailable` instead of being proxied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Maintenance>,
+    /// When requests failing upstream are tried again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<Retry>,
+    /// Name of an upstream in the `upstreams` section to fail over to once the backends
+    /// of the route are all down or the request to them fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+    /// How `https://` addresses of the route are connected to. Routes naming an
+    /// upstream use the settings of the upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<UpstreamTls>,
+    /// Who tells clients sending `Expect: 100-continue` to go ahead with the body
+    #[serde(default)]
+    pub expect_continue: ExpectContinue,
+    /// Whether responses are relayed in the time allowed for them as a whole, or
+    /// streamed for as long as the upstream keeps sending
+    #[serde(default)]
+    pub buffering: Buffering,
+    /// Proxy gRPC calls, whose responses are streamed and whose failures in the proxy
+    /// are reported with a gRPC status
+    #[serde(default)]
+    pub grpc: bool,
+    /// The headers telling the upstream about the client
+    #[serde(default)]
+    pub forwarded: ForwardedHeaders,
+    /// Security headers added to responses instead of the global ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_headers: Option<SecurityHeaders>,
+}
+
+/// The response to requests for a route under maintenance
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Maintenance {
+    /// Seconds clients should wait before retrying, sent as `Retry-After`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    /// Plain text body of the response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// A file the route is only under maintenance while it exists, so it can be taken
+    /// down and brought back up without editing the config. Always under maintenance
+    /// without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+impl Maintenance {
+    /// Whether the route is under maintenance right now
+    pub async fn is_active(&self) -> bool {
+        match &self.file {
+            Some(file) => tokio::fs::try_exists(file).await.unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// The response, with the headers added and the `connection` header telling the
+    /// client whether it can send another request
+    pub fn response(&self, headers: &Headers, connection: Option<HeaderValue>) -> Vec<u8> {
+        let mut response = Response::new(StatusCode::SERVICE_UNAVAILABLE);
+        if let Some(retry_after) = self.retry_after {
+            response.header(HeaderName::from_static("retry-after"), retry_after.into());
+        }
+        let message = self.message.as_deref().unwrap_or_default();
+        if !message.is_empty() {
+            response.header(
+                HeaderName::from_static("content-type"),
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+        }
+        response.header(
+            HeaderName::from_static("content-length"),
+            (message.len() as u64).into(),
+        );
+        for (name, value) in headers.iter() {
+            response.header(name.clone(), value.clone());
+        }
+        if let Some(connection) = connection {
+            response.header(HeaderName::from_static("connection"), connection);
+        }
+
+        let mut bytes = response.into_bytes();
+        bytes.extend_from_slice(message.as_bytes());
+        bytes
+    }
+}
+
+/// When and how often a request failing upstream is tried again, each time with the
+/// backend the pool picks next. Once a request was sent, it is only tried again if its
+/// whole body was read along with the head, since the rest of a body is streamed to the
+/// upstream without being kept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retry {
+    /// Tries in total, including the first
+    pub attempts: u32,
+    /// The failures tried again
+    pub on: Vec<RetryOn>,
+    /// Seconds each try waits for the head of the response, instead of the whole
+    /// upstream timeout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_try_timeout: Option<u64>,
+    /// Also try again requests whose method isn't idempotent, like `POST`, which the
+    /// upstream may have acted on before failing
+    pub non_idempotent: bool,
+    /// Limits retries to a share of the requests in flight, so a failing upstream
+    /// doesn't get several times the load
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<RetryBudget>,
+    /// Milliseconds after which a try still waiting for a response is raced by another
+    /// try with the next backend, taking whichever response comes first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hedge_after_ms: Option<u64>,
+}
+
+/// How many retries, and hedges, may be in flight for a route at once
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryBudget {
+    /// The percentage of the requests in flight which may be retries
+    pub percent: u32,
+    /// Retries allowed regardless of the percentage, so routes with few requests can
+    /// still retry
+    pub min_retries: u32,
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self {
+            percent: 20,
+            min_retries: 3,
+        }
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            on: vec![
+                RetryOn::ConnectFailure,
+                RetryOn::Status(502),
+                RetryOn::Status(503),
+                RetryOn::Status(504),
+            ],
+            per_try_timeout: None,
+            non_idempotent: false,
+            budget: None,
+            hedge_after_ms: None,
+        }
+    }
+}
+
+impl Retry {
+    /// Whether a try failing this way is tried again, given tries are left
+    pub fn covers(&self, failure: RetryOn) -> bool {
+        self.on.iter().any(|&on| match (on, failure) {
+            (RetryOn::ServerError, RetryOn::Status(status)) => (500..600).contains(&status),
+            (on, failure) => on == failure,
+        })
+    }
+
+    /// Whether a request with the method can be tried again after it was sent
+    pub fn allows(&self, method: &HTTPMethod) -> bool {
+        self.non_idempotent || method.is_idempotent()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.attempts == 0 {
+            return Err(String::from("attempts: must be at least 1"));
+        }
+        if self.per_try_timeout == Some(0) {
+            return Err(String::from("per_try_timeout: must be at least 1"));
+        }
+        if self.hedge_after_ms == Some(0) {
+            return Err(String::from("hedge_after_ms: must be at least 1"));
+        }
+        if let Some(budget) = &self.budget
+            && budget.percent > 100
+        {
+            return Err(String::from("budget.percent: must be at most 100"));
+        }
+        Ok(())
+    }
+}
+
+/// The ways a try can fail, written as `"connect_failure"`, `"reset"`, `"timeout"`,
+/// `"5xx"`, or a status code like `"503"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum RetryOn {
+    /// No backend of the pool accepted a connection
+    ConnectFailure,
+    /// The connection to the backend failed before the head of the response arrived
+    Reset,
+    /// The head of the response didn't arrive in time
+    Timeout,
+    /// Any `5xx` response
+    ServerError,
+    /// A response with this status
+    Status(u16),
+}
+
+impl TryFrom<String> for RetryOn {
+    type Error = String;
+
+    fn try_from(condition: String) -> Result<Self, Self::Error> {
+        match condition.as_str() {
+            "connect_failure" => Ok(Self::ConnectFailure),
+            "reset" => Ok(Self::Reset),
+            "timeout" => Ok(Self::Timeout),
+            "5xx" => Ok(Self::ServerError),
+            status => match status.parse() {
+                Ok(status @ 100..600) => Ok(Self::Status(status)),
+                _ => Err(format!(
+                    "unknown retry condition `{condition}`, expected `connect_failure`, \
+                     `reset`, `timeout`, `5xx`, or a status code"
+                )),
+            },
+        }
+    }
+}
+
+impl From<RetryOn> for String {
+    fn from(condition: RetryOn) -> Self {
+        match condition {
+            RetryOn::ConnectFailure => String::from("connect_failure"),
+            RetryOn::Reset => String::from("reset"),
+            RetryOn::Timeout => String::from("timeout"),
+            RetryOn::ServerError => String::from("5xx"),
+            RetryOn::Status(status) => status.to_string(),
+        }
+    }
+}
+
+/// The Host header to send upstream, written in config files as `"preserve"`,
+/// `"upstream"`, or `{ "value": "example.com" }`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostHeader {
+    /// Forward the Host header of the client
+    #[default]
+    Preserve,
+    /// Use the address of the upstream
+    Upstream,
+    /// Use this value
+    Value(String),
+}
+
+impl HostHeader {
+    /// The host replacing the one sent by the client, if any
+    pub fn rewrite<'a>(&'a self, upstream_addr: &'a str) -> Option<&'a str> {
+        match self {
+            HostHeader::Preserve => None,
+            HostHeader::Upstream => Some(upstream_addr),
+            HostHeader::Value(host) => Some(host),
+        }
+    }
+}
+
+/// Who answers a client that sent `Expect: 100-continue` and waits to be told to send
+/// the body, written in config files as `"local"` or `"forward"`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpectContinue {
+    /// The proxy, as soon as it connected to a backend. The expectation isn't sent
+    /// upstream.
+    #[default]
+    Local,
+    /// The backend, which can turn the request down before the body is sent. Its
+    /// `100 Continue` is relayed to the client.
+    Forward,
+}
+
+/// How long the body of a response may take, written in config files as `"on"` or
+/// `"off"`. Server-Sent Events and responses without a Content-Length are streamed
+/// either way.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Buffering {
+    /// The whole response is relayed within the upstream timeout
+    #[default]
+    On,
+    /// Responses are streamed, only timing out once the upstream sends nothing for
+    /// as long as the upstream timeout
+    Off,
+}
+
+/// The route a request matches, found with [`ServerConfig::route`]
+#[derive(Debug)]
+pub struct RouteMatch<'a> {
+    /// Where the route is in the config, like `routes[2]`
+    pub location: String,
+    pub route: &'a Route,
+    /// The path sent upstream, or looked up in the static files
+    pub path: String,
+    /// The addresses the request can be proxied to, unless the route serves it itself
+    pub upstream: &'a Addrs,
+    /// The addresses of the upstream failed over to, if the route has one
+    pub backup: Option<&'a Addrs>,
+}
+
+impl ProxyEntry {
+    /// The path of a request matching the route once its prefix is stripped and rewritten
+    pub fn upstream_path(&self, path: &str, path_match: &PathMatch) -> String {
+        let mut path = if self.strip_prefix {
+            let mut stripped = path[path_match.end..].to_string();
+            if !stripped.starts_with('/') {
+                stripped.insert(0, '/');
+            }
+            stripped
+        } else {
+            path.to_string()
+        };
+
+        if let Some(rewrite) = &self.rewrite {
+            path = rewrite.apply(&path);
+        }
+        path
+    }
+
+    /// Whether the request satisfies the header and query predicates of the route
+    pub fn accepts(&self, request: &Request) -> bool {
+        let headers_match = self.headers.iter().all(|predicate| {
+            let value = request.headers.get(&predicate.name);
+            predicate.matches(value.map(|value| value.to_str_lossy()).as_deref())
+        });
+        if !headers_match || self.query.is_empty() {
+            return headers_match;
+        }
+
+        let Ok(uri) = request.uri() else {
+            return false;
+        };
+        let pairs: Vec<_> = uri.query_pairs().collect();
+        self.query.iter().all(|predicate| {
+            let value = pairs.iter().find(|(name, _)| *name == predicate.name);
+            predicate.matches(value.map(|(_, value)| value.as_str()))
+        })
+    }
+}
+
+/// The formats config files can be written in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConfigFormat {
+    #[default]
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// The format of a file, from its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Parse the contents of a file into a format independent tree
+    fn parse(self, contents: &str) -> Result<Value, String> {
+        match self {
+            ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Deserialize part of a config, naming the key that failed in the error
+fn deserialize<T: for<'de> Deserialize<'de>>(value: Value) -> Result<T, String> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let path = e.path().to_string();
+        if path == "." {
+            e.into_inner().to_string()
+        } else {
+            format!("{path}: {}", e.into_inner())
+        }
+    })
+}
+
+impl ServerConfig {
+    /// Parse a config file in the format of its extension, or JSON if it has none
+    /// along with the files it includes
+    pub fn parse(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse_as(path, ConfigFormat::from_path(path).unwrap_or_default())
+    }
+
+    pub fn parse_as(path: &Path, format: ConfigFormat) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = include::load(path, format)?;
+        let config: Self =
+            deserialize(Value::Object(config)).map_err(|e| format!("Invalid config: {e}"))?;
+        config
+            .validate()
+            .map_err(|e| format!("Invalid config: {e}"))?;
+        Ok(config)
+    }
+
+    /// Check what can't be checked while deserializing, like references between sections
+    pub fn validate(&self) -> Result<(), String> {
+        if self.limits.keepalive_requests == 0 {
+            return Err(String::from(
+                "limits.keepalive_requests: must be at least 1",
+            ));
+        }
+        if self.http2.max_concurrent_streams == 0 {
+            return Err(String::from(
+                "http2.max_concurrent_streams: must be at least 1",
+            ));
+        }
+        if !(h2::DEFAULT_WINDOW_SIZE..=h2::MAX_WINDOW_SIZE)
+            .contains(&self.http2.initial_window_size)
+        {
+            return Err(format!(
+                "http2.initial_window_size: must be between {} and {}",
+                h2::DEFAULT_WINDOW_SIZE,
+                h2::MAX_WINDOW_SIZE
+            ));
+        }
+        if !(h2::DEFAULT_MAX_FRAME_SIZE..=h2::MAX_ALLOWED_FRAME_SIZE)
+            .contains(&self.http2.max_frame_size)
+        {
+            return Err(format!(
+                "http2.max_frame_size: must be between {} and {}",
+                h2::DEFAULT_MAX_FRAME_SIZE,
+                h2::MAX_ALLOWED_FRAME_SIZE
+            ));
+        }
+        if let Some(forward_proxy) = &self.forward_proxy {
+            forward_proxy
+                .validate()
+                .map_err(|e| format!("forward_proxy.{e}"))?;
+        }
+        for (i, listener) in self.listeners.iter().enumerate() {
+            if listener.udp.is_some() && listener.tls_passthrough.is_some() {
+                return Err(format!(
+                    "listeners[{i}]: `udp` and `tls_passthrough` can't be combined"
+                ));
+            }
+            if listener.proxy_protocol && listener.udp.is_some() {
+                return Err(format!(
+                    "listeners[{i}].proxy_protocol: doesn't apply to `udp` listeners"
+                ));
+            }
+            if let Some(udp) = &listener.udp {
+                udp.validate()
+                    .map_err(|e| format!("listeners[{i}].udp.{e}"))?;
+            }
+            if listener.tls.is_some() && !listener.is_http() {
+                return Err(format!(
+                    "listeners[{i}].tls: only applies to listeners serving HTTP"
+                ));
+            }
+            if let Some(listener_tls) = &listener.tls {
+                if listener_tls.cert.as_os_str().is_empty() {
+                    return Err(format!("listeners[{i}].tls.cert: is required"));
+                }
+                if listener_tls.key.as_os_str().is_empty() {
+                    return Err(format!("listeners[{i}].tls.key: is required"));
+                }
+                if let Some(client_auth) = &listener_tls.client_auth
+                    && client_auth.ca.as_os_str().is_empty()
+                {
+                    return Err(format!("listeners[{i}].tls.client_auth.ca: is required"));
+                }
+                for (j, protocol) in listener_tls.alpn.iter().enumerate() {
+                    if listener_tls.alpn[..j].contains(protocol) {
+                        return Err(format!("listeners[{i}].tls.alpn[{j}]: is listed already"));
+                    }
+                }
+                tls::server_config(listener_tls).map_err(|e| format!("listeners[{i}].tls.{e}"))?;
+            }
+            if let Some(redirect) = &listener.https_redirect {
+                if listener.tls.is_some() || !listener.is_http() {
+                    return Err(format!(
+                        "listeners[{i}].https_redirect: only applies to cleartext listeners serving HTTP"
+                    ));
+                }
+                redirect
+                    .validate()
+                    .map_err(|e| format!("listeners[{i}].https_redirect.{e}"))?;
+            }
+            if let Some(passthrough) = &listener.tls_passthrough {
+                passthrough
+                    .validate()
+                    .map_err(|e| format!("listeners[{i}].tls_passthrough.{e}"))?;
+            }
+        }
+        if let Some(via) = &self.via
+            && !Via::parse_all(&format!("1.1 {via}"))
+                .is_ok_and(|hops| hops == [Via::new(HTTPVersion::HTTP1_1, via)])
+        {
+            return Err(String::from("via: must be a host name or a single word"));
+        }
+        self.security_headers
+            .validate()
+            .map_err(|e| format!("security_headers.{e}"))?;
+        let route_lists = std::iter::once((String::from("routes"), &self.routes)).chain(
+            self.virtual_hosts
+                .iter()
+                .map(|(name, routes)| (format!("virtual_hosts.{name}"), routes)),
+        );
+        for (section, routes) in route_lists {
+            for (i, route) in routes.iter().enumerate() {
+                self.validate_entry(&route.entry)
+                    .map_err(|e| format!("{section}[{i}]: {e}"))?;
+            }
+        }
+        for (name, upstream) in &self.upstreams {
+            if upstream.proxy_protocol && upstream.http2 {
+                return Err(format!(
+                    "upstreams.{name}.proxy_protocol: can't be combined with `http2`"
+                ));
+            }
+            if upstream.file.is_some()
+                && (upstream.consul.is_some()
+                    || upstream.addr.iter().any(|addr| addr.starts_with("srv://")))
+            {
+                return Err(format!(
+                    "upstreams.{name}.file: can't be combined with SRV names or `consul`"
+                ));
+            }
+            match &upstream.consul {
+                None if upstream.addr.is_empty() && upstream.file.is_none() => {
+                    return Err(format!(
+                        "upstreams.{name}.addr: at least one address is required"
+                    ));
+                }
+                Some(_) if upstream.addr.iter().any(|addr| addr.starts_with("srv://")) => {
+                    return Err(format!(
+                        "upstreams.{name}.addr: SRV names can't be combined with `consul`"
+                    ));
+                }
+                Some(_) if !cfg!(feature = "consul") => {
+                    return Err(format!(
+                        "upstreams.{name}.consul: requires a build with the `consul` feature"
+                    ));
+                }
+                Some(consul) => consul
+                    .validate()
+                    .map_err(|e| format!("upstreams.{name}.consul.{e}"))?,
+                None => {}
+            }
+            if let Some(tls) = &upstream.tls {
+                tls::client_config(tls).map_err(|e| format!("upstreams.{name}.tls.{e}"))?;
+            }
+            if let Some(proxy) = &upstream.proxy {
+                proxy
+                    .validate()
+                    .map_err(|e| format!("upstreams.{name}.proxy.{e}"))?;
+            }
+            if let Some(health_check) = &upstream.health_check {
+                health_check
+                    .validate()
+                    .map_err(|e| format!("upstreams.{name}.health_check.{e}"))?;
+            }
+            upstream
+                .keepalive
+                .validate()
+                .map_err(|e| format!("upstreams.{name}.keepalive.{e}"))?;
+            if let Some(outlier_detection) = &upstream.outlier_detection {
+                outlier_detection
+                    .validate()
+                    .map_err(|e| format!("upstreams.{name}.outlier_detection.{e}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_entry(&self, entry: &ProxyEntry) -> Result<(), String> {
+        match &entry.upstream {
+            Some(_) if !entry.addr.is_empty() => {
+                Err(String::from("only one of `addr` and `upstream` can be set"))
+            }
+            Some(name) if !self.upstreams.contains_key(name) => {
+                Err(format!("upstream: no upstream named `{name}`"))
+            }
+            None if entry.addr.is_empty()
+                && entry.static_files.is_none()
+                && entry.maintenance.is_none() =>
+            {
+                Err(String::from(
+                    "one of `addr`, `upstream`, `static_files`, or `maintenance` is required",
+                ))
+            }
+            _ => Ok(()),
+        }?;
+        if let Some(Maintenance { file: Some(_), .. }) = &entry.maintenance
+            && entry.upstream.is_none()
+            && entry.addr.is_empty()
+            && entry.static_files.is_none()
+        {
+            return Err(String::from(
+                "maintenance.file: the route also needs one of `addr`, `upstream`, or `static_files` for when the file doesn't exist",
+            ));
+        }
+        if let Some(retry) = &entry.retry {
+            retry.validate().map_err(|e| format!("retry.{e}"))?;
+        }
+        if let Some(tls) = &entry.tls {
+            if entry.upstream.is_some() {
+                return Err(String::from(
+                    "tls: only applies to `addr`, set it on the upstream instead",
+                ));
+            }
+            tls::client_config(tls).map_err(|e| format!("tls.{e}"))?;
+        }
+        if let Some(name) = &entry.backup
+            && !self.upstreams.contains_key(name)
+        {
+            return Err(format!("backup: no upstream named `{name}`"));
+        }
+        if let Some(security_headers) = &entry.security_headers {
+            security_headers
+                .validate()
+                .map_err(|e| format!("security_headers.{e}"))?;
+        }
+        Ok(())
+    }
+
+    /// The addresses a route proxies to, from its upstream if it names one
+    pub fn upstream_addr<'a>(&'a self, entry: &'a ProxyEntry) -> &'a Addrs {
+        entry
+            .upstream
+            .as_ref()
+            .and_then(|name| self.upstreams.get(name))
+            .map_or(&entry.addr, |upstream| &upstream.addr)
+    }
+
+    /// The routes for requests to the host. Exact names win over wildcards, and more
+    /// specific wildcards over less specific ones.
+    pub fn routes_for_host(&self, host: Option<&str>) -> &[Route] {
+        host.and_then(|host| virtual_host(&self.virtual_hosts, host))
+            .map_or(&self.routes, |(_, routes)| routes)
+    }
+
+    /// Route the request like the server would, without contacting any upstream
+    pub fn route(&self, request: &Request) -> Option<RouteMatch<'_>> {
+        let (section, routes) =
+            match request_host(request).and_then(|host| virtual_host(&self.virtual_hosts, &host)) {
+                Some((name, routes)) => (format!("virtual_hosts.{name}"), routes),
+                None => (String::from("routes"), &self.routes),
+            };
+
+        let router = Router::new(
+            routes
+                .iter()
+                .enumerate()
+                .map(|(i, route)| (route.priority, route.path.clone(), (i, route))),
+        );
+        let (path_match, (i, route)) =
+            router.route_where(&request.path, |(_, route)| route.entry.accepts(request))?;
+        Some(RouteMatch {
+            location: format!("{section}[{i}]"),
+            route,
+            path: route.entry.upstream_path(&request.path, &path_match),
+            upstream: self.upstream_addr(&route.entry),
+            backup: route
+                .entry
+                .backup
+                .as_ref()
+                .and_then(|name| self.upstreams.get(name))
+                .map(|upstream| &upstream.addr),
+        })
+    }
+}
+
+/// The name and value of the virtual host best matching the host
+pub(crate) fn virtual_host<'a, T>(
+    virtual_hosts: &'a HashMap<String, T>,
+    host: &str,
+) -> Option<(&'a String, &'a T)> {
+    virtual_hosts
+        .iter()
+        .filter(|(pattern, _)| host_matches(pattern, host))
+        .max_by_key(|(pattern, _)| (!pattern.starts_with("*."), pattern.len()))
+}
+
+/// The host a request is for, without the port, which doesn't matter for picking a
+/// virtual host
+pub(crate) fn request_host(request: &Request) -> Option<String> {
+    request
+        .host()
+        .ok()
+        .and_then(|authority| Uri::from_authority(authority).ok())
+        .and_then(|uri| uri.host().map(String::from))
+}
+
+/// Whether the host matches a virtual host name, ignoring case
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let Some(domain) = pattern.strip_prefix("*.") else {
+        return pattern.eq_ignore_ascii_case(host);
+    };
+
+    // at least one label followed by a dot must come before the domain
+    let (host, domain) = (host.as_bytes(), domain.as_bytes());
+    host.len() > domain.len() + 1
+        && host[host.len() - domain.len() - 1] == b'.'
+        && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    fn parse(name: &str, contents: &str) -> Result<ServerConfig, String> {
+        let dir = std::env::temp_dir().join(format!("agora-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+
+        let config = ServerConfig::parse(&path).map_err(|e| e.to_string());
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[rstest]
+    #[case("config.json", Some(ConfigFormat::Json))]
+    #[case("config.TOML", Some(ConfigFormat::Toml))]
+    #[case("config.yml", Some(ConfigFormat::Yaml))]
+    #[case("config.yaml", Some(ConfigFormat::Yaml))]
+    #[case("config", None)]
+    #[case("config.ini", None)]
+    fn test_format_from_path(#[case] path: &str, #[case] expected: Option<ConfigFormat>) {
+        assert_eq!(expected, ConfigFormat::from_path(Path::new(path)));
+    }
+
+    #[rstest]
+    #[case(
+        "config.json",
+        r#"{ "routes": [{ "path": "/api", "addr": "localhost:3000", "strip_prefix": true }] }"#
+    )]
+    #[case(
+        "legacy.json",
+        r#"{ "reverse_proxy_mapping": [{ "path": "/api", "addr": "localhost:3000", "strip_prefix": true }] }"#
+    )]
+    #[case(
+        "config.toml",
+        r#"
+        [[routes]]
+        path = "/api"
+        addr = "localhost:3000"
+        strip_prefix = true
+        "#
+    )]
+    #[case(
+        "config.yaml",
+        r#"
+        routes:
+          - path: /api
+            addr: localhost:3000
+            strip_prefix: true
+        "#
+    )]
+    #[case(
+        "routes.yml",
+        r#"
+        /api:
+          addr: localhost:3000
+          strip_prefix: true
+        "#
+    )]
+    fn test_parse_formats(#[case] name: &str, #[case] contents: &str) {
+        let config = parse(name, contents).unwrap();
+
+        let [route] = config.routes.as_slice() else {
+            panic!("expected a single route: {config:?}");
+        };
+        assert_eq!(PathPattern::prefix("/api"), route.path);
+        assert_eq!(Addrs::from("localhost:3000"), route.entry.addr);
+        assert!(route.entry.strip_prefix);
+    }
+
+    #[test]
+    fn test_parse_as_overrides_extension() {
+        let dir = std::env::temp_dir().join(format!("agora-config-as-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.conf");
+        fs::write(
+            &path,
+            "[[routes]]\npath = \"/\"\naddr = \"a:1\"\nstrip_prefix = false\n",
+        )
+        .unwrap();
+
+        assert!(ServerConfig::parse(&path).is_err());
+        assert!(ServerConfig::parse_as(&path, ConfigFormat::Toml).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_sections() {
+        let config = parse(
+            "sections.yaml",
+            r#"
+            listeners:
+              - address: 0.0.0.0:8080
+              - address: "[::]:8080"
+                proxy_protocol: true
+              - address: 0.0.0.0:53
+                udp:
+                  addr: 10.0.0.53:53
+                  idle_timeout: 30
+            upstreams:
+              api:
+                addr: [localhost:3000, localhost:3001]
+                balance: least_conn
+                http2: true
+                health_check:
+                  path: /health
+                  interval: 5
+            routes:
+              - path: /api
+                upstream: api
+                strip_prefix: true
+            limits:
+              request_timeout: 5
+            logging:
+              level: debug
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(3, config.listeners.len());
+        assert_eq!(None, config.listeners[0].udp);
+        assert!(!config.listeners[0].proxy_protocol);
+        assert!(config.listeners[1].proxy_protocol);
+        assert_eq!(
+            Some(UdpForward {
+                addr: String::from("10.0.0.53:53"),
+                idle_timeout: 30,
+                ..Default::default()
+            }),
+            config.listeners[2].udp
+        );
+        assert_eq!(
+            &Addrs(vec![
+                String::from("localhost:3000"),
+                String::from("localhost:3001")
+            ]),
+            config.upstream_addr(&config.routes[0].entry)
+        );
+        assert_eq!(
+            Limits {
+                request_timeout: 5,
+                ..Default::default()
+            },
+            config.limits
+        );
+        assert_eq!(Balance::LeastConn, config.upstreams["api"].balance);
+        assert!(config.upstreams["api"].http2);
+        assert_eq!(
+            Some(HealthCheck {
+                path: String::from("/health"),
+                interval: 5,
+                ..Default::default()
+            }),
+            config.upstreams["api"].health_check
+        );
+        assert_eq!(LogLevel::Debug, config.logging.level);
+    }
+
+    #[rstest]
+    #[case(r#""round_robin""#, Balance::RoundRobin)]
+    #[case(r#""least_conn""#, Balance::LeastConn)]
+    #[case(r#""p2c""#, Balance::P2c)]
+    #[case(r#"{ "hash": "ip" }"#, Balance::Hash(HashKey::Ip))]
+    #[case(
+        r#"{ "hash": { "header": "x-user-id" } }"#,
+        Balance::Hash(HashKey::Header(String::from("x-user-id")))
+    )]
+    #[case(
+        r#"{ "hash": { "cookie": "session" } }"#,
+        Balance::Hash(HashKey::Cookie(String::from("session")))
+    )]
+    fn test_parse_balance(#[case] balance: &str, #[case] expected: Balance) {
+        assert_eq!(expected, serde_json::from_str::<Balance>(balance).unwrap());
+    }
+
+    #[rstest]
+    #[case(r#"["connect_failure"]"#, RetryOn::ConnectFailure, true)]
+    #[case(r#"["connect_failure"]"#, RetryOn::Timeout, false)]
+    #[case(r#"["503"]"#, RetryOn::Status(503), true)]
+    #[case(r#"["503"]"#, RetryOn::Status(502), false)]
+    #[case(r#"["5xx"]"#, RetryOn::Status(500), true)]
+    #[case(r#"["5xx"]"#, RetryOn::Status(429), false)]
+    #[case(r#"["reset", "429"]"#, RetryOn::Status(429), true)]
+    fn test_retry_covers(#[case] on: &str, #[case] failure: RetryOn, #[case] expected: bool) {
+        let retry = Retry {
+            on: serde_json::from_str(on).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(expected, retry.covers(failure));
+        assert_eq!(
+            on.replace(' ', ""),
+            serde_json::to_string(&retry.on).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case(HTTPMethod::GET, false, true)]
+    #[case(HTTPMethod::PUT, false, true)]
+    #[case(HTTPMethod::POST, false, false)]
+    #[case(HTTPMethod::PATCH, false, false)]
+    #[case(HTTPMethod::POST, true, true)]
+    fn test_retry_allows(
+        #[case] method: HTTPMethod,
+        #[case] non_idempotent: bool,
+        #[case] expected: bool,
+    ) {
+        let retry = Retry {
+            non_idempotent,
+            ..Default::default()
+        };
+        assert_eq!(expected, retry.allows(&method));
+    }
+
+    #[rstest]
+    #[case(Some("example.com"), Some("10.0.0.1:443"))]
+    #[case(Some("api.example.com"), Some("10.0.0.2:443"))]
+    #[case(Some("v1.api.example.com"), Some("10.0.0.3:443"))]
+    #[case(Some("www.example.com"), Some("10.0.0.4:443"))]
+    #[case(Some("example.org"), None)]
+    #[case(None, None)]
+    fn test_tls_passthrough_server(#[case] name: Option<&str>, #[case] expected: Option<&str>) {
+        let mut passthrough = TlsPassthrough::default();
+        for (name, addr) in [
+            ("EXAMPLE.com", "10.0.0.1:443"),
+            ("api.example.com", "10.0.0.2:443"),
+            ("*.api.example.com", "10.0.0.3:443"),
+            ("*.example.com", "10.0.0.4:443"),
+        ] {
+            passthrough
+                .servers
+                .insert(String::from(name), String::from(addr));
+        }
+        assert_eq!(expected, passthrough.server(name));
+
+        passthrough.default = Some(String::from("10.0.0.5:443"));
+        assert_eq!(expected.or(Some("10.0.0.5:443")), passthrough.server(name));
+    }
+
+    #[rstest]
+    #[case("/a?b=1", "host: example.com", None, Some("https://example.com/a?b=1"))]
+    #[case(
+        "/",
+        "host: example.com:8080",
+        Some(8443),
+        Some("https://example.com:8443/")
+    )]
+    #[case("/", "host: example.com:8080", Some(443), Some("https://example.com/"))]
+    #[case(
+        "http://example.com/a",
+        "host: other",
+        None,
+        Some("https://example.com/a")
+    )]
+    #[case("/", "host: [2001:db8::1]:80", None, Some("https://[2001:db8::1]/"))]
+    #[case("/", "x-other: 1", None, None)]
+    fn test_https_redirect_location(
+        #[case] target: &str,
+        #[case] header: &str,
+        #[case] port: Option<u16>,
+        #[case] expected: Option<&str>,
+    ) {
+        let head = format!("GET {target} HTTP/1.1\r\n{header}\r\n\r\n");
+        let (request, _) = Request::parse(head.as_bytes()).unwrap();
+        let redirect = HttpsRedirect {
+            port,
+            ..Default::default()
+        };
+        assert_eq!(expected.map(String::from), redirect.location(&request));
+    }
+
+    #[rstest]
+    #[case("/.well-known/acme-challenge/token", true, true)]
+    #[case("/.well-known/acme-challenge/token", false, false)]
+    #[case("http://example.com/.well-known/acme-challenge/token", true, true)]
+    #[case("/.well-known/security.txt", true, false)]
+    fn test_https_redirect_excludes(
+        #[case] target: &str,
+        #[case] exclude_acme_challenges: bool,
+        #[case] expected: bool,
+    ) {
+        let head = format!("GET {target} HTTP/1.1\r\nhost: example.com\r\n\r\n");
+        let (request, _) = Request::parse(head.as_bytes()).unwrap();
+        let redirect = HttpsRedirect {
+            exclude_acme_challenges,
+            ..Default::default()
+        };
+        assert_eq!(expected, redirect.excludes(&request));
+    }
+
+    #[rstest]
+    #[case("api.github.com", 443, true)]
+    #[case("API.GitHub.com", 443, true)]
+    #[case("api.github.com", 22, false)]
+    #[case("github.com", 443, false)]
+    #[case("uploads.example.com", 443, true)]
+    #[case("example.com", 443, false)]
+    #[case("10.1.2.3", 443, true)]
+    #[case("[fd00::1]", 443, true)]
+    #[case("192.168.1.1", 443, false)]
+    fn test_forward_proxy_allows(#[case] host: &str, #[case] port: u16, #[case] expected: bool) {
+        let forward_proxy = ForwardProxy {
+            allow: vec![
+                String::from("api.github.com"),
+                String::from("*.example.com"),
+                String::from("10.0.0.0/8"),
+                String::from("fd00::/8"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(expected, forward_proxy.allows(host, port));
+    }
+
+    #[rstest]
+    #[case("/api/v1/users", None, Some(("routes[0]", "/v2/users", "localhost:4000")))]
+    #[case("/other", None, Some(("routes[1]", "/other", "localhost:3000")))]
+    #[case("/other", Some("x-tenant: acme"), Some(("routes[2]", "/other", "localhost:5000")))]
+    #[case("/api", Some("host: a.example.com:8080"), Some(("virtual_hosts.*.example.com[0]", "/api", "localhost:6000")))]
+    #[case("/api", Some("host: example.com"), None)]
+    fn test_route(
+        #[case] path: &str,
+        #[case] header: Option<&str>,
+        #[case] expected: Option<(&str, &str, &str)>,
+    ) {
+        let expected =
+            expected.map(|(location, path, upstream)| (location, path, upstream.to_string()));
+        let config: ServerConfig = serde_json::from_value(serde_json::json!({
+            "upstreams": { "api": { "addr": "localhost:4000" } },
+            "routes": [
+                {
+                    "path": "/api",
+                    "upstream": "api",
+                    "strip_prefix": true,
+                    "rewrite": { "pattern": "^/v1/(.*)", "replacement": "/v2/$1" }
+                },
+                { "path": "/", "addr": "localhost:3000", "strip_prefix": false },
+                {
+                    "path": "/",
+                    "priority": 1,
+                    "addr": "localhost:5000",
+                    "strip_prefix": false,
+                    "headers": [{ "name": "x-tenant", "equals": "acme" }]
+                }
+            ],
+            "virtual_hosts": {
+                "*.example.com": [{ "path": "/", "addr": "localhost:6000", "strip_prefix": false }],
+                "example.com": [{ "path": "/other", "addr": "localhost:7000", "strip_prefix": false }]
+            }
+        }))
+        .unwrap();
+        let head = match header {
+            Some(header) => format!("GET {path} HTTP/1.1\r\n{header}\r\n\r\n"),
+            None => format!("GET {path} HTTP/1.1\r\n\r\n"),
+        };
+        let (request, _) = Request::parse(head.as_bytes()).unwrap();
+
+        let route_match = config.route(&request);
+        assert_eq!(
+            expected,
+            route_match.as_ref().map(|route_match| (
+                route_match.location.as_str(),
+                route_match.path.as_str(),
+                route_match.upstream.to_string()
+            ))
+        );
+    }
+
+    #[rstest]
+    #[case(
+        r#"{ "routse": [] }"#,
+        "Invalid config: routse: unknown field `routse`"
+    )]
+    #[case(
+        r#"{ "limits": { "request_timeout": "soon" } }"#,
+        "Invalid config: limits.request_timeout: invalid type"
+    )]
+    #[case(
+        r#"{ "limits": { "keepalive_requests": 0 } }"#,
+        "Invalid config: limits.keepalive_requests: must be at least 1"
+    )]
+    #[case(
+        r#"{ "http2": { "initial_window_size": 1024 } }"#,
+        "Invalid config: http2.initial_window_size: must be between 65535 and 2147483647"
+    )]
+    #[case(
+        r#"{ "http2": { "max_frame_size": 16777216 } }"#,
+        "Invalid config: http2.max_frame_size: must be between 16384 and 16777215"
+    )]
+    #[case(
+        r#"{ "forward_proxy": { "ports": [443] } }"#,
+        "Invalid config: forward_proxy.allow: at least one host is required"
+    )]
+    #[case(
+        r#"{ "forward_proxy": { "allow": ["*"], "ports": [] } }"#,
+        "Invalid config: forward_proxy.ports: at least one port is required"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:53", "udp": { "idle_timeout": 30 } }] }"#,
+        "Invalid config: listeners[0].udp.addr: is required"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:53", "udp": { "addr": "a:53", "max_sessions": 0 } }] }"#,
+        "Invalid config: listeners[0].udp.max_sessions: must be at least 1"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls_passthrough": {} }] }"#,
+        "Invalid config: listeners[0].tls_passthrough.servers: at least one server is required without a `default`"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls_passthrough": { "default": "a:443" }, "udp": { "addr": "a:443" } }] }"#,
+        "Invalid config: listeners[0]: `udp` and `tls_passthrough` can't be combined"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:53", "proxy_protocol": true, "udp": { "addr": "a:53" } }] }"#,
+        "Invalid config: listeners[0].proxy_protocol: doesn't apply to `udp` listeners"
+    )]
+    #[case(
+        r#"{ "trusted_proxies": ["10.0.0.0/33"] }"#,
+        "Invalid config: trusted_proxies[0]: invalid IP address syntax"
+    )]
+    #[case(
+        r#"{ "via": "edge, 1.1 origin" }"#,
+        "Invalid config: via: must be a host name or a single word"
+    )]
+    #[case(
+        r#"{ "logging": { "level": "loud" } }"#,
+        "Invalid config: logging.level: unknown variant `loud`"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "addr": "a:1", "strip_prefix": false, "buffering": "none" }] }"#,
+        "Invalid config: routes[0]: unknown variant `none`, expected `on` or `off`"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:80" }, { "port": 80 }] }"#,
+        "Invalid config: listeners[1].port: unknown field `port`"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "addr": "a:1", "strip_prefix": false }, { "path": "/b" }] }"#,
+        "Invalid config: routes[1]: missing field `strip_prefix`"
+    )]
+    #[case(
+        r#"{ "routes": { "/": { "addr": "a:1", "strip_prefix": "no" } } }"#,
+        "Invalid config: routes./.strip_prefix: invalid type: string \"no\", expected a boolean"
+    )]
+    #[case(
+        r#"{ "routes": 5 }"#,
+        "Invalid config: routes: invalid type: integer `5`"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "upstream": "api", "strip_prefix": false }] }"#,
+        "Invalid config: routes[0]: upstream: no upstream named `api`"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "addr": "a:1", "backup": "sorry", "strip_prefix": false }] }"#,
+        "Invalid config: routes[0]: backup: no upstream named `sorry`"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "strip_prefix": false, "maintenance": { "file": "/run/agora/down" } }] }"#,
+        "Invalid config: routes[0]: maintenance.file: the route also needs one of `addr`, `upstream`, or `static_files` for when the file doesn't exist"
+    )]
+    #[case(
+        r#"{ "security_headers": { "strict_transport_security": { "max_age": 300, "preload": true } } }"#,
+        "Invalid config: security_headers.strict_transport_security.preload: requires `include_subdomains` and a `max_age` of at least 31536000"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "addr": "a:1", "strip_prefix": false, "security_headers": { "content_security_policy": "" } }] }"#,
+        "Invalid config: routes[0]: security_headers.content_security_policy: must be a non-empty header value"
+    )]
+    #[case(
+        r#"{ "security_headers": { "x_frame_options": "allow" } }"#,
+        "Invalid config: security_headers.x_frame_options: unknown variant `allow`, expected `deny` or `same_origin`"
+    )]
+    #[case(
+        r#"{ "virtual_hosts": { "example.com": [{ "path": "/", "strip_prefix": false }] } }"#,
+        "Invalid config: virtual_hosts.example.com[0]: one of `addr`, `upstream`, `static_files`, or `maintenance` is required"
+    )]
+    #[case(
+        r#"{
+            "upstreams": { "api": { "addr": "localhost:3000" } },
+            "routes": [{ "path": "/", "addr": "a:1", "upstream": "api", "strip_prefix": false }]
+        }"#,
+        "Invalid config: routes[0]: only one of `addr` and `upstream` can be set"
+    )]
+    #[case(
+        r#"{
+            "upstreams": { "api": { "addr": "https://api.internal" } },
+            "routes": [{ "path": "/", "upstream": "api", "tls": { "verify": false }, "strip_prefix": false }]
+        }"#,
+        "Invalid config: routes[0]: tls: only applies to `addr`, set it on the upstream instead"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "https://api.internal", "tls": { "ca": "missing.pem" } } } }"#,
+        "Invalid config: upstreams.api.tls.ca: failed to read missing.pem: No such file or directory (os error 2)"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "https://api.internal", "tls": { "cert": "proxy.pem" } } } }"#,
+        "Invalid config: upstreams.api.tls.key: required along with `cert`"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "a:1", "http2": true, "proxy_protocol": true } } }"#,
+        "Invalid config: upstreams.api.proxy_protocol: can't be combined with `http2`"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "key": "proxy-key.pem" } }] }"#,
+        "Invalid config: listeners[0].tls.cert: is required"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "min_version": "1.3", "max_version": "1.2" } }] }"#,
+        "Invalid config: listeners[0].tls.min_version: is newer than `max_version`"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "min_version": "1.1" } }] }"#,
+        "Invalid config: listeners[0].tls.min_version: unknown variant `1.1`, expected `1.2` or `1.3`"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "cipher_suites": ["TLS_RSA_WITH_RC4_128_SHA"] } }] }"#,
+        "Invalid config: listeners[0].tls.cipher_suites: unknown cipher suite TLS_RSA_WITH_RC4_128_SHA"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "min_version": "1.3", "cipher_suites": ["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"] } }] }"#,
+        "Invalid config: listeners[0].tls.cipher_suites: TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 isn't for an allowed version"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "cipher_suites": ["TLS13_AES_256_GCM_SHA384"] } }] }"#,
+        "Invalid config: listeners[0].tls.cipher_suites: none of them is for TLS 1.2, which is allowed"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "curves": ["secp521r1"] } }] }"#,
+        "Invalid config: listeners[0].tls.curves: unknown curve secp521r1"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "alpn": ["spdy/3"] } }] }"#,
+        "Invalid config: listeners[0].tls.alpn[0]: unknown variant `spdy/3`, expected one of `http/1.1`, `h2`, `acme-tls/1`"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "alpn": ["h2", "http/1.1", "h2"] } }] }"#,
+        "Invalid config: listeners[0].tls.alpn[2]: is listed already"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "client_auth": { "required": false } } }] }"#,
+        "Invalid config: listeners[0].tls.client_auth.ca: is required"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:80", "https_redirect": { "status": 302 } }] }"#,
+        "Invalid config: listeners[0].https_redirect.status: must be 301 or 308"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:80", "https_redirect": {}, "tls_passthrough": { "default": "10.0.0.1:443" } }] }"#,
+        "Invalid config: listeners[0].https_redirect: only applies to cleartext listeners serving HTTP"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "proxy.pem", "key": "proxy-key.pem", "client_auth": { "ca": "missing.pem" } } }] }"#,
+        "Invalid config: listeners[0].tls.client_auth.ca: failed to read missing.pem: No such file or directory (os error 2)"
+    )]
+    #[case(
+        r#"{ "listeners": [{ "address": "0.0.0.0:443", "tls": { "cert": "missing.pem", "key": "proxy-key.pem" } }] }"#,
+        "Invalid config: listeners[0].tls.cert: failed to read missing.pem: No such file or directory (os error 2)"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "a:1", "proxy": { "http": { "addr": "" } } } } }"#,
+        "Invalid config: upstreams.api.proxy.http.addr: is required"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "a:1", "proxy": { "http": { "addr": "proxy:3128", "password": "secret" } } } } }"#,
+        "Invalid config: upstreams.api.proxy.http.password: requires `username`"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "a:1", "proxy": { "socks5": { "addr": "" } } } } }"#,
+        "Invalid config: upstreams.api.proxy.socks5.addr: is required"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "addr": 5, "strip_prefix": false }] }"#,
+        "Invalid config: routes[0]: invalid type: integer `5`, expected an address, or a list of addresses"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": [] } } }"#,
+        "Invalid config: upstreams.api.addr: at least one address is required"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "srv://_api._tcp.internal", "consul": { "service": "api" } } } }"#,
+        "Invalid config: upstreams.api.addr: SRV names can't be combined with `consul`"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "srv://_api._tcp.internal", "file": "api.txt" } } }"#,
+        "Invalid config: upstreams.api.file: can't be combined with SRV names or `consul`"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "a:1", "health_check": { "interval": 0 } } } }"#,
+        "Invalid config: upstreams.api.health_check.interval: must be at least 1"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "a:1", "health_check": { "path": "health" } } } }"#,
+        "Invalid config: upstreams.api.health_check.path: must start with `/`"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "a:1", "outlier_detection": { "max_ejection_time": 10 } } } }"#,
+        "Invalid config: upstreams.api.outlier_detection.max_ejection_time: must be at least `base_ejection_time`"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "a:1", "keepalive": { "idle_timeout": 0 } } } }"#,
+        "Invalid config: upstreams.api.keepalive.idle_timeout: must be at least 1"
+    )]
+    #[case(
+        r#"{ "upstreams": { "api": { "addr": "a:1", "keepalive": { "max_requests": 0 } } } }"#,
+        "Invalid config: upstreams.api.keepalive.max_requests: must be at least 1"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "addr": "a:1", "strip_prefix": false, "retry": { "attempts": 0 } }] }"#,
+        "Invalid config: routes[0]: retry.attempts: must be at least 1"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "addr": "a:1", "strip_prefix": false, "retry": { "budget": { "percent": 150 } } }] }"#,
+        "Invalid config: routes[0]: retry.budget.percent: must be at most 100"
+    )]
+    #[case(
+        r#"{ "routes": [{ "path": "/", "addr": "a:1", "strip_prefix": false, "retry": { "on": ["4xx"] } }] }"#,
+        "Invalid config: routes[0]: unknown retry condition `4xx`, expected `connect_failure`, `reset`, `timeout`, `5xx`, or a status code"
+    )]
+    fn test_parse_errors(#[case] contents: &str, #[case] expected: &str) {
+        let error = parse("invalid.json", contents).unwrap_err();
+        assert!(error.starts_with(expected), "{error}");
+    }
+}
@@ -0,0 +1,194 @@
+//! Checks for mistakes which still make a valid config, like routes that can never match.
+
+use std::collections::HashSet;
+
+use super::{ProxyEntry, Route, ServerConfig};
+use crate::routing::PathPattern;
+
+impl ServerConfig {
+    /// Describe the likely mistakes in the config, each prefixed with where it is
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut used_upstreams = HashSet::new();
+
+        let mut route_lists: Vec<_> = self
+            .virtual_hosts
+            .iter()
+            .map(|(name, routes)| (format!("virtual_hosts.{name}"), routes))
+            .collect();
+        route_lists.sort_by(|(a, _), (b, _)| a.cmp(b));
+        route_lists.insert(0, (String::from("routes"), &self.routes));
+
+        for (section, routes) in route_lists {
+            for (i, route) in routes.iter().enumerate() {
+                if let Some(j) =
+                    (0..routes.len()).find(|&j| j != i && shadows(&routes[j], j < i, route))
+                {
+                    warnings.push(format!(
+                        "{section}[{i}]: never matches, since {section}[{j}] ({}) matches first",
+                        routes[j].path
+                    ));
+                }
+                if let Some(upstream) = &route.entry.upstream {
+                    used_upstreams.insert(upstream.as_str());
+                }
+                warnings.extend(
+                    entry_warnings(&route.entry)
+                        .into_iter()
+                        .map(|warning| format!("{section}[{i}]: {warning}")),
+                );
+            }
+        }
+
+        let mut upstreams: Vec<_> = self.upstreams.iter().collect();
+        upstreams.sort_by_key(|(name, _)| name.as_str());
+        for (name, upstream) in upstreams {
+            if !used_upstreams.contains(name.as_str()) {
+                warnings.push(format!("upstreams.{name}: not used by any route"));
+            }
+            for addr in upstream.addr.iter().filter(|addr| !has_port(addr)) {
+                warnings.push(format!("upstreams.{name}.addr: {addr} has no port"));
+            }
+        }
+
+        warnings
+    }
+}
+
+/// Whether every request that could match `route` matches `other` first. `before` is
+/// whether `other` is listed before `route`.
+fn shadows(other: &Route, before: bool, route: &Route) -> bool {
+    if !other.entry.headers.is_empty() || !other.entry.query.is_empty() {
+        return false;
+    }
+
+    match (&other.path, &route.path) {
+        (PathPattern::Prefix(other_prefix), PathPattern::Prefix(prefix))
+            if other.priority > route.priority =>
+        {
+            prefix.starts_with(other_prefix.as_str())
+        }
+        // among the same patterns, the first one of the highest priority wins
+        _ => {
+            other.path == route.path
+                && (other.priority > route.priority || (other.priority == route.priority && before))
+        }
+    }
+}
+
+fn entry_warnings(entry: &ProxyEntry) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let addrs = entry.addr.iter().map(|addr| ("addr", addr));
+    for (name, addr) in addrs.chain(entry.mirror.as_deref().map(|addr| ("mirror", addr))) {
+        if !addr.is_empty() && !has_port(addr) {
+            warnings.push(format!("{name}: {addr} has no port"));
+        }
+    }
+    if let Some(static_files) = &entry.static_files
+        && !static_files.root.is_dir()
+    {
+        warnings.push(format!(
+            "static_files.root: {} isn't a directory",
+            static_files.root.display()
+        ));
+    }
+    warnings
+}
+
+/// Whether an address ends with a port, like `localhost:3000` or `[::1]:3000`.
+/// Addresses with a scheme, like `https://api.internal` or SRV names, need none.
+fn has_port(addr: &str) -> bool {
+    addr.contains("://")
+        || addr
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.ends_with(':') && port.parse::<u16>().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    fn warnings(config: serde_json::Value) -> Vec<String> {
+        serde_json::from_value::<ServerConfig>(config)
+            .unwrap()
+            .warnings()
+    }
+
+    #[rstest]
+    #[case("localhost:3000", true)]
+    #[case("[::1]:3000", true)]
+    #[case("localhost", false)]
+    #[case("localhost:http", false)]
+    #[case("::1", false)]
+    #[case("https://api.internal", true)]
+    #[case("srv://_api._tcp.internal", true)]
+    fn test_has_port(#[case] addr: &str, #[case] expected: bool) {
+        assert_eq!(expected, has_port(addr));
+    }
+
+    #[test]
+    fn test_warnings() {
+        let warnings = warnings(json!({
+            "upstreams": { "api": { "addr": "a:1" }, "old": { "addr": ["b:1", "b"] } },
+            "routes": [
+                { "path": "/", "addr": "a:1", "strip_prefix": false },
+                { "path": "/api", "upstream": "api", "strip_prefix": false },
+                { "path": "/api", "addr": "a:2", "strip_prefix": false },
+                { "path": "/admin", "priority": 5, "addr": "localhost", "strip_prefix": false },
+                { "path": "/admin/users", "addr": ["a:3", "c"], "strip_prefix": false },
+                {
+                    "path": "/beta",
+                    "priority": 5,
+                    "addr": "a:4",
+                    "strip_prefix": false,
+                    "headers": [{ "name": "x-beta", "exists": true }]
+                },
+                { "path": "/beta", "addr": "a:5", "strip_prefix": false },
+                { "path": "~^/v[0-9]+", "addr": "a:6", "strip_prefix": false },
+                { "path": "~^/v[0-9]+", "addr": "a:7", "strip_prefix": false },
+                {
+                    "path": "/files",
+                    "strip_prefix": false,
+                    "static_files": { "root": "/does/not/exist" }
+                }
+            ]
+        }));
+
+        assert_eq!(
+            vec![
+                "routes[2]: never matches, since routes[1] (/api) matches first",
+                "routes[3]: addr: localhost has no port",
+                "routes[4]: never matches, since routes[3] (/admin) matches first",
+                "routes[4]: addr: c has no port",
+                "routes[8]: never matches, since routes[7] (~^/v[0-9]+) matches first",
+                "routes[9]: static_files.root: /does/not/exist isn't a directory",
+                "upstreams.old: not used by any route",
+                "upstreams.old.addr: b has no port",
+            ],
+            warnings
+        );
+    }
+
+    #[test]
+    fn test_warnings_virtual_hosts() {
+        let warnings = warnings(json!({
+            "routes": [{ "path": "/", "addr": "a:1", "strip_prefix": false }],
+            "virtual_hosts": {
+                "example.com": [
+                    { "path": "/", "priority": 1, "addr": "a:1", "strip_prefix": false },
+                    { "path": "/api", "addr": "a:2", "strip_prefix": false }
+                ]
+            }
+        }));
+
+        assert_eq!(
+            vec![
+                "virtual_hosts.example.com[1]: never matches, since virtual_hosts.example.com[0] (/) matches first"
+            ],
+            warnings
+        );
+    }
+}
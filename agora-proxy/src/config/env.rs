@@ -0,0 +1,173 @@
+//! Environment variables in config values.
+//!
+//! String values can refer to environment variables as `${NAME}`, or `${NAME:-default}`
+//! to fall back to a default when the variable is unset or empty. `$${` is a literal
+//! `${`. Rewrite replacements are left as they are, since `${name}` refers to a capture
+//! group there.
+
+use serde_json::Value;
+
+/// Replace the environment variables in the string values of a config file
+pub(super) fn interpolate(config: &mut Value) -> Result<(), String> {
+    interpolate_with(config, &|name| std::env::var(name).ok())
+}
+
+fn interpolate_with(
+    config: &mut Value,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    walk(config, &mut String::new(), env)
+}
+
+/// Interpolate every string under the value, with `path` naming the value in errors
+fn walk(
+    value: &mut Value,
+    path: &mut String,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    match value {
+        Value::String(string) => {
+            *string = expand(string, env).map_err(|e| {
+                if path.is_empty() {
+                    e
+                } else {
+                    format!("{path}: {e}")
+                }
+            })?;
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                let len = path.len();
+                path.push_str(&format!("[{i}]"));
+                walk(value, path, env)?;
+                path.truncate(len);
+            }
+        }
+        Value::Object(values) => {
+            let is_rewrite = path == "rewrite" || path.ends_with(".rewrite");
+            for (key, value) in values.iter_mut() {
+                if is_rewrite && key == "replacement" {
+                    continue;
+                }
+                let len = path.len();
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+                walk(value, path, env)?;
+                path.truncate(len);
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+/// Replace the variables in a string
+fn expand(template: &str, env: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            // an escaped placeholder
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        let end = rest
+            .find('}')
+            .ok_or_else(|| format!("unclosed variable in {template:?}"))?;
+        let (name, default) = match rest[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&rest[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid variable name {name:?}"));
+        }
+
+        match (env(name).filter(|value| !value.is_empty()), default) {
+            (Some(value), _) => expanded.push_str(&value),
+            (None, Some(default)) => expanded.push_str(default),
+            (None, None) => return Err(format!("environment variable {name} is not set")),
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    fn env(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some(String::from("backend")),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[rstest]
+    #[case("plain", Ok("plain"))]
+    #[case("${HOST}:80", Ok("backend:80"))]
+    #[case("0.0.0.0:${PORT:-8080}", Ok("0.0.0.0:8080"))]
+    #[case("${HOST:-other}", Ok("backend"))]
+    #[case("${EMPTY:-default}", Ok("default"))]
+    #[case("${PORT:-}", Ok(""))]
+    #[case("$${HOST} and $HOST", Ok("${HOST} and $HOST"))]
+    #[case("${PORT}", Err("environment variable PORT is not set"))]
+    #[case("${HOST", Err("unclosed variable in \"${HOST\""))]
+    #[case("${NOT-VALID}", Err("invalid variable name \"NOT-VALID\""))]
+    fn test_expand(#[case] template: &str, #[case] expected: Result<&str, &str>) {
+        assert_eq!(
+            expected.map(String::from).map_err(String::from),
+            expand(template, &env)
+        );
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let mut config = json!({
+            "listeners": [{ "address": "0.0.0.0:${PORT:-8080}" }],
+            "routes": [{
+                "path": "/",
+                "addr": "${HOST}:3000",
+                "priority": 1,
+                "rewrite": { "pattern": "^/(?<rest>.*)", "replacement": "/${rest}" }
+            }]
+        });
+        interpolate_with(&mut config, &env).unwrap();
+
+        assert_eq!(
+            json!({
+                "listeners": [{ "address": "0.0.0.0:8080" }],
+                "routes": [{
+                    "path": "/",
+                    "addr": "backend:3000",
+                    "priority": 1,
+                    "rewrite": { "pattern": "^/(?<rest>.*)", "replacement": "/${rest}" }
+                }]
+            }),
+            config
+        );
+    }
+
+    #[test]
+    fn test_interpolate_error_names_key() {
+        let mut config = json!({ "upstreams": { "api": { "addr": "${API_ADDR}" } } });
+        assert_eq!(
+            Err(String::from(
+                "upstreams.api.addr: environment variable API_ADDR is not set"
+            )),
+            interpolate_with(&mut config, &env)
+        );
+    }
+}
@@ -0,0 +1,330 @@
+//! Loading a config file together with the files it includes.
+//!
+//! A file lists the paths or glob patterns of other files under `include`, relative to
+//! its own directory. Their `routes` and `listeners` are appended after those of the
+//! including file, and their `upstreams` and `virtual_hosts` are added to its own.
+//! Anything defined twice is an error, as is a file including itself.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Map, Value};
+
+use super::{ConfigFormat, env};
+
+/// Read the config file and everything it includes into a single config tree
+pub(super) fn load(path: &Path, format: ConfigFormat) -> Result<Map<String, Value>, String> {
+    load_file(path, format, &mut Vec::new())
+}
+
+/// Read a config file and its includes. `including` has the files currently being
+/// loaded, to catch include cycles.
+fn load_file(
+    path: &Path,
+    format: ConfigFormat,
+    including: &mut Vec<PathBuf>,
+) -> Result<Map<String, Value>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config {}: {e}", path.display()))?;
+    let name = if including.is_empty() {
+        String::new()
+    } else {
+        format!(" {}", path.display())
+    };
+    let mut config = format
+        .parse(&contents)
+        .map_err(|e| format!("Failed to parse config{name}: {e}"))?;
+    env::interpolate(&mut config).map_err(|e| format!("Invalid config{name}: {e}"))?;
+    let mut config = sections(config);
+
+    let Some(patterns) = config.remove("include") else {
+        return Ok(config);
+    };
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if including.contains(&canonical) {
+        return Err(format!("{} includes itself", path.display()));
+    }
+    including.push(canonical);
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let included_paths =
+        included_paths(dir, patterns).map_err(|e| format!("{}: {e}", path.display()))?;
+    for included in included_paths {
+        let included_format = ConfigFormat::from_path(&included).unwrap_or(format);
+        let other = load_file(&included, included_format, including)?;
+        merge(&mut config, other).map_err(|e| format!("{}: {e}", included.display()))?;
+    }
+
+    including.pop();
+    Ok(config)
+}
+
+/// The sections of a config file, which might only have routes
+fn sections(config: Value) -> Map<String, Value> {
+    let mut sections = match config {
+        Value::Object(sections) if !is_route_mapping(&sections) => sections,
+        routes => return Map::from_iter([(String::from("routes"), routes)]),
+    };
+    // merge routes under their current name
+    if !sections.contains_key("routes")
+        && let Some(routes) = sections.remove("reverse_proxy_mapping")
+    {
+        sections.insert(String::from("routes"), routes);
+    }
+    sections
+}
+
+/// Whether the keys of an object are path patterns, like in the old form of configs
+fn is_route_mapping(object: &Map<String, Value>) -> bool {
+    !object.is_empty()
+        && object
+            .keys()
+            .all(|key| key.starts_with('/') || key.starts_with('~'))
+}
+
+/// The files to include, in the order they are listed. Matches of a glob pattern are
+/// sorted, and may be empty, but paths without wildcards must exist.
+fn included_paths(dir: &Path, patterns: Value) -> Result<Vec<PathBuf>, String> {
+    let patterns = match patterns {
+        Value::String(pattern) => vec![pattern],
+        Value::Array(patterns) => patterns
+            .into_iter()
+            .map(|pattern| match pattern {
+                Value::String(pattern) => Ok(pattern),
+                _ => Err(String::from("include: expected a list of paths")),
+            })
+            .collect::<Result<_, _>>()?,
+        _ => return Err(String::from("include: expected a list of paths")),
+    };
+
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let full = dir.join(&pattern);
+        let full = full.to_string_lossy();
+        if glob::Pattern::escape(&full) == full {
+            if !Path::new(full.as_ref()).exists() {
+                return Err(format!("include: {pattern} doesn't exist"));
+            }
+            paths.push(PathBuf::from(full.as_ref()));
+            continue;
+        }
+
+        let mut matches = glob::glob(&full)
+            .map_err(|e| format!("include: invalid pattern {pattern}: {e}"))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("include: {e}"))?;
+        matches.sort();
+        paths.extend(matches);
+    }
+    Ok(paths)
+}
+
+/// Add the sections of an included file to a config
+fn merge(config: &mut Map<String, Value>, other: Map<String, Value>) -> Result<(), String> {
+    for (section, value) in other {
+        let Some(existing) = config.get_mut(&section) else {
+            config.insert(section, value);
+            continue;
+        };
+
+        match section.as_str() {
+            "routes" => {
+                let routes = route_list(existing);
+                let before = routes.len();
+                for route in into_route_list(value) {
+                    if let Some(i) = routes[..before]
+                        .iter()
+                        .position(|existing| same_route(existing, &route))
+                    {
+                        return Err(format!("route {} duplicates routes[{i}]", route["path"]));
+                    }
+                    routes.push(route);
+                }
+            }
+            "listeners" => {
+                let (Value::Array(listeners), Value::Array(others)) = (existing, value) else {
+                    return Err(String::from("listeners: expected a list"));
+                };
+                for listener in others {
+                    if listeners.contains(&listener) {
+                        return Err(format!("listener {listener} is already defined"));
+                    }
+                    listeners.push(listener);
+                }
+            }
+            "upstreams" | "virtual_hosts" => {
+                let (Value::Object(names), Value::Object(others)) = (existing, value) else {
+                    return Err(format!("{section}: expected a mapping"));
+                };
+                for (name, value) in others {
+                    if names.contains_key(&name) {
+                        return Err(format!("{section}.{name} is already defined"));
+                    }
+                    names.insert(name, value);
+                }
+            }
+            _ => return Err(format!("{section} is already set")),
+        }
+    }
+    Ok(())
+}
+
+/// The routes of a section, converting a mapping of path patterns to a list in place
+fn route_list(routes: &mut Value) -> &mut Vec<Value> {
+    if !routes.is_array() {
+        *routes = Value::Array(into_route_list(routes.take()));
+    }
+    match routes {
+        Value::Array(routes) => routes,
+        _ => unreachable!(),
+    }
+}
+
+fn into_route_list(routes: Value) -> Vec<Value> {
+    match routes {
+        Value::Array(routes) => routes,
+        // mappings are ordered by pattern, which is also how they are ordered when
+        // loaded on their own
+        Value::Object(mapping) => mapping
+            .into_iter()
+            .map(|(path, mut entry)| {
+                if let Value::Object(entry) = &mut entry {
+                    entry.insert(String::from("path"), Value::String(path));
+                }
+                entry
+            })
+            .collect(),
+        other => vec![other],
+    }
+}
+
+/// Whether two routes match exactly the same requests
+fn same_route(a: &Value, b: &Value) -> bool {
+    let priority = |route: &Value| route.get("priority").and_then(Value::as_i64).unwrap_or(0);
+    a.get("path") == b.get("path")
+        && priority(a) == priority(b)
+        && a.get("headers") == b.get("headers")
+        && a.get("query") == b.get("query")
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("agora-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_load_includes() {
+        let dir = temp_dir("include");
+        let main = write(
+            &dir,
+            "main.json",
+            r#"{
+                "include": ["sites/*.yaml", "upstreams.toml"],
+                "routes": [{ "path": "/", "addr": "a:1", "strip_prefix": false }]
+            }"#,
+        );
+        write(
+            &dir,
+            "sites/b.yaml",
+            "virtual_hosts:\n  b.example.com:\n    - { path: /, upstream: b, strip_prefix: false }\n",
+        );
+        write(
+            &dir,
+            "sites/a.yaml",
+            "- { path: /a, upstream: a, strip_prefix: false }\n",
+        );
+        write(
+            &dir,
+            "upstreams.toml",
+            "[upstreams.a]\naddr = \"a:2\"\n[upstreams.b]\naddr = \"b:1\"\n",
+        );
+
+        let config = load(&main, ConfigFormat::Json).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            json!([
+                { "path": "/", "addr": "a:1", "strip_prefix": false },
+                { "path": "/a", "upstream": "a", "strip_prefix": false },
+            ]),
+            config["routes"]
+        );
+        assert_eq!(
+            json!({ "a": { "addr": "a:2" }, "b": { "addr": "b:1" } }),
+            config["upstreams"]
+        );
+        assert!(config["virtual_hosts"].get("b.example.com").is_some());
+        assert!(config.get("include").is_none());
+    }
+
+    #[test]
+    fn test_load_legacy_mappings() {
+        let dir = temp_dir("include-legacy");
+        let main = write(
+            &dir,
+            "main.json",
+            r#"{ "include": "more.json", "reverse_proxy_mapping": { "/b": { "addr": "b:1" } } }"#,
+        );
+        write(&dir, "more.json", r#"{ "/a": { "addr": "a:1" } }"#);
+
+        let config = load(&main, ConfigFormat::Json).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            json!([{ "path": "/b", "addr": "b:1" }, { "path": "/a", "addr": "a:1" }]),
+            config["routes"]
+        );
+    }
+
+    #[rstest]
+    #[case(
+        r#"[{ "path": "/", "addr": "b:1", "strip_prefix": false }]"#,
+        "route \"/\" duplicates routes[0]"
+    )]
+    #[case(
+        r#"{ "upstreams": { "a": { "addr": "b:1" } } }"#,
+        "upstreams.a is already defined"
+    )]
+    #[case(r#"{ "limits": { "request_timeout": 1 } }"#, "limits is already set")]
+    #[case(r#"{ "include": ["../main.json"] }"#, "main.json includes itself")]
+    #[case(
+        r#"{ "include": ["missing.json"] }"#,
+        "include: missing.json doesn't exist"
+    )]
+    fn test_load_errors(#[case] included: &str, #[case] expected: &str) {
+        let dir = temp_dir("include-errors");
+        let main = write(
+            &dir,
+            "main.json",
+            r#"{
+                "include": ["conf.d/*.json"],
+                "routes": [{ "path": "/", "addr": "a:1", "strip_prefix": false }],
+                "upstreams": { "a": { "addr": "a:1" } },
+                "limits": { "request_timeout": 5 }
+            }"#,
+        );
+        write(&dir, "conf.d/other.json", included);
+
+        let error = load(&main, ConfigFormat::Json).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(error.contains(expected), "{error}");
+    }
+}
@@ -0,0 +1,487 @@
+//! Headers telling upstreams about the client and the request it sent, which the
+//! proxy otherwise hides from them. The same headers tell the proxy about the client
+//! behind a trusted proxy in front of it.
+
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use agora_http_parser::{HeaderName, HeaderValue, Headers, Request};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+/// The headers a client could use to pass for another, only kept from trusted proxies
+const FORWARDING: [&str; 7] = [
+    "x-forwarded-for",
+    "x-forwarded-proto",
+    "x-forwarded-host",
+    "x-forwarded-port",
+    "forwarded",
+    "x-client-cert-subject",
+    "x-client-cert-fingerprint",
+];
+
+/// Which of the headers about the client are sent upstream. All of them are by default,
+/// except the standard `Forwarded` header which few backends read, and those about the
+/// certificate of the client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForwardedHeaders {
+    /// Append the IP address of the client to `X-Forwarded-For`
+    pub x_forwarded_for: bool,
+    /// Set `X-Forwarded-Proto` to the scheme the client used, unless a trusted proxy did
+    pub x_forwarded_proto: bool,
+    /// Set `X-Forwarded-Host` to the Host header sent by the client, unless a trusted
+    /// proxy did
+    pub x_forwarded_host: bool,
+    /// Set `X-Forwarded-Port` to the port the client connected to, unless a trusted
+    /// proxy did
+    pub x_forwarded_port: bool,
+    /// Append an element with all of the above to `Forwarded` (RFC 7239)
+    pub forwarded: bool,
+    /// Set `X-Client-Cert-Subject` to the subject of the certificate the client
+    /// authenticated with
+    pub x_client_cert_subject: bool,
+    /// Set `X-Client-Cert-Fingerprint` to the SHA-256 fingerprint of that certificate
+    pub x_client_cert_fingerprint: bool,
+}
+
+impl Default for ForwardedHeaders {
+    fn default() -> Self {
+        Self {
+            x_forwarded_for: true,
+            x_forwarded_proto: true,
+            x_forwarded_host: true,
+            x_forwarded_port: true,
+            forwarded: false,
+            x_client_cert_subject: false,
+            x_client_cert_fingerprint: false,
+        }
+    }
+}
+
+/// The certificate a client authenticated with over TLS
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCert {
+    /// Its subject, like `CN=client, O=Example`
+    pub subject: String,
+    /// The SHA-256 digest of the certificate, in lowercase hex
+    pub fingerprint: String,
+}
+
+/// What the proxy knows about how a request reached it
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// The address the request came from
+    pub addr: SocketAddr,
+    /// The address of the proxy it was sent to
+    pub local_addr: Option<SocketAddr>,
+    /// Whether it was sent over TLS
+    pub tls: bool,
+    /// The certificate the client authenticated with over TLS, if any
+    pub cert: Option<Arc<ClientCert>>,
+    /// Whether `addr` is a trusted proxy, whose forwarding headers are kept
+    pub trusted: bool,
+    /// The IP address of the client, behind any trusted proxies
+    pub ip: IpAddr,
+}
+
+impl Client {
+    /// How a request from `addr` reached the proxy. Behind trusted proxies, the client
+    /// is the last address in `X-Forwarded-For`, or else `Forwarded`, which isn't one of
+    /// them.
+    pub fn new(
+        request: &Request,
+        addr: SocketAddr,
+        local_addr: Option<SocketAddr>,
+        tls: bool,
+        cert: Option<Arc<ClientCert>>,
+        trusted_proxies: &[IpNet],
+    ) -> Self {
+        let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+        let trusted = is_trusted(&addr.ip());
+        let mut ip = addr.ip();
+        if trusted {
+            for hop in forwarded_for(request).into_iter().rev() {
+                // a hop that can't be read can't be told apart from a spoofed one
+                let Some(hop) = hop else { break };
+                ip = hop;
+                if !is_trusted(&hop) {
+                    break;
+                }
+            }
+        }
+        Self {
+            addr,
+            local_addr,
+            tls,
+            cert,
+            trusted,
+            ip,
+        }
+    }
+
+    /// The scheme the request was sent to the proxy with
+    fn proto(&self) -> &'static str {
+        if self.tls { "https" } else { "http" }
+    }
+}
+
+/// Shows the address the request came from, along with the client behind it if that is
+/// another
+impl Display for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.ip == self.addr.ip() {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{} via {}", self.ip, self.addr)
+        }
+    }
+}
+
+/// The addresses the request was forwarded for, from the first to the last proxy
+fn forwarded_for(request: &Request) -> Vec<Option<IpAddr>> {
+    if let Some(chain) = request.headers.get("x-forwarded-for") {
+        return String::from_utf8_lossy(chain.as_bytes())
+            .split(',')
+            .map(node_ip)
+            .collect();
+    }
+    let Some(forwarded) = request.headers.get("forwarded") else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(forwarded.as_bytes())
+        .split(',')
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| node_ip(node))
+        })
+        .collect()
+}
+
+/// The IP address of a node, which may be quoted, bracketed, or come with a port
+fn node_ip(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| node.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+impl ForwardedHeaders {
+    /// Add the headers to a request about to be sent upstream, replacing those sent by
+    /// a client that isn't a trusted proxy. Those of a trusted proxy about how the
+    /// client reached it are kept, since it is the one the client sent the request to.
+    pub fn apply(&self, request: &mut Request, client: &Client) {
+        let host = request.headers.get("host").cloned();
+        let headers = &mut request.headers;
+        if !client.trusted {
+            for name in FORWARDING {
+                headers.remove(name);
+            }
+        }
+        if self.x_forwarded_for
+            && let Ok(ip) = HeaderValue::try_from(client.addr.ip().to_string())
+        {
+            headers.append(HeaderName::from_static("x-forwarded-for"), ip);
+        }
+        // set only when missing, as those of untrusted clients are gone already
+        let set_missing = |headers: &mut Headers, name: &'static str, value: HeaderValue| {
+            if !headers.contains_key(name) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        };
+        if self.x_forwarded_proto {
+            set_missing(
+                headers,
+                "x-forwarded-proto",
+                HeaderValue::from_static(client.proto()),
+            );
+        }
+        if self.x_forwarded_host
+            && let Some(host) = &host
+        {
+            set_missing(headers, "x-forwarded-host", host.clone());
+        }
+        if self.x_forwarded_port
+            && let Some(local_addr) = client.local_addr
+        {
+            set_missing(
+                headers,
+                "x-forwarded-port",
+                u64::from(local_addr.port()).into(),
+            );
+        }
+        if self.forwarded {
+            let element = element(client, host.as_ref().and_then(|host| host.to_str().ok()));
+            if let Ok(element) = HeaderValue::try_from(element) {
+                headers.append(HeaderName::from_static("forwarded"), element);
+            }
+        }
+        if let Some(cert) = &client.cert {
+            if self.x_client_cert_subject
+                && let Ok(subject) = HeaderValue::try_from(cert.subject.as_str())
+            {
+                headers.insert(HeaderName::from_static("x-client-cert-subject"), subject);
+            }
+            if self.x_client_cert_fingerprint
+                && let Ok(fingerprint) = HeaderValue::try_from(cert.fingerprint.as_str())
+            {
+                headers.insert(
+                    HeaderName::from_static("x-client-cert-fingerprint"),
+                    fingerprint,
+                );
+            }
+        }
+    }
+}
+
+/// The element of the `Forwarded` header for this hop, like
+/// `for=192.0.2.43;host=example.com;proto=http`
+fn element(client: &Client, host: Option<&str>) -> String {
+    let mut element = format!("for={}", node(client.addr.ip()));
+    if let Some(host) = host {
+        element.push_str(";host=");
+        element.push_str(&value(host));
+    }
+    element.push_str(";proto=");
+    element.push_str(client.proto());
+    element
+}
+
+/// A node identifier, IPv6 addresses being bracketed and quoted (RFC 7239 section 6)
+fn node(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{ip}]\""),
+    }
+}
+
+/// A parameter value, quoted unless it is a token
+fn value(value: &str) -> String {
+    let is_token = !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if is_token {
+        return value.to_string();
+    }
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn client(addr: &str) -> Client {
+        let addr: SocketAddr = addr.parse().unwrap();
+        Client {
+            addr,
+            local_addr: Some("10.0.0.1:8080".parse().unwrap()),
+            tls: false,
+            cert: None,
+            trusted: true,
+            ip: addr.ip(),
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let (mut request, _) = Request::parse(
+            b"GET / HTTP/1.1\r\nhost: example.com\r\nx-forwarded-for: 203.0.113.7\r\n\r\n",
+        )
+        .unwrap();
+        let forwarded = ForwardedHeaders {
+            forwarded: true,
+            ..Default::default()
+        };
+        forwarded.apply(&mut request, &client("192.0.2.43:51000"));
+
+        let header = |name| request.headers.get(name).map(|v| v.to_str().unwrap());
+        assert_eq!(Some("203.0.113.7, 192.0.2.43"), header("x-forwarded-for"));
+        assert_eq!(Some("http"), header("x-forwarded-proto"));
+        assert_eq!(Some("example.com"), header("x-forwarded-host"));
+        assert_eq!(Some("8080"), header("x-forwarded-port"));
+        assert_eq!(
+            Some("for=192.0.2.43;host=example.com;proto=http"),
+            header("forwarded")
+        );
+    }
+
+    #[test]
+    fn test_apply_none() {
+        let (mut request, _) =
+            Request::parse(b"GET / HTTP/1.1\r\nhost: example.com\r\n\r\n").unwrap();
+        let forwarded = ForwardedHeaders {
+            x_forwarded_for: false,
+            x_forwarded_proto: false,
+            x_forwarded_host: false,
+            x_forwarded_port: false,
+            forwarded: false,
+            x_client_cert_subject: false,
+            x_client_cert_fingerprint: false,
+        };
+        forwarded.apply(&mut request, &client("192.0.2.43:51000"));
+
+        assert_eq!(1, request.headers.len());
+    }
+
+    #[test]
+    fn test_apply_tls() {
+        let (mut request, _) =
+            Request::parse(b"GET / HTTP/1.1\r\nhost: example.com\r\n\r\n").unwrap();
+        let forwarded = ForwardedHeaders {
+            forwarded: true,
+            ..Default::default()
+        };
+        let client = Client {
+            tls: true,
+            ..client("192.0.2.43:51000")
+        };
+        forwarded.apply(&mut request, &client);
+
+        let header = |name| request.headers.get(name).map(|v| v.to_str().unwrap());
+        assert_eq!(Some("https"), header("x-forwarded-proto"));
+        assert_eq!(
+            Some("for=192.0.2.43;host=example.com;proto=https"),
+            header("forwarded")
+        );
+    }
+
+    #[test]
+    fn test_apply_client_cert() {
+        let (mut request, _) = Request::parse(
+            b"GET / HTTP/1.1\r\nx-client-cert-subject: CN=admin\r\nx-client-cert-fingerprint: 00\r\n\r\n",
+        )
+        .unwrap();
+        let forwarded = ForwardedHeaders {
+            x_client_cert_subject: true,
+            x_client_cert_fingerprint: true,
+            ..Default::default()
+        };
+        let client = Client {
+            tls: true,
+            cert: Some(Arc::new(ClientCert {
+                subject: String::from("CN=client, O=Example"),
+                fingerprint: String::from("9f86d081884c7d65"),
+            })),
+            trusted: false,
+            ..client("192.0.2.43:51000")
+        };
+        forwarded.apply(&mut request, &client);
+
+        let header = |name| request.headers.get(name).map(|v| v.to_str().unwrap());
+        assert_eq!(
+            Some("CN=client, O=Example"),
+            header("x-client-cert-subject")
+        );
+        assert_eq!(
+            Some("9f86d081884c7d65"),
+            header("x-client-cert-fingerprint")
+        );
+
+        // clients without a certificate can't make one up
+        let (mut request, _) =
+            Request::parse(b"GET / HTTP/1.1\r\nx-client-cert-subject: CN=admin\r\n\r\n").unwrap();
+        let client = Client {
+            cert: None,
+            ..client
+        };
+        forwarded.apply(&mut request, &client);
+        assert_eq!(None, request.headers.get("x-client-cert-subject"));
+    }
+
+    #[test]
+    fn test_apply_untrusted() {
+        let (mut request, _) = Request::parse(
+            b"GET / HTTP/1.1\r\nx-forwarded-for: 203.0.113.7\r\nx-forwarded-proto: https\r\n\r\n",
+        )
+        .unwrap();
+        let client = Client {
+            trusted: false,
+            ..client("192.0.2.43:51000")
+        };
+        ForwardedHeaders {
+            x_forwarded_proto: false,
+            ..Default::default()
+        }
+        .apply(&mut request, &client);
+
+        let header = |name| request.headers.get(name).map(|v| v.to_str().unwrap());
+        assert_eq!(Some("192.0.2.43"), header("x-forwarded-for"));
+        assert_eq!(None, header("x-forwarded-proto"));
+    }
+
+    #[test]
+    fn test_apply_trusted() {
+        // behind a load balancer terminating TLS
+        let (mut request, _) = Request::parse(
+            b"GET / HTTP/1.1\r\nhost: backend.internal\r\nx-forwarded-for: 203.0.113.7\r\n\
+              x-forwarded-proto: https\r\nx-forwarded-host: example.com\r\nx-forwarded-port: 443\r\n\r\n",
+        )
+        .unwrap();
+        ForwardedHeaders::default().apply(&mut request, &client("10.0.0.2:51000"));
+
+        let header = |name| request.headers.get(name).map(|v| v.to_str().unwrap());
+        assert_eq!(Some("203.0.113.7, 10.0.0.2"), header("x-forwarded-for"));
+        assert_eq!(Some("https"), header("x-forwarded-proto"));
+        assert_eq!(Some("example.com"), header("x-forwarded-host"));
+        assert_eq!(Some("443"), header("x-forwarded-port"));
+    }
+
+    #[rstest]
+    // only trusted proxies are believed
+    #[case("203.0.113.7:4000", "x-forwarded-for: 198.51.100.1", "203.0.113.7")]
+    #[case("10.0.0.2:4000", "x-forwarded-for: 198.51.100.1", "198.51.100.1")]
+    // a client can put anything at the start of the chain, but not after it
+    #[case(
+        "10.0.0.2:4000",
+        "x-forwarded-for: 1.2.3.4, 198.51.100.1, 10.0.0.3",
+        "198.51.100.1"
+    )]
+    #[case("10.0.0.2:4000", "x-forwarded-for: junk, 10.0.0.3", "10.0.0.3")]
+    #[case("10.0.0.2:4000", "x-forwarded-for: 10.0.0.3", "10.0.0.3")]
+    #[case("10.0.0.2:4000", "x-other: 1", "10.0.0.2")]
+    #[case(
+        "10.0.0.2:4000",
+        "forwarded: for=198.51.100.1, for=\"[2001:db8::1]:4711\";proto=https",
+        "2001:db8::1"
+    )]
+    #[case(
+        "10.0.0.2:4000",
+        "forwarded: For=198.51.100.1;by=10.0.0.2",
+        "198.51.100.1"
+    )]
+    fn test_client_ip(#[case] addr: &str, #[case] header: &str, #[case] expected: &str) {
+        let head = format!("GET / HTTP/1.1\r\n{header}\r\n\r\n");
+        let (request, _) = Request::parse(head.as_bytes()).unwrap();
+        let trusted_proxies = ["10.0.0.0/8".parse().unwrap()];
+        let client = Client::new(
+            &request,
+            addr.parse().unwrap(),
+            None,
+            false,
+            None,
+            &trusted_proxies,
+        );
+        assert_eq!(expected.parse::<IpAddr>().unwrap(), client.ip);
+    }
+
+    #[rstest]
+    #[case("192.0.2.43:51000", None, "for=192.0.2.43;proto=http")]
+    #[case(
+        "[2001:db8::1]:51000",
+        Some("example.com:8080"),
+        "for=\"[2001:db8::1]\";host=\"example.com:8080\";proto=http"
+    )]
+    fn test_element(#[case] addr: &str, #[case] host: Option<&str>, #[case] expected: &str) {
+        assert_eq!(expected, element(&client(addr), host));
+    }
+}
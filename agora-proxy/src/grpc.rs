@@ -0,0 +1,79 @@
+//! gRPC routes, whose clients read the outcome of a call from the `grpc-status` and
+//! `grpc-message` trailers rather than from the HTTP status.
+
+use agora_http_parser::{HeaderName, HeaderValue, Response};
+use http::StatusCode;
+
+/// The gRPC status codes a proxy answers with (from `grpc/status.h`)
+const UNKNOWN: u32 = 2;
+const PERMISSION_DENIED: u32 = 7;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+const UNAVAILABLE: u32 = 14;
+const UNAUTHENTICATED: u32 = 16;
+
+/// The gRPC status for a call failing with the HTTP status, following the mapping gRPC
+/// clients apply to responses without a `grpc-status`
+pub fn status(status: StatusCode) -> u32 {
+    match status.as_u16() {
+        400 => INTERNAL,
+        401 => UNAUTHENTICATED,
+        403 => PERMISSION_DENIED,
+        404 => UNIMPLEMENTED,
+        429 | 502 | 503 | 504 => UNAVAILABLE,
+        _ => UNKNOWN,
+    }
+}
+
+/// A Trailers-Only response failing the call, for the proxy to answer gRPC clients
+/// with instead of an HTTP error they would only see as an unknown failure
+pub fn error_response(status_code: StatusCode) -> Response {
+    let mut response = Response::new(StatusCode::OK);
+    response.header(
+        HeaderName::from_static("content-type"),
+        HeaderValue::from_static("application/grpc"),
+    );
+    response.header(
+        HeaderName::from_static("grpc-status"),
+        u64::from(status(status_code)).into(),
+    );
+    // reasons are printable ASCII, which doesn't need percent-encoding
+    if let Some(reason) = status_code.canonical_reason() {
+        response.header(
+            HeaderName::from_static("grpc-message"),
+            HeaderValue::from_static(reason),
+        );
+    }
+    response.header(
+        HeaderName::from_static("content-length"),
+        HeaderValue::from_static("0"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(StatusCode::BAD_REQUEST, INTERNAL)]
+    #[case(StatusCode::NOT_FOUND, UNIMPLEMENTED)]
+    #[case(StatusCode::BAD_GATEWAY, UNAVAILABLE)]
+    #[case(StatusCode::GATEWAY_TIMEOUT, UNAVAILABLE)]
+    #[case(StatusCode::REQUEST_TIMEOUT, UNKNOWN)]
+    fn test_status(#[case] status_code: StatusCode, #[case] expected: u32) {
+        assert_eq!(expected, status(status_code));
+    }
+
+    #[test]
+    fn test_error_response() {
+        let response = error_response(StatusCode::BAD_GATEWAY);
+        let header = |name| response.get_header(name).map(|v| v.to_str().unwrap());
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(Some("application/grpc"), header("content-type"));
+        assert_eq!(Some("14"), header("grpc-status"));
+        assert_eq!(Some("Bad Gateway"), header("grpc-message"));
+    }
+}
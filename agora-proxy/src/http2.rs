@@ -0,0 +1,428 @@
+//! What both kinds of HTTP/2 connections the proxy has share, with clients and with
+//! backends: flow control windows for sending, the writer putting the frames of all
+//! streams on the wire in order, and the header blocks coming in.
+
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU32, Ordering},
+};
+
+use agora_http_parser::{
+    Headers,
+    h2::{
+        DEFAULT_MAX_FRAME_SIZE, DEFAULT_WINDOW_SIZE, ErrorCode, FRAME_HEADER_LEN, Frame,
+        MAX_WINDOW_SIZE, Setting, SettingId,
+    },
+    hpack::{Decoder, Encoder, HpackError},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{Notify, mpsc},
+    task::JoinHandle,
+};
+
+/// Frames waiting for the writer before the streams sending them have to wait as well
+const QUEUED_FRAMES: usize = 64;
+
+/// The bytes of a request or response held in the pipe of a stream
+pub const PIPE_SIZE: usize = 64 * 1024;
+
+/// The largest header block taken in, which would never fit in a message head anyway
+pub const MAX_HEADER_BLOCK: usize = 64 * 1024;
+
+/// The largest header list taken in once decoded, counting 32 bytes for each field on
+/// top of its name and value, which peers are told with SETTINGS_MAX_HEADER_LIST_SIZE
+pub const MAX_HEADER_LIST_SIZE: u32 = 64 * 1024;
+
+/// A flow control window for sending, which the peer widens with WINDOW_UPDATE and
+/// SETTINGS_INITIAL_WINDOW_SIZE. It can go below zero when the peer shrinks it.
+#[derive(Debug)]
+pub struct Window {
+    size: Mutex<i64>,
+    widened: Notify,
+}
+
+impl Window {
+    pub fn new(size: u32) -> Self {
+        Self {
+            size: Mutex::new(size.into()),
+            widened: Notify::new(),
+        }
+    }
+
+    /// Change the size of the window, returning false if it grew past the largest
+    /// one allowed
+    pub fn widen(&self, increment: i64) -> bool {
+        let mut size = self.size.lock().unwrap();
+        *size += increment;
+        self.widened.notify_waiters();
+        *size <= i64::from(MAX_WINDOW_SIZE)
+    }
+
+    /// Wait until the window is open, and take up to `max` bytes of it
+    async fn take(&self, max: usize) -> usize {
+        loop {
+            let widened = self.widened.notified();
+            tokio::pin!(widened);
+            widened.as_mut().enable();
+            {
+                let mut size = self.size.lock().unwrap();
+                if *size > 0 {
+                    let taken = (*size).min(max as i64);
+                    *size -= taken;
+                    return taken as usize;
+                }
+            }
+            widened.await;
+        }
+    }
+}
+
+/// What the writer sends to the peer
+enum Out {
+    Frame(Vec<u8>),
+    /// Header fields, encoded once it's their turn since the HPACK table follows the
+    /// order of header blocks on the connection
+    Headers {
+        stream_id: u32,
+        headers: Box<Headers>,
+        end_stream: bool,
+    },
+    /// The settings of the peer the writer follows
+    Settings {
+        header_table_size: Option<u32>,
+        max_frame_size: Option<u32>,
+    },
+}
+
+/// The sending side of an HTTP/2 connection, shared by its streams
+#[derive(Debug)]
+pub struct Sender {
+    queued: mpsc::Sender<Out>,
+    /// The bytes of DATA the peer accepts on the connection, whatever the stream
+    window: Window,
+    /// The largest frame the peer accepts
+    max_frame_size: AtomicU32,
+}
+
+impl Sender {
+    /// Write the frames sent with the returned sender in the background, until it is
+    /// dropped or the connection breaks
+    pub fn spawn(writer: impl AsyncWrite + Unpin + Send + 'static) -> (Self, JoinHandle<()>) {
+        let (queued, frames) = mpsc::channel(QUEUED_FRAMES);
+        let writing = tokio::spawn(write_frames(writer, frames));
+        let sender = Self {
+            queued,
+            window: Window::new(DEFAULT_WINDOW_SIZE),
+            max_frame_size: AtomicU32::new(DEFAULT_MAX_FRAME_SIZE),
+        };
+        (sender, writing)
+    }
+
+    /// Queue a frame for the peer, failing once the connection is broken
+    pub async fn send(&self, frame: Frame<'_>) -> Result<(), ErrorCode> {
+        self.queue(Out::Frame(encode(&frame))).await
+    }
+
+    /// Queue a frame if there is room right away, for when waiting isn't an option
+    pub fn try_send(&self, frame: Frame<'_>) {
+        let _ = self.queued.try_send(Out::Frame(encode(&frame)));
+    }
+
+    pub async fn send_headers(
+        &self,
+        stream_id: u32,
+        headers: Headers,
+        end_stream: bool,
+    ) -> Result<(), ErrorCode> {
+        self.queue(Out::Headers {
+            stream_id,
+            headers: Box::new(headers),
+            end_stream,
+        })
+        .await
+    }
+
+    /// Send DATA as the flow control windows of the stream and of the connection let it
+    pub async fn send_data(
+        &self,
+        stream_id: u32,
+        window: &Window,
+        mut data: &[u8],
+    ) -> Result<(), ErrorCode> {
+        while !data.is_empty() {
+            let max_frame_size = self.max_frame_size.load(Ordering::Relaxed) as usize;
+            let len = window.take(data.len().min(max_frame_size)).await;
+            let taken = self.window.take(len).await;
+            if taken < len {
+                window.widen((len - taken) as i64);
+            }
+            let mut bytes = Vec::with_capacity(FRAME_HEADER_LEN + taken);
+            Frame::Data {
+                stream_id,
+                end_stream: false,
+                data: &data[..taken],
+            }
+            .encode(&mut bytes);
+            self.queue(Out::Frame(bytes)).await?;
+            data = &data[taken..];
+        }
+        Ok(())
+    }
+
+    /// End the stream with an empty DATA frame, which flow control doesn't hold back
+    pub async fn end_stream(&self, stream_id: u32) -> Result<(), ErrorCode> {
+        self.send(Frame::Data {
+            stream_id,
+            end_stream: true,
+            data: &[],
+        })
+        .await
+    }
+
+    /// Widen the connection window by the increment of a WINDOW_UPDATE, returning false
+    /// if it grew past the largest one allowed
+    pub fn widen(&self, increment: u32) -> bool {
+        self.window.widen(increment.into())
+    }
+
+    /// Take care of the frames about the connection as a whole that both kinds of
+    /// connections treat alike, returning whether the frame was one of them
+    pub async fn answer(&self, frame: &Frame<'_>) -> Result<bool, ErrorCode> {
+        match *frame {
+            Frame::Ping { ack: false, data } => {
+                // dropped once the connection is broken, which the reading side finds
+                // out about as well
+                let _ = self.send(Frame::Ping { ack: true, data }).await;
+            }
+            Frame::WindowUpdate {
+                stream_id: 0,
+                increment,
+            } => {
+                if !self.widen(increment) {
+                    return Err(ErrorCode::FlowControlError);
+                }
+            }
+            Frame::Settings { ack: true, .. } | Frame::Ping { ack: true, .. } => {}
+            // PRIORITY and extensions are of no use to the proxy
+            Frame::Unknown { .. } => {}
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Follow the settings of the peer on the size of its HPACK table and of frames
+    pub async fn follow(&self, settings: &[Setting]) {
+        let mut header_table_size = None;
+        let mut max_frame_size = None;
+        for setting in settings {
+            match setting.id {
+                SettingId::HeaderTableSize => header_table_size = Some(setting.value),
+                SettingId::MaxFrameSize => {
+                    self.max_frame_size.store(setting.value, Ordering::Relaxed);
+                    max_frame_size = Some(setting.value);
+                }
+                _ => {}
+            }
+        }
+        if header_table_size.is_some() || max_frame_size.is_some() {
+            let _ = self
+                .queue(Out::Settings {
+                    header_table_size,
+                    max_frame_size,
+                })
+                .await;
+        }
+    }
+
+    async fn queue(&self, out: Out) -> Result<(), ErrorCode> {
+        self.queued.send(out).await.map_err(|_| ErrorCode::Cancel)
+    }
+}
+
+/// A complete header block received on a stream, decoded
+#[derive(Debug)]
+pub struct HeaderBlock {
+    pub stream_id: u32,
+    /// Whether the stream ends with it
+    pub end_stream: bool,
+    pub headers: Headers,
+}
+
+/// The receiving side of the header blocks of a connection, which come in a HEADERS
+/// frame followed by CONTINUATION frames until one ends the block
+#[derive(Debug)]
+pub struct HeaderBlocks {
+    decoder: Decoder,
+    /// A header block that continues in CONTINUATION frames: its stream, whether the
+    /// stream ends with it, and the fragments so far
+    partial: Option<(u32, bool, Vec<u8>)>,
+}
+
+impl Default for HeaderBlocks {
+    fn default() -> Self {
+        let mut decoder = Decoder::default();
+        decoder.set_max_header_list_size(MAX_HEADER_LIST_SIZE as usize);
+        Self {
+            decoder,
+            partial: None,
+        }
+    }
+}
+
+impl HeaderBlocks {
+    /// Fail unless the frame may come next, since nothing may come between the
+    /// fragments of a header block
+    pub fn expect(&self, frame: &Frame<'_>) -> Result<(), ErrorCode> {
+        match self.partial.is_some() && !matches!(frame, Frame::Continuation { .. }) {
+            true => Err(ErrorCode::ProtocolError),
+            false => Ok(()),
+        }
+    }
+
+    /// Take in a HEADERS or CONTINUATION frame, returning the header block once it is
+    /// complete. Other frames are left alone.
+    pub fn take(&mut self, frame: Frame<'_>) -> Result<Option<HeaderBlock>, ErrorCode> {
+        match frame {
+            Frame::Headers {
+                stream_id,
+                end_stream,
+                end_headers: true,
+                header_block,
+                ..
+            } => self.decode(stream_id, end_stream, header_block).map(Some),
+            Frame::Headers {
+                stream_id,
+                end_stream,
+                header_block,
+                ..
+            } => {
+                self.partial = Some((stream_id, end_stream, header_block.to_vec()));
+                Ok(None)
+            }
+            Frame::Continuation {
+                stream_id,
+                end_headers,
+                header_block: fragment,
+            } => {
+                let Some((id, end_stream, mut header_block)) = self.partial.take() else {
+                    return Err(ErrorCode::ProtocolError);
+                };
+                if id != stream_id {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                header_block.extend_from_slice(fragment);
+                if header_block.len() > MAX_HEADER_BLOCK {
+                    return Err(ErrorCode::EnhanceYourCalm);
+                }
+                if !end_headers {
+                    self.partial = Some((stream_id, end_stream, header_block));
+                    return Ok(None);
+                }
+                self.decode(stream_id, end_stream, &header_block).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Decode a header block whatever happens to its stream, to keep the HPACK table in
+    /// step with the peer
+    fn decode(
+        &mut self,
+        stream_id: u32,
+        end_stream: bool,
+        header_block: &[u8],
+    ) -> Result<HeaderBlock, ErrorCode> {
+        let headers = self
+            .decoder
+            .decode_headers(header_block)
+            .map_err(|e| match e {
+                HpackError::HeaderListTooLarge => ErrorCode::EnhanceYourCalm,
+                _ => ErrorCode::CompressionError,
+            })?;
+        Ok(HeaderBlock {
+            stream_id,
+            end_stream,
+            headers,
+        })
+    }
+}
+
+/// The last chunk of a chunked HTTP/1.1 body, carrying the trailers of a stream.
+/// Pseudo-header fields have no place among them.
+pub fn last_chunk(trailers: Option<&Headers>) -> Vec<u8> {
+    let mut last_chunk = b"0\r\n".to_vec();
+    for (name, value) in trailers.into_iter().flat_map(Headers::iter) {
+        if name.is_pseudo() {
+            continue;
+        }
+        last_chunk.extend_from_slice(name.as_str().as_bytes());
+        last_chunk.extend_from_slice(b": ");
+        last_chunk.extend_from_slice(value.as_bytes());
+        last_chunk.extend_from_slice(b"\r\n");
+    }
+    last_chunk.extend_from_slice(b"\r\n");
+    last_chunk
+}
+
+fn encode(frame: &Frame<'_>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    frame.encode(&mut bytes);
+    bytes
+}
+
+/// Send what is queued, until the senders are all gone
+async fn write_frames(mut writer: impl AsyncWrite + Unpin, mut queued: mpsc::Receiver<Out>) {
+    let mut encoder = Encoder::default();
+    let mut max_frame_size = DEFAULT_MAX_FRAME_SIZE as usize;
+    let mut bytes = Vec::new();
+    while let Some(out) = queued.recv().await {
+        bytes.clear();
+        match out {
+            Out::Frame(frame) => bytes = frame,
+            Out::Headers {
+                stream_id,
+                headers,
+                end_stream,
+            } => {
+                let mut header_block = Vec::new();
+                encoder.encode_headers(&headers, &mut header_block);
+                let mut fragments = header_block.chunks(max_frame_size);
+                let first = fragments.next().unwrap_or_default();
+                let mut fragments = fragments.peekable();
+                Frame::Headers {
+                    stream_id,
+                    end_stream,
+                    end_headers: fragments.peek().is_none(),
+                    priority: None,
+                    header_block: first,
+                }
+                .encode(&mut bytes);
+                while let Some(fragment) = fragments.next() {
+                    Frame::Continuation {
+                        stream_id,
+                        end_headers: fragments.peek().is_none(),
+                        header_block: fragment,
+                    }
+                    .encode(&mut bytes);
+                }
+            }
+            Out::Settings {
+                header_table_size,
+                max_frame_size: frame_size,
+            } => {
+                if let Some(size) = header_table_size {
+                    encoder.set_max_table_size(size as usize);
+                }
+                if let Some(size) = frame_size {
+                    max_frame_size = size as usize;
+                }
+                continue;
+            }
+        }
+        if writer.write_all(&bytes).await.is_err() {
+            return;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
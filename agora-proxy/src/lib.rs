@@ -1 +1,18 @@
+pub mod config;
+pub mod forwarded;
+pub mod grpc;
+pub mod http2;
+pub mod proxy_protocol;
+pub mod reload;
+pub mod retry;
+pub mod routing;
+pub mod security_headers;
 pub mod server;
+pub mod sni;
+pub mod static_files;
+pub mod templates;
+pub mod tls;
+pub mod transform;
+pub mod tunnel;
+pub mod udp;
+pub mod upstream;
@@ -1,8 +1,16 @@
-use std::path::PathBuf;
+use std::{fs::File, io::Write, path::PathBuf, process, sync::Arc};
 
-use agora_proxy::server::{Server, ServerConfig};
+use agora_http_parser::Request;
+use agora_proxy::{
+    config::{ConfigFormat, Listener, ServerConfig},
+    reload::{ConfigSource, reload_on_change},
+    server::Server,
+    templates::Template,
+    udp::UdpForwarder,
+};
 use clap::{Parser, Subcommand};
-use tracing::info;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -15,13 +23,73 @@ struct Args {
 enum Commands {
     /// Start the server
     Start {
-        #[arg(short, long, default_value_t = 8080)]
-        /// The port the server should listen on
-        port: u16,
+        #[arg(short, long)]
+        /// The port the server should listen on, instead of the plain HTTP listeners of
+        /// the config. Defaults to 8080 if the config has no listeners.
+        port: Option<u16>,
 
         #[arg(short, long)]
         /// Path to server config
         config: Option<PathBuf>,
+
+        #[arg(short, long)]
+        /// Format of the server config, instead of guessing it from the file extension
+        format: Option<ConfigFormat>,
+
+        #[arg(short, long, requires = "config")]
+        /// Reload the config when files in its directory change, as well as on SIGHUP
+        watch: bool,
+    },
+    /// Show which route a request would match, and where it would go, without sending it
+    TestRoute {
+        #[arg(short, long)]
+        /// Path to server config
+        config: PathBuf,
+
+        #[arg(short, long)]
+        /// Format of the server config, instead of guessing it from the file extension
+        format: Option<ConfigFormat>,
+
+        #[arg(short, long, default_value = "GET")]
+        /// Method of the request
+        method: String,
+
+        #[arg(long)]
+        /// Host header of the request
+        host: Option<String>,
+
+        #[arg(short = 'H', long = "header")]
+        /// Header of the request, like `X-Tenant: acme`. Can be repeated.
+        headers: Vec<String>,
+
+        /// Path of the request, including any query
+        path: String,
+    },
+    /// Print a starter config to build on
+    GenerateConfig {
+        #[arg(short, long, value_enum, default_value_t)]
+        /// The kind of setup to generate a config for
+        template: Template,
+
+        #[arg(short, long)]
+        /// Format of the config, instead of guessing it from the output file extension.
+        /// Defaults to YAML, whose comments explain the config.
+        format: Option<ConfigFormat>,
+
+        #[arg(short, long)]
+        /// File to write the config to instead of printing it, which must not exist yet
+        output: Option<PathBuf>,
+    },
+    /// Check a config for errors and likely mistakes, like routes that never match,
+    /// exiting with a non-zero status if there are any
+    Validate {
+        #[arg(short, long)]
+        /// Path to server config
+        config: PathBuf,
+
+        #[arg(short, long)]
+        /// Format of the server config, instead of guessing it from the file extension
+        format: Option<ConfigFormat>,
     },
 }
 
@@ -30,24 +98,210 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
     match args.command {
-        Commands::Start { port, config } => run(port, config).await,
+        Commands::Start {
+            port,
+            config,
+            format,
+            watch,
+        } => {
+            let source = config.map(|path| ConfigSource { path, format });
+            run(port, source, watch).await
+        }
+        Commands::TestRoute {
+            config,
+            format,
+            method,
+            host,
+            headers,
+            path,
+        } => {
+            let config = ConfigSource {
+                path: config,
+                format,
+            }
+            .load()?;
+            test_route(&config, &method, host.as_deref(), &headers, &path)
+        }
+        Commands::GenerateConfig {
+            template,
+            format,
+            output,
+        } => {
+            let format = format
+                .or_else(|| output.as_deref().and_then(ConfigFormat::from_path))
+                .unwrap_or(ConfigFormat::Yaml);
+            let config = template.render(format);
+            match output {
+                Some(output) => {
+                    let mut file = File::create_new(&output)
+                        .map_err(|e| format!("Couldn't create {}: {e}", output.display()))?;
+                    file.write_all(config.as_bytes())?;
+                }
+                None => print!("{config}"),
+            }
+            Ok(())
+        }
+        Commands::Validate { config, format } => {
+            validate(ConfigSource {
+                path: config,
+                format,
+            });
+            Ok(())
+        }
     }
 }
 
-async fn run(port: u16, config_path: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt::init();
+async fn run(
+    port: Option<u16>,
+    source: Option<ConfigSource>,
+    watch: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = match &source {
+        Some(source) => source.load()?,
+        None => ServerConfig::default(),
+    };
+
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::from(config.logging.level))
+        .init();
+    match &source {
+        Some(source) => info!("Loaded server config from {}", source.path.display()),
+        None => info!("No config found: loaded default config."),
+    }
+    for warning in config.warnings() {
+        warn!("{warning}");
+    }
+
+    let http = |address: String| Listener {
+        address,
+        ..Default::default()
+    };
+    let listeners: Vec<Listener> = match port {
+        // only replacing the listeners serving plain HTTP
+        Some(port) => std::iter::once(http(format!("0.0.0.0:{port}")))
+            .chain(
+                config
+                    .listeners
+                    .iter()
+                    .filter(|l| !l.is_http() || l.tls.is_some() || l.https_redirect.is_some())
+                    .cloned(),
+            )
+            .collect(),
+        None if config.listeners.is_empty() => vec![http(String::from("0.0.0.0:8080"))],
+        None => config.listeners.clone(),
+    };
+
+    let server = Arc::new(Server::new(config));
+    if let Some(source) = source {
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = reload_on_change(server, source, watch).await {
+                error!("Config reloading stopped: {e}");
+            }
+        });
+    }
+
+    let mut tasks = JoinSet::new();
+    for listener in listeners {
+        match listener.udp {
+            Some(udp) => {
+                let forwarder = UdpForwarder::new(udp);
+                tasks.spawn(async move { forwarder.listen(&listener.address).await });
+            }
+            None => {
+                let server = server.clone();
+                tasks.spawn(async move { server.listen_on(&listener).await });
+            }
+        }
+    }
+    // serve until a listener fails
+    if let Some(result) = tasks.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
 
-    let addr = format!("0.0.0.0:{}", port);
-    let config = if let Some(config_path) = config_path {
-        info!("Loading server config from {}", config_path.display());
-        ServerConfig::parse(&config_path)?
-    } else {
-        info!("No config found: loading default config.");
-        ServerConfig::default()
+fn validate(source: ConfigSource) {
+    let config = match source.load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}: {e}", source.path.display());
+            process::exit(1);
+        }
     };
 
-    let server = Server::new(config);
-    server.listen(&addr).await?;
+    let warnings = config.warnings();
+    if warnings.is_empty() {
+        println!("{} is valid", source.path.display());
+        return;
+    }
+    for warning in &warnings {
+        eprintln!("{}: {warning}", source.path.display());
+    }
+    eprintln!("{} problems found", warnings.len());
+    process::exit(1);
+}
+
+fn test_route(
+    config: &ServerConfig,
+    method: &str,
+    host: Option<&str>,
+    headers: &[String],
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut head = format!("{method} {path} HTTP/1.1\r\n");
+    if let Some(host) = host {
+        head.push_str(&format!("Host: {host}\r\n"));
+    }
+    for header in headers {
+        head.push_str(&format!("{header}\r\n"));
+    }
+    head.push_str("\r\n");
+    let (request, _) =
+        Request::parse(head.as_bytes()).map_err(|e| format!("Invalid request: {e:?}"))?;
 
+    let Some(route_match) = config.route(&request) else {
+        println!("No route matches {method} {path}");
+        process::exit(1);
+    };
+    let route = route_match.route;
+    println!(
+        "Route:    {} ({}, priority {})",
+        route_match.location, route.path, route.priority
+    );
+    println!("Path:     {} -> {}", request.path, route_match.path);
+    if let Some(maintenance) = &route.entry.maintenance {
+        println!(
+            "Response: 503 Service Unavailable (maintenance{}){}",
+            maintenance
+                .file
+                .as_ref()
+                .map(|file| format!(" while {} exists", file.display()))
+                .unwrap_or_default(),
+            maintenance
+                .message
+                .as_ref()
+                .map(|message| format!(": {message}"))
+                .unwrap_or_default()
+        );
+    }
+    // routes toggled with a file are served as usual while it doesn't exist
+    let served = route
+        .entry
+        .maintenance
+        .as_ref()
+        .is_none_or(|m| m.file.is_some());
+    if let Some(static_files) = route.entry.static_files.as_ref().filter(|_| served) {
+        println!("Files:    {}", static_files.root.display());
+    } else if served {
+        println!("Upstream: {}", route_match.upstream);
+        if let Some(backup) = route_match.backup {
+            println!("Backup:   {backup}");
+        }
+    }
+    if let Some(mirror) = &route.entry.mirror {
+        println!("Mirror:   {mirror}");
+    }
     Ok(())
 }
@@ -0,0 +1,255 @@
+//! The PROXY protocol, which load balancers passing TCP connections on use to tell the
+//! client they came from, in a header at the start of the connection.
+//!
+//! Both the text header of version 1 and the binary header of version 2 are read, as
+//! described in <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>. Headers
+//! sent to backends are of version 2.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// The start of a version 2 header
+const SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// The longest version 1 header, line end included
+const MAX_V1_SIZE: usize = 107;
+
+const LOCAL: u8 = 0x20;
+const PROXY: u8 = 0x21;
+const TCP4: u8 = 0x11;
+const TCP6: u8 = 0x21;
+
+/// What the start of a connection says about the client
+#[derive(Debug, PartialEq)]
+pub enum Header {
+    /// More bytes are needed to tell
+    Incomplete,
+    /// The connection doesn't start with a header
+    Invalid,
+    /// A header of `len` bytes, with the address of the client unless the balancer
+    /// opened the connection itself, like for health checks, or didn't know it
+    Parsed {
+        source: Option<SocketAddr>,
+        len: usize,
+    },
+}
+
+/// Read the header at the start of a connection, of either version
+pub fn parse(buf: &[u8]) -> Header {
+    let len = buf.len().min(SIGNATURE.len());
+    if buf[..len] == SIGNATURE[..len] {
+        return parse_v2(buf);
+    }
+    let len = buf.len().min(6);
+    if buf[..len] == b"PROXY "[..len] {
+        return parse_v1(buf);
+    }
+    Header::Invalid
+}
+
+/// The text header, like `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+fn parse_v1(buf: &[u8]) -> Header {
+    let Some(end) = buf.windows(2).position(|end| end == b"\r\n") else {
+        return if buf.len() < MAX_V1_SIZE {
+            Header::Incomplete
+        } else {
+            Header::Invalid
+        };
+    };
+    let len = end + 2;
+    if len > MAX_V1_SIZE {
+        return Header::Invalid;
+    }
+    let Ok(line) = std::str::from_utf8(&buf[..end]) else {
+        return Header::Invalid;
+    };
+    let fields: Vec<&str> = line.split(' ').collect();
+    let source = match fields[1..] {
+        ["UNKNOWN", ..] => None,
+        [protocol, source, destination, source_port, destination_port] => {
+            let (Ok(source), Ok(destination), Ok(port), Ok(_)) = (
+                source.parse::<IpAddr>(),
+                destination.parse::<IpAddr>(),
+                source_port.parse::<u16>(),
+                destination_port.parse::<u16>(),
+            ) else {
+                return Header::Invalid;
+            };
+            let matches = match protocol {
+                "TCP4" => source.is_ipv4() && destination.is_ipv4(),
+                "TCP6" => source.is_ipv6() && destination.is_ipv6(),
+                _ => false,
+            };
+            if !matches {
+                return Header::Invalid;
+            }
+            Some(SocketAddr::new(source, port))
+        }
+        _ => return Header::Invalid,
+    };
+    Header::Parsed { source, len }
+}
+
+/// The binary header: the signature, the version and command, the address family and
+/// protocol, the length of the rest, then the addresses followed by any TLVs
+fn parse_v2(buf: &[u8]) -> Header {
+    let Some(fixed) = buf.get(..16) else {
+        return Header::Incomplete;
+    };
+    let len = 16 + usize::from(u16::from_be_bytes([fixed[14], fixed[15]]));
+    let Some(addresses) = buf.get(16..len) else {
+        return Header::Incomplete;
+    };
+    let source = match (fixed[12], fixed[13]) {
+        (LOCAL, _) => None,
+        (PROXY, TCP4) => {
+            let Some(addresses) = addresses.get(..12) else {
+                return Header::Invalid;
+            };
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[..4]).unwrap());
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Some(SocketAddr::new(ip.into(), port))
+        }
+        (PROXY, TCP6) => {
+            let Some(addresses) = addresses.get(..36) else {
+                return Header::Invalid;
+            };
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16]).unwrap());
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Some(SocketAddr::new(ip.into(), port))
+        }
+        // like UDP or Unix sockets, whose addresses don't stand for a TCP client
+        (PROXY, _) => None,
+        _ => return Header::Invalid,
+    };
+    Header::Parsed { source, len }
+}
+
+/// A version 2 header telling a connection is from `source` to `destination`. An IPv4
+/// address along with an IPv6 one is given as mapped to IPv6, both having to be of the
+/// same family.
+pub fn v2_header(source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let mut header = SIGNATURE.to_vec();
+    let addresses = match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            header.extend_from_slice(&[PROXY, TCP4, 0, 12]);
+            [source.octets().as_slice(), &destination.octets()].concat()
+        }
+        (source, destination) => {
+            header.extend_from_slice(&[PROXY, TCP6, 0, 36]);
+            [to_ipv6(source).octets(), to_ipv6(destination).octets()].concat()
+        }
+    };
+    header.extend_from_slice(&addresses);
+    header.extend_from_slice(&source.port().to_be_bytes());
+    header.extend_from_slice(&destination.port().to_be_bytes());
+    header
+}
+
+/// A version 2 header telling the connection was opened by the sender itself, like for
+/// health checks, rather than for a client
+pub fn v2_local_header() -> Vec<u8> {
+    [SIGNATURE.as_slice(), &[LOCAL, 0, 0, 0]].concat()
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn parsed(source: Option<&str>, len: usize) -> Header {
+        Header::Parsed {
+            source: source.map(|source| source.parse().unwrap()),
+            len,
+        }
+    }
+
+    #[rstest]
+    #[case(
+        b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET",
+        parsed(Some("192.0.2.1:56324"), 45)
+    )]
+    #[case(
+        b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n",
+        parsed(Some("[2001:db8::1]:56324"), 46)
+    )]
+    #[case(b"PROXY UNKNOWN\r\n", parsed(None, 15))]
+    #[case(
+        b"PROXY UNKNOWN 192.0.2.1 198.51.100.1 56324 443\r\n",
+        parsed(None, 48)
+    )]
+    #[case(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324", Header::Incomplete)]
+    #[case(b"PRO", Header::Incomplete)]
+    #[case(b"", Header::Incomplete)]
+    #[case(b"PROXY TCP4 2001:db8::1 198.51.100.1 56324 443\r\n", Header::Invalid)]
+    #[case(b"PROXY TCP4 192.0.2.1 198.51.100.1 65536 443\r\n", Header::Invalid)]
+    #[case(b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n", Header::Invalid)]
+    #[case(b"PROXY TCP4 192.0.2.1\r\n", Header::Invalid)]
+    #[case(b"GET / HTTP/1.1\r\n\r\n", Header::Invalid)]
+    fn test_parse_v1(#[case] buf: &[u8], #[case] expected: Header) {
+        assert_eq!(expected, parse(buf));
+    }
+
+    #[test]
+    fn test_parse_v1_too_long() {
+        let mut buf = b"PROXY TCP4 ".to_vec();
+        buf.resize(MAX_V1_SIZE, b'1');
+        assert_eq!(Header::Invalid, parse(&buf));
+    }
+
+    /// A version 2 header with the command and family, and the addresses and TLVs
+    fn v2(command: u8, family: u8, rest: &[u8]) -> Vec<u8> {
+        let mut header = SIGNATURE.to_vec();
+        header.extend_from_slice(&[command, family]);
+        header.extend_from_slice(&(rest.len() as u16).to_be_bytes());
+        header.extend_from_slice(rest);
+        header
+    }
+
+    #[rstest]
+    #[case(
+        v2(PROXY, TCP4, &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 1, 0xbb]),
+        parsed(Some("192.0.2.1:56324"), 28)
+    )]
+    #[case(
+        v2(PROXY, TCP6, &[&[0x20, 1, 0xd, 0xb8][..], &[0; 11], &[1], &[0; 16], &[0xdc, 0x04, 1, 0xbb]].concat()),
+        parsed(Some("[2001:db8::1]:56324"), 52)
+    )]
+    // a TLV follows the addresses
+    #[case(
+        v2(PROXY, TCP4, &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 1, 0xbb, 4, 0, 0]),
+        parsed(Some("192.0.2.1:56324"), 31)
+    )]
+    #[case(v2(LOCAL, 0, &[]), parsed(None, 16))]
+    #[case(v2(PROXY, 0x12, &[192, 0, 2, 1, 198, 51, 100, 1, 0, 53, 0, 53]), parsed(None, 28))]
+    #[case(v2(PROXY, TCP4, &[192, 0, 2, 1]), Header::Invalid)]
+    #[case(v2(0x22, TCP4, &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 1, 0xbb]), Header::Invalid)]
+    fn test_parse_v2(#[case] buf: Vec<u8>, #[case] expected: Header) {
+        assert_eq!(expected, parse(&buf));
+        if let Header::Parsed { len, .. } = expected {
+            for end in [0, 5, 12, 15, len - 1] {
+                assert_eq!(Header::Incomplete, parse(&buf[..end]));
+            }
+        }
+    }
+
+    #[rstest]
+    #[case("192.0.2.1:56324", "198.51.100.1:443", "192.0.2.1:56324")]
+    #[case("[2001:db8::1]:56324", "[2001:db8::2]:443", "[2001:db8::1]:56324")]
+    #[case("192.0.2.1:56324", "[2001:db8::2]:443", "[::ffff:192.0.2.1]:56324")]
+    fn test_v2_header(#[case] source: &str, #[case] destination: &str, #[case] expected: &str) {
+        let header = v2_header(source.parse().unwrap(), destination.parse().unwrap());
+        assert_eq!(parsed(Some(expected), header.len()), parse(&header));
+    }
+
+    #[test]
+    fn test_v2_local_header() {
+        assert_eq!(parsed(None, 16), parse(&v2_local_header()));
+    }
+}
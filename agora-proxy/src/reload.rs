@@ -0,0 +1,101 @@
+//! Reloading the config of a running server on `SIGHUP`, or when its files change.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use notify::{RecursiveMode, Watcher};
+use tokio::{
+    io,
+    signal::unix::{SignalKind, signal},
+    sync::mpsc,
+    time::sleep,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{ConfigFormat, ServerConfig},
+    server::Server,
+};
+
+/// How long to wait for more changes after a file changes, since editors and deploy
+/// tools often write several files, or a file several times, in a row
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Where the config of a server is loaded from
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub path: PathBuf,
+    /// The format of the file, instead of guessing it from its extension
+    pub format: Option<ConfigFormat>,
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+        match self.format {
+            Some(format) => ServerConfig::parse_as(&self.path, format),
+            None => ServerConfig::parse(&self.path),
+        }
+    }
+}
+
+/// Reload the config of the server on `SIGHUP`, and with `watch` whenever a file in the
+/// directory of the config changes, which includes files it includes from there. A
+/// config that fails to load is logged and the server keeps its current one.
+pub async fn reload_on_change(
+    server: Arc<Server>,
+    source: ConfigSource,
+    watch: bool,
+) -> io::Result<()> {
+    let mut hangups = signal(SignalKind::hangup())?;
+    let (changes_tx, mut changes) = mpsc::unbounded_channel();
+
+    // dropping the watcher stops it, so it has to live as long as the loop
+    let _watcher = if watch {
+        let dir = match source.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if event.kind.is_access() => {}
+                Ok(_) => {
+                    let _ = changes_tx.send(());
+                }
+                Err(e) => warn!("Error watching config files: {e}"),
+            })
+            .map_err(io::Error::other)?;
+        watcher
+            .watch(dir, RecursiveMode::Recursive)
+            .map_err(io::Error::other)?;
+        info!("Watching {} for config changes", dir.display());
+        Some(watcher)
+    } else {
+        None
+    };
+
+    loop {
+        tokio::select! {
+            Some(()) = hangups.recv() => info!("Received SIGHUP, reloading config"),
+            Some(()) = changes.recv() => {
+                sleep(SETTLE_TIME).await;
+                while changes.try_recv().is_ok() {}
+                info!("Config files changed, reloading config");
+            }
+            else => return Ok(()),
+        }
+
+        match source.load() {
+            Ok(config) => {
+                for warning in config.warnings() {
+                    warn!("{warning}");
+                }
+                server.reload(config);
+                info!("Reloaded config from {}", source.path.display());
+            }
+            Err(e) => error!("Failed to reload config, keeping the current one: {e}"),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Retry budgets, bounding how many of the requests of a route may be retries at once.
+//!
+//! When a backend starts failing, every request to it fails and gets retried, which adds
+//! load right when there is the least capacity for it. A budget only lets a share of
+//! the requests in flight be retries or hedges, so failures stop multiplying.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::RetryBudget;
+
+/// The requests and retries in flight for a route
+#[derive(Debug, Default)]
+pub struct Budget {
+    requests: AtomicUsize,
+    retries: AtomicUsize,
+}
+
+/// A request counting as in flight until this is dropped
+#[derive(Debug)]
+pub struct ActiveRequest<'a> {
+    budget: &'a Budget,
+}
+
+/// A retry counting as in flight until this is dropped
+#[derive(Debug)]
+pub struct ActiveRetry<'a> {
+    budget: &'a Budget,
+}
+
+impl Budget {
+    /// Count a request, for as long as the returned guard lives
+    pub fn request(&self) -> ActiveRequest<'_> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        ActiveRequest { budget: self }
+    }
+
+    /// Count a retry if the budget allows another one, for as long as the returned
+    /// guard lives. Retries are allowed while they are fewer than the percentage of the
+    /// requests in flight, or than the minimum.
+    pub fn retry(&self, limits: &RetryBudget) -> Option<ActiveRetry<'_>> {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let allowed = (requests * limits.percent as usize / 100).max(limits.min_retries as usize);
+        self.retries
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |retries| {
+                (retries < allowed).then_some(retries + 1)
+            })
+            .ok()
+            .map(|_| ActiveRetry { budget: self })
+    }
+}
+
+impl Drop for ActiveRequest<'_> {
+    fn drop(&mut self) {
+        self.budget.requests.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ActiveRetry<'_> {
+    fn drop(&mut self) {
+        self.budget.retries.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    // the minimum holds while there are few requests
+    #[case(1, 2)]
+    #[case(10, 2)]
+    // past that, retries are a share of the requests
+    #[case(20, 4)]
+    #[case(100, 20)]
+    fn test_budget(#[case] requests: usize, #[case] expected: usize) {
+        let limits = RetryBudget {
+            percent: 20,
+            min_retries: 2,
+        };
+        let budget = Budget::default();
+        let _requests: Vec<_> = (0..requests).map(|_| budget.request()).collect();
+
+        let retries: Vec<_> = std::iter::from_fn(|| budget.retry(&limits)).collect();
+        assert_eq!(expected, retries.len());
+
+        // retries that are done make room for others
+        drop(retries);
+        assert!(budget.retry(&limits).is_some());
+    }
+}
@@ -0,0 +1,530 @@
+//! Matching request paths against the routes of the config.
+
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// What a route matches request paths against.
+///
+/// In config files a pattern is a string, either a path prefix like `/api` or a regular
+/// expression prefixed with `~`, like `~^/users/(?<id>[0-9]+)`. Regular expressions are
+/// compiled when the config is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PathPattern {
+    /// Paths starting with this prefix
+    Prefix(String),
+    /// Paths matching this regular expression anywhere, unless it is anchored
+    Regex(Regex),
+}
+
+/// The patterns of a route list, compiled for matching request paths.
+///
+/// Routes are tried from the highest priority to the lowest. Among routes of the same
+/// priority, regular expressions are tried first in the order they were given, then
+/// prefixes from the longest to the shortest, so `/api/v2` takes precedence over `/api`.
+/// Routes with the same prefix are tried in the order they were given.
+#[derive(Debug, Clone)]
+pub struct Router<T> {
+    tiers: BTreeMap<Reverse<i32>, Tier<T>>,
+}
+
+/// The routes of a single priority
+#[derive(Debug, Clone)]
+struct Tier<T> {
+    prefixes: PrefixTrie<T>,
+    regexes: Vec<(Regex, T)>,
+}
+
+impl<T> Default for Tier<T> {
+    fn default() -> Self {
+        Self {
+            prefixes: PrefixTrie::default(),
+            regexes: Vec::new(),
+        }
+    }
+}
+
+impl<T> Router<T> {
+    /// Compile routes given as their priority, pattern, and value
+    pub fn new(routes: impl IntoIterator<Item = (i32, PathPattern, T)>) -> Self {
+        let mut tiers: BTreeMap<_, Tier<T>> = BTreeMap::new();
+        for (priority, pattern, value) in routes {
+            let tier = tiers.entry(Reverse(priority)).or_default();
+            match pattern {
+                PathPattern::Prefix(prefix) => tier.prefixes.insert(prefix.as_bytes(), value),
+                PathPattern::Regex(regex) => tier.regexes.push((regex, value)),
+            }
+        }
+
+        Self { tiers }
+    }
+
+    /// Find the route for the path
+    pub fn route(&self, path: &str) -> Option<(PathMatch, &T)> {
+        self.route_where(path, |_| true)
+    }
+
+    /// Find the route for the path, skipping routes whose value isn't accepted
+    pub fn route_where(
+        &self,
+        path: &str,
+        mut accept: impl FnMut(&T) -> bool,
+    ) -> Option<(PathMatch, &T)> {
+        self.tiers
+            .values()
+            .find_map(|tier| tier.route_where(path, &mut accept))
+    }
+}
+
+impl<T> Tier<T> {
+    fn route_where(
+        &self,
+        path: &str,
+        accept: &mut impl FnMut(&T) -> bool,
+    ) -> Option<(PathMatch, &T)> {
+        self.regexes
+            .iter()
+            .filter(|(_, value)| accept(value))
+            .find_map(|(regex, value)| Some((regex_match(regex, path)?, value)))
+            .or_else(|| {
+                let (end, value) = self
+                    .prefixes
+                    .prefixes(path.as_bytes())
+                    .into_iter()
+                    .find(|(_, value)| accept(value))?;
+                Some((
+                    PathMatch {
+                        end,
+                        captures: Vec::new(),
+                    },
+                    value,
+                ))
+            })
+    }
+}
+
+/// A byte-wise trie of path prefixes
+#[derive(Debug, Clone)]
+struct PrefixTrie<T> {
+    values: Vec<T>,
+    children: BTreeMap<u8, PrefixTrie<T>>,
+}
+
+impl<T> Default for PrefixTrie<T> {
+    fn default() -> Self {
+        Self {
+            values: Vec::new(),
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> PrefixTrie<T> {
+    fn insert(&mut self, prefix: &[u8], value: T) {
+        let node = prefix
+            .iter()
+            .fold(self, |node, byte| node.children.entry(*byte).or_default());
+        node.values.push(value);
+    }
+
+    /// The values of every prefix of the path along with the prefix length, from the
+    /// longest prefix to the shortest
+    fn prefixes(&self, path: &[u8]) -> Vec<(usize, &T)> {
+        let mut node = self;
+        let mut nodes = vec![(0, node)];
+        for (i, byte) in path.iter().enumerate() {
+            let Some(child) = node.children.get(byte) else {
+                break;
+            };
+            node = child;
+            nodes.push((i + 1, node));
+        }
+
+        nodes
+            .into_iter()
+            .rev()
+            .flat_map(|(len, node)| node.values.iter().map(move |value| (len, value)))
+            .collect()
+    }
+}
+
+/// A condition on a request header or query parameter, written in config files like
+/// `{ "name": "x-tenant", "equals": "acme" }`. Only the first value of a repeated name
+/// is checked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Predicate {
+    pub name: String,
+    #[serde(flatten)]
+    pub condition: Condition,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// Whether the value is present at all
+    Exists(bool),
+    /// The value is exactly this string
+    Equals(String),
+    /// The value matches this regular expression
+    Regex(#[serde(with = "serde_regex")] Regex),
+}
+
+impl Predicate {
+    /// Whether the value of the name satisfies the condition
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        match (&self.condition, value) {
+            (Condition::Exists(exists), value) => *exists == value.is_some(),
+            (Condition::Equals(expected), Some(value)) => expected == value,
+            (Condition::Regex(regex), Some(value)) => regex.is_match(value),
+            (_, None) => false,
+        }
+    }
+}
+
+/// Rewrites the first match of the pattern in a path with the replacement, which can
+/// refer to capture groups like `$1` or `${name}`. For example `^/v1/(.*)` with the
+/// replacement `/api/$1` rewrites `/v1/users` to `/api/users`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rewrite {
+    #[serde(with = "serde_regex")]
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+impl Rewrite {
+    pub fn apply(&self, path: &str) -> String {
+        self.pattern
+            .replace(path, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+mod serde_regex {
+    use regex::Regex;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(regex: &Regex, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(regex.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+        Regex::new(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// How a path matched a [`PathPattern`]
+#[derive(Debug, PartialEq)]
+pub struct PathMatch {
+    /// The length of the path up to the end of the match, which is removed when the
+    /// route strips its prefix
+    pub end: usize,
+    /// The named capture groups of a regular expression which participated in the match
+    pub captures: Vec<(String, String)>,
+}
+
+impl PathPattern {
+    pub fn prefix(prefix: &str) -> Self {
+        Self::Prefix(prefix.to_string())
+    }
+
+    /// Match the path against the pattern
+    pub fn matches(&self, path: &str) -> Option<PathMatch> {
+        match self {
+            PathPattern::Prefix(prefix) => path.starts_with(prefix.as_str()).then(|| PathMatch {
+                end: prefix.len(),
+                captures: Vec::new(),
+            }),
+            PathPattern::Regex(regex) => regex_match(regex, path),
+        }
+    }
+
+    /// The pattern as written in config files
+    pub fn as_str(&self) -> &str {
+        match self {
+            PathPattern::Prefix(prefix) => prefix,
+            PathPattern::Regex(regex) => regex.as_str(),
+        }
+    }
+}
+
+fn regex_match(regex: &Regex, path: &str) -> Option<PathMatch> {
+    let captures = regex.captures(path)?;
+    Some(PathMatch {
+        end: captures.get_match().end(),
+        captures: regex
+            .capture_names()
+            .flatten()
+            .filter_map(|name| {
+                let value = captures.name(name)?;
+                Some((name.to_string(), value.as_str().to_string()))
+            })
+            .collect(),
+    })
+}
+
+impl TryFrom<String> for PathPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        match pattern.strip_prefix('~') {
+            Some(regex) => Ok(Self::Regex(Regex::new(regex)?)),
+            None => Ok(Self::Prefix(pattern)),
+        }
+    }
+}
+
+impl From<Regex> for PathPattern {
+    fn from(regex: Regex) -> Self {
+        Self::Regex(regex)
+    }
+}
+
+impl From<PathPattern> for String {
+    fn from(pattern: PathPattern) -> Self {
+        pattern.to_string()
+    }
+}
+
+impl Display for PathPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathPattern::Prefix(prefix) => write!(f, "{prefix}"),
+            PathPattern::Regex(regex) => write!(f, "~{}", regex.as_str()),
+        }
+    }
+}
+
+/// Patterns are equal if they are written the same way
+impl PartialEq for PathPattern {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (PathPattern::Prefix(_), PathPattern::Prefix(_))
+                | (PathPattern::Regex(_), PathPattern::Regex(_))
+        ) && self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for PathPattern {}
+
+impl Hash for PathPattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_string().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn captures(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[rstest]
+    #[case("/api", "/api/users", Some(PathMatch { end: 4, captures: vec![] }))]
+    #[case("/api", "/other/api", None)]
+    #[case(
+        "~^/users/(?<id>[0-9]+)",
+        "/users/42/posts",
+        Some(PathMatch { end: 9, captures: captures(&[("id", "42")]) })
+    )]
+    #[case("~^/users/(?<id>[0-9]+)", "/users/me", None)]
+    #[case(
+        "~\\.(?<ext>png|jpg)$",
+        "/img/cat.png",
+        Some(PathMatch { end: 12, captures: captures(&[("ext", "png")]) })
+    )]
+    #[case(
+        "~^/(?<a>x)?(?<b>y)",
+        "/y",
+        Some(PathMatch { end: 2, captures: captures(&[("b", "y")]) })
+    )]
+    fn test_matches(
+        #[case] pattern: &str,
+        #[case] path: &str,
+        #[case] expected: Option<PathMatch>,
+    ) {
+        let pattern = PathPattern::try_from(pattern.to_string()).unwrap();
+        assert_eq!(expected, pattern.matches(path));
+    }
+
+    #[rstest]
+    #[case("/api")]
+    #[case("~^/api/v[0-9]+")]
+    fn test_round_trip(#[case] pattern: &str) {
+        let parsed: PathPattern = serde_json::from_str(&format!("{pattern:?}")).unwrap();
+        assert_eq!(pattern, parsed.to_string());
+        assert_eq!(
+            format!("{pattern:?}"),
+            serde_json::to_string(&parsed).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        assert!(PathPattern::try_from(String::from("~^/(unclosed")).is_err());
+        assert!(serde_json::from_str::<PathPattern>("\"~[\"").is_err());
+    }
+
+    #[test]
+    fn test_eq() {
+        assert_eq!(PathPattern::prefix("^/api"), PathPattern::prefix("^/api"));
+        assert_ne!(
+            PathPattern::prefix("^/api"),
+            PathPattern::from(Regex::new("^/api").unwrap())
+        );
+    }
+
+    fn router(patterns: &[&'static str]) -> Router<&'static str> {
+        Router::new(patterns.iter().map(|pattern| {
+            (
+                0,
+                PathPattern::try_from(pattern.to_string()).unwrap(),
+                *pattern,
+            )
+        }))
+    }
+
+    #[test]
+    fn test_route_where() {
+        let router = router(&["/", "/api", "/api/v2", "~^/api/v2/admin"]);
+        let route = |path, rejected: &[&str]| {
+            router
+                .route_where(path, |pattern| !rejected.contains(pattern))
+                .map(|(_, pattern)| *pattern)
+        };
+        assert_eq!(Some("~^/api/v2/admin"), route("/api/v2/admin", &[]));
+        assert_eq!(
+            Some("/api/v2"),
+            route("/api/v2/admin", &["~^/api/v2/admin"])
+        );
+        assert_eq!(Some("/api"), route("/api/v2/users", &["/api/v2"]));
+        assert_eq!(Some("/"), route("/api/v2/users", &["/api/v2", "/api"]));
+        assert_eq!(None, route("/api/v2/users", &["/api/v2", "/api", "/"]));
+    }
+
+    #[rstest]
+    #[case(r#"{ "name": "x-debug", "exists": true }"#, Some("1"), true)]
+    #[case(r#"{ "name": "x-debug", "exists": true }"#, None, false)]
+    #[case(r#"{ "name": "x-debug", "exists": false }"#, None, true)]
+    #[case(r#"{ "name": "x-tenant", "equals": "acme" }"#, Some("acme"), true)]
+    #[case(r#"{ "name": "x-tenant", "equals": "acme" }"#, Some("other"), false)]
+    #[case(r#"{ "name": "x-tenant", "equals": "acme" }"#, None, false)]
+    #[case(
+        r#"{ "name": "accept", "regex": "json" }"#,
+        Some("application/json"),
+        true
+    )]
+    #[case(r#"{ "name": "accept", "regex": "json" }"#, Some("text/html"), false)]
+    fn test_predicate(
+        #[case] predicate: &str,
+        #[case] value: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        let predicate: Predicate = serde_json::from_str(predicate).unwrap();
+        assert_eq!(expected, predicate.matches(value));
+    }
+
+    #[rstest]
+    #[case("^/v1/(.*)", "/api/$1", "/v1/users", "/api/users")]
+    #[case("^/v1/(.*)", "/api/$1", "/other/v1/users", "/other/v1/users")]
+    #[case(
+        "^/users/(?<id>[0-9]+)",
+        "/u/${id}/",
+        "/users/42/posts",
+        "/u/42//posts"
+    )]
+    #[case("/old", "/new", "/old/a/old", "/new/a/old")]
+    fn test_rewrite(
+        #[case] pattern: &str,
+        #[case] replacement: &str,
+        #[case] path: &str,
+        #[case] expected: &str,
+    ) {
+        let rewrite = Rewrite {
+            pattern: Regex::new(pattern).unwrap(),
+            replacement: replacement.to_string(),
+        };
+        assert_eq!(expected, rewrite.apply(path));
+    }
+
+    #[test]
+    fn test_predicate_invalid() {
+        assert!(serde_json::from_str::<Predicate>(r#"{ "name": "a", "regex": "[" }"#).is_err());
+        assert!(serde_json::from_str::<Predicate>(r#"{ "name": "a" }"#).is_err());
+    }
+
+    #[rstest]
+    #[case(&["/api", "/api/v2", "/"], "/api/v2/users", Some(("/api/v2", 7)))]
+    #[case(&["/api/v2", "/api", "/"], "/api/v1/users", Some(("/api", 4)))]
+    #[case(&["/api", "/"], "/other", Some(("/", 1)))]
+    #[case(&["/api", "/api/v2"], "/other", None)]
+    #[case(&["", "/api"], "/other", Some(("", 0)))]
+    #[case(&["/", "~^/users/[0-9]+"], "/users/42", Some(("~^/users/[0-9]+", 9)))]
+    #[case(&["~^/b", "~^/a", "~^/"], "/a", Some(("~^/a", 2)))]
+    fn test_router(
+        #[case] patterns: &[&'static str],
+        #[case] path: &str,
+        #[case] expected: Option<(&str, usize)>,
+    ) {
+        let router = router(patterns);
+        let actual = router
+            .route(path)
+            .map(|(path_match, pattern)| (*pattern, path_match.end));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_router_priority() {
+        let router = Router::new([
+            (0, PathPattern::prefix("/api/v2"), "v2"),
+            (0, PathPattern::prefix("/api"), "api"),
+            (1, PathPattern::prefix("/"), "root"),
+            (-1, PathPattern::prefix("/other"), "other"),
+        ]);
+        assert_eq!(
+            Some("root"),
+            router.route("/api/v2").map(|(_, value)| *value)
+        );
+        assert_eq!(
+            Some("api"),
+            router
+                .route_where("/api/v2", |value| *value != "root" && *value != "v2")
+                .map(|(_, value)| *value)
+        );
+        assert_eq!(
+            Some("other"),
+            router
+                .route_where("/other", |value| *value != "root")
+                .map(|(_, value)| *value)
+        );
+    }
+
+    #[test]
+    fn test_router_same_prefix() {
+        let router = Router::new([
+            (0, PathPattern::prefix("/api"), "first"),
+            (0, PathPattern::prefix("/api"), "second"),
+        ]);
+        assert_eq!(Some("first"), router.route("/api").map(|(_, value)| *value));
+        assert_eq!(
+            Some("second"),
+            router
+                .route_where("/api", |value| *value != "first")
+                .map(|(_, value)| *value)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Headers telling browsers how to protect the pages the proxy serves, added to
+//! responses so backends don't each have to send them.
+
+use agora_http_parser::{HeaderName, HeaderValue, Headers};
+use serde::{Deserialize, Serialize};
+
+/// The max age HSTS preload lists require, of a year
+const PRELOAD_MIN_MAX_AGE: u64 = 31_536_000;
+
+/// The security headers added to responses, none by default. Responses over cleartext
+/// HTTP don't get `Strict-Transport-Security`, which browsers ignore there.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeaders {
+    /// `Strict-Transport-Security`, having browsers only use HTTPS for the host
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict_transport_security: Option<StrictTransportSecurity>,
+    /// `X-Content-Type-Options: nosniff`, having browsers trust the Content-Type
+    /// rather than guess it
+    pub x_content_type_options: bool,
+    /// `X-Frame-Options`, telling who may embed the pages in a frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x_frame_options: Option<FrameOptions>,
+    /// `Referrer-Policy`, telling how much of the URL links send on as the referrer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referrer_policy: Option<ReferrerPolicy>,
+    /// `Content-Security-Policy`, like `default-src 'self'`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_security_policy: Option<String>,
+    /// Replace the headers a response already has, which are kept otherwise so
+    /// backends can set their own for some pages
+    pub replace: bool,
+}
+
+/// How long browsers only use HTTPS for the host, and for which other hosts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StrictTransportSecurity {
+    /// Seconds browsers remember to, a year by default
+    pub max_age: u64,
+    /// Also for the subdomains of the host
+    pub include_subdomains: bool,
+    /// Consent to the host being in the preload lists of browsers, which requires
+    /// `include_subdomains` and a max age of at least a year
+    pub preload: bool,
+}
+
+impl Default for StrictTransportSecurity {
+    fn default() -> Self {
+        Self {
+            max_age: PRELOAD_MIN_MAX_AGE,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+}
+
+/// Who may embed the pages in a frame, written in config files as `"deny"` or
+/// `"same_origin"`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameOptions {
+    /// Nobody
+    Deny,
+    /// Pages of the same origin only
+    SameOrigin,
+}
+
+/// The policies of `Referrer-Policy`, written in config files as they are in the
+/// header, like `"strict-origin-when-cross-origin"`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReferrerPolicy {
+    NoReferrer,
+    NoReferrerWhenDowngrade,
+    Origin,
+    OriginWhenCrossOrigin,
+    SameOrigin,
+    StrictOrigin,
+    StrictOriginWhenCrossOrigin,
+    UnsafeUrl,
+}
+
+impl SecurityHeaders {
+    /// The headers for a response, sent over TLS or not
+    pub fn headers(&self, tls: bool) -> Headers {
+        let mut headers = Headers::new();
+        if let Some(hsts) = &self.strict_transport_security
+            && tls
+        {
+            headers.insert(
+                HeaderName::from_static("strict-transport-security"),
+                hsts.value(),
+            );
+        }
+        if self.x_content_type_options {
+            headers.insert(
+                HeaderName::from_static("x-content-type-options"),
+                HeaderValue::from_static("nosniff"),
+            );
+        }
+        if let Some(frame_options) = self.x_frame_options {
+            let value = match frame_options {
+                FrameOptions::Deny => "DENY",
+                FrameOptions::SameOrigin => "SAMEORIGIN",
+            };
+            headers.insert(
+                HeaderName::from_static("x-frame-options"),
+                HeaderValue::from_static(value),
+            );
+        }
+        if let Some(policy) = self.referrer_policy {
+            headers.insert(
+                HeaderName::from_static("referrer-policy"),
+                HeaderValue::from_static(policy.as_str()),
+            );
+        }
+        if let Some(policy) = &self.content_security_policy
+            && let Ok(value) = HeaderValue::try_from(policy.as_str())
+        {
+            headers.insert(HeaderName::from_static("content-security-policy"), value);
+        }
+        headers
+    }
+
+    /// Add the headers to those of a response, keeping any it has unless set to
+    /// replace them
+    pub fn apply(&self, headers: &mut Headers, tls: bool) {
+        for (name, value) in self.headers(tls).iter() {
+            if self.replace || !headers.contains_key(name.as_str()) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(hsts) = &self.strict_transport_security
+            && hsts.preload
+            && (!hsts.include_subdomains || hsts.max_age < PRELOAD_MIN_MAX_AGE)
+        {
+            return Err(format!(
+                "strict_transport_security.preload: requires `include_subdomains` and a `max_age` of at least {PRELOAD_MIN_MAX_AGE}"
+            ));
+        }
+        if let Some(policy) = &self.content_security_policy
+            && (policy.trim().is_empty() || HeaderValue::try_from(policy.as_str()).is_err())
+        {
+            return Err(String::from(
+                "content_security_policy: must be a non-empty header value",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl StrictTransportSecurity {
+    fn value(&self) -> HeaderValue {
+        let mut value = format!("max-age={}", self.max_age);
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        HeaderValue::try_from(value).expect("the value is made of valid characters")
+    }
+}
+
+impl ReferrerPolicy {
+    fn as_str(self) -> &'static str {
+        match self {
+            ReferrerPolicy::NoReferrer => "no-referrer",
+            ReferrerPolicy::NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+            ReferrerPolicy::Origin => "origin",
+            ReferrerPolicy::OriginWhenCrossOrigin => "origin-when-cross-origin",
+            ReferrerPolicy::SameOrigin => "same-origin",
+            ReferrerPolicy::StrictOrigin => "strict-origin",
+            ReferrerPolicy::StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+            ReferrerPolicy::UnsafeUrl => "unsafe-url",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn header(headers: &Headers, name: &str) -> Option<String> {
+        headers
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_headers() {
+        let security: SecurityHeaders = serde_json::from_str(
+            r#"{
+                "strict_transport_security": { "max_age": 63072000, "include_subdomains": true, "preload": true },
+                "x_content_type_options": true,
+                "x_frame_options": "same_origin",
+                "referrer_policy": "strict-origin-when-cross-origin",
+                "content_security_policy": "default-src 'self'"
+            }"#,
+        )
+        .unwrap();
+
+        let headers = security.headers(true);
+        assert_eq!(
+            Some("max-age=63072000; includeSubDomains; preload"),
+            header(&headers, "strict-transport-security").as_deref()
+        );
+        assert_eq!(
+            Some("nosniff"),
+            header(&headers, "x-content-type-options").as_deref()
+        );
+        assert_eq!(
+            Some("SAMEORIGIN"),
+            header(&headers, "x-frame-options").as_deref()
+        );
+        assert_eq!(
+            Some("strict-origin-when-cross-origin"),
+            header(&headers, "referrer-policy").as_deref()
+        );
+        assert_eq!(
+            Some("default-src 'self'"),
+            header(&headers, "content-security-policy").as_deref()
+        );
+
+        // HSTS only means something over HTTPS
+        let headers = security.headers(false);
+        assert_eq!(None, header(&headers, "strict-transport-security"));
+        assert_eq!(4, headers.len());
+    }
+
+    #[rstest]
+    #[case(false, "DENY")]
+    #[case(true, "SAMEORIGIN")]
+    fn test_apply(#[case] replace: bool, #[case] expected: &str) {
+        let security = SecurityHeaders {
+            x_content_type_options: true,
+            x_frame_options: Some(FrameOptions::SameOrigin),
+            replace,
+            ..Default::default()
+        };
+        let mut headers = Headers::new();
+        headers.insert(
+            HeaderName::from_static("x-frame-options"),
+            HeaderValue::from_static("DENY"),
+        );
+        security.apply(&mut headers, false);
+
+        assert_eq!(
+            Some(expected),
+            header(&headers, "x-frame-options").as_deref()
+        );
+        assert_eq!(
+            Some("nosniff"),
+            header(&headers, "x-content-type-options").as_deref()
+        );
+    }
+}
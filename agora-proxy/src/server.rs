@@ -1,79 +1,431 @@
-use std::{
-    collections::HashMap, fs::File, io::BufReader, net::SocketAddr, path::Path, time::Duration,
-};
+use std::{collections::HashMap, future::pending, net::SocketAddr, sync::Arc, time::Duration};
 
-use agora_http_parser::{HTTPVersion, Headers, Request, Response, is_terminated};
+use agora_http_parser::{
+    Body, HTTPMethod, HTTPParseError, HTTPVersion, HeaderName, HeaderValue, Headers, Request,
+    Response,
+    chunked::{ChunkedDecoder, chunked_body_len},
+    h2::CONNECTION_PREFACE,
+    is_terminated_from,
+};
+use arc_swap::ArcSwap;
 use http::StatusCode;
-use serde::{Deserialize, Serialize};
+use ipnet::IpNet;
 use tokio::{
-    io::{self, AsyncReadExt, AsyncWriteExt},
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
     net::{TcpListener, TcpStream},
-    time::timeout,
+    time::{Instant, sleep, timeout, timeout_at},
 };
+use tokio_rustls::TlsAcceptor;
 use tracing::{debug, error, info, warn};
 
+use crate::{
+    config::{
+        Alpn, Buffering, ExpectContinue, ForwardProxy, HostHeader, Http2, Limits, Listener,
+        ProxyEntry, RetryOn, Route, ServerConfig, Upstream, request_host, virtual_host,
+    },
+    forwarded::Client,
+    grpc,
+    proxy_protocol::{self, Header},
+    retry::{ActiveRetry, Budget},
+    routing::Router,
+    security_headers::SecurityHeaders,
+    tls,
+    transform::{HeaderTransform, Variables},
+    tunnel::{self, Transferred, TunnelLimits, Tunnels},
+    upstream::{InFlight, Pool, Stream},
+};
+
+mod client;
+mod connect;
+mod h2;
+mod passthrough;
+mod redirect;
+
+pub use client::ClientStream;
+
 const MAX_BUF_SIZE: usize = 4096 * 2;
 
 pub struct Server {
-    config: ServerConfig,
+    /// Swapped as a whole on reload. Requests keep the state they started with, and
+    /// the next request on a connection takes the current one.
+    state: Arc<ArcSwap<State>>,
+    /// Kept across reloads, like the tunnels themselves
+    tunnels: Arc<Tunnels>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProxyEntry {
-    pub addr: String,
-    pub strip_prefix: bool,
+/// What handling requests needs from a [`ServerConfig`], compiled once when the server
+/// is created or reloaded
+struct State {
+    default: Router<Target>,
+    virtual_hosts: HashMap<String, Router<Target>>,
+    limits: Limits,
+    http2: Http2,
+    forward_proxy: Option<ForwardProxy>,
+    /// The name in the `Via` header of forwarded messages
+    via: String,
+    trusted_proxies: Vec<IpNet>,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct ServerConfig {
-    /// Mapping of Path prefix to proxy entry
-    pub reverse_proxy_mapping: HashMap<String, ProxyEntry>,
+/// A route along with the pool of backends it proxies to
+struct Target {
+    entry: ProxyEntry,
+    pool: Arc<Pool>,
+    /// The pool failed over to
+    backup: Option<Arc<Pool>>,
+    budget: Budget,
+    /// The security headers of the route, or the global ones
+    security_headers: SecurityHeaders,
 }
 
-impl ServerConfig {
-    pub fn parse(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
-        let config_file = File::open(path)?;
-        let reader = BufReader::new(config_file);
-        let reverse_proxy_mapping: HashMap<String, ProxyEntry> =
-            serde_json::from_reader(reader).map_err(|e| format!("Failed to parse config: {e}"))?;
+/// The head of the final response to a request, along with the connection its body
+/// comes from
+struct Exchange<'a, 'buf> {
+    backend: InFlight<'a>,
+    stream: Stream,
+    variables: Variables,
+    response: Response,
+    /// The bytes read after the head
+    remaining: &'buf [u8],
+    /// When the response has to be relayed by
+    deadline: Instant,
+}
 
-        Ok(Self {
-            reverse_proxy_mapping,
-        })
+/// What a client connection is used for once a request on it was handled
+enum Next {
+    /// Nothing, it gets closed
+    Close,
+    /// The next request, starting with these bytes read along with the one before
+    Request(Vec<u8>),
+    /// HTTP/2, the client having asked to upgrade with the request, which is answered
+    /// on the first stream. The bytes read after it are the start of the frames.
+    Http2(Box<Request>, Vec<u8>),
+}
+
+/// Which of the tries racing for a request responded first
+enum Head<'a, 'buf> {
+    /// The try sent along with the client, with the bytes read after the head
+    Sent(Response, &'buf [u8]),
+    /// The hedge, counted as a retry in the budget while it is in flight
+    Hedged(Exchange<'a, 'buf>, Option<ActiveRetry<'a>>),
+}
+
+impl State {
+    fn new(config: ServerConfig) -> Self {
+        let pools = config
+            .upstreams
+            .iter()
+            .map(|(name, upstream)| {
+                let pool = Arc::new(Pool::new(upstream));
+                pool.discover();
+                if let Some(check) = &upstream.health_check {
+                    pool.check_health(check.clone());
+                }
+                (name.clone(), pool)
+            })
+            .collect();
+        Self {
+            default: compile(config.routes, &pools, &config.security_headers),
+            virtual_hosts: config
+                .virtual_hosts
+                .into_iter()
+                .map(|(name, routes)| (name, compile(routes, &pools, &config.security_headers)))
+                .collect(),
+            limits: config.limits,
+            http2: config.http2,
+            forward_proxy: config.forward_proxy,
+            via: config.via.unwrap_or_else(|| String::from("agora")),
+            trusted_proxies: config.trusted_proxies,
+        }
+    }
+
+    fn for_host(&self, host: Option<&str>) -> &Router<Target> {
+        host.and_then(|host| virtual_host(&self.virtual_hosts, host))
+            .map_or(&self.default, |(_, router)| router)
     }
 }
 
+/// Compile the routes, giving those naming an upstream its pool and the others a pool
+/// of their own addresses, along with the pool of their backup
+fn compile(
+    routes: Vec<Route>,
+    pools: &HashMap<String, Arc<Pool>>,
+    security_headers: &SecurityHeaders,
+) -> Router<Target> {
+    Router::new(routes.into_iter().map(|route| {
+        let pool = match route
+            .entry
+            .upstream
+            .as_ref()
+            .and_then(|name| pools.get(name))
+        {
+            Some(pool) => pool.clone(),
+            None => {
+                let pool = Arc::new(Pool::new(&Upstream {
+                    tls: route.entry.tls.clone(),
+                    ..Upstream::from(route.entry.addr.clone())
+                }));
+                pool.discover();
+                pool
+            }
+        };
+        let backup = route
+            .entry
+            .backup
+            .as_ref()
+            .and_then(|name| pools.get(name))
+            .cloned();
+        let security_headers = route
+            .entry
+            .security_headers
+            .clone()
+            .unwrap_or_else(|| security_headers.clone());
+        let target = Target {
+            entry: route.entry,
+            pool,
+            backup,
+            budget: Budget::default(),
+            security_headers,
+        };
+        (route.priority, route.path, target)
+    }))
+}
+
 impl Server {
     pub fn new(config: ServerConfig) -> Self {
-        Self { config }
+        Self {
+            state: Arc::new(ArcSwap::from_pointee(State::new(config))),
+            tunnels: Arc::default(),
+        }
+    }
+
+    /// The connections passed through as tunnels once they switched protocols
+    pub fn tunnels(&self) -> &Tunnels {
+        &self.tunnels
+    }
+
+    /// Handle new requests with the routes and limits of the config. Requests already
+    /// being handled finish with the previous config, and the listeners stay the same.
+    pub fn reload(&self, config: ServerConfig) {
+        self.state.store(Arc::new(State::new(config)));
     }
 
     pub async fn listen(&self, address: &str) -> io::Result<()> {
         let listener = TcpListener::bind(address).await?;
         info!("Listening on {}", address);
+        self.serve(listener).await
+    }
+
+    /// Accept connections from an already bound listener
+    pub async fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        self.accept(listener, &Listener::default()).await
+    }
+
+    /// Bind the address of the listener and serve the connections accepted on it the
+    /// way it says
+    pub async fn listen_on(&self, listener: &Listener) -> io::Result<()> {
+        let bound = TcpListener::bind(&listener.address).await?;
+        match listener.tls_passthrough {
+            Some(_) => info!("Passing TLS through on {}", listener.address),
+            None => info!("Listening on {}", listener.address),
+        }
+        self.accept(bound, listener).await
+    }
+
+    /// Accept connections from an already bound listener, serving them the way the
+    /// config of the listener says. Its address isn't used.
+    pub async fn accept(&self, bound: TcpListener, listener: &Listener) -> io::Result<()> {
+        let servers = listener.tls_passthrough.clone().map(Arc::new);
+        let https_redirect = listener.https_redirect.clone().map(Arc::new);
+        let acceptor = match &listener.tls {
+            Some(settings) => {
+                let config = tls::server_config(settings).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid TLS settings: {e}"),
+                    )
+                })?;
+                Some(TlsAcceptor::from(config))
+            }
+            None => None,
+        };
+        let proxy_protocol = listener.proxy_protocol;
         loop {
-            let (stream, addr) = listener.accept().await?;
+            let (mut stream, mut addr) = bound.accept().await?;
 
-            let config = self.config.clone();
+            let state = self.state.clone();
+            let tunnels = self.tunnels.clone();
+            let servers = servers.clone();
+            let acceptor = acceptor.clone();
+            let https_redirect = https_redirect.clone();
             tokio::spawn(async move {
-                Self::process(stream, addr, config).await;
+                if proxy_protocol {
+                    let request_timeout = Duration::from_secs(state.load().limits.request_timeout);
+                    match timeout(request_timeout, read_proxy_header(&mut stream)).await {
+                        Ok(Ok(source)) => addr = source.unwrap_or(addr),
+                        Ok(Err(e)) => {
+                            debug!("Closing connection from {addr}: {e}");
+                            return;
+                        }
+                        Err(_) => {
+                            debug!("Closing connection from {addr}: no PROXY protocol header");
+                            return;
+                        }
+                    }
+                }
+                match (servers, https_redirect) {
+                    (Some(servers), _) => {
+                        let limits = state.load().limits.clone();
+                        passthrough::pass_through(stream, addr, &servers, &limits, &tunnels).await;
+                    }
+                    (None, Some(https_redirect)) => {
+                        let state = state.load_full();
+                        redirect::redirect(stream, addr, &https_redirect, &state, &tunnels).await;
+                    }
+                    (None, None) => Self::process(stream, addr, state, tunnels, acceptor).await,
+                }
             });
         }
     }
 
-    async fn process(mut client_stream: TcpStream, addr: SocketAddr, config: ServerConfig) {
+    /// Handle the requests of a connection until the client closes it or stops sending
+    /// requests for a while, once done with the TLS handshake if there's an acceptor.
+    /// Over TLS, the protocol agreed on with ALPN decides how.
+    async fn process(
+        mut stream: TcpStream,
+        addr: SocketAddr,
+        state: Arc<ArcSwap<State>>,
+        tunnels: Arc<Tunnels>,
+        acceptor: Option<TlsAcceptor>,
+    ) {
         debug!("Connection Accepted: {addr}");
 
         let mut buf = [0; MAX_BUF_SIZE];
+        // the start of the next request, read along with the one before it
+        let mut filled = 0;
+        let current = state.load();
+        let request_timeout = Duration::from_secs(current.limits.request_timeout);
+        let mut client_stream = match acceptor {
+            Some(acceptor) => match timeout(request_timeout, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let protocol = stream.get_ref().1.alpn_protocol();
+                    if protocol == Some(Alpn::AcmeTls.id()) {
+                        // the challenge is done once the handshake is
+                        debug!("Closing connection from {addr}: ACME challenge answered");
+                        return;
+                    }
+                    let h2 = protocol == Some(Alpn::H2.id());
+                    let cert = stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(|certs| certs.first())
+                        .and_then(tls::client_cert)
+                        .map(Arc::new);
+                    let client_stream = ClientStream::Tls(Box::new(BufReader::new(stream)), cert);
+                    if h2 {
+                        drop(current);
+                        return h2::serve(client_stream, addr, state, tunnels, None, Vec::new())
+                            .await;
+                    }
+                    client_stream
+                }
+                Ok(Err(e)) => {
+                    debug!("TLS handshake with {addr} failed: {e}");
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake with {addr} timed out");
+                    return;
+                }
+            },
+            None if current.http2.enabled => {
+                match timeout(request_timeout, read_preface(&mut stream, &mut buf)).await {
+                    Ok(Ok((true, read))) => {
+                        drop(current);
+                        let client_stream = ClientStream::Tcp(stream);
+                        let read = buf[..read].to_vec();
+                        return h2::serve(client_stream, addr, state, tunnels, None, read).await;
+                    }
+                    Ok(Ok((false, read))) => {
+                        filled = read;
+                        ClientStream::Tcp(stream)
+                    }
+                    Ok(Err(_)) => return,
+                    Err(_) => {
+                        let mut client_stream = ClientStream::Tcp(stream);
+                        close_connection_with_reason(
+                            &mut client_stream,
+                            StatusCode::REQUEST_TIMEOUT,
+                        )
+                        .await;
+                        return;
+                    }
+                }
+            }
+            None => ClientStream::Tcp(stream),
+        };
+        drop(current);
 
+        for served in 1.. {
+            let current = state.load_full();
+            if served > 1 && filled == 0 {
+                let idle_timeout = Duration::from_secs(current.limits.keepalive_timeout);
+                match timeout(idle_timeout, client_stream.peek(&mut [0; 1])).await {
+                    Ok(Ok(1..)) => {}
+                    // the client closed the connection, or sent nothing for too long
+                    _ => break,
+                }
+            }
+            let keep_alive =
+                served < current.limits.keepalive_requests && current.limits.keepalive_timeout > 0;
+            let next = Self::handle(
+                &mut client_stream,
+                addr,
+                &current,
+                &tunnels,
+                &mut buf,
+                filled,
+                keep_alive,
+            )
+            .await;
+            match next {
+                Next::Close => break,
+                Next::Request(next) => {
+                    buf[..next.len()].copy_from_slice(&next);
+                    filled = next.len();
+                }
+                Next::Http2(request, read) => {
+                    return h2::serve(client_stream, addr, state, tunnels, Some(*request), read)
+                        .await;
+                }
+            }
+        }
+        // over TLS, telling the client the connection is closing on purpose
+        if client_stream.is_tls() {
+            let _ = client_stream.shutdown().await;
+        }
+    }
+
+    /// Handle a request of the client, the first `filled` bytes of the buffer being its
+    /// start. Returns what the connection is used for next: the next request, whose start
+    /// may have been read already, only if `keep_alive` is set.
+    async fn handle(
+        client_stream: &mut ClientStream,
+        addr: SocketAddr,
+        state: &State,
+        tunnels: &Tunnels,
+        buf: &mut [u8; MAX_BUF_SIZE],
+        filled: usize,
+        keep_alive: bool,
+    ) -> Next {
         let Ok(read_result) = timeout(
-            Duration::from_secs(30),
-            read_request(&mut client_stream, &mut buf),
+            Duration::from_secs(state.limits.request_timeout),
+            read_request(client_stream, buf, filled),
         )
         .await
         else {
-            close_connection_with_reason(&mut client_stream, StatusCode::REQUEST_TIMEOUT).await;
-            return;
+            close_connection_with_reason(client_stream, StatusCode::REQUEST_TIMEOUT).await;
+            return Next::Close;
         };
 
         let (mut request, remaining_body) = match read_result {
@@ -98,122 +450,766 @@ impl Server {
                     _ => {
                         // not much we can do to recover from this
                         error!("Failed to read request from {addr}: {e}");
-                        return;
+                        return Next::Close;
                     }
                 };
-                close_connection_with_reason(&mut client_stream, reason).await;
-                return;
+                close_connection_with_reason(client_stream, reason).await;
+                return Next::Close;
             }
         };
 
         debug!("{request}");
 
-        if request.version != HTTPVersion::HTTP1_1 {
-            close_connection_with_reason(
-                &mut client_stream,
-                StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+        // HTTP/1.0 requests are sent upstream as they are, so backends answer them
+        // without chunking a response the client couldn't read
+        if !matches!(request.version, HTTPVersion::HTTP1_0 | HTTPVersion::HTTP1_1) {
+            close_connection_with_reason(client_stream, StatusCode::HTTP_VERSION_NOT_SUPPORTED)
+                .await;
+            return Next::Close;
+        }
+
+        if state.http2.enabled
+            && matches!(client_stream, ClientStream::Tcp(_))
+            && request.upgrades_to_h2c()
+            && request.body == Body::Empty
+        {
+            return Next::Http2(Box::new(request), remaining_body.to_vec());
+        }
+
+        if request.method == HTTPMethod::CONNECT
+            && let Some(forward_proxy) = &state.forward_proxy
+        {
+            connect::tunnel(
+                client_stream,
+                addr,
+                &request,
+                remaining_body,
+                forward_proxy,
+                state,
+                tunnels,
             )
             .await;
-            return;
+            return Next::Close;
         }
 
-        // could be a performance issue iterating through lots of mappings
-        // this could be cachable.
-        let matching_entry = config
-            .reverse_proxy_mapping
-            .into_iter()
-            .find(|(prefix, _)| request.path.starts_with(prefix));
+        // a request that went through this proxy before would keep coming back
+        if request
+            .via()
+            .is_ok_and(|hops| hops.iter().any(|hop| hop.received_by == state.via))
+        {
+            warn!("Request for {} came back around in a loop", request.path);
+            close_connection_with_reason(client_stream, StatusCode::LOOP_DETECTED).await;
+            return Next::Close;
+        }
+        request.append_via(&state.via);
+        let client = Client::new(
+            &request,
+            addr,
+            client_stream.local_addr().ok(),
+            client_stream.is_tls(),
+            client_stream.client_cert(),
+            &state.trusted_proxies,
+        );
 
-        if let Some((prefix, entry)) = matching_entry {
-            let Ok(mut server_stream) = TcpStream::connect(&entry.addr).await else {
-                error!(
-                    "Failed to establish TCP connection with server: {}",
-                    entry.addr
-                );
-                close_connection_with_reason(&mut client_stream, StatusCode::BAD_GATEWAY).await;
-                return;
+        let host = request_host(&request);
+
+        let matching_entry = state
+            .for_host(host.as_deref())
+            .route_where(&request.path, |target| target.entry.accepts(&request));
+
+        if let Some((
+            path_match,
+            Target {
+                entry,
+                pool,
+                backup,
+                budget,
+                security_headers,
+            },
+        )) = matching_entry
+        {
+            // responses the proxy makes itself leave the connection ready for another
+            // request once the body of this one was all read, which is then skipped
+            let (body, next_request) =
+                remaining_body.split_at(body_start(request.body, remaining_body).len());
+            let local_keep_alive =
+                keep_alive && request.wants_keep_alive() && body_complete(&request, body);
+            let local_next = || match local_keep_alive {
+                true => Next::Request(next_request.to_vec()),
+                false => Next::Close,
             };
+            let connection = connection_header(request.version, local_keep_alive);
+            let headers = security_headers.headers(client.tls);
 
-            let mut proxy_conn = ProxyConnection::new(&mut client_stream, &mut server_stream);
+            if let Some(maintenance) = &entry.maintenance
+                && maintenance.is_active().await
+            {
+                debug!("Route for {} is under maintenance", request.path);
+                if let Err(e) = client_stream
+                    .write_all(&maintenance.response(&headers, connection))
+                    .await
+                {
+                    error!("Failed to send response: {e}");
+                    return Next::Close;
+                }
+                return local_next();
+            }
+
+            request.path = entry.upstream_path(&request.path, &path_match);
 
-            if entry.strip_prefix {
-                request.path = request.path.replace(&prefix, "").to_string();
-                if !request.path.starts_with('/') {
-                    request.path.insert(0, '/');
+            if let Some(static_files) = &entry.static_files {
+                if let Err(e) = static_files
+                    .serve(client_stream, &request, &headers, connection)
+                    .await
+                {
+                    error!("Failed to serve {} to {client}: {e}", request.path);
+                    return Next::Close;
                 }
+                return local_next();
             }
 
-            let Ok(proxy_result) = timeout(
-                Duration::from_secs(30),
-                proxy_conn.proxy_request(request, remaining_body),
-            )
-            .await
-            else {
-                close_connection_with_reason(&mut client_stream, StatusCode::REQUEST_TIMEOUT).await;
-                return;
+            entry.forwarded.apply(&mut request, &client);
+            // gRPC servers turn down calls from clients that can't take the trailers the
+            // status comes in, which the proxy can on their behalf
+            if entry.grpc {
+                request.headers.insert(
+                    HeaderName::from_static("te"),
+                    HeaderValue::from_static("trailers"),
+                );
+            }
+
+            // bytes after the body are the start of the next request, which only a client
+            // not waiting for the response sends
+            let (remaining_body, next_request) =
+                remaining_body.split_at(body_start(request.body, remaining_body).len());
+            let next_request = next_request.to_vec();
+            // the rest of a chunked body is read in pieces that may run into the next
+            // request, which is then lost
+            let keep_alive = keep_alive
+                && request.wants_keep_alive()
+                && (request.body != Body::Chunked || body_complete(&request, remaining_body));
+            // kept apart from the buffer the response is read into, so the request can be
+            // sent again
+            let remaining_body = remaining_body.to_vec();
+            let retry = entry.retry.as_ref();
+            let attempts = retry.map_or(1, |retry| retry.attempts);
+            // once sent, a request can only be sent again if none of its body was streamed
+            let resendable = body_complete(&request, &remaining_body)
+                && retry.map_or(request.method.is_idempotent(), |retry| {
+                    retry.allows(&request.method)
+                });
+            let hedge_after = retry
+                .and_then(|retry| retry.hedge_after_ms)
+                .filter(|_| resendable)
+                .map(Duration::from_millis);
+            let upstream_timeout = Duration::from_secs(state.limits.upstream_timeout);
+            // the client waits to be told to send the rest of the body
+            let expects_continue =
+                request.expects_continue() && !body_complete(&request, &remaining_body);
+            let forward_expectation =
+                expects_continue && entry.expect_continue == ExpectContinue::Forward;
+            let mut continued = false;
+            let mut body_sent = true;
+
+            let _request = budget.request();
+            // retries and hedges count against the budget of the route while in flight.
+            // Only the latest retry is, the tries before it having failed.
+            let mut active_retry = None;
+            let mut _active_hedge = None;
+            let mut hedge_buf = hedge_after.map(|_| Box::new([0; MAX_BUF_SIZE]));
+
+            let mut pool = pool;
+            let mut backup = backup.as_ref();
+            // the backup is tried once the route gives up, when it can still send the request
+            let mut failover = |sent: bool| {
+                if sent && !resendable {
+                    return None;
+                }
+                let backup = backup.take()?;
+                warn!("Failing over to the backup upstream for {}", request.path);
+                Some(backup)
             };
 
-            if let Err(ref e) = proxy_result {
-                let reason = match e.kind() {
-                    io::ErrorKind::InvalidData => {
-                        warn!("Invalid Request: {e}");
-                        StatusCode::BAD_REQUEST
+            let mut attempt = 0;
+            let mut mirrored = false;
+            let exchange = loop {
+                attempt += 1;
+                let mut retries = |failure: RetryOn| {
+                    let Some(retry) = retry.filter(|retry| retry.covers(failure)) else {
+                        return false;
+                    };
+                    if attempt >= attempts {
+                        return false;
                     }
-                    _ => {
-                        error!("Failed to proxy request to {}: {e}", entry.addr);
-                        StatusCode::BAD_GATEWAY
+                    let Some(limits) = &retry.budget else {
+                        return true;
+                    };
+                    active_retry = budget.retry(limits);
+                    if active_retry.is_none() {
+                        warn!(
+                            "Not retrying request for {}: retry budget spent",
+                            request.path
+                        );
                     }
+                    active_retry.is_some()
                 };
 
-                close_connection_with_reason(&mut client_stream, reason).await;
-                return;
+                let (backend, mut server_stream) = match pool.connect(&request, &client).await {
+                    Ok(connection) => connection,
+                    Err(e) if retries(RetryOn::ConnectFailure) => {
+                        warn!("Retrying request for {}: {e}", request.path);
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Failed to establish TCP connection with any backend: {e}");
+                        if let Some(backup) = failover(false) {
+                            pool = backup;
+                            continue;
+                        }
+                        reject(client_stream, StatusCode::BAD_GATEWAY, entry).await;
+                        return Next::Close;
+                    }
+                };
+
+                let mut proxy_conn = ProxyConnection::new(client_stream, &mut server_stream);
+
+                let variables = Variables {
+                    remote_addr: addr.to_string(),
+                    upstream_addr: backend.addr.clone(),
+                    host: request.host().unwrap_or_default().to_string(),
+                };
+
+                let mut upstream_request = request.clone();
+                rewrite_host(&mut upstream_request, &entry.host, &backend.authority);
+                if expects_continue && !forward_expectation {
+                    upstream_request.headers.remove("expect");
+                    if !continued {
+                        continued = true;
+                        if let Err(e) = proxy_conn.send_continue().await {
+                            error!("Failed to send response: {e}");
+                            return Next::Close;
+                        }
+                    }
+                }
+
+                if !mirrored && let Some(mirror_addr) = &entry.mirror {
+                    mirrored = true;
+                    mirror_request(
+                        mirror_addr,
+                        &upstream_request,
+                        &remaining_body,
+                        &entry.request_headers,
+                        &variables,
+                    );
+                }
+
+                let started = Instant::now();
+                let Ok(proxy_result) = timeout(
+                    upstream_timeout,
+                    proxy_conn.proxy_request(
+                        upstream_request,
+                        &remaining_body,
+                        &entry.request_headers,
+                        &variables,
+                        forward_expectation.then_some(&mut *buf),
+                    ),
+                )
+                .await
+                else {
+                    reject(client_stream, StatusCode::REQUEST_TIMEOUT, entry).await;
+                    return Next::Close;
+                };
+
+                let filled = match proxy_result {
+                    Ok(Sent::Request { filled }) => filled,
+                    Ok(Sent::Head { filled }) => {
+                        body_sent = false;
+                        filled
+                    }
+                    Err(ref e) => {
+                        let reason = match e.kind() {
+                            io::ErrorKind::InvalidData => {
+                                warn!("Invalid Request: {e}");
+                                StatusCode::BAD_REQUEST
+                            }
+                            _ if backend.is_reused() && resendable => {
+                                debug!("Reused connection to {} was closed: {e}", backend.addr);
+                                attempt -= 1;
+                                continue;
+                            }
+                            _ => {
+                                error!("Failed to proxy request to {}: {e}", backend.addr);
+                                backend.report(false);
+                                if resendable && retries(RetryOn::Reset) {
+                                    warn!(
+                                        "Retrying request after sending it to {} failed",
+                                        backend.addr
+                                    );
+                                    continue;
+                                }
+                                if let Some(backup) = failover(true) {
+                                    pool = backup;
+                                    continue;
+                                }
+                                StatusCode::BAD_GATEWAY
+                            }
+                        };
+
+                        reject(client_stream, reason, entry).await;
+                        return Next::Close;
+                    }
+                };
+
+                // the response gets the same time whether or not its head is slow to come,
+                // though each try may only get part of it to wait for the head
+                let deadline = Instant::now() + upstream_timeout;
+                let try_deadline = retry.and_then(|retry| retry.per_try_timeout).map_or(
+                    deadline,
+                    |per_try_timeout| {
+                        deadline.min(Instant::now() + Duration::from_secs(per_try_timeout))
+                    },
+                );
+
+                // a try still waiting for its response after a while is raced by another
+                let hedge = async {
+                    let (Some(delay), Some(buf)) = (hedge_after, hedge_buf.as_deref_mut()) else {
+                        return pending().await;
+                    };
+                    sleep(delay).await;
+                    let active = match retry.and_then(|retry| retry.budget.as_ref()) {
+                        Some(limits) => match budget.retry(limits) {
+                            Some(active) => Some(active),
+                            None => return pending().await,
+                        },
+                        None => None,
+                    };
+                    debug!("Hedging request for {} after {delay:?}", request.path);
+                    match send_hedge(
+                        pool,
+                        &request,
+                        &remaining_body,
+                        &client,
+                        entry,
+                        buf,
+                        deadline,
+                    )
+                    .await
+                    {
+                        Ok(exchange) => (exchange, active),
+                        Err(e) => {
+                            warn!("Hedged request for {} failed: {e}", request.path);
+                            pending().await
+                        }
+                    }
+                };
+                let head_result = timeout_at(try_deadline, async {
+                    tokio::select! {
+                        head = proxy_conn.read_response(buf, filled, request.version) => {
+                            head.map(|(response, remaining)| Head::Sent(response, remaining))
+                        }
+                        (exchange, active) = hedge => Ok(Head::Hedged(exchange, active)),
+                    }
+                })
+                .await;
+
+                let exchange = match head_result {
+                    Ok(Ok(Head::Sent(response, remaining))) => {
+                        backend.record_latency(started.elapsed());
+                        Exchange {
+                            backend,
+                            stream: server_stream,
+                            variables,
+                            response,
+                            remaining,
+                            deadline,
+                        }
+                    }
+                    Ok(Ok(Head::Hedged(exchange, active))) => {
+                        _active_hedge = active;
+                        exchange
+                    }
+                    // the backend closing an idle connection just as it was reused doesn't
+                    // count as a try
+                    Ok(Err(e)) if backend.is_reused() && resendable => {
+                        debug!("Reused connection to {} was closed: {e}", backend.addr);
+                        attempt -= 1;
+                        continue;
+                    }
+                    Ok(Err(e)) => {
+                        error!("Failed to read response from {}: {e}", backend.addr);
+                        backend.report(false);
+                        if resendable && retries(RetryOn::Reset) {
+                            warn!("Retrying request after {} failed", backend.addr);
+                            continue;
+                        }
+                        if let Some(backup) = failover(true) {
+                            pool = backup;
+                            continue;
+                        }
+                        reject(client_stream, StatusCode::BAD_GATEWAY, entry).await;
+                        return Next::Close;
+                    }
+                    Err(_) => {
+                        backend.report(false);
+                        if resendable && retries(RetryOn::Timeout) {
+                            warn!("Retrying request after {} timed out", backend.addr);
+                            continue;
+                        }
+                        if let Some(backup) = failover(true) {
+                            pool = backup;
+                            continue;
+                        }
+                        reject(client_stream, StatusCode::GATEWAY_TIMEOUT, entry).await;
+                        return Next::Close;
+                    }
+                };
+                let status = exchange.response.status();
+                exchange.backend.report(!status.is_server_error());
+
+                if resendable && retries(RetryOn::Status(status.as_u16())) {
+                    warn!(
+                        "Retrying request after {} responded with {status}",
+                        exchange.backend.addr
+                    );
+                    continue;
+                }
+                if is_gateway_failure(status)
+                    && let Some(backup) = failover(true)
+                {
+                    pool = backup;
+                    continue;
+                }
+
+                break exchange;
             };
 
-            let Ok(proxy_result) =
-                timeout(Duration::from_secs(30), proxy_conn.proxy_response(&mut buf)).await
-            else {
-                close_connection_with_reason(&mut client_stream, StatusCode::GATEWAY_TIMEOUT).await;
-                return;
+            let Exchange {
+                backend,
+                mut stream,
+                variables,
+                mut response,
+                remaining,
+                deadline,
+            } = exchange;
+            let switching = response.status() == StatusCode::SWITCHING_PROTOCOLS;
+            if switching && !request.upgrades_to_websocket() {
+                error!("{} switched protocols without being asked to", backend.addr);
+                reject(client_stream, StatusCode::BAD_GATEWAY, entry).await;
+                return Next::Close;
+            }
+            if request.method == HTTPMethod::HEAD {
+                response.set_body(Body::Empty);
+            }
+            response.append_via(&state.via);
+            if !switching {
+                security_headers.apply(response.get_headers_mut(), client.tls);
+            }
+            let body = response.body();
+            // only a response whose end is known for sure leaves the connection ready for
+            // another request
+            let reusable = body_sent
+                && !switching
+                && response.wants_keep_alive()
+                && match body {
+                    Body::Empty => remaining.is_empty(),
+                    Body::Sized(length) => remaining.len() as u64 <= length,
+                    Body::Chunked | Body::UntilClose => false,
+                };
+            // the client can send another request once the end of the response is known
+            // and once the body it didn't get to send is no longer expected
+            let keep_alive = keep_alive && body != Body::UntilClose && body_sent;
+            // responses with no known end, like Server-Sent Events, go on for as long
+            // as the upstream keeps sending, which each read gets the timeout to do
+            let streaming = entry.buffering == Buffering::Off
+                || entry.grpc
+                || is_event_stream(&response)
+                || matches!(body, Body::Chunked | Body::UntilClose);
+            let mut proxy_conn = ProxyConnection::new(client_stream, &mut stream);
+            let relay = proxy_conn.relay_response(
+                response,
+                remaining,
+                &entry.response_headers,
+                &variables,
+                // a switched connection stays open for the new protocol
+                connection_header(request.version, keep_alive).filter(|_| !switching),
+                streaming.then_some(upstream_timeout),
+            );
+            let relayed = if streaming {
+                Ok(relay.await)
+            } else {
+                timeout_at(deadline, relay).await
+            };
+            let Ok(proxy_result) = relayed else {
+                close_connection_with_reason(client_stream, StatusCode::GATEWAY_TIMEOUT).await;
+                return Next::Close;
             };
 
             if let Err(e) = proxy_result {
-                error!("Failed to proxy response to {addr}: {e}");
-                close_connection_with_reason(&mut client_stream, StatusCode::BAD_GATEWAY).await;
-            };
+                error!(
+                    "Failed to proxy response from {} to {client}: {e}",
+                    backend.addr
+                );
+                close_connection_with_reason(client_stream, StatusCode::BAD_GATEWAY).await;
+                return Next::Close;
+            }
+            if switching {
+                // the upstream timeout covers the handshake only, the connection then
+                // being as long lived as the tunnel limits let it
+                debug!(
+                    "WebSocket opened between {client} and {}, {} open",
+                    backend.addr,
+                    tunnels.active() + 1
+                );
+                let Transferred {
+                    sent,
+                    received,
+                    closed_by,
+                } = proxy_conn
+                    .tunnel(
+                        &next_request,
+                        remaining,
+                        TunnelLimits::from(&state.limits),
+                        tunnels,
+                    )
+                    .await;
+                match closed_by {
+                    None => debug!(
+                        "WebSocket between {client} and {} closed after {sent} bytes sent and {received} received",
+                        backend.addr
+                    ),
+                    Some(e) => info!(
+                        "WebSocket between {client} and {} closed after {sent} bytes sent and {received} received: {e}",
+                        backend.addr
+                    ),
+                }
+                return Next::Close;
+            }
+            if reusable {
+                backend.release(stream);
+            }
+            if keep_alive {
+                Next::Request(next_request)
+            } else {
+                Next::Close
+            }
         } else {
-            close_connection_with_reason(&mut client_stream, StatusCode::NOT_FOUND).await;
-        };
+            close_connection_with_reason(client_stream, StatusCode::NOT_FOUND).await;
+            Next::Close
+        }
     }
 }
 
-async fn close_connection_with_reason(stream: &mut TcpStream, status_code: StatusCode) {
+/// Whether the response is a stream of Server-Sent Events, which may never end
+fn is_event_stream(response: &Response) -> bool {
+    response
+        .get_header("content-type")
+        .is_some_and(|content_type| {
+            let essence = content_type.as_bytes().split(|&b| b == b';').next();
+            essence.is_some_and(|essence| {
+                essence
+                    .trim_ascii()
+                    .eq_ignore_ascii_case(b"text/event-stream")
+            })
+        })
+}
+
+/// The `connection` header telling the client whether it can send another request on
+/// the connection. HTTP/1.1 clients assume so unless told otherwise, HTTP/1.0 clients
+/// only when told so.
+fn connection_header(version: HTTPVersion, keep_alive: bool) -> Option<HeaderValue> {
+    match (version, keep_alive) {
+        (_, false) => Some(HeaderValue::from_static("close")),
+        (HTTPVersion::HTTP1_0, true) => Some(HeaderValue::from_static("keep-alive")),
+        (_, true) => None,
+    }
+}
+
+/// Send a copy of the request to the mirror in the background, only logging failures.
+/// Requests are only mirrored if their whole body was read along with the head, since
+/// the rest of a body is streamed to the upstream without being kept.
+fn mirror_request(
+    mirror_addr: &str,
+    request: &Request,
+    body: &[u8],
+    headers: &HeaderTransform,
+    variables: &Variables,
+) {
+    if !body_complete(request, body) {
+        debug!("Not mirroring request to {mirror_addr}: body wasn't fully read");
+        return;
+    }
+
+    let mut request = request.clone();
+    strip_hop_by_hop(&mut request.headers);
+    headers.apply(&mut request.headers, variables);
+    request.headers.insert(
+        HeaderName::from_static("connection"),
+        HeaderValue::from_static("close"),
+    );
+    let mut bytes = request.into_bytes();
+    bytes.extend_from_slice(body);
+
+    let mirror_addr = mirror_addr.to_string();
+    tokio::spawn(async move {
+        match timeout(
+            Duration::from_secs(30),
+            send_to_mirror(&mirror_addr, &bytes),
+        )
+        .await
+        {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to mirror request to {mirror_addr}: {e}"),
+            Err(_) => warn!("Timed out mirroring request to {mirror_addr}"),
+        }
+    });
+}
+
+/// Send a copy of a request whose body was fully read to the backend the pool picks
+/// next, and read the head of its final response. This doesn't involve the client, so it
+/// can race a try that does, and interim responses are dropped since the client gets
+/// those of the try it races.
+async fn send_hedge<'a, 'buf>(
+    pool: &'a Pool,
+    request: &Request,
+    body: &[u8],
+    client: &Client,
+    entry: &ProxyEntry,
+    buf: &'buf mut [u8; MAX_BUF_SIZE],
+    deadline: Instant,
+) -> io::Result<Exchange<'a, 'buf>> {
+    let (backend, mut stream) = pool.connect(request, client).await?;
+    let variables = Variables {
+        remote_addr: client.addr.to_string(),
+        upstream_addr: backend.addr.clone(),
+        host: request.host().unwrap_or_default().to_string(),
+    };
+    let mut request = request.clone();
+    rewrite_host(&mut request, &entry.host, &backend.authority);
+    prepare_request(&mut request, &entry.request_headers, &variables);
+    let mut bytes = request.into_bytes();
+    bytes.extend_from_slice(body);
+
+    let started = Instant::now();
+    let head = async {
+        stream.write_all(&bytes).await?;
+        read_final_response(&mut stream, buf, 0, None).await
+    }
+    .await;
+    let (response, remaining) = match head {
+        Ok(head) => head,
+        Err(e) => {
+            backend.report(false);
+            return Err(e);
+        }
+    };
+    backend.record_latency(started.elapsed());
+    Ok(Exchange {
+        backend,
+        stream,
+        variables,
+        response,
+        remaining,
+        deadline,
+    })
+}
+
+/// Replace the Host header with the one configured for the route
+fn rewrite_host(request: &mut Request, host: &HostHeader, upstream_addr: &str) {
+    if let Some(upstream_host) = host.rewrite(upstream_addr)
+        && let Ok(upstream_host) = HeaderValue::try_from(upstream_host)
+    {
+        request
+            .headers
+            .insert(HeaderName::from_static("host"), upstream_host);
+    }
+}
+
+/// Whether the status means the upstream couldn't handle the request, rather than the
+/// request being handled and failing, which is when the route fails over to its backup
+fn is_gateway_failure(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Whether the whole body of the request was read along with its head
+fn body_complete(request: &Request, body: &[u8]) -> bool {
+    match request.body {
+        Body::Empty => true,
+        Body::Sized(length) => body.len() as u64 >= length,
+        Body::Chunked => matches!(chunked_body_len(body), Ok(Some(_))),
+        Body::UntilClose => false,
+    }
+}
+
+/// The bytes of the body among those read after the head, leaving out any of the next
+/// message
+fn body_start(body: Body, bytes: &[u8]) -> &[u8] {
+    match body {
+        Body::Empty => &[],
+        Body::Sized(length) => &bytes[..bytes.len().min(length as usize)],
+        Body::Chunked => match chunked_body_len(bytes) {
+            Ok(Some(len)) => &bytes[..len],
+            // an invalid body fails once it is proxied
+            _ => bytes,
+        },
+        Body::UntilClose => bytes,
+    }
+}
+
+async fn send_to_mirror(addr: &str, request: &[u8]) -> io::Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(request).await?;
+    // the response is discarded, but reading it lets the mirror finish the request
+    io::copy(&mut stream, &mut io::sink()).await?;
+    Ok(())
+}
+
+async fn close_connection_with_reason(stream: &mut ClientStream, status_code: StatusCode) {
     let mut response = Response::new(status_code);
-    response.header("Connection", "close");
+    response.header(
+        HeaderName::from_static("connection"),
+        HeaderValue::from_static("close"),
+    );
     send_response(stream, response).await;
 }
 
-async fn send_response(stream: &mut TcpStream, response: Response) {
+/// Fail a request before any of the response was sent, in the way the clients of its
+/// route understand
+async fn reject(stream: &mut ClientStream, status_code: StatusCode, entry: &ProxyEntry) {
+    if !entry.grpc {
+        return close_connection_with_reason(stream, status_code).await;
+    }
+    let mut response = grpc::error_response(status_code);
+    response.header(
+        HeaderName::from_static("connection"),
+        HeaderValue::from_static("close"),
+    );
+    send_response(stream, response).await;
+}
+
+async fn send_response(stream: &mut ClientStream, response: Response) {
     if let Err(e) = stream.write_all(&response.into_bytes()).await {
         error!("Failed to send response: {e}");
     };
 }
 
+/// Read until the end of a message head. The first `filled` bytes of `buf` are left over
+/// from a previous read and are treated as already read.
 async fn read_message_into_buffer(
-    stream: &mut TcpStream,
+    stream: &mut (impl AsyncRead + Unpin),
     buf: &mut [u8; MAX_BUF_SIZE],
+    filled: usize,
 ) -> io::Result<usize> {
-    let mut total_bytes_read: usize = 0;
-    let mut recent_bytes_read = 0;
-
-    // We only scan the most recent bytes.
-    // There could be a case where the terminator is split into 2 reads,
-    // in that case we want to reread the last 4 bytes instead of just the most recently
-    // appended bytes.
-    // TLDR; we always read at least 4 bytes to ensure that we are able to find the terminator
-    while !is_terminated(
-        &buf[(total_bytes_read - recent_bytes_read).min(total_bytes_read.saturating_sub(4))
-            ..total_bytes_read],
-    ) {
+    let mut total_bytes_read: usize = filled;
+    let mut scanned = 0;
+
+    // We only scan the most recently read bytes (plus enough of the old ones to catch
+    // a terminator split over 2 reads) instead of the whole buffer each time.
+    while !is_terminated_from(&buf[..total_bytes_read], scanned) {
+        scanned = total_bytes_read;
+
         if total_bytes_read >= buf.len() {
             // request header is too big
             return Err(io::Error::new(
@@ -231,7 +1227,6 @@ async fn read_message_into_buffer(
             }
             Ok(n) => {
                 total_bytes_read += n;
-                recent_bytes_read = n;
             }
             Err(e) => return Err(e),
         }
@@ -240,12 +1235,34 @@ async fn read_message_into_buffer(
     Ok(total_bytes_read)
 }
 
-async fn read_response<'buf>(
-    stream: &mut TcpStream,
-    buf: &'buf mut [u8; MAX_BUF_SIZE],
-) -> io::Result<(Response, &'buf [u8])> {
-    let total_bytes_read = read_message_into_buffer(stream, buf).await?;
-    Response::parse(&buf[..total_bytes_read]).map_err(|e| {
+/// Read from the stream, giving up once nothing arrived for `idle_timeout`
+async fn read_within(
+    stream: &mut (dyn AsyncRead + Unpin + Send),
+    buf: &mut [u8],
+    idle_timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let Some(idle_timeout) = idle_timeout else {
+        return stream.read(buf).await;
+    };
+    timeout(idle_timeout, stream.read(buf))
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Nothing sent for too long",
+            ))
+        })
+}
+
+fn invalid_body(e: HTTPParseError) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Couldn't proxy body: {e}"),
+    )
+}
+
+fn parse_response(bytes: &[u8]) -> io::Result<(Response, &[u8])> {
+    Response::parse(bytes).map_err(|e| {
         io::Error::new(
             io::ErrorKind::InvalidData,
             format!("Couldn't parse response: {e}"),
@@ -253,11 +1270,65 @@ async fn read_response<'buf>(
     })
 }
 
-async fn read_request<'buf>(
+/// Read the start of a connection until it is clear whether the client speaks HTTP/2,
+/// which it opens with the connection preface. Returns that along with the bytes read.
+async fn read_preface(
     stream: &mut TcpStream,
+    buf: &mut [u8; MAX_BUF_SIZE],
+) -> io::Result<(bool, usize)> {
+    let mut filled = 0;
+    loop {
+        let len = filled.min(CONNECTION_PREFACE.len());
+        if !CONNECTION_PREFACE.starts_with(&buf[..len]) {
+            return Ok((false, filled));
+        }
+        if len == CONNECTION_PREFACE.len() {
+            return Ok((true, filled));
+        }
+        match stream.read(&mut buf[filled..]).await? {
+            0 => return Ok((false, filled)),
+            read => filled += read,
+        }
+    }
+}
+
+/// Read the PROXY protocol header the connection starts with, and nothing after it,
+/// which may be the start of a TLS handshake. Returns the client the header tells, if
+/// any.
+async fn read_proxy_header(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut buf = [0; MAX_BUF_SIZE];
+    let mut taken = 0;
+    loop {
+        // what was peeked at is only taken once known to be part of the header
+        let peeked = taken + stream.peek(&mut buf[taken..]).await?;
+        match proxy_protocol::parse(&buf[..peeked]) {
+            Header::Parsed { source, len } => {
+                stream.read_exact(&mut buf[taken..len]).await?;
+                return Ok(source);
+            }
+            Header::Incomplete if peeked > taken && peeked < MAX_BUF_SIZE => {
+                stream.read_exact(&mut buf[taken..peeked]).await?;
+                taken = peeked;
+            }
+            _ => {
+                // taken anyway, so closing doesn't reset the connection over unread bytes
+                stream.read_exact(&mut buf[taken..peeked]).await?;
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "no PROXY protocol header",
+                ));
+            }
+        }
+    }
+}
+
+/// Read a request, the first `filled` bytes of `buf` being its start
+async fn read_request<'buf>(
+    stream: &mut ClientStream,
     buf: &'buf mut [u8; MAX_BUF_SIZE],
+    filled: usize,
 ) -> io::Result<(Request, &'buf [u8])> {
-    let total_bytes_read = read_message_into_buffer(stream, buf).await?;
+    let total_bytes_read = read_message_into_buffer(stream, buf, filled).await?;
     Request::parse(&buf[..total_bytes_read]).map_err(|e| {
         io::Error::new(
             io::ErrorKind::InvalidData,
@@ -266,9 +1337,103 @@ async fn read_request<'buf>(
     })
 }
 
+/// Remove the hop-by-hop fields of a message before forwarding it.
+/// Bodies are forwarded in the transfer coding they were received with, trailers
+/// included, so the Transfer-Encoding describing it and the Trailer announcing the
+/// trailer fields are kept, as is a client's TE saying it accepts trailers.
+fn strip_hop_by_hop(headers: &mut Headers) {
+    let transfer_encoding = headers.remove("transfer-encoding");
+    let trailer = headers.remove("trailer");
+    let accepts_trailers = headers.get("te").is_some_and(|te| {
+        te.as_bytes()
+            .split(|&b| b == b',')
+            .any(|coding| coding.trim_ascii().eq_ignore_ascii_case(b"trailers"))
+    });
+    headers.strip_hop_by_hop();
+    if let Some(transfer_encoding) = transfer_encoding {
+        headers.insert(
+            HeaderName::from_static("transfer-encoding"),
+            transfer_encoding,
+        );
+    }
+    if let Some(trailer) = trailer {
+        headers.insert(HeaderName::from_static("trailer"), trailer);
+    }
+    if accepts_trailers {
+        headers.insert(
+            HeaderName::from_static("te"),
+            HeaderValue::from_static("trailers"),
+        );
+    }
+}
+
+/// Ask for, or agree to, switching the connection over to WebSocket, which the hop-by-hop
+/// Upgrade and Connection fields of each message along the way have to say
+fn upgrade_to_websocket(headers: &mut Headers) {
+    headers.insert(
+        HeaderName::from_static("upgrade"),
+        HeaderValue::from_static("websocket"),
+    );
+    headers.insert(
+        HeaderName::from_static("connection"),
+        HeaderValue::from_static("upgrade"),
+    );
+}
+
+/// Read the head of the final response from the server, forwarding any interim responses
+/// before it to the client if there is one. The first `filled` bytes of `buf` are the
+/// start of the response, already read. Also returns the bytes read after the head.
+async fn read_final_response<'buf>(
+    server: &mut Stream,
+    buf: &'buf mut [u8; MAX_BUF_SIZE],
+    mut filled: usize,
+    mut client: Option<&mut ClientStream>,
+) -> io::Result<(Response, &'buf [u8])> {
+    let (response, head_len, read) = loop {
+        let read = read_message_into_buffer(server, buf, filled).await?;
+        let (response, remaining) = parse_response(&buf[..read])?;
+        debug!("{response}");
+        let head_len = read - remaining.len();
+        if !response.is_interim() {
+            break (response, head_len, read);
+        }
+
+        // interim responses never have a body, so forward the head and keep reading for
+        // the final response, which may have already started arriving
+        if let Some(client) = client.as_deref_mut() {
+            let mut response = response;
+            strip_hop_by_hop(response.get_headers_mut());
+            client.write_all(&response.into_bytes()).await?;
+        }
+        buf.copy_within(head_len..read, 0);
+        filled = read - head_len;
+    };
+    Ok((response, &buf[head_len..read]))
+}
+
+/// Prepare the head of a request for the upstream, with the hop-by-hop fields removed,
+/// save for a WebSocket upgrade, and the changes of the route applied
+fn prepare_request(request: &mut Request, headers: &HeaderTransform, variables: &Variables) {
+    let upgrade = request.upgrades_to_websocket();
+    strip_hop_by_hop(&mut request.headers);
+    if upgrade {
+        upgrade_to_websocket(&mut request.headers);
+    }
+    headers.apply(&mut request.headers, variables);
+}
+
+/// How much of a request was sent upstream
+pub enum Sent {
+    /// All of it, the first `filled` bytes of the response having been read already
+    Request { filled: usize },
+    /// Only the head, the upstream answering with the final response read into the
+    /// first `filled` bytes before asking for the body
+    Head { filled: usize },
+}
+
 pub struct ProxyConnection<'conn> {
-    client: &'conn mut TcpStream,
-    server: &'conn mut TcpStream,
+    client: &'conn mut ClientStream,
+    server: &'conn mut Stream,
 }
 
 enum DataDirection {
@@ -277,145 +1442,252 @@ enum DataDirection {
 }
 
 impl<'conn> ProxyConnection<'conn> {
-    pub fn new(client: &'conn mut TcpStream, server: &'conn mut TcpStream) -> Self {
+    pub fn new(client: &'conn mut ClientStream, server: &'conn mut Stream) -> Self {
         Self { client, server }
     }
 
     async fn proxy_body(
         &mut self,
-        headers: &Headers,
+        body: Body,
         direction: DataDirection,
         remaining_bytes: &[u8],
+        idle_timeout: Option<Duration>,
     ) -> io::Result<()> {
-        let (sender, receiver) = match direction {
-            DataDirection::ClientToServer => (&mut self.client, &mut self.server),
-            DataDirection::ServerToClient => (&mut self.server, &mut self.client),
+        let (sender, receiver): (
+            &mut (dyn AsyncRead + Unpin + Send),
+            &mut (dyn AsyncWrite + Unpin + Send),
+        ) = match direction {
+            DataDirection::ClientToServer => (self.client, self.server),
+            DataDirection::ServerToClient => (self.server, self.client),
         };
 
-        let content_length = headers.get("content-length");
-        let transfer_encoding = headers.get("transfer-encoding");
-        if content_length.is_some() && transfer_encoding.is_some() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "HTTP Message cannot have both Content-Length and Transfer-Encoding headers",
-            ));
-        }
-
         let mut buf = [0; 4096];
-        if let Some(transfer_encoding) = transfer_encoding
-            && transfer_encoding
-                .to_lowercase()
-                .split(",")
-                .any(|value| value.trim() == "chunked")
-            && !is_terminated(remaining_bytes)
-        {
-            let mut bytes_read = 0;
-
-            // we will keep the last 3 bytes of the *last* buffer in the beginning 3 bytes of the
-            // *current* buffer. The reason for this is to handle the case where the message terminator
-            // was split over two messages. For example imagine:
-            // first request: [H, E, L, L, O, \r, \n, \r]
-            // second request: [\r, \n, \r, \n <- new]
-            //
-            // Since our terminator is 4 bytes, we only need to keep the last 3 bytes to determine
-            // if the terminator carried over from the last buffer. Since we keep the last 3 bytes
-            // of the last buffer in the first 3 of the current buffer, the order of the
-            // terminator bytes will also be in the correct order, we will just need to be careful
-            // not to resend those bytes.
-            while !is_terminated(&buf[..bytes_read + 3]) {
-                match sender.read(&mut buf[3..]).await {
-                    Ok(0) => {
-                        return Err(io::Error::new(
+        match body {
+            Body::Empty => {}
+            Body::Chunked => {
+                let mut decoder = ChunkedDecoder::default();
+                decoder.decode(remaining_bytes).map_err(invalid_body)?;
+
+                while !decoder.is_done() {
+                    let bytes_read = match read_within(sender, &mut buf, idle_timeout).await {
+                        Ok(0) => Err(io::Error::new(
                             io::ErrorKind::UnexpectedEof,
                             "Message body not terminated",
-                        ));
-                    }
-                    Ok(n) => {
-                        bytes_read = n;
-                    }
-                    Err(e) => return Err(e),
-                }
-
-                receiver.write_all(&buf[3..bytes_read + 3]).await?;
+                        )),
+                        Ok(n) => Ok(n),
+                        Err(e) => Err(e),
+                    }?;
 
-                // move the last 3 bytes to the front
-                buf[0] = buf[bytes_read];
-                buf[1] = buf[bytes_read + 1];
-                buf[2] = buf[bytes_read + 2];
+                    // anything after the end of the body is left out
+                    let body_len = decoder.decode(&buf[..bytes_read]).map_err(invalid_body)?;
+                    receiver.write_all(&buf[..body_len]).await?;
+                }
+                if !decoder.trailers().is_empty() {
+                    debug!("Forwarded trailers {:?}", decoder.trailers());
+                }
             }
-        }
+            Body::Sized(length) => {
+                let mut bytes_written = remaining_bytes.len() as u64;
 
-        if let Some(length) = content_length {
-            let mut bytes_written = remaining_bytes.len();
-
-            let length = length.parse().map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("Content-Length is not valid number: {e}"),
-                )
-            })?;
-
-            while bytes_written < length {
-                let bytes_read = match sender.read(&mut buf).await {
-                    Ok(0) => Err(io::Error::new(
-                        io::ErrorKind::UnexpectedEof,
-                        "Stream closed with bytes remaining",
-                    )),
-                    Ok(n) => Ok(n),
-                    Err(e) => Err(e),
-                }?;
+                while bytes_written < length {
+                    // reading past the body would take bytes of the next message
+                    let max = buf.len().min((length - bytes_written) as usize);
+                    let bytes_read = match read_within(sender, &mut buf[..max], idle_timeout).await
+                    {
+                        Ok(0) => Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "Stream closed with bytes remaining",
+                        )),
+                        Ok(n) => Ok(n),
+                        Err(e) => Err(e),
+                    }?;
 
-                receiver.write_all(&buf[..bytes_read]).await?;
-                bytes_written += bytes_read;
+                    receiver.write_all(&buf[..bytes_read]).await?;
+                    bytes_written += bytes_read as u64;
+                }
             }
+            Body::UntilClose => loop {
+                let bytes_read = read_within(sender, &mut buf, idle_timeout).await?;
+                if bytes_read == 0 {
+                    break;
+                }
+                receiver.write_all(&buf[..bytes_read]).await?;
+            },
         }
 
         Ok(())
     }
 
+    /// Send the request to the server, followed by its body from the client. Given a
+    /// buffer, the body is held back until the server asks for it, the server's answer
+    /// being read into the buffer.
     pub async fn proxy_request(
         &mut self,
         mut request: Request,
         remaining_bytes: &[u8],
-    ) -> io::Result<()> {
+        headers: &HeaderTransform,
+        variables: &Variables,
+        expectation: Option<&mut [u8; MAX_BUF_SIZE]>,
+    ) -> io::Result<Sent> {
         // For now, assume that the full request fits into our buffer.
         // We will need to amend this assumption later, once we get the proxy working.
 
-        if let Ok(client_addr) = self.client.peer_addr() {
-            request
-                .headers
-                .insert("X-Forwarded-For".to_lowercase(), client_addr.to_string());
-        }
+        prepare_request(&mut request, headers, variables);
 
         let mut request_bytes = request.into_bytes();
         request_bytes.extend(remaining_bytes);
         self.server.write_all(&request_bytes).await?;
 
+        let filled = match expectation {
+            Some(buf) => match self.await_continue(buf).await? {
+                (true, filled) => filled,
+                (false, filled) => return Ok(Sent::Head { filled }),
+            },
+            None => 0,
+        };
         self.proxy_body(
-            &request.headers,
+            request.body,
             DataDirection::ClientToServer,
             remaining_bytes,
+            None,
         )
         .await?;
 
-        Ok(())
+        Ok(Sent::Request { filled })
     }
 
-    pub async fn proxy_response(&mut self, buf: &mut [u8; MAX_BUF_SIZE]) -> io::Result<()> {
-        let (response, remaining) = read_response(self.server, buf).await?;
-        debug!("{response}");
+    /// Read the head of the final response from the server, forwarding any interim
+    /// responses before it to clients of the `version` that understand them. Also returns
+    /// the bytes read after the head.
+    pub async fn read_response<'buf>(
+        &mut self,
+        buf: &'buf mut [u8; MAX_BUF_SIZE],
+        filled: usize,
+        version: HTTPVersion,
+    ) -> io::Result<(Response, &'buf [u8])> {
+        // HTTP/1.0 clients don't expect interim responses (RFC 9110 section 15.2)
+        let client = (version != HTTPVersion::HTTP1_0).then_some(&mut *self.client);
+        read_final_response(self.server, buf, filled, client).await
+    }
+
+    /// Tell the client to go ahead and send the body it holds back
+    pub async fn send_continue(&mut self) -> io::Result<()> {
+        self.client
+            .write_all(&Response::continue_().into_bytes())
+            .await
+    }
+
+    /// Wait for the upstream to answer a request expecting `100 Continue`, relaying
+    /// interim responses to the client. Returns whether the body is to be sent, and how
+    /// many bytes of the response were read into `buf` by then.
+    async fn await_continue(&mut self, buf: &mut [u8; MAX_BUF_SIZE]) -> io::Result<(bool, usize)> {
+        let mut filled = 0;
+        loop {
+            // clients stop waiting after a while and send the body anyway, which is also
+            // how upstreams not answering the expectation get it. Reads are cancel safe,
+            // so a response cut short stays in `buf`.
+            let read = tokio::select! {
+                read = self.server.read(&mut buf[filled..]) => read?,
+                readable = self.client.readable() => {
+                    readable?;
+                    return Ok((true, filled));
+                }
+            };
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Couldn't parse message",
+                ));
+            }
+            filled += read;
+            if !is_terminated_from(&buf[..filled], filled - read) {
+                if filled == buf.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::OutOfMemory,
+                        "Response Header too large",
+                    ));
+                }
+                continue;
+            }
+
+            let (response, remaining) = parse_response(&buf[..filled])?;
+            if !response.is_interim() {
+                return Ok((false, filled));
+            }
+            let head_len = filled - remaining.len();
+            let status = response.status();
+            let mut response = response;
+            strip_hop_by_hop(response.get_headers_mut());
+            self.client.write_all(&response.into_bytes()).await?;
+            buf.copy_within(head_len..filled, 0);
+            filled -= head_len;
+            if status == StatusCode::CONTINUE {
+                return Ok((true, filled));
+            }
+        }
+    }
+
+    /// Send the final response to the client, followed by its body from the server.
+    /// Given an idle timeout, the body is streamed, each piece being sent on right away
+    /// and the server only having that long to send the next.
+    pub async fn relay_response(
+        &mut self,
+        response: Response,
+        remaining: &[u8],
+        headers: &HeaderTransform,
+        variables: &Variables,
+        connection: Option<HeaderValue>,
+        idle_timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        if idle_timeout.is_some() {
+            // small pieces like events aren't held back to be sent along with the next
+            self.client.set_nodelay(true)?;
+        }
+        let body = response.body();
+        let remaining = body_start(body, remaining);
+        let mut response = response;
+        strip_hop_by_hop(response.get_headers_mut());
+        if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+            upgrade_to_websocket(response.get_headers_mut());
+        }
+        headers.apply(response.get_headers_mut(), variables);
+        if let Some(connection) = connection {
+            response.header(HeaderName::from_static("connection"), connection);
+        }
 
         let mut bytes = response.into_bytes();
         bytes.extend_from_slice(remaining);
         self.client.write_all(&bytes).await?;
 
-        self.proxy_body(
-            response.get_headers(),
-            DataDirection::ServerToClient,
-            remaining,
-        )
-        .await?;
+        self.proxy_body(body, DataDirection::ServerToClient, remaining, idle_timeout)
+            .await?;
 
         Ok(())
     }
+
+    /// Pass bytes both ways, outside of HTTP, once the connection switched protocols,
+    /// until both sides are done with it or the limits close it. The bytes either side
+    /// sent along with its head go first.
+    pub async fn tunnel(
+        &mut self,
+        from_client: &[u8],
+        from_server: &[u8],
+        limits: TunnelLimits,
+        tunnels: &Tunnels,
+    ) -> Transferred {
+        let early = async {
+            self.server.write_all(from_client).await?;
+            self.client.write_all(from_server).await
+        };
+        if let Err(e) = early.await {
+            return Transferred {
+                closed_by: Some(e),
+                ..Default::default()
+            };
+        }
+        let mut transferred = tunnel::relay(self.client, self.server, limits, tunnels).await;
+        transferred.sent += from_client.len() as u64;
+        transferred.received += from_server.len() as u64;
+        transferred
+    }
 }
@@ -0,0 +1,152 @@
+//! Connections requests come in on. Requests are always read and answered in
+//! HTTP/1.1, those of HTTP/2 clients being translated at the other end of a pipe.
+//! Connections over TLS are read through a buffer, so what their clients sent can be
+//! looked at without taking it.
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader, DuplexStream, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::server::TlsStream;
+
+use crate::forwarded::ClientCert;
+
+/// The connection of a client, or the stream of one an HTTP/2 client opened
+#[derive(Debug)]
+pub enum ClientStream {
+    Tcp(TcpStream),
+    /// A connection over TLS, with the certificate the client authenticated with
+    Tls(
+        Box<BufReader<TlsStream<TcpStream>>>,
+        Option<Arc<ClientCert>>,
+    ),
+    /// A stream of an HTTP/2 connection, translated from and to frames at the other end
+    /// of the pipe
+    Http2 {
+        pipe: BufReader<DuplexStream>,
+        local_addr: Option<SocketAddr>,
+        tls: bool,
+        cert: Option<Arc<ClientCert>>,
+    },
+}
+
+impl ClientStream {
+    /// The address of the proxy the client connected to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            ClientStream::Tcp(stream) => stream.local_addr(),
+            ClientStream::Tls(stream, _) => stream.get_ref().get_ref().0.local_addr(),
+            ClientStream::Http2 { local_addr, .. } => local_addr
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "No local address")),
+        }
+    }
+
+    /// Whether the client sent its requests over TLS
+    pub fn is_tls(&self) -> bool {
+        matches!(
+            self,
+            ClientStream::Tls(..) | ClientStream::Http2 { tls: true, .. }
+        )
+    }
+
+    /// The certificate the client authenticated with over TLS, if any
+    pub fn client_cert(&self) -> Option<Arc<ClientCert>> {
+        match self {
+            ClientStream::Tcp(_) => None,
+            ClientStream::Tls(_, cert) | ClientStream::Http2 { cert, .. } => cert.clone(),
+        }
+    }
+
+    /// Read bytes the client sent without taking them, waiting for some if there are
+    /// none yet
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Tcp(stream) => stream.peek(buf).await,
+            ClientStream::Tls(stream, _) => fill(stream.as_mut(), buf).await,
+            ClientStream::Http2 { pipe, .. } => fill(pipe, buf).await,
+        }
+    }
+
+    /// Wait until the client sent something, or closed the connection
+    pub async fn readable(&mut self) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(stream) => stream.readable().await,
+            ClientStream::Tls(stream, _) => stream.fill_buf().await.map(|_| ()),
+            ClientStream::Http2 { pipe, .. } => pipe.fill_buf().await.map(|_| ()),
+        }
+    }
+
+    /// Send small writes right away instead of holding them back to be sent with the
+    /// next. Frames of HTTP/2 streams are never held back.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(stream) => stream.set_nodelay(nodelay),
+            ClientStream::Tls(stream, _) => stream.get_ref().get_ref().0.set_nodelay(nodelay),
+            ClientStream::Http2 { .. } => Ok(()),
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            ClientStream::Tls(stream, _) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+            ClientStream::Http2 { pipe, .. } => Pin::new(pipe).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            ClientStream::Tls(stream, _) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+            ClientStream::Http2 { pipe, .. } => Pin::new(pipe).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            ClientStream::Tls(stream, _) => Pin::new(stream.as_mut()).poll_flush(cx),
+            ClientStream::Http2 { pipe, .. } => Pin::new(pipe).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            ClientStream::Tls(stream, _) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+            ClientStream::Http2 { pipe, .. } => Pin::new(pipe).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Copy what the buffer holds into `buf` without taking it, waiting for more if it is
+/// empty
+async fn fill<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    let available = reader.fill_buf().await?;
+    let len = available.len().min(buf.len());
+    buf[..len].copy_from_slice(&available[..len]);
+    Ok(len)
+}
@@ -0,0 +1,107 @@
+//! Tunnels clients open with `CONNECT` (RFC 9110 section 9.3.6), which make the proxy a
+//! forward proxy for the destinations the config allows.
+//!
+//! Once the destination accepted the connection, the client is told so and the bytes
+//! are passed through both ways, the proxy seeing nothing more of what goes on inside.
+
+use std::{net::SocketAddr, time::Duration};
+
+use agora_http_parser::{Request, Uri};
+use http::StatusCode;
+use tokio::{io::AsyncWriteExt, net::TcpStream, time::timeout};
+use tracing::{debug, error, info, warn};
+
+use super::{ClientStream, State, close_connection_with_reason};
+use crate::{
+    config::ForwardProxy,
+    tunnel::{self, Transferred, TunnelLimits, Tunnels},
+};
+
+const CONNECTION_ESTABLISHED: &[u8] = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+
+/// Open a tunnel to the destination of the request if it is allowed, and pass bytes
+/// through it until either side is done. `early` is what the client sent after the
+/// head, without waiting to be told the tunnel is open.
+pub(super) async fn tunnel(
+    client_stream: &mut ClientStream,
+    addr: SocketAddr,
+    request: &Request,
+    early: &[u8],
+    forward_proxy: &ForwardProxy,
+    state: &State,
+    tunnels: &Tunnels,
+) {
+    let destination = &request.path;
+    let Some((host, port)) = host_and_port(destination) else {
+        warn!("Refusing tunnel from {addr} to {destination}: not a host and port");
+        return close_connection_with_reason(client_stream, StatusCode::BAD_REQUEST).await;
+    };
+    if !forward_proxy.allows(&host, port) {
+        warn!("Refusing tunnel from {addr} to {destination}: not allowed");
+        return close_connection_with_reason(client_stream, StatusCode::FORBIDDEN).await;
+    }
+
+    let upstream_timeout = Duration::from_secs(state.limits.upstream_timeout);
+    let connect = TcpStream::connect((unbracketed(&host), port));
+    let mut server = match timeout(upstream_timeout, connect).await {
+        Ok(Ok(server)) => server,
+        Ok(Err(e)) => {
+            warn!("Failed to open tunnel from {addr} to {destination}: {e}");
+            return close_connection_with_reason(client_stream, StatusCode::BAD_GATEWAY).await;
+        }
+        Err(_) => {
+            warn!("Timed out opening tunnel from {addr} to {destination}");
+            return close_connection_with_reason(client_stream, StatusCode::GATEWAY_TIMEOUT).await;
+        }
+    };
+    if let Err(e) = client_stream.write_all(CONNECTION_ESTABLISHED).await {
+        error!("Failed to send response: {e}");
+        return;
+    }
+    debug!(
+        "Tunnel opened from {addr} to {destination}, {} open",
+        tunnels.active() + 1
+    );
+
+    let limits = TunnelLimits::from(&state.limits);
+    let Transferred {
+        sent,
+        received,
+        closed_by,
+    } = match server.write_all(early).await {
+        Ok(()) => {
+            let mut transferred = tunnel::relay(client_stream, &mut server, limits, tunnels).await;
+            transferred.sent += early.len() as u64;
+            transferred
+        }
+        Err(e) => Transferred {
+            closed_by: Some(e),
+            ..Default::default()
+        },
+    };
+    match closed_by {
+        None => debug!(
+            "Tunnel from {addr} to {destination} closed after {sent} bytes sent and {received} received"
+        ),
+        Some(e) => info!(
+            "Tunnel from {addr} to {destination} closed after {sent} bytes sent and {received} received: {e}"
+        ),
+    }
+}
+
+/// The host and port of an authority-form target, which has to have both
+fn host_and_port(target: &str) -> Option<(String, u16)> {
+    if target.contains('@') {
+        return None;
+    }
+    let uri = Uri::from_authority(target).ok()?;
+    let host = uri.host().filter(|host| !host.is_empty())?;
+    Some((host.to_string(), uri.port()?))
+}
+
+/// The host without the brackets of IPv6 addresses, as it is connected to
+fn unbracketed(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
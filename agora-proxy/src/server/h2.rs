@@ -0,0 +1,801 @@
+//! HTTP/2 connections from clients (RFC 9113).
+//!
+//! Each stream is handled like a request on an HTTP/1.1 connection of its own: the
+//! request is written to one end of an in-memory pipe, which [`Server::handle`] reads
+//! from the other end, and the response it writes back is turned into frames. Routing
+//! and proxying work the same whichever version the client speaks.
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use agora_http_parser::{
+    Body, HTTPMethod, HTTPVersion, HeaderName, HeaderValue, Headers, Request, Response,
+    chunked::ChunkedDecoder,
+    h2::{
+        DEFAULT_WINDOW_SIZE, ErrorCode, FRAME_HEADER_LEN, Frame, FrameError, FrameHeader, Setting,
+        SettingId, parse_preface,
+    },
+};
+use arc_swap::ArcSwap;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf},
+    sync::mpsc,
+    task::{self, AbortHandle, JoinError, JoinSet},
+    time::sleep,
+};
+use tracing::{debug, warn};
+
+use super::{
+    ClientStream, MAX_BUF_SIZE, Server, State, parse_response, read_message_into_buffer,
+    strip_hop_by_hop,
+};
+use crate::{
+    config::Http2,
+    forwarded::ClientCert,
+    http2::{
+        HeaderBlock, HeaderBlocks, MAX_HEADER_LIST_SIZE, PIPE_SIZE, Sender, Window, last_chunk,
+    },
+    tunnel::Tunnels,
+};
+
+const FRAME_SETTINGS: u8 = 0x4;
+
+/// What the streams of a connection share with it
+struct Connection {
+    addr: SocketAddr,
+    local_addr: Option<SocketAddr>,
+    tls: bool,
+    cert: Option<Arc<ClientCert>>,
+    state: Arc<ArcSwap<State>>,
+    tunnels: Arc<Tunnels>,
+    sender: Sender,
+    /// Bytes of request bodies passed on by the streams, which the client may send again
+    consumed: mpsc::UnboundedSender<(u32, u32)>,
+}
+
+/// A stream whose task is done, and what to reset it with if the response couldn't be
+/// sent
+type Finished = (u32, Result<(), ErrorCode>);
+
+/// A piece of a request body, passed from the connection to its stream
+enum Piece {
+    Data(Vec<u8>),
+    Trailers(Box<Headers>),
+}
+
+/// A stream of the connection, from when the client opens it until the response is
+/// sent and the request body received, or either side resets it
+struct Open {
+    /// Where the request body goes, until the client ends the stream
+    body: Option<mpsc::UnboundedSender<Piece>>,
+    /// The bytes of request body the client may still send
+    recv_window: i64,
+    send_window: Arc<Window>,
+    task: AbortHandle,
+}
+
+/// The reading side of a connection, which owns its streams
+struct Session {
+    conn: Arc<Connection>,
+    config: Http2,
+    /// How long the client has to open the first stream
+    request_timeout: Duration,
+    /// How long the connection stays open without streams after that
+    keepalive_timeout: Duration,
+    header_blocks: HeaderBlocks,
+    streams: HashMap<u32, Open>,
+    tasks: JoinSet<Finished>,
+    consumed: mpsc::UnboundedReceiver<(u32, u32)>,
+    /// The highest stream the client opened so far
+    last_stream_id: u32,
+    /// The size the client gives the send windows of new streams
+    initial_window_size: u32,
+    /// The bytes of DATA the client may still send on the connection
+    recv_window: i64,
+    /// Whether the client is closing the connection, opening no more streams
+    going_away: bool,
+}
+
+/// Serve an HTTP/2 connection until the client closes it or leaves it idle. `read` is
+/// what was read from it so far, starting with the connection preface unless the client
+/// upgraded with `request`, which is answered on the first stream.
+pub(super) async fn serve(
+    stream: ClientStream,
+    addr: SocketAddr,
+    state: Arc<ArcSwap<State>>,
+    tunnels: Arc<Tunnels>,
+    upgrade: Option<Request>,
+    read: Vec<u8>,
+) {
+    debug!("HTTP/2 connection from {addr}");
+    let current = state.load_full();
+    let local_addr = stream.local_addr().ok();
+    let tls = stream.is_tls();
+    let cert = stream.client_cert();
+    let (mut reader, mut writer) = io::split(stream);
+    if upgrade.is_some() {
+        let switching =
+            b"HTTP/1.1 101 Switching Protocols\r\nconnection: upgrade\r\nupgrade: h2c\r\n\r\n";
+        if let Err(e) = writer.write_all(switching).await {
+            warn!("Failed to upgrade the connection of {addr} to HTTP/2: {e}");
+            return;
+        }
+    }
+
+    let (sender, writing) = Sender::spawn(writer);
+    let (consumed, consumed_rx) = mpsc::unbounded_channel();
+    let conn = Arc::new(Connection {
+        addr,
+        local_addr,
+        tls,
+        cert,
+        state,
+        tunnels,
+        sender,
+        consumed,
+    });
+    let mut session = Session {
+        conn,
+        config: current.http2.clone(),
+        request_timeout: Duration::from_secs(current.limits.request_timeout),
+        keepalive_timeout: Duration::from_secs(current.limits.keepalive_timeout),
+        header_blocks: HeaderBlocks::default(),
+        streams: HashMap::new(),
+        tasks: JoinSet::new(),
+        consumed: consumed_rx,
+        last_stream_id: 0,
+        initial_window_size: DEFAULT_WINDOW_SIZE,
+        recv_window: DEFAULT_WINDOW_SIZE.into(),
+        going_away: false,
+    };
+    drop(current);
+
+    if let Err(code) = session.run(&mut reader, upgrade, read).await {
+        debug!("Closing HTTP/2 connection from {addr}: {code:?}");
+        session.go_away(code).await;
+    }
+    // the streams let go of the connection, and with it of the writer, once done
+    session.tasks.shutdown().await;
+    drop(session);
+    let _ = writing.await;
+}
+
+impl Session {
+    async fn run(
+        &mut self,
+        reader: &mut ReadHalf<ClientStream>,
+        upgrade: Option<Request>,
+        mut buf: Vec<u8>,
+    ) -> Result<(), ErrorCode> {
+        let settings = vec![
+            Setting {
+                id: SettingId::MaxConcurrentStreams,
+                value: self.config.max_concurrent_streams,
+            },
+            Setting {
+                id: SettingId::InitialWindowSize,
+                value: self.config.initial_window_size,
+            },
+            Setting {
+                id: SettingId::MaxFrameSize,
+                value: self.config.max_frame_size,
+            },
+            Setting {
+                id: SettingId::MaxHeaderListSize,
+                value: MAX_HEADER_LIST_SIZE,
+            },
+        ];
+        self.send(Frame::Settings {
+            ack: false,
+            settings,
+        })
+        .await;
+        // the connection window can only be widened by WINDOW_UPDATE
+        let increment = self.config.initial_window_size - DEFAULT_WINDOW_SIZE;
+        if increment > 0 {
+            self.recv_window += i64::from(increment);
+            self.send(Frame::WindowUpdate {
+                stream_id: 0,
+                increment,
+            })
+            .await;
+        }
+
+        if let Some(request) = upgrade {
+            let settings = upgrade_settings(&request).ok_or(ErrorCode::ProtocolError)?;
+            self.apply_settings(&settings).await?;
+            self.last_stream_id = 1;
+            let mut request = request;
+            strip_hop_by_hop(&mut request.headers);
+            self.open(1, request, true);
+        }
+
+        loop {
+            match parse_preface(&buf) {
+                Ok(rest) => {
+                    let preface = buf.len() - rest.len();
+                    buf.drain(..preface);
+                    break;
+                }
+                Err(FrameError::Incomplete) => {}
+                Err(_) => return Err(ErrorCode::ProtocolError),
+            }
+            if read(reader, &mut buf).await == 0 {
+                return Ok(());
+            }
+        }
+
+        loop {
+            let mut parsed = 0;
+            loop {
+                match Frame::parse(&buf[parsed..], self.config.max_frame_size) {
+                    Ok((frame, rest)) => {
+                        let len = buf.len() - parsed - rest.len();
+                        self.frame(frame).await?;
+                        parsed += len;
+                    }
+                    Err(FrameError::Incomplete) => break,
+                    Err(e) => return Err(e.error_code()),
+                }
+            }
+            buf.drain(..parsed);
+            if self.going_away && self.streams.is_empty() {
+                return Ok(());
+            }
+
+            let idle = self.streams.is_empty();
+            let idle_timeout = match self.last_stream_id {
+                0 => self.request_timeout,
+                _ => self.keepalive_timeout,
+            };
+            tokio::select! {
+                read = read(reader, &mut buf) => {
+                    if read == 0 {
+                        return Ok(());
+                    }
+                }
+                Some(joined) = self.tasks.join_next_with_id() => self.finished(joined).await,
+                Some((stream_id, len)) = self.consumed.recv() => {
+                    self.passed_on(stream_id, len).await;
+                }
+                _ = sleep(idle_timeout), if idle => {
+                    self.go_away(ErrorCode::NoError).await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn frame(&mut self, frame: Frame<'_>) -> Result<(), ErrorCode> {
+        self.header_blocks.expect(&frame)?;
+        if self.conn.sender.answer(&frame).await? {
+            return Ok(());
+        }
+        match frame {
+            Frame::Settings {
+                ack: false,
+                settings,
+            } => {
+                self.apply_settings(&settings).await?;
+                self.send(Frame::Settings {
+                    ack: true,
+                    settings: Vec::new(),
+                })
+                .await;
+            }
+            Frame::GoAway { .. } => self.going_away = true,
+            Frame::WindowUpdate {
+                stream_id,
+                increment,
+            } => {
+                if let Some(open) = self.streams.get(&stream_id)
+                    && !open.send_window.widen(increment.into())
+                {
+                    self.close(stream_id, ErrorCode::FlowControlError).await;
+                }
+            }
+            Frame::RstStream { stream_id, .. } => {
+                if let Some(open) = self.streams.remove(&stream_id) {
+                    open.task.abort();
+                }
+            }
+            frame @ (Frame::Headers { .. } | Frame::Continuation { .. }) => {
+                if let Some(block) = self.header_blocks.take(frame)? {
+                    self.headers(block).await?;
+                }
+            }
+            Frame::Data {
+                stream_id,
+                end_stream,
+                data,
+            } => self.data(stream_id, end_stream, data).await?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn apply_settings(&mut self, settings: &[Setting]) -> Result<(), ErrorCode> {
+        self.conn.sender.follow(settings).await;
+        for setting in settings {
+            if setting.id == SettingId::InitialWindowSize {
+                let change = i64::from(setting.value) - i64::from(self.initial_window_size);
+                self.initial_window_size = setting.value;
+                for open in self.streams.values() {
+                    if !open.send_window.widen(change) {
+                        return Err(ErrorCode::FlowControlError);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Take in a complete header block, which opens a stream or ends it with trailers
+    async fn headers(&mut self, block: HeaderBlock) -> Result<(), ErrorCode> {
+        let HeaderBlock {
+            stream_id,
+            end_stream,
+            headers,
+        } = block;
+        if stream_id <= self.last_stream_id {
+            let Some(body) = self
+                .streams
+                .get_mut(&stream_id)
+                .and_then(|open| open.body.take())
+            else {
+                // the stream was closed already, and what's still on its way ignored
+                return Ok(());
+            };
+            if !end_stream {
+                return Err(ErrorCode::ProtocolError);
+            }
+            let _ = body.send(Piece::Trailers(Box::new(headers)));
+            return Ok(());
+        }
+
+        if stream_id.is_multiple_of(2) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        self.last_stream_id = stream_id;
+        if self.going_away {
+            return Ok(());
+        }
+        if self.streams.len() >= self.config.max_concurrent_streams as usize {
+            self.reset(stream_id, ErrorCode::RefusedStream).await;
+            return Ok(());
+        }
+        match to_request(headers, end_stream) {
+            Some(request) => self.open(stream_id, request, end_stream),
+            None => self.reset(stream_id, ErrorCode::ProtocolError).await,
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, stream_id: u32, request: Request, end_stream: bool) {
+        let (body, pieces) = match end_stream {
+            true => (None, None),
+            false => {
+                let (body, pieces) = mpsc::unbounded_channel();
+                (Some(body), Some(pieces))
+            }
+        };
+        let send_window = Arc::new(Window::new(self.initial_window_size));
+        let task = self.tasks.spawn(handle_stream(
+            stream_id,
+            request,
+            pieces,
+            send_window.clone(),
+            self.conn.clone(),
+        ));
+        self.streams.insert(
+            stream_id,
+            Open {
+                body,
+                recv_window: self.config.initial_window_size.into(),
+                send_window,
+                task,
+            },
+        );
+    }
+
+    async fn data(
+        &mut self,
+        stream_id: u32,
+        end_stream: bool,
+        data: &[u8],
+    ) -> Result<(), ErrorCode> {
+        let len = data.len() as i64;
+        self.recv_window -= len;
+        if self.recv_window < 0 {
+            return Err(ErrorCode::FlowControlError);
+        }
+        let open = self.streams.get_mut(&stream_id);
+        let Some((open, body)) = open.and_then(|open| open.body.clone().map(|body| (open, body)))
+        else {
+            if stream_id == 0 || stream_id > self.last_stream_id {
+                return Err(ErrorCode::ProtocolError);
+            }
+            // the stream is closed, but the bytes still count against the connection
+            self.credit(0, len as u32).await;
+            return Ok(());
+        };
+        open.recv_window -= len;
+        if open.recv_window < 0 {
+            self.close(stream_id, ErrorCode::FlowControlError).await;
+            self.credit(0, len as u32).await;
+            return Ok(());
+        }
+        if end_stream {
+            open.body = None;
+        }
+        if !data.is_empty() && body.send(Piece::Data(data.to_vec())).is_err() {
+            self.credit(0, len as u32).await;
+        }
+        Ok(())
+    }
+
+    /// Let the client send again the bytes a stream passed on
+    async fn passed_on(&mut self, stream_id: u32, len: u32) {
+        self.credit(0, len).await;
+        if let Some(open) = self.streams.get_mut(&stream_id)
+            && open.body.is_some()
+        {
+            open.recv_window += i64::from(len);
+            self.credit(stream_id, len).await;
+        }
+    }
+
+    async fn credit(&mut self, stream_id: u32, increment: u32) {
+        if stream_id == 0 {
+            self.recv_window += i64::from(increment);
+        }
+        self.send(Frame::WindowUpdate {
+            stream_id,
+            increment,
+        })
+        .await;
+    }
+
+    /// Forget about a stream once its task is done, resetting it unless both sides ended it
+    async fn finished(&mut self, joined: Result<(task::Id, Finished), JoinError>) {
+        let (stream_id, result) = match joined {
+            Ok((_, (stream_id, result))) => (stream_id, result),
+            // aborted when the client reset the stream, which is then gone already
+            Err(e) => {
+                let Some(stream_id) = self
+                    .streams
+                    .iter()
+                    .find(|(_, open)| open.task.id() == e.id())
+                    .map(|(&stream_id, _)| stream_id)
+                else {
+                    return;
+                };
+                (stream_id, Err(ErrorCode::InternalError))
+            }
+        };
+        let Some(open) = self.streams.remove(&stream_id) else {
+            return;
+        };
+        match result {
+            Err(code) => self.reset(stream_id, code).await,
+            // the response is complete, so the rest of the request body isn't needed
+            Ok(()) if open.body.is_some() => self.reset(stream_id, ErrorCode::NoError).await,
+            Ok(()) => {}
+        }
+    }
+
+    /// Reset a stream that is open, stopping its task
+    async fn close(&mut self, stream_id: u32, code: ErrorCode) {
+        if let Some(open) = self.streams.remove(&stream_id) {
+            open.task.abort();
+            self.reset(stream_id, code).await;
+        }
+    }
+
+    async fn reset(&self, stream_id: u32, error_code: ErrorCode) {
+        self.send(Frame::RstStream {
+            stream_id,
+            error_code,
+        })
+        .await;
+    }
+
+    async fn go_away(&self, error_code: ErrorCode) {
+        self.send(Frame::GoAway {
+            last_stream_id: self.last_stream_id,
+            error_code,
+            debug_data: &[],
+        })
+        .await;
+    }
+
+    /// Queue a frame for the client. Frames are dropped once the connection is broken,
+    /// which the reading side finds out about as well.
+    async fn send(&self, frame: Frame<'_>) {
+        let _ = self.conn.sender.send(frame).await;
+    }
+}
+
+async fn read(reader: &mut ReadHalf<ClientStream>, buf: &mut Vec<u8>) -> usize {
+    buf.reserve(MAX_BUF_SIZE);
+    reader.read_buf(buf).await.unwrap_or(0)
+}
+
+/// The settings a client upgrading to HTTP/2 sent along in HTTP2-Settings, as the
+/// base64url payload of a SETTINGS frame
+fn upgrade_settings(request: &Request) -> Option<Vec<Setting>> {
+    let payload = URL_SAFE_NO_PAD
+        .decode(request.headers.get("http2-settings")?.as_bytes())
+        .ok()?;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    FrameHeader {
+        length: payload.len() as u32,
+        frame_type: FRAME_SETTINGS,
+        flags: 0,
+        stream_id: 0,
+    }
+    .encode(&mut frame);
+    frame.extend_from_slice(&payload);
+    match Frame::parse(&frame, u32::MAX).ok()? {
+        (Frame::Settings { settings, .. }, _) => Some(settings),
+        _ => None,
+    }
+}
+
+/// Translate the header fields opening a stream into an HTTP/1.1 request head, or
+/// `None` if they are malformed. A body of unknown length is sent chunked.
+fn to_request(mut headers: Headers, end_stream: bool) -> Option<Request> {
+    let method = headers.remove(":method")?;
+    let path = headers.remove(":path")?;
+    headers.remove(":scheme")?;
+    let authority = headers.remove(":authority");
+    if headers.iter().any(|(name, _)| name.is_pseudo()) {
+        return None;
+    }
+    let method = HTTPMethod::try_from(method.as_bytes()).ok()?;
+    if method == HTTPMethod::CONNECT {
+        return None;
+    }
+
+    // connection-specific fields have no place in HTTP/2 (RFC 9113 section 8.2.2)
+    headers.remove("transfer-encoding");
+    strip_hop_by_hop(&mut headers);
+    if let Some(authority) = authority
+        && !headers.contains_key("host")
+    {
+        headers.insert(HeaderName::from_static("host"), authority);
+    }
+    if !end_stream && !headers.contains_key("content-length") {
+        headers.insert(
+            HeaderName::from_static("transfer-encoding"),
+            HeaderValue::from_static("chunked"),
+        );
+    }
+    let mut request = Request {
+        path: path.to_str().ok()?.to_string(),
+        method,
+        headers,
+        version: HTTPVersion::HTTP1_1,
+        body: Body::Empty,
+    };
+    request.body = request.framing().ok()?;
+    Some(request)
+}
+
+/// The header fields of a response sent on a stream
+fn response_headers(response: &Response) -> Headers {
+    let mut fields = response.get_headers().clone();
+    fields.strip_hop_by_hop();
+    let mut headers = Headers::with_capacity(fields.len() + 1);
+    headers.insert(
+        HeaderName::from_static(":status"),
+        u64::from(response.status().as_u16()).into(),
+    );
+    for (name, value) in fields.iter() {
+        headers.append(name.clone(), value.clone());
+    }
+    headers
+}
+
+/// Handle the request of a stream, its body coming in `pieces`, and send the response.
+async fn handle_stream(
+    stream_id: u32,
+    request: Request,
+    pieces: Option<mpsc::UnboundedReceiver<Piece>>,
+    send_window: Arc<Window>,
+    conn: Arc<Connection>,
+) -> Finished {
+    let chunked = request.body == Body::Chunked;
+    let head = request.method == HTTPMethod::HEAD;
+    let (proxy_end, stream_end) = io::duplex(PIPE_SIZE);
+    let (from_proxy, to_proxy) = io::split(stream_end);
+
+    let mut client_stream = ClientStream::Http2 {
+        pipe: BufReader::new(proxy_end),
+        local_addr: conn.local_addr,
+        tls: conn.tls,
+        cert: conn.cert.clone(),
+    };
+    let state = conn.state.load_full();
+    let addr = conn.addr;
+    let tunnels = conn.tunnels.clone();
+    let handle = async move {
+        let mut buf = [0; MAX_BUF_SIZE];
+        Server::handle(
+            &mut client_stream,
+            addr,
+            &state,
+            &tunnels,
+            &mut buf,
+            0,
+            false,
+        )
+        .await;
+        // closing the pipe ends a response delimited by the connection closing
+        drop(client_stream);
+    };
+    let responding = async {
+        let (_, responded) = tokio::join!(
+            Box::pin(handle),
+            respond(stream_id, from_proxy, head, &send_window, &conn)
+        );
+        responded
+    };
+    let feeding = feed(
+        stream_id,
+        to_proxy,
+        request.into_bytes(),
+        pieces,
+        chunked,
+        &conn,
+    );
+
+    tokio::pin!(responding);
+    let result = tokio::select! {
+        responded = &mut responding => responded,
+        // the request is all sent, or the proxy stopped reading it
+        _ = feeding => responding.await,
+    };
+    (stream_id, result)
+}
+
+/// Write the request to the pipe, followed by its body as it comes in
+async fn feed(
+    stream_id: u32,
+    mut pipe: WriteHalf<DuplexStream>,
+    head: Vec<u8>,
+    pieces: Option<mpsc::UnboundedReceiver<Piece>>,
+    chunked: bool,
+    conn: &Connection,
+) -> io::Result<()> {
+    pipe.write_all(&head).await?;
+    let Some(mut pieces) = pieces else {
+        return pipe.shutdown().await;
+    };
+    let mut trailers = None;
+    while let Some(piece) = pieces.recv().await {
+        let data = match piece {
+            Piece::Data(data) => data,
+            Piece::Trailers(fields) => {
+                trailers = Some(*fields);
+                continue;
+            }
+        };
+        if chunked {
+            pipe.write_all(format!("{:x}\r\n", data.len()).as_bytes())
+                .await?;
+            pipe.write_all(&data).await?;
+            pipe.write_all(b"\r\n").await?;
+        } else {
+            pipe.write_all(&data).await?;
+        }
+        let _ = conn.consumed.send((stream_id, data.len() as u32));
+    }
+    if chunked {
+        pipe.write_all(&last_chunk(trailers.as_ref())).await?;
+    }
+    pipe.shutdown().await
+}
+
+/// Read the response from the pipe and send it on the stream
+async fn respond(
+    stream_id: u32,
+    mut pipe: ReadHalf<DuplexStream>,
+    head: bool,
+    window: &Window,
+    conn: &Connection,
+) -> Result<(), ErrorCode> {
+    let failed = |e: io::Error| {
+        warn!("Failed to send response on stream {stream_id}: {e}");
+        ErrorCode::InternalError
+    };
+    let mut buf = Box::new([0; MAX_BUF_SIZE]);
+    let mut filled = 0;
+    let (response, head_len, read) = loop {
+        let read = read_message_into_buffer(&mut pipe, &mut buf, filled)
+            .await
+            .map_err(failed)?;
+        let (response, remaining) = parse_response(&buf[..read]).map_err(failed)?;
+        let head_len = read - remaining.len();
+        if !response.is_interim() {
+            break (response, head_len, read);
+        }
+        conn.sender
+            .send_headers(stream_id, response_headers(&response), false)
+            .await?;
+        buf.copy_within(head_len..read, 0);
+        filled = read - head_len;
+    };
+
+    let body = match head {
+        true => Body::Empty,
+        false => response.body(),
+    };
+    let end_stream = matches!(body, Body::Empty | Body::Sized(0));
+    conn.sender
+        .send_headers(stream_id, response_headers(&response), end_stream)
+        .await?;
+    if end_stream {
+        return Ok(());
+    }
+
+    let cut_short = || failed(io::Error::from(io::ErrorKind::UnexpectedEof));
+    let mut pending = buf[head_len..read].to_vec();
+    match body {
+        Body::Sized(len) => {
+            let mut left = len;
+            loop {
+                let piece = &pending[..pending
+                    .len()
+                    .min(usize::try_from(left).unwrap_or(usize::MAX))];
+                left -= piece.len() as u64;
+                conn.sender.send_data(stream_id, window, piece).await?;
+                if left == 0 {
+                    break;
+                }
+                let read = pipe.read(&mut buf[..]).await.map_err(failed)?;
+                if read == 0 {
+                    return Err(cut_short());
+                }
+                pending.clear();
+                pending.extend_from_slice(&buf[..read]);
+            }
+        }
+        Body::Chunked => {
+            let mut decoder = ChunkedDecoder::default();
+            let mut data = Vec::new();
+            loop {
+                decoder
+                    .decode_data(&pending, &mut data)
+                    .map_err(|e| failed(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+                conn.sender.send_data(stream_id, window, &data).await?;
+                data.clear();
+                if decoder.is_done() {
+                    break;
+                }
+                let read = pipe.read(&mut buf[..]).await.map_err(failed)?;
+                if read == 0 {
+                    return Err(cut_short());
+                }
+                pending.clear();
+                pending.extend_from_slice(&buf[..read]);
+            }
+            if !decoder.trailers().is_empty() {
+                let mut trailers = decoder.trailers().clone();
+                trailers.strip_hop_by_hop();
+                return conn.sender.send_headers(stream_id, trailers, true).await;
+            }
+        }
+        Body::UntilClose => loop {
+            conn.sender.send_data(stream_id, window, &pending).await?;
+            let read = pipe.read(&mut buf[..]).await.map_err(failed)?;
+            if read == 0 {
+                break;
+            }
+            pending.clear();
+            pending.extend_from_slice(&buf[..read]);
+        },
+        Body::Empty => {}
+    }
+    conn.sender.end_stream(stream_id).await
+}
@@ -0,0 +1,110 @@
+//! TLS connections passed through to the server named in their ClientHello, so
+//! backends sharing the address of the proxy keep TLS end to end.
+//!
+//! Only the ClientHello is read, to pick the server. It is then sent on along with
+//! everything after it, the proxy seeing nothing more of the connection.
+
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{Limits, TlsPassthrough},
+    sni::{self, ClientHello},
+    tunnel::{self, Transferred, TunnelLimits, Tunnels},
+};
+
+/// Read the ClientHello of the connection and pass it through to the server it names,
+/// until either side is done
+pub(super) async fn pass_through(
+    mut client: TcpStream,
+    addr: SocketAddr,
+    servers: &TlsPassthrough,
+    limits: &Limits,
+    tunnels: &Tunnels,
+) {
+    let mut hello = Vec::new();
+    let request_timeout = Duration::from_secs(limits.request_timeout);
+    let name = match timeout(request_timeout, read_client_hello(&mut client, &mut hello)).await {
+        Ok(Some(name)) => name,
+        Ok(None) => {
+            debug!("Closing connection from {addr}: not a TLS ClientHello");
+            return;
+        }
+        Err(_) => {
+            debug!("Closing connection from {addr}: timed out waiting for a ClientHello");
+            return;
+        }
+    };
+    let shown = name.as_deref().unwrap_or("no server name");
+    let Some(destination) = servers.server(name.as_deref()) else {
+        warn!("Refusing TLS from {addr} for {shown}: no server has the name");
+        let _ = client.write_all(sni::UNRECOGNIZED_NAME).await;
+        return;
+    };
+
+    let upstream_timeout = Duration::from_secs(limits.upstream_timeout);
+    let mut server = match timeout(upstream_timeout, TcpStream::connect(destination)).await {
+        Ok(Ok(server)) => server,
+        Ok(Err(e)) => {
+            warn!("Failed to pass TLS from {addr} for {shown} to {destination}: {e}");
+            return;
+        }
+        Err(_) => {
+            warn!("Timed out passing TLS from {addr} for {shown} to {destination}");
+            return;
+        }
+    };
+    debug!(
+        "Passing TLS from {addr} for {shown} to {destination}, {} tunnels open",
+        tunnels.active() + 1
+    );
+
+    let Transferred {
+        sent,
+        received,
+        closed_by,
+    } = match server.write_all(&hello).await {
+        Ok(()) => {
+            let limits = TunnelLimits::from(limits);
+            let mut transferred = tunnel::relay(&mut client, &mut server, limits, tunnels).await;
+            transferred.sent += hello.len() as u64;
+            transferred
+        }
+        Err(e) => Transferred {
+            closed_by: Some(e),
+            ..Default::default()
+        },
+    };
+    match closed_by {
+        None => debug!(
+            "TLS from {addr} to {destination} closed after {sent} bytes sent and {received} received"
+        ),
+        Some(e) => info!(
+            "TLS from {addr} to {destination} closed after {sent} bytes sent and {received} received: {e}"
+        ),
+    }
+}
+
+/// Read from the client until its ClientHello is complete, and get the server name in
+/// it. `None` if the client doesn't send one.
+async fn read_client_hello(client: &mut TcpStream, hello: &mut Vec<u8>) -> Option<Option<String>> {
+    let mut buf = [0; 4096];
+    loop {
+        match sni::peek(hello) {
+            ClientHello::ServerName(name) => return Some(name),
+            ClientHello::Invalid => return None,
+            ClientHello::Incomplete if hello.len() >= sni::MAX_CLIENT_HELLO_SIZE => return None,
+            ClientHello::Incomplete => {}
+        }
+        match client.read(&mut buf).await {
+            Ok(0) | Err(_) => return None,
+            Ok(read) => hello.extend_from_slice(&buf[..read]),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Cleartext listeners sending clients over to HTTPS, answering each request with a
+//! redirect to the same URL on the `https` scheme.
+//!
+//! Requests for ACME HTTP-01 challenges can be routed instead, since certificate
+//! authorities fetch them over cleartext HTTP. Every connection is closed after its
+//! first request, clients having nothing more to ask of the listener.
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use agora_http_parser::Request;
+use http::StatusCode;
+use tokio::{io::AsyncWriteExt, net::TcpStream, time::timeout};
+use tracing::{debug, error, warn};
+
+use super::{
+    ClientStream, MAX_BUF_SIZE, Server, State, close_connection_with_reason,
+    read_message_into_buffer,
+};
+use crate::{config::HttpsRedirect, tunnel::Tunnels};
+
+/// Read the request of the client and redirect it to HTTPS, or route it if the
+/// redirect excludes it
+pub(super) async fn redirect(
+    stream: TcpStream,
+    addr: SocketAddr,
+    redirect: &HttpsRedirect,
+    state: &State,
+    tunnels: &Tunnels,
+) {
+    let mut client_stream = ClientStream::Tcp(stream);
+    let mut buf = [0; MAX_BUF_SIZE];
+    let request_timeout = Duration::from_secs(state.limits.request_timeout);
+    let read = timeout(
+        request_timeout,
+        read_message_into_buffer(&mut client_stream, &mut buf, 0),
+    );
+    let filled = match read.await {
+        Ok(Ok(filled)) => filled,
+        Ok(Err(e)) => {
+            match e.kind() {
+                io::ErrorKind::UnexpectedEof => {}
+                io::ErrorKind::OutOfMemory => {
+                    close_connection_with_reason(
+                        &mut client_stream,
+                        StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                    )
+                    .await
+                }
+                _ => debug!("Failed to read request from {addr}: {e}"),
+            }
+            return;
+        }
+        Err(_) => {
+            close_connection_with_reason(&mut client_stream, StatusCode::REQUEST_TIMEOUT).await;
+            return;
+        }
+    };
+    let request = match Request::parse(&buf[..filled]) {
+        Ok((request, _)) => request,
+        Err(e) => {
+            warn!("Couldn't parse request from {addr}: {e}");
+            close_connection_with_reason(&mut client_stream, StatusCode::BAD_REQUEST).await;
+            return;
+        }
+    };
+
+    if redirect.excludes(&request) {
+        debug!(
+            "Routing {} from {addr} rather than redirecting it",
+            request.path
+        );
+        Server::handle(
+            &mut client_stream,
+            addr,
+            state,
+            tunnels,
+            &mut buf,
+            filled,
+            false,
+        )
+        .await;
+        return;
+    }
+    let Some(location) = redirect.location(&request) else {
+        close_connection_with_reason(&mut client_stream, StatusCode::BAD_REQUEST).await;
+        return;
+    };
+    debug!("Redirecting {addr} to {location}");
+    if let Err(e) = client_stream.write_all(&redirect.response(&location)).await {
+        error!("Failed to send response: {e}");
+    }
+}
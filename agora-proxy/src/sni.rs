@@ -0,0 +1,189 @@
+//! The server name TLS clients ask for in their ClientHello (RFC 8446 section 4.1.2),
+//! read without terminating TLS so connections can be passed through to the server
+//! holding the keys.
+
+/// The largest ClientHello read, which is plenty even with post-quantum key shares
+pub const MAX_CLIENT_HELLO_SIZE: usize = 1 << 14;
+
+const HANDSHAKE: u8 = 22;
+const CLIENT_HELLO: u8 = 1;
+const SERVER_NAME: u16 = 0;
+const HOST_NAME: u8 = 0;
+
+/// The fatal `unrecognized_name` alert, telling a client no server has its name
+pub const UNRECOGNIZED_NAME: &[u8] = &[21, 3, 1, 0, 2, 2, 112];
+
+/// What the start of a connection tells about the server the client wants
+#[derive(Debug, PartialEq)]
+pub enum ClientHello {
+    /// More bytes are needed to tell
+    Incomplete,
+    /// The client doesn't open with a ClientHello
+    Invalid,
+    /// A ClientHello, with the server name if the client sent one
+    ServerName(Option<String>),
+}
+
+/// Read the ClientHello at the start of a connection, which may span several records
+pub fn peek(buf: &[u8]) -> ClientHello {
+    let mut handshake = Vec::new();
+    let mut records = buf;
+    loop {
+        let Some(header) = records.get(..5) else {
+            return ClientHello::Incomplete;
+        };
+        if header[0] != HANDSHAKE || header[1] != 3 {
+            return ClientHello::Invalid;
+        }
+        let len = usize::from(u16::from_be_bytes([header[3], header[4]]));
+        let Some(fragment) = records.get(5..5 + len) else {
+            return ClientHello::Incomplete;
+        };
+        handshake.extend_from_slice(fragment);
+        records = &records[5 + len..];
+
+        if handshake.len() >= 4 {
+            if handshake[0] != CLIENT_HELLO {
+                return ClientHello::Invalid;
+            }
+            let len = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]) as usize;
+            if handshake.len() >= 4 + len {
+                return match server_name(&handshake[4..4 + len]) {
+                    Some(name) => ClientHello::ServerName(name),
+                    None => ClientHello::Invalid,
+                };
+            }
+        }
+    }
+}
+
+/// The host name in the server name extension of the body of a ClientHello, or `None`
+/// if the body is malformed
+fn server_name(body: &[u8]) -> Option<Option<String>> {
+    let mut reader = Reader(body);
+    // the legacy version and the random
+    reader.take(2 + 32)?;
+    let session_id = usize::from(reader.u8()?);
+    reader.take(session_id)?;
+    let cipher_suites = usize::from(reader.u16()?);
+    reader.take(cipher_suites)?;
+    let compression_methods = usize::from(reader.u8()?);
+    reader.take(compression_methods)?;
+    if reader.0.is_empty() {
+        return Some(None);
+    }
+
+    let extensions_len = usize::from(reader.u16()?);
+    let mut extensions = Reader(reader.take(extensions_len)?);
+    while !extensions.0.is_empty() {
+        let extension = extensions.u16()?;
+        let len = usize::from(extensions.u16()?);
+        let data = extensions.take(len)?;
+        if extension != SERVER_NAME {
+            continue;
+        }
+        let mut data = Reader(data);
+        let list_len = usize::from(data.u16()?);
+        let mut names = Reader(data.take(list_len)?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let len = usize::from(names.u16()?);
+            let name = names.take(len)?;
+            if name_type == HOST_NAME {
+                return std::str::from_utf8(name)
+                    .ok()
+                    .map(|name| Some(name.to_ascii_lowercase()));
+            }
+        }
+        return Some(None);
+    }
+    Some(None)
+}
+
+/// Reads the fields of a handshake message in order
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rstest::rstest;
+    use rustls::{
+        ClientConfig, ClientConnection, RootCertStore, crypto::ring, pki_types::ServerName,
+    };
+
+    use super::*;
+
+    /// The first bytes a rustls client sends to the server
+    fn client_hello(server_name: &str) -> Vec<u8> {
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let mut connection = ClientConnection::new(Arc::new(config), server_name).unwrap();
+        let mut hello = Vec::new();
+        connection.write_tls(&mut hello).unwrap();
+        hello
+    }
+
+    #[rstest]
+    #[case("example.com", Some("example.com"))]
+    #[case("API.Example.com", Some("api.example.com"))]
+    // clients don't send addresses as server names
+    #[case("10.0.0.1", None)]
+    fn test_peek(#[case] server_name: &str, #[case] expected: Option<&str>) {
+        let hello = client_hello(server_name);
+        assert_eq!(
+            ClientHello::ServerName(expected.map(String::from)),
+            peek(&hello)
+        );
+        for len in [0, 3, 5, hello.len() - 1] {
+            assert_eq!(ClientHello::Incomplete, peek(&hello[..len]));
+        }
+    }
+
+    #[test]
+    fn test_peek_split_records() {
+        let hello = client_hello("example.com");
+        // the same handshake message in two records
+        let handshake = &hello[5..];
+        let (first, second) = handshake.split_at(10);
+        let mut split = Vec::new();
+        for fragment in [first, second] {
+            split.extend_from_slice(&[HANDSHAKE, 3, 1]);
+            split.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+            split.extend_from_slice(fragment);
+        }
+        assert_eq!(
+            ClientHello::ServerName(Some(String::from("example.com"))),
+            peek(&split)
+        );
+    }
+
+    #[rstest]
+    #[case(b"GET / HTTP/1.1\r\n\r\n")]
+    #[case(b"\x16\x03\x01\x00\x04\x02\x00\x00\x00")]
+    #[case(b"\x16\x03\x01\x00\x06\x01\x00\x00\x02\x03\x03")]
+    fn test_peek_invalid(#[case] buf: &[u8]) {
+        assert_eq!(ClientHello::Invalid, peek(buf));
+    }
+}
@@ -0,0 +1,427 @@
+//! Serving routes from files on disk instead of an upstream.
+
+use std::path::{Component, Path, PathBuf};
+
+use agora_http_parser::{
+    ContentCoding, HTTPMethod, HeaderName, HeaderValue, Headers, Request, Response,
+};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, File},
+    io::{self, AsyncWrite, AsyncWriteExt},
+};
+
+/// Files served for a route, written in config files like
+/// `{ "root": "./dist", "index": "index.html", "fallback": "index.html" }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaticFiles {
+    /// The directory files are served from
+    pub root: PathBuf,
+    /// The file served for requests to a directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    /// The file served for paths that don't exist, like the entry point of a single page
+    /// app which does its own routing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<String>,
+    /// Whether requests to a directory without an index file get a listing of its entries
+    #[serde(default)]
+    pub directory_listing: bool,
+}
+
+/// What a request path refers to
+#[derive(Debug, PartialEq)]
+pub enum Resolved {
+    File(PathBuf),
+    /// A directory to list the entries of
+    Directory(PathBuf),
+}
+
+/// The codings of pre-compressed siblings of files in order of preference, along with
+/// their extension, like `app.js.br` for `app.js`
+const PRECOMPRESSED: [(ContentCoding, &str, &str); 2] = [
+    (ContentCoding::Br, "br", "br"),
+    (ContentCoding::Gzip, "gzip", "gz"),
+];
+
+impl StaticFiles {
+    /// Respond to the request with the file its path resolves to. The headers are added
+    /// to the response, whichever it is, along with the `connection` header telling the
+    /// client whether it can send another request. Responses are all sized, so the
+    /// connection can be kept open after them.
+    pub async fn serve(
+        &self,
+        stream: &mut (impl AsyncWrite + Unpin),
+        request: &Request,
+        headers: &Headers,
+        connection: Option<HeaderValue>,
+    ) -> io::Result<()> {
+        let mut headers = headers.clone();
+        if let Some(connection) = connection {
+            headers.insert(HeaderName::from_static("connection"), connection);
+        }
+        let headers = &headers;
+        if !matches!(request.method, HTTPMethod::GET | HTTPMethod::HEAD) {
+            let mut response = Response::new(StatusCode::METHOD_NOT_ALLOWED);
+            response.header(
+                HeaderName::from_static("allow"),
+                HeaderValue::from_static("GET, HEAD"),
+            );
+            return send_empty(stream, response, headers).await;
+        }
+
+        let resolved = match self.resolve(&request.path).await {
+            Some(resolved) => Some(resolved),
+            None => match &self.fallback {
+                Some(fallback) => self
+                    .file(&self.root.join(fallback))
+                    .await
+                    .map(Resolved::File),
+                None => None,
+            },
+        };
+
+        match resolved {
+            Some(Resolved::File(file)) => send_file(stream, request, &file, headers).await,
+            Some(Resolved::Directory(directory)) => {
+                let listing = directory_listing(&directory, &request.path).await?;
+                let mut response = Response::new(StatusCode::OK);
+                response.header(
+                    HeaderName::from_static("content-type"),
+                    HeaderValue::from_static("text/html; charset=utf-8"),
+                );
+                response.header(
+                    HeaderName::from_static("content-length"),
+                    (listing.len() as u64).into(),
+                );
+                add_headers(&mut response, headers);
+                stream.write_all(&response.into_bytes()).await?;
+                if request.method == HTTPMethod::GET {
+                    stream.write_all(listing.as_bytes()).await?;
+                }
+                Ok(())
+            }
+            None => send_empty(stream, Response::new(StatusCode::NOT_FOUND), headers).await,
+        }
+    }
+
+    /// What a request target refers to, if it exists under the root
+    pub async fn resolve(&self, target: &str) -> Option<Resolved> {
+        let path = target.split(['?', '#']).next().unwrap_or_default();
+        let path = self.root.join(relative_path(&percent_decode(path)?)?);
+        if !fs::metadata(&path).await.ok()?.is_dir() {
+            return Some(Resolved::File(path));
+        }
+
+        let index = match &self.index {
+            Some(index) => self.file(&path.join(index)).await,
+            None => None,
+        };
+        match index {
+            Some(index) => Some(Resolved::File(index)),
+            None => self.directory_listing.then_some(Resolved::Directory(path)),
+        }
+    }
+
+    async fn file(&self, path: &Path) -> Option<PathBuf> {
+        fs::metadata(path)
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+            .then(|| path.to_path_buf())
+    }
+}
+
+/// Respond with the file, or a pre-compressed sibling of it if the client accepts its
+/// coding
+async fn send_file(
+    stream: &mut (impl AsyncWrite + Unpin),
+    request: &Request,
+    file: &Path,
+    headers: &Headers,
+) -> io::Result<()> {
+    let mut response = Response::new(StatusCode::OK);
+    response.header(
+        HeaderName::from_static("content-type"),
+        HeaderValue::from_static(content_type(file)),
+    );
+
+    let mut available = Vec::new();
+    for (coding, name, extension) in PRECOMPRESSED {
+        let mut sibling = file.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(extension);
+        let sibling = PathBuf::from(sibling);
+        if fs::metadata(&sibling)
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+        {
+            available.push((coding, name, sibling));
+        }
+    }
+
+    let mut path = file.to_path_buf();
+    if !available.is_empty() {
+        // the response depends on the Accept-Encoding as soon as there is a choice
+        response.header(
+            HeaderName::from_static("vary"),
+            HeaderValue::from_static("accept-encoding"),
+        );
+        let accept_encoding = request.accept_encoding().ok().flatten().unwrap_or_default();
+        let codings: Vec<_> = available
+            .iter()
+            .map(|(coding, _, _)| coding.clone())
+            .collect();
+        if let Some(coding) = accept_encoding.preferred(&codings)
+            && let Some((_, name, sibling)) = available.iter().find(|(c, _, _)| c == coding)
+        {
+            response.header(
+                HeaderName::from_static("content-encoding"),
+                HeaderValue::from_static(name),
+            );
+            path = sibling.clone();
+        }
+    }
+
+    let mut contents = File::open(&path).await?;
+    let length = contents.metadata().await?.len();
+    response.header(HeaderName::from_static("content-length"), length.into());
+    add_headers(&mut response, headers);
+    stream.write_all(&response.into_bytes()).await?;
+
+    if request.method == HTTPMethod::GET {
+        io::copy(&mut contents, stream).await?;
+    }
+    Ok(())
+}
+
+/// An HTML page linking to the entries of the directory. Links are relative to the
+/// request path, so they resolve from the directory itself.
+async fn directory_listing(directory: &Path, target: &str) -> io::Result<String> {
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(directory).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type().await?.is_dir() {
+            name.push('/');
+        }
+        entries.push(name);
+    }
+    entries.sort();
+
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    // without a trailing slash, relative links resolve from the parent directory
+    let base = match path.rsplit_once('/') {
+        Some((_, "")) | None => String::new(),
+        Some((_, last)) => html_escape(&format!("{last}/")),
+    };
+    let title = html_escape(&percent_decode(path).unwrap_or_else(|| path.to_string()));
+
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n\
+         <body>\n<h1>Index of {title}</h1>\n<ul>\n"
+    );
+    for name in entries {
+        page.push_str(&format!(
+            "<li><a href=\"{base}{}\">{}</a></li>\n",
+            html_escape(&percent_encode(&name)),
+            html_escape(&name)
+        ));
+    }
+    page.push_str("</ul>\n</body>\n</html>\n");
+    Ok(page)
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent encode everything but unreserved characters and `/` in a path
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+async fn send_empty(
+    stream: &mut (impl AsyncWrite + Unpin),
+    mut response: Response,
+    headers: &Headers,
+) -> io::Result<()> {
+    response.header(
+        HeaderName::from_static("content-length"),
+        HeaderValue::from_static("0"),
+    );
+    add_headers(&mut response, headers);
+    stream.write_all(&response.into_bytes()).await
+}
+
+fn add_headers(response: &mut Response, headers: &Headers) {
+    for (name, value) in headers.iter() {
+        response.header(name.clone(), value.clone());
+    }
+}
+
+/// The request path as a path relative to the root. Paths with `..` segments, or
+/// anything else which could point outside of the root, are rejected.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        if segment.contains(['\\', '\0']) {
+            return None;
+        }
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(segment)), None) => relative.push(segment),
+            (Some(Component::CurDir), None) => {}
+            _ => return None,
+        }
+    }
+    Some(relative)
+}
+
+/// Decode the `%XX` percent encoded bytes of a path
+fn percent_decode(path: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                decoded.push(u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => decoded.push(b),
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// The media type of a file, guessed from its extension
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "woff2" => "font/woff2",
+        "woff" => "font/woff",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("/", Some(""))]
+    #[case("/assets/app.js", Some("assets/app.js"))]
+    #[case("//assets/./app.js", Some("assets/app.js"))]
+    #[case("/assets/../../etc/passwd", None)]
+    #[case("/..", None)]
+    #[case("/a\\..\\b", None)]
+    fn test_relative_path(#[case] path: &str, #[case] expected: Option<&str>) {
+        assert_eq!(expected.map(PathBuf::from), relative_path(path));
+    }
+
+    #[rstest]
+    #[case("/a%20b", Some("/a b"))]
+    #[case("/a+b", Some("/a+b"))]
+    #[case("/%2e%2e", Some("/.."))]
+    #[case("/%zz", None)]
+    #[case("/%2", None)]
+    fn test_percent_decode(#[case] path: &str, #[case] expected: Option<&str>) {
+        assert_eq!(expected.map(String::from), percent_decode(path));
+    }
+
+    #[rstest]
+    #[case("a b/c.txt", "a%20b/c.txt")]
+    #[case("<x>&\"", "%3Cx%3E%26%22")]
+    #[case("ü", "%C3%BC")]
+    fn test_percent_encode(#[case] path: &str, #[case] expected: &str) {
+        assert_eq!(expected, percent_encode(path));
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing() {
+        let root = std::env::temp_dir().join(format!("agora-listing-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub dir")).unwrap();
+        std::fs::write(root.join("<b>.txt"), "").unwrap();
+
+        let listing = directory_listing(&root, "/files").await.unwrap();
+        assert!(listing.contains("<title>Index of /files</title>"));
+        assert!(listing.contains("<a href=\"files/%3Cb%3E.txt\">&lt;b&gt;.txt</a>"));
+        assert!(listing.contains("<a href=\"files/sub%20dir/\">sub dir/</a>"));
+
+        let listing = directory_listing(&root, "/files/").await.unwrap();
+        assert!(listing.contains("<a href=\"sub%20dir/\">sub dir/</a>"));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let root = std::env::temp_dir().join(format!("agora-static-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir_all(root.join("empty")).unwrap();
+        std::fs::write(root.join("index.html"), "index").unwrap();
+        std::fs::write(root.join("docs/index.html"), "docs").unwrap();
+        std::fs::write(root.join("app.js"), "app").unwrap();
+
+        let mut files = StaticFiles {
+            root: root.clone(),
+            index: Some(String::from("index.html")),
+            fallback: None,
+            directory_listing: false,
+        };
+        let file = |path: &str| Some(Resolved::File(root.join(path)));
+        assert_eq!(file("index.html"), files.resolve("/").await);
+        assert_eq!(file("docs/index.html"), files.resolve("/docs?page=2").await);
+        assert_eq!(file("app.js"), files.resolve("/app%2Ejs").await);
+        assert_eq!(None, files.resolve("/empty/").await);
+        assert_eq!(None, files.resolve("/missing.js").await);
+        assert_eq!(None, files.resolve("/%2e%2e/index.html").await);
+
+        files.directory_listing = true;
+        assert_eq!(
+            Some(Resolved::Directory(root.join("empty"))),
+            files.resolve("/empty/").await
+        );
+        assert_eq!(file("docs/index.html"), files.resolve("/docs/").await);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
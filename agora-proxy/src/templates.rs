@@ -0,0 +1,76 @@
+//! Starter configs for common setups, printed by the `generate-config` command.
+
+use clap::ValueEnum;
+
+use crate::config::ConfigFormat;
+
+/// The setups there are starter configs for
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Template {
+    /// A reverse proxy in front of a single backend
+    #[default]
+    Proxy,
+    /// A single page app served from its build directory, with its API behind the same
+    /// address
+    StaticApi,
+}
+
+impl Template {
+    /// The config in the format. JSON has no comments, so the other formats explain more.
+    pub fn render(self, format: ConfigFormat) -> &'static str {
+        match (self, format) {
+            (Template::Proxy, ConfigFormat::Json) => include_str!("../templates/proxy.json"),
+            (Template::Proxy, ConfigFormat::Toml) => include_str!("../templates/proxy.toml"),
+            (Template::Proxy, ConfigFormat::Yaml) => include_str!("../templates/proxy.yaml"),
+            (Template::StaticApi, ConfigFormat::Json) => {
+                include_str!("../templates/static-api.json")
+            }
+            (Template::StaticApi, ConfigFormat::Toml) => {
+                include_str!("../templates/static-api.toml")
+            }
+            (Template::StaticApi, ConfigFormat::Yaml) => {
+                include_str!("../templates/static-api.yaml")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::config::ServerConfig;
+
+    fn load(template: Template, format: ConfigFormat) -> ServerConfig {
+        let dir = std::env::temp_dir().join(format!("agora-templates-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{template:?}.{format:?}"));
+        fs::write(&path, template.render(format)).unwrap();
+
+        let config = ServerConfig::parse_as(&path, format);
+        fs::remove_file(&path).unwrap();
+        config.unwrap()
+    }
+
+    #[rstest]
+    fn test_templates(#[values(Template::Proxy, Template::StaticApi)] template: Template) {
+        let config = load(template, ConfigFormat::Json);
+        let warnings: Vec<_> = config
+            .warnings()
+            .into_iter()
+            // the directory of the frontend is up to the user
+            .filter(|warning| !warning.contains("static_files.root"))
+            .collect();
+        assert!(warnings.is_empty(), "{warnings:?}");
+
+        // the formats only differ in their comments
+        let expected = serde_json::to_value(&config).unwrap();
+        for format in [ConfigFormat::Toml, ConfigFormat::Yaml] {
+            let config = serde_json::to_value(load(template, format)).unwrap();
+            assert_eq!(expected, config, "{format:?}");
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! TLS for connections to `https://` backends, and for clients of listeners serving
+//! HTTPS.
+
+use std::{path::Path, sync::Arc};
+
+use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+    SupportedProtocolVersion,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{
+        CertificateDer, PrivateKeyDer, ServerName, UnixTime,
+        pem::{self, PemObject},
+    },
+    server::WebPkiClientVerifier,
+};
+use tracing::warn;
+
+use crate::{
+    config::{ListenerTls, TlsVersion, UpstreamTls},
+    forwarded::ClientCert,
+};
+
+/// The client config for connecting to backends with the settings. Errors start with
+/// the setting they are about.
+pub fn client_config(tls: &UpstreamTls) -> Result<Arc<ClientConfig>, String> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = if tls.verify {
+        builder.with_root_certificates(roots(tls.ca.as_deref())?)
+    } else {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+    };
+    let config = match (&tls.cert, &tls.key) {
+        (None, None) => builder.with_no_client_auth(),
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(certs(cert)?, private_key(key)?)
+            .map_err(|e| format!("key: doesn't match the certificate: {e}"))?,
+        (Some(_), None) => return Err(String::from("key: required along with `cert`")),
+        (None, Some(_)) => return Err(String::from("cert: required along with `key`")),
+    };
+    Ok(Arc::new(config))
+}
+
+/// The server config for clients of a listener with the settings. Errors start with the
+/// setting they are about.
+pub fn server_config(tls: &ListenerTls) -> Result<Arc<ServerConfig>, String> {
+    if tls.min_version > tls.max_version {
+        return Err(String::from("min_version: is newer than `max_version`"));
+    }
+    let allowed: Vec<(&str, &'static SupportedProtocolVersion)> = [
+        (TlsVersion::Tls12, "TLS 1.2", &rustls::version::TLS12),
+        (TlsVersion::Tls13, "TLS 1.3", &rustls::version::TLS13),
+    ]
+    .into_iter()
+    .filter(|(version, ..)| (tls.min_version..=tls.max_version).contains(version))
+    .map(|(_, name, supported)| (name, supported))
+    .collect();
+    let versions: Vec<_> = allowed.iter().map(|(_, supported)| *supported).collect();
+
+    let mut provider = crypto::ring::default_provider();
+    provider
+        .cipher_suites
+        .retain(|suite| versions.contains(&suite.version()));
+    if !tls.cipher_suites.is_empty() {
+        let mut suites = Vec::new();
+        for name in &tls.cipher_suites {
+            let named = |suite: &&rustls::SupportedCipherSuite| {
+                suite
+                    .suite()
+                    .as_str()
+                    .is_some_and(|suite| suite.eq_ignore_ascii_case(name))
+            };
+            let Some(suite) = provider.cipher_suites.iter().find(named) else {
+                return Err(match crypto::ring::ALL_CIPHER_SUITES.iter().find(named) {
+                    Some(_) => format!("cipher_suites: {name} isn't for an allowed version"),
+                    None => format!("cipher_suites: unknown cipher suite {name}"),
+                });
+            };
+            suites.push(*suite);
+        }
+        for (version, supported) in &allowed {
+            if !suites.iter().any(|suite| suite.version() == *supported) {
+                return Err(format!(
+                    "cipher_suites: none of them is for {version}, which is allowed"
+                ));
+            }
+        }
+        provider.cipher_suites = suites;
+    }
+    if !tls.curves.is_empty() {
+        let mut groups = Vec::new();
+        for name in &tls.curves {
+            let group = provider.kx_groups.iter().find(|group| {
+                group
+                    .name()
+                    .as_str()
+                    .is_some_and(|group| group.eq_ignore_ascii_case(name))
+            });
+            match group {
+                Some(group) => groups.push(*group),
+                None => return Err(format!("curves: unknown curve {name}")),
+            }
+        }
+        provider.kx_groups = groups;
+    }
+
+    let provider = Arc::new(provider);
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&versions)
+        .map_err(|e| e.to_string())?;
+    let builder = match &tls.client_auth {
+        Some(client_auth) => {
+            let roots = roots(Some(&client_auth.ca)).map_err(|e| format!("client_auth.{e}"))?;
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if client_auth.required {
+                verifier
+            } else {
+                verifier.allow_unauthenticated()
+            };
+            let verifier = verifier
+                .build()
+                .map_err(|e| format!("client_auth.ca: {e}"))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certs(&tls.cert)?, private_key(&tls.key)?)
+        .map_err(|e| format!("key: doesn't match the certificate: {e}"))?;
+    config.alpn_protocols = tls
+        .alpn
+        .iter()
+        .map(|protocol| protocol.id().to_vec())
+        .collect();
+    Ok(Arc::new(config))
+}
+
+/// The subject and fingerprint of the certificate a client authenticated with, `None`
+/// if it can't be read
+pub fn client_cert(cert: &CertificateDer<'_>) -> Option<ClientCert> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert).ok()?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, cert);
+    Some(ClientCert {
+        subject: parsed.subject().to_string(),
+        fingerprint: digest.as_ref().iter().map(|b| format!("{b:02x}")).collect(),
+    })
+}
+
+/// The certificate chain in the PEM file, starting with the one of the proxy
+fn certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("cert: failed to read {}: {}", path.display(), pem_error(e)))?;
+    if certs.is_empty() {
+        return Err(format!("cert: no certificates in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// The first private key in the PEM file
+fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| match e {
+        pem::Error::NoItemsFound => format!("key: no private key in {}", path.display()),
+        e => format!("key: failed to read {}: {}", path.display(), pem_error(e)),
+    })
+}
+
+/// The error, without the prefix I/O errors get
+fn pem_error(e: pem::Error) -> String {
+    match e {
+        pem::Error::Io(e) => e.to_string(),
+        e => e.to_string(),
+    }
+}
+
+/// The certificates in the PEM file, or the ones trusted by the system
+fn roots(ca: Option<&Path>) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    let Some(path) = ca else {
+        let native = rustls_native_certs::load_native_certs();
+        for e in native.errors {
+            warn!("Failed to load a certificate trusted by the system: {e}");
+        }
+        roots.add_parsable_certificates(native.certs);
+        return Ok(roots);
+    };
+
+    let read_error =
+        |e: pem::Error| format!("ca: failed to read {}: {}", path.display(), pem_error(e));
+    for cert in CertificateDer::pem_file_iter(path).map_err(read_error)? {
+        roots
+            .add(cert.map_err(read_error)?)
+            .map_err(|e| format!("ca: invalid certificate in {}: {e}", path.display()))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("ca: no certificates in {}", path.display()));
+    }
+    Ok(roots)
+}
+
+/// Accepts any certificate, only checking that the backend has its key
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
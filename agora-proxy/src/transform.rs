@@ -0,0 +1,172 @@
+//! Config-driven changes to the headers of proxied messages.
+
+use std::collections::BTreeMap;
+
+use agora_http_parser::{HeaderName, HeaderValue, Headers};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Changes to the headers of a message, applied in the order removals, then sets, then
+/// additions. Values of `set` and `add` can refer to [`Variables`] like `$remote_addr`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HeaderTransform {
+    /// Headers to remove
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+    /// Headers to set, replacing any existing value
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, String>,
+    /// Headers to add, keeping any existing values
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub add: BTreeMap<String, String>,
+}
+
+/// The values template variables in header values expand to
+#[derive(Debug, Default, Clone)]
+pub struct Variables {
+    /// `$remote_addr`, the address of the client
+    pub remote_addr: String,
+    /// `$upstream_addr`, the address of the upstream the request is proxied to
+    pub upstream_addr: String,
+    /// `$host`, the Host header sent by the client
+    pub host: String,
+}
+
+impl HeaderTransform {
+    pub fn is_empty(&self) -> bool {
+        self.remove.is_empty() && self.set.is_empty() && self.add.is_empty()
+    }
+
+    pub fn apply(&self, headers: &mut Headers, variables: &Variables) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+
+        for (name, value) in &self.set {
+            if let Some((name, value)) = header(name, value, variables) {
+                headers.insert(name, value);
+            }
+        }
+
+        for (name, value) in &self.add {
+            if let Some((name, value)) = header(name, value, variables) {
+                headers.append(name, value);
+            }
+        }
+    }
+}
+
+/// Build a header from the config, skipping it if the name or expanded value is invalid
+fn header(name: &str, value: &str, variables: &Variables) -> Option<(HeaderName, HeaderValue)> {
+    let Ok(name) = HeaderName::try_from(name) else {
+        warn!("Skipping invalid header name in config: {name}");
+        return None;
+    };
+    let Ok(value) = HeaderValue::try_from(variables.expand(value)) else {
+        warn!("Skipping invalid value for header {name}");
+        return None;
+    };
+    Some((name, value))
+}
+
+impl Variables {
+    /// Replace the variables in the template. Unknown variables are left as they are.
+    pub fn expand(&self, template: &str) -> String {
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('$') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let (name, after) = rest.split_at(end);
+            match self.get(name) {
+                Some(value) => expanded.push_str(value),
+                None => {
+                    expanded.push('$');
+                    expanded.push_str(name);
+                }
+            }
+            rest = after;
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "remote_addr" => Some(&self.remote_addr),
+            "upstream_addr" => Some(&self.upstream_addr),
+            "host" => Some(&self.host),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn variables() -> Variables {
+        Variables {
+            remote_addr: String::from("10.0.0.1:5000"),
+            upstream_addr: String::from("backend:80"),
+            host: String::from("example.com"),
+        }
+    }
+
+    #[rstest]
+    #[case("plain", "plain")]
+    #[case("$remote_addr", "10.0.0.1:5000")]
+    #[case("for=$remote_addr;host=$host", "for=10.0.0.1:5000;host=example.com")]
+    #[case("$upstream_addr/$hostname", "backend:80/$hostname")]
+    #[case("$5 and $", "$5 and $")]
+    fn test_expand(#[case] template: &str, #[case] expected: &str) {
+        assert_eq!(expected, variables().expand(template));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut headers = Headers::new();
+        headers.insert(
+            HeaderName::from_static("server"),
+            HeaderValue::from_static("backend"),
+        );
+        headers.insert(
+            HeaderName::from_static("x-token"),
+            HeaderValue::from_static("client"),
+        );
+        headers.insert(
+            HeaderName::from_static("vary"),
+            HeaderValue::from_static("accept"),
+        );
+
+        let transform: HeaderTransform = serde_json::from_str(
+            r#"{
+                "remove": ["Server"],
+                "set": { "x-token": "internal", "x-client": "$remote_addr", "bad name": "a" },
+                "add": { "vary": "accept-encoding" }
+            }"#,
+        )
+        .unwrap();
+        transform.apply(&mut headers, &variables());
+
+        assert_eq!(None, headers.get("server"));
+        assert_eq!(
+            Some(&HeaderValue::from_static("internal")),
+            headers.get("x-token")
+        );
+        assert_eq!(
+            Some(&HeaderValue::from_static("10.0.0.1:5000")),
+            headers.get("x-client")
+        );
+        assert_eq!(
+            Some(&HeaderValue::from_static("accept, accept-encoding")),
+            headers.get("vary")
+        );
+    }
+}
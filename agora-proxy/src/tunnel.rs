@@ -0,0 +1,289 @@
+//! Connections passed through as they are once they leave HTTP, like WebSockets.
+//!
+//! A tunnel can stay open for as long as both ends want it to, holding on to a client
+//! connection and an upstream connection all the while. Limits close the ones that
+//! have gone quiet, lasted too long, or moved too much data.
+
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::{Instant, sleep, sleep_until},
+};
+
+use crate::config::Limits;
+
+/// When a tunnel gets closed whatever its ends are doing
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TunnelLimits {
+    /// Time without bytes going either way
+    pub idle_timeout: Option<Duration>,
+    /// Time since the tunnel was opened
+    pub max_duration: Option<Duration>,
+    /// Bytes passed through both ways together
+    pub max_bytes: Option<u64>,
+}
+
+impl From<&Limits> for TunnelLimits {
+    fn from(limits: &Limits) -> Self {
+        Self {
+            idle_timeout: Some(limits.tunnel_idle_timeout)
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            max_duration: limits.tunnel_max_duration.map(Duration::from_secs),
+            max_bytes: limits.tunnel_max_bytes,
+        }
+    }
+}
+
+/// The tunnels open through the server, and those that were
+#[derive(Debug, Default)]
+pub struct Tunnels {
+    active: AtomicUsize,
+    opened: AtomicU64,
+    bytes: AtomicU64,
+}
+
+/// A tunnel counting as open until this is dropped
+#[derive(Debug)]
+pub struct ActiveTunnel<'a> {
+    tunnels: &'a Tunnels,
+}
+
+impl Tunnels {
+    /// Count a tunnel as open, for as long as the returned guard lives
+    pub fn open(&self) -> ActiveTunnel<'_> {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        ActiveTunnel { tunnels: self }
+    }
+
+    /// The tunnels open right now
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// The tunnels opened since the server started
+    pub fn opened(&self) -> u64 {
+        self.opened.load(Ordering::Relaxed)
+    }
+
+    /// The bytes passed through tunnels since the server started, both ways together
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for ActiveTunnel<'_> {
+    fn drop(&mut self) {
+        self.tunnels.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The bytes passed through a tunnel, and why it was closed if its ends didn't close it
+#[derive(Debug, Default)]
+pub struct Transferred {
+    /// Bytes sent to the upstream
+    pub sent: u64,
+    /// Bytes sent to the client
+    pub received: u64,
+    /// The error or limit that closed the tunnel
+    pub closed_by: Option<io::Error>,
+}
+
+/// Pass bytes both ways between the client and the upstream until both are done with
+/// the connection, or a limit is reached. Either side closing its end is passed on to
+/// the other, which can still send the rest of what it has. The tunnel is counted among
+/// the `tunnels` while open.
+pub async fn relay(
+    client: &mut (impl AsyncRead + AsyncWrite + Unpin + Send),
+    server: &mut (impl AsyncRead + AsyncWrite + Unpin + Send),
+    limits: TunnelLimits,
+    tunnels: &Tunnels,
+) -> Transferred {
+    let _active = tunnels.open();
+    let mut transferred = Transferred::default();
+    let closed_by = relay_until_closed(client, server, limits, tunnels, &mut transferred).await;
+    transferred.closed_by = closed_by.err();
+    transferred
+}
+
+async fn relay_until_closed(
+    client: &mut (impl AsyncRead + AsyncWrite + Unpin + Send),
+    server: &mut (impl AsyncRead + AsyncWrite + Unpin + Send),
+    limits: TunnelLimits,
+    tunnels: &Tunnels,
+    transferred: &mut Transferred,
+) -> io::Result<()> {
+    let deadline = limits
+        .max_duration
+        .map(|max_duration| Instant::now() + max_duration);
+    let mut client_buf = [0; 4096];
+    let mut server_buf = [0; 4096];
+    let mut client_open = true;
+    let mut server_open = true;
+
+    while client_open || server_open {
+        // reads are cancel safe, so whichever side sends first is passed on and the
+        // other read starts over
+        let (read, to_server) = tokio::select! {
+            read = client.read(&mut client_buf), if client_open => (read?, true),
+            read = server.read(&mut server_buf), if server_open => (read?, false),
+            _ = sleep_or_pending(limits.idle_timeout) => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "idle for too long"));
+            }
+            _ = sleep_until_or_pending(deadline) => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "open for too long"));
+            }
+        };
+
+        let (bytes, writer, open): (_, &mut (dyn AsyncWrite + Unpin + Send), _) = if to_server {
+            (&client_buf[..read], &mut *server, &mut client_open)
+        } else {
+            (&server_buf[..read], &mut *client, &mut server_open)
+        };
+        if read == 0 {
+            *open = false;
+            writer.shutdown().await?;
+            continue;
+        }
+
+        let total = transferred.sent + transferred.received + read as u64;
+        if limits.max_bytes.is_some_and(|max_bytes| total > max_bytes) {
+            return Err(io::Error::other("too many bytes transferred"));
+        }
+        writer.write_all(bytes).await?;
+        tunnels.bytes.fetch_add(read as u64, Ordering::Relaxed);
+        if to_server {
+            transferred.sent += read as u64;
+        } else {
+            transferred.received += read as u64;
+        }
+    }
+    Ok(())
+}
+
+async fn sleep_or_pending(duration: Option<Duration>) {
+    match duration {
+        Some(duration) => sleep(duration).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until_or_pending(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{DuplexStream, duplex};
+
+    use super::*;
+
+    /// A tunnel between two in-memory connections, returning the far ends of the client
+    /// and of the upstream
+    fn open(
+        limits: TunnelLimits,
+        tunnels: &'static Tunnels,
+    ) -> (
+        DuplexStream,
+        DuplexStream,
+        tokio::task::JoinHandle<Transferred>,
+    ) {
+        let (client, mut client_end) = duplex(1024);
+        let (server, mut server_end) = duplex(1024);
+        let handle =
+            tokio::spawn(
+                async move { relay(&mut client_end, &mut server_end, limits, tunnels).await },
+            );
+        (client, server, handle)
+    }
+
+    #[tokio::test]
+    async fn test_relay() {
+        let tunnels: &'static Tunnels = Box::leak(Box::default());
+        let (mut client, mut server, handle) = open(TunnelLimits::default(), tunnels);
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"ping", &buf);
+        assert_eq!(1, tunnels.active());
+
+        // the upstream can still answer once the client is done sending
+        client.shutdown().await.unwrap();
+        assert_eq!(0, server.read(&mut buf).await.unwrap());
+        server.write_all(b"pong!").await.unwrap();
+        server.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        assert_eq!(b"pong!", received.as_slice());
+
+        let transferred = handle.await.unwrap();
+        assert_eq!(4, transferred.sent);
+        assert_eq!(5, transferred.received);
+        assert!(transferred.closed_by.is_none());
+        assert_eq!(0, tunnels.active());
+        assert_eq!(1, tunnels.opened());
+        assert_eq!(9, tunnels.bytes());
+    }
+
+    #[tokio::test]
+    async fn test_relay_limits() {
+        let tunnels: &'static Tunnels = Box::leak(Box::default());
+        let limits = TunnelLimits {
+            max_bytes: Some(6),
+            ..Default::default()
+        };
+        let (mut client, mut server, handle) = open(limits, tunnels);
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        server.write_all(b"pong").await.unwrap();
+        let transferred = handle.await.unwrap();
+        assert_eq!(4, transferred.sent);
+        assert_eq!(0, transferred.received);
+        assert!(transferred.closed_by.is_some());
+
+        let limits = TunnelLimits {
+            idle_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let (mut client, _server, handle) = open(limits, tunnels);
+        let started = std::time::Instant::now();
+        let transferred = handle.await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            Some(io::ErrorKind::TimedOut),
+            transferred.closed_by.map(|e| e.kind())
+        );
+        // the ends are closed along with the tunnel
+        assert_eq!(0, client.read(&mut buf).await.unwrap());
+
+        let limits = TunnelLimits {
+            idle_timeout: Some(Duration::from_millis(100)),
+            max_duration: Some(Duration::from_millis(150)),
+            ..Default::default()
+        };
+        let (mut client, _server, handle) = open(limits, tunnels);
+        // traffic keeps it from being idle, but not past its deadline
+        let keep_busy = tokio::spawn(async move {
+            while client.write_all(b".").await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        let transferred = handle.await.unwrap();
+        assert!(transferred.sent > 0);
+        assert_eq!(
+            Some(io::ErrorKind::TimedOut),
+            transferred.closed_by.map(|e| e.kind())
+        );
+        keep_busy.abort();
+    }
+}
@@ -0,0 +1,264 @@
+//! Listeners forwarding UDP datagrams rather than serving HTTP, for DNS, QUIC or game
+//! servers behind the proxy.
+//!
+//! UDP has no connections, so the proxy makes up sessions: the first datagram of a
+//! client opens one, with its own socket upstream, and replies coming back on that
+//! socket go to that client. A session is forgotten once nothing went either way for
+//! the idle timeout, which is all there is to tell a client is done.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    net::{UdpSocket, lookup_host},
+    time::{Instant, sleep_until},
+};
+use tracing::{debug, info};
+
+use crate::config::UdpForward;
+
+/// The largest datagram UDP can carry
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+type Sessions = Arc<Mutex<HashMap<SocketAddr, Arc<Session>>>>;
+
+/// Forwards the datagrams of a listener, keeping a session per client
+#[derive(Debug)]
+pub struct UdpForwarder {
+    config: UdpForward,
+}
+
+/// A client datagrams were forwarded for, and the socket its replies come back on
+#[derive(Debug)]
+struct Session {
+    upstream: UdpSocket,
+    last_active: Mutex<Instant>,
+}
+
+impl Session {
+    fn touch(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_since(&self) -> Instant {
+        *self.last_active.lock().unwrap()
+    }
+}
+
+impl UdpForwarder {
+    pub fn new(config: UdpForward) -> Self {
+        Self { config }
+    }
+
+    /// Bind the address and forward the datagrams received on it
+    pub async fn listen(&self, address: &str) -> io::Result<()> {
+        let socket = UdpSocket::bind(address).await?;
+        info!("Forwarding UDP on {address} to {}", self.config.addr);
+        self.serve(socket).await
+    }
+
+    /// Forward the datagrams received on an already bound socket
+    pub async fn serve(&self, socket: UdpSocket) -> io::Result<()> {
+        let socket = Arc::new(socket);
+        let sessions = Sessions::default();
+        let idle_timeout = Duration::from_secs(self.config.idle_timeout);
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            let (len, client) = socket.recv_from(&mut buf).await?;
+
+            let existing = sessions.lock().unwrap().get(&client).cloned();
+            let session = match existing {
+                Some(session) => session,
+                None => {
+                    if sessions.lock().unwrap().len() >= self.config.max_sessions {
+                        debug!("Dropping datagram from {client}: too many UDP sessions");
+                        continue;
+                    }
+                    let upstream = match connect(&self.config.addr).await {
+                        Ok(upstream) => upstream,
+                        Err(e) => {
+                            debug!(
+                                "Failed to forward UDP from {client} to {}: {e}",
+                                self.config.addr
+                            );
+                            continue;
+                        }
+                    };
+                    let session = Arc::new(Session {
+                        upstream,
+                        last_active: Mutex::new(Instant::now()),
+                    });
+                    sessions.lock().unwrap().insert(client, session.clone());
+                    debug!("UDP session opened for {client}");
+                    tokio::spawn(reply(
+                        socket.clone(),
+                        client,
+                        session.clone(),
+                        sessions.clone(),
+                        idle_timeout,
+                    ));
+                    session
+                }
+            };
+
+            {
+                // touched while holding the sessions, so the session can't expire
+                // between being found and the datagram going out on it
+                let _sessions = sessions.lock().unwrap();
+                session.touch();
+            }
+            if let Err(e) = session.upstream.send(&buf[..len]).await {
+                debug!("Failed to forward UDP from {client}: {e}");
+            }
+        }
+    }
+}
+
+/// A socket connected to the first address the upstream resolves to, which only
+/// receives what comes from there
+async fn connect(addr: &str) -> io::Result<UdpSocket> {
+    let target = lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?;
+    let local = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let upstream = UdpSocket::bind(local).await?;
+    upstream.connect(target).await?;
+    Ok(upstream)
+}
+
+/// Send the replies to a session back to its client until it has been idle for the
+/// timeout, then forget it
+async fn reply(
+    socket: Arc<UdpSocket>,
+    client: SocketAddr,
+    session: Arc<Session>,
+    sessions: Sessions,
+    idle_timeout: Duration,
+) {
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    loop {
+        tokio::select! {
+            result = session.upstream.recv(&mut buf) => match result {
+                Ok(len) => {
+                    session.touch();
+                    if let Err(e) = socket.send_to(&buf[..len], client).await {
+                        debug!("Failed to send UDP reply to {client}: {e}");
+                    }
+                }
+                // like a refused port, which a later datagram may find open
+                Err(e) => debug!("Failed to receive UDP reply for {client}: {e}"),
+            },
+            _ = sleep_until(session.idle_since() + idle_timeout) => {
+                let mut sessions = sessions.lock().unwrap();
+                if session.idle_since().elapsed() >= idle_timeout {
+                    sessions.remove(&client);
+                    break;
+                }
+            }
+        }
+    }
+    debug!("UDP session closed for {client}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An upstream replying to each datagram with the address it came from
+    async fn spawn_upstream() -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buf = [0; 1024];
+            loop {
+                let (_, from) = socket.recv_from(&mut buf).await.unwrap();
+                socket
+                    .send_to(from.to_string().as_bytes(), from)
+                    .await
+                    .unwrap();
+            }
+        });
+        addr
+    }
+
+    async fn spawn_forwarder(config: UdpForward) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move { UdpForwarder::new(config).serve(socket).await });
+        addr
+    }
+
+    /// Send a datagram through the forwarder, and get what the upstream said about it
+    async fn exchange(client: &UdpSocket, forwarder: SocketAddr) -> Option<String> {
+        client.send_to(b"ping", forwarder).await.unwrap();
+        let mut buf = [0; 1024];
+        let (len, from) =
+            tokio::time::timeout(Duration::from_millis(500), client.recv_from(&mut buf))
+                .await
+                .ok()?
+                .unwrap();
+        assert_eq!(forwarder, from);
+        Some(String::from_utf8(buf[..len].to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_sessions_per_client() {
+        let forwarder = spawn_forwarder(UdpForward {
+            addr: spawn_upstream().await,
+            ..Default::default()
+        })
+        .await;
+        let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let session = exchange(&first, forwarder).await.unwrap();
+        assert_eq!(Some(session.clone()), exchange(&first, forwarder).await);
+        let other = exchange(&second, forwarder).await.unwrap();
+        assert_ne!(session, other);
+    }
+
+    #[tokio::test]
+    async fn test_max_sessions() {
+        let forwarder = spawn_forwarder(UdpForward {
+            addr: spawn_upstream().await,
+            max_sessions: 1,
+            ..Default::default()
+        })
+        .await;
+        let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        assert!(exchange(&first, forwarder).await.is_some());
+        assert_eq!(None, exchange(&second, forwarder).await);
+        assert!(exchange(&first, forwarder).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_expire() {
+        let forwarder = spawn_forwarder(UdpForward {
+            addr: spawn_upstream().await,
+            idle_timeout: 1,
+            max_sessions: 1,
+        })
+        .await;
+        let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let session = exchange(&first, forwarder).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        // the expired session makes room for another client
+        assert!(exchange(&second, forwarder).await.is_some());
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_ne!(session, exchange(&first, forwarder).await.unwrap());
+    }
+}
@@ -0,0 +1,938 @@
+//! Pools of backends that routes proxy requests to.
+
+#[cfg(feature = "consul")]
+mod consul;
+mod dns;
+mod file;
+mod h2;
+mod hash;
+mod health;
+mod keepalive;
+mod outlier;
+mod srv;
+mod stream;
+mod tunnel;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    ops::Deref,
+    path::PathBuf,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use agora_http_parser::Request;
+use arc_swap::ArcSwap;
+use rustls::{ClientConfig, pki_types::ServerName};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::TcpStream,
+    sync::watch,
+};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::{
+        Balance, HealthCheck, KeepAlive, OutlierDetection, ParentProxy, Upstream, UpstreamTls,
+    },
+    forwarded::Client,
+    proxy_protocol, tls,
+};
+use dns::Resolved;
+use h2::Connections;
+use hash::Ring;
+use keepalive::{Idle, Usage};
+use outlier::Outliers;
+pub use stream::Stream;
+
+/// How much each response moves the average latency of its backend. Higher values
+/// follow changes faster, lower ones smooth out single slow responses more.
+const LATENCY_WEIGHT: f64 = 0.3;
+
+/// The scheme of backends connected to over TLS, on port 443 unless they have one
+const HTTPS: &str = "https://";
+
+/// The scheme backends may be listed with when not connected to over TLS, on port 80
+/// unless they have one
+const HTTP: &str = "http://";
+
+/// The backends of an upstream, or of a route listing its own addresses. Routes naming
+/// the same upstream share its pool.
+#[derive(Debug)]
+pub struct Pool {
+    members: ArcSwap<Members>,
+    /// The addresses of the backends listed in the config
+    listed: Vec<String>,
+    /// The SRV names more backends are discovered from
+    srv: Vec<String>,
+    /// The service in the Consul catalog the backends are discovered from
+    #[cfg(feature = "consul")]
+    consul: Option<crate::config::Consul>,
+    /// The file listing more backends, watched for changes
+    file: Option<PathBuf>,
+    /// Set once backends were first discovered, which requests wait for
+    discovered: watch::Sender<bool>,
+    balance: Balance,
+    /// Counts the connections to the pool, to take turns between its backends
+    next: AtomicUsize,
+    outlier_detection: Option<OutlierDetection>,
+    keepalive: KeepAlive,
+    /// Whether cleartext backends are spoken to over HTTP/2
+    http2: bool,
+    /// Whether connections start with a PROXY protocol header telling the client
+    proxy_protocol: bool,
+    tls: UpstreamTls,
+    /// Made from the TLS settings once the first `https://` backend is connected to
+    client_config: OnceLock<Result<Arc<ClientConfig>, String>>,
+    /// The proxy connections to the backends go through, which resolves their names
+    proxy: Option<ParentProxy>,
+}
+
+/// The backends of a pool, replaced as a whole when discovery finds others
+#[derive(Debug)]
+struct Members {
+    backends: Vec<Arc<Backend>>,
+    /// Only has points when balancing by hash
+    ring: Ring,
+}
+
+/// A backend of a pool
+#[derive(Debug)]
+pub struct Backend {
+    pub addr: String,
+    /// The host and port of the address, without its scheme
+    pub authority: String,
+    /// Whether the address is an `https://` one
+    tls: bool,
+    /// How many requests the backend gets compared to the others, 1 unless listed
+    /// otherwise in a file
+    weight: AtomicU32,
+    /// How many times the backend was picked, for the debug logs
+    selected: AtomicU64,
+    /// Requests currently proxied to the backend
+    in_flight: AtomicUsize,
+    /// Exponentially weighted moving average of the response latency in nanoseconds,
+    /// as the bits of an `f64`. Zero until the first response.
+    latency: AtomicU64,
+    /// Cleared while health checks fail, which stops the backend from being picked
+    healthy: AtomicBool,
+    /// Failures of requests to the backend, which eject it for a while
+    outliers: Outliers,
+    /// Connections kept open for later requests
+    idle: Idle,
+    /// The HTTP/2 connections the requests share, for upstreams speaking HTTP/2
+    http2: Connections,
+    /// The addresses the host name of the backend resolves to
+    resolved: Resolved,
+}
+
+impl Backend {
+    fn new(addr: String, weight: u32) -> Self {
+        let (tls, authority) = if let Some(rest) = addr.strip_prefix(HTTPS) {
+            (true, with_port(rest, 443))
+        } else if let Some(rest) = addr.strip_prefix(HTTP) {
+            (false, with_port(rest, 80))
+        } else {
+            (false, addr.clone())
+        };
+        Self {
+            addr,
+            authority,
+            tls,
+            weight: AtomicU32::new(weight),
+            selected: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            latency: AtomicU64::new(0),
+            healthy: AtomicBool::new(true),
+            outliers: Outliers::default(),
+            idle: Idle::default(),
+            http2: Connections::default(),
+            resolved: Resolved::default(),
+        }
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.weight.load(Ordering::Relaxed)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    /// Add the time the backend took to respond to its average latency
+    pub fn record_latency(&self, latency: Duration) {
+        let sample = latency.as_nanos() as f64;
+        let _ = self
+            .latency
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                let average = f64::from_bits(average);
+                let updated = if average == 0.0 {
+                    sample
+                } else {
+                    average + LATENCY_WEIGHT * (sample - average)
+                };
+                Some(updated.max(1.0).to_bits())
+            });
+    }
+
+    /// The average latency of the backend, once it responded at least once
+    pub fn latency(&self) -> Option<Duration> {
+        let average = f64::from_bits(self.latency.load(Ordering::Relaxed));
+        (average > 0.0).then(|| Duration::from_nanos(average as u64))
+    }
+
+    /// Connect to the first address of the backend accepting the connection
+    async fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+        for addr in self.resolved.addrs(&self.authority).await? {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// How costly sending another request to the backend is expected to be, from its
+    /// latency and the requests it already has, shared by its weight. Backends without
+    /// a latency yet cost nothing, so they are tried.
+    fn cost(&self) -> f64 {
+        let in_flight = self.in_flight.load(Ordering::Relaxed) as f64;
+        f64::from_bits(self.latency.load(Ordering::Relaxed)) * (in_flight + 1.0)
+            / f64::from(self.weight())
+    }
+}
+
+/// The host and port, with the default port added if there is none
+fn with_port(host: &str, default_port: u16) -> String {
+    let host = host.trim_end_matches('/');
+    let has_port = host
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.ends_with(':') && port.parse::<u16>().is_ok());
+    if has_port {
+        host.to_string()
+    } else {
+        format!("{host}:{default_port}")
+    }
+}
+
+/// The host of the address, without its port and the brackets of IPv6 addresses
+fn host(authority: &str) -> &str {
+    let host = authority
+        .rsplit_once(':')
+        .map_or(authority, |(host, _)| host);
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// The PROXY protocol header telling a backend about the client, or that the proxy
+/// opened the connection for itself. The port the client connected from is only known
+/// when it connected to the proxy directly, and the proxy address it connected to is
+/// unspecified when the socket couldn't tell.
+fn proxy_header(client: Option<&Client>) -> Vec<u8> {
+    let Some(client) = client else {
+        return proxy_protocol::v2_local_header();
+    };
+    let port = if client.ip == client.addr.ip() {
+        client.addr.port()
+    } else {
+        0
+    };
+    let destination = client
+        .local_addr
+        .unwrap_or_else(|| SocketAddr::new(IpAddr::from([0; 4]), 0));
+    proxy_protocol::v2_header(SocketAddr::new(client.ip, port), destination)
+}
+
+/// A backend picked for a request, which counts as in flight until this is dropped
+#[derive(Debug)]
+pub struct InFlight<'a> {
+    pool: &'a Pool,
+    backend: Arc<Backend>,
+    /// The requests sent on the connection and when it was opened
+    usage: Usage,
+}
+
+impl<'a> InFlight<'a> {
+    fn new(pool: &'a Pool, backend: &Arc<Backend>) -> Self {
+        backend.in_flight.fetch_add(1, Ordering::Relaxed);
+        Self {
+            pool,
+            backend: backend.clone(),
+            usage: Usage::new(Instant::now()),
+        }
+    }
+
+    /// Whether the connection was kept open after an earlier request. The backend may
+    /// have closed it just as the request was sent, in which case sending the request
+    /// again on a new connection is safe.
+    pub fn is_reused(&self) -> bool {
+        self.usage.requests > 1
+    }
+
+    /// Keep the connection open for a later request once its response is done, unless
+    /// it is due to be retired. Streams of HTTP/2 connections are done with instead,
+    /// their connection being shared already, and so are connections whose PROXY
+    /// protocol header tells about the client they were opened for.
+    pub fn release(&self, stream: Stream) {
+        if let Stream::Http2(_) = stream {
+            return;
+        }
+        if self.pool.proxy_protocol {
+            return;
+        }
+        self.backend
+            .idle
+            .release(stream, self.usage, &self.pool.keepalive, Instant::now());
+    }
+
+    /// Report whether the request succeeded, with failures being connection errors,
+    /// timeouts, and `5xx` responses, to eject backends failing too often
+    pub fn report(&self, passed: bool) {
+        let Some(detection) = &self.pool.outlier_detection else {
+            return;
+        };
+        if let Some(time) = self
+            .backend
+            .outliers
+            .record(passed, detection, Instant::now())
+        {
+            warn!(
+                "Ejecting backend {} for {}s after {} failed requests in a row",
+                self.backend.addr,
+                time.as_secs(),
+                detection.consecutive_failures
+            );
+        }
+    }
+}
+
+impl Deref for InFlight<'_> {
+    type Target = Backend;
+
+    fn deref(&self) -> &Backend {
+        &self.backend
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.backend.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Members {
+    fn new(backends: Vec<Arc<Backend>>, balance: &Balance) -> Self {
+        let ring = match balance {
+            Balance::Hash(_) => Ring::new(
+                backends
+                    .iter()
+                    .map(|backend| (backend.addr.as_str(), backend.weight())),
+            ),
+            _ => Ring::default(),
+        };
+        Self { backends, ring }
+    }
+}
+
+impl Pool {
+    pub fn new(upstream: &Upstream) -> Self {
+        let (srv, listed): (Vec<_>, Vec<_>) = upstream
+            .addr
+            .iter()
+            .map(String::from)
+            .partition(|addr| addr.starts_with(srv::SCHEME));
+        let backends = listed
+            .iter()
+            .map(|addr| Arc::new(Backend::new(addr.clone(), 1)))
+            .collect();
+        let pool = Self {
+            members: ArcSwap::from_pointee(Members::new(backends, &upstream.balance)),
+            listed,
+            srv: srv
+                .iter()
+                .map(|addr| addr[srv::SCHEME.len()..].to_string())
+                .collect(),
+            #[cfg(feature = "consul")]
+            consul: upstream.consul.clone(),
+            file: upstream.file.clone(),
+            discovered: watch::Sender::new(false),
+            balance: upstream.balance.clone(),
+            next: AtomicUsize::new(0),
+            outlier_detection: upstream.outlier_detection.clone(),
+            keepalive: upstream.keepalive.clone(),
+            http2: upstream.http2,
+            proxy_protocol: upstream.proxy_protocol,
+            tls: upstream.tls.clone().unwrap_or_default(),
+            client_config: OnceLock::new(),
+            proxy: upstream.proxy.clone(),
+        };
+        if let Some(path) = &pool.file {
+            match file::read(path) {
+                Ok(backends) => pool.set_discovered(backends),
+                Err(e) => error!("Failed to read backends from {}: {e}", path.display()),
+            }
+        }
+        pool
+    }
+
+    /// The backends the pool has at the moment
+    pub fn backends(&self) -> Vec<Arc<Backend>> {
+        self.members.load().backends.clone()
+    }
+
+    /// Check the health of the backends in the background for as long as the pool is
+    /// in use. Backends count as healthy until the checks say otherwise.
+    pub fn check_health(self: &Arc<Self>, check: HealthCheck) {
+        health::spawn(Arc::downgrade(self), check);
+    }
+
+    /// Look up the SRV names or the Consul service of the pool, or watch its file, in
+    /// the background for as long as the pool is in use, adding the backends found.
+    /// Does nothing if the pool has none of them.
+    pub fn discover(self: &Arc<Self>) {
+        if !self.srv.is_empty() {
+            srv::spawn(Arc::downgrade(self));
+        }
+        #[cfg(feature = "consul")]
+        if self.consul.is_some() {
+            consul::spawn(Arc::downgrade(self));
+        }
+        if let Some(path) = &self.file {
+            file::spawn(Arc::downgrade(self), path.clone());
+        }
+    }
+
+    /// Whether requests wait for the backends of the pool to be discovered. The file
+    /// of a pool is read as it is created, so only lookups are waited for.
+    fn discovers(&self) -> bool {
+        #[cfg(feature = "consul")]
+        if self.consul.is_some() {
+            return true;
+        }
+        !self.srv.is_empty()
+    }
+
+    /// Make the discovered addresses the backends of the pool with their weights, along
+    /// with the listed ones. Backends the pool already has keep their state.
+    fn set_discovered(&self, discovered: Vec<(String, u32)>) {
+        let members = self.members.load();
+        let backends: Vec<_> = self
+            .listed
+            .iter()
+            .map(|addr| (addr.clone(), 1))
+            .chain(discovered)
+            .collect();
+        if members
+            .backends
+            .iter()
+            .map(|backend| (&backend.addr, backend.weight()))
+            .eq(backends.iter().map(|(addr, weight)| (addr, *weight)))
+        {
+            return;
+        }
+        info!(
+            "Discovered backends {}",
+            backends
+                .iter()
+                .map(|(addr, weight)| match weight {
+                    1 => addr.clone(),
+                    weight => format!("{addr} (weight {weight})"),
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let backends = backends
+            .into_iter()
+            .map(|(addr, weight)| {
+                match members.backends.iter().find(|backend| backend.addr == addr) {
+                    Some(backend) => {
+                        backend.weight.store(weight, Ordering::Relaxed);
+                        backend.clone()
+                    }
+                    None => Arc::new(Backend::new(addr, weight)),
+                }
+            })
+            .collect();
+        self.members
+            .store(Arc::new(Members::new(backends, &self.balance)));
+    }
+
+    /// The backends in the order to try them for a connection, starting with the next
+    /// one in turn. Each backend takes as many turns in a row as its weight.
+    fn round_robin(&self) -> impl Iterator<Item = Arc<Backend>> {
+        let backends = self.backends();
+        let weights: Vec<_> = backends.iter().map(|backend| backend.weight()).collect();
+        let start = match weights.iter().map(|&weight| weight as usize).sum::<usize>() {
+            0 => 0,
+            total => {
+                let mut turn = self.next.fetch_add(1, Ordering::Relaxed) % total;
+                weights
+                    .iter()
+                    .position(|&weight| match turn.checked_sub(weight as usize) {
+                        Some(rest) => {
+                            turn = rest;
+                            false
+                        }
+                        None => true,
+                    })
+                    .unwrap_or(0)
+            }
+        };
+        let (before, after) = backends.split_at(start);
+        let order: Vec<_> = after.iter().chain(before).cloned().collect();
+        order.into_iter()
+    }
+
+    /// The backends in the order to try them for a connection to proxy the request of
+    /// the client, by the balancing policy of the pool
+    fn candidates(&self, request: &Request, client: IpAddr) -> Vec<Arc<Backend>> {
+        match &self.balance {
+            Balance::RoundRobin => self.round_robin().collect(),
+            // backends with as many requests in flight take turns
+            Balance::LeastConn => {
+                let mut backends: Vec<_> = self
+                    .round_robin()
+                    .map(|backend| {
+                        let in_flight = backend.in_flight.load(Ordering::Relaxed) as u64;
+                        (in_flight, u64::from(backend.weight()), backend)
+                    })
+                    .collect();
+                // fewest requests in flight for their weight first
+                backends.sort_by(|(a, a_weight, _), (b, b_weight, _)| {
+                    (a * b_weight).cmp(&(b * a_weight))
+                });
+                backends
+                    .into_iter()
+                    .map(|(_, _, backend)| backend)
+                    .collect()
+            }
+            Balance::P2c => self.power_of_two_choices(),
+            Balance::Hash(key) => match hash::request_hash(key, request, client) {
+                Some(hash) => {
+                    let members = self.members.load();
+                    members
+                        .ring
+                        .walk(hash, members.backends.len())
+                        .into_iter()
+                        .map(|i| members.backends[i].clone())
+                        .collect()
+                }
+                None => self.round_robin().collect(),
+            },
+        }
+    }
+
+    /// Two random backends, the one with the lower cost first, then the rest in the
+    /// order they are listed after it
+    fn power_of_two_choices(&self) -> Vec<Arc<Backend>> {
+        let backends = self.backends();
+        let len = backends.len();
+        if len < 2 {
+            return backends;
+        }
+        let first = fastrand::usize(..len);
+        let mut second = fastrand::usize(..len - 1);
+        if second >= first {
+            second += 1;
+        }
+        let best = if backends[second].cost() < backends[first].cost() {
+            second
+        } else {
+            first
+        };
+        backends[best..]
+            .iter()
+            .chain(&backends[..best])
+            .cloned()
+            .collect()
+    }
+
+    /// The candidates backends can be picked from. Unhealthy backends never are, and
+    /// ejected ones only when all healthy backends are ejected, since ejecting them
+    /// all would fail every request.
+    fn available(&self, candidates: Vec<Arc<Backend>>) -> Vec<Arc<Backend>> {
+        let now = Instant::now();
+        let healthy: Vec<_> = candidates
+            .into_iter()
+            .filter(|backend| backend.is_healthy())
+            .collect();
+        if healthy
+            .iter()
+            .all(|backend| backend.outliers.is_ejected(now))
+        {
+            return healthy;
+        }
+        healthy
+            .into_iter()
+            .filter(|backend| !backend.outliers.is_ejected(now))
+            .collect()
+    }
+
+    /// Open a connection to the backend, through the parent proxy if there is one, with
+    /// the TLS handshake done for `https://` backends. Upstreams speaking HTTP/2 get a
+    /// stream of a connection shared with other requests instead. Without a client,
+    /// the connection is the proxy's own, like for health checks.
+    async fn open(&self, backend: &Backend, client: Option<&Client>) -> io::Result<Stream> {
+        let connect = async {
+            let mut stream = match &self.proxy {
+                Some(proxy) => tunnel::connect(proxy, &backend.authority).await?,
+                None => backend.connect().await?,
+            };
+            if self.proxy_protocol {
+                stream.write_all(&proxy_header(client)).await?;
+            }
+            Ok(stream)
+        };
+        if self.http2 && !backend.tls {
+            let stream = backend
+                .http2
+                .open_stream(&backend.authority, &self.keepalive, connect)
+                .await?;
+            return Ok(Stream::Http2(stream));
+        }
+        let stream = connect.await?;
+        if !backend.tls {
+            return Ok(Stream::Plain(stream));
+        }
+        let config = self
+            .client_config
+            .get_or_init(|| tls::client_config(&self.tls))
+            .as_ref()
+            .map_err(|e| io::Error::other(format!("invalid TLS settings: {e}")))?;
+        let name = match &self.tls.server_name {
+            Some(name) => name.clone(),
+            None => host(&backend.authority).to_string(),
+        };
+        let name = ServerName::try_from(name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let stream = TlsConnector::from(config.clone())
+            .connect(name, stream)
+            .await?;
+        Ok(Stream::Tls(Box::new(stream)))
+    }
+
+    /// Connect to a backend picked by the balancing policy for the request of the
+    /// client, reusing an idle connection to it if there is one. A backend refusing the
+    /// connection is skipped for the next one.
+    pub async fn connect(
+        &self,
+        request: &Request,
+        client: &Client,
+    ) -> io::Result<(InFlight<'_>, Stream)> {
+        if self.discovers() {
+            // never closed, since the pool holds the sender
+            let _ = self.discovered.subscribe().wait_for(|&done| done).await;
+        }
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no healthy backends");
+        for backend in self.available(self.candidates(request, client.ip)) {
+            let mut backend = InFlight::new(self, &backend);
+            let selected = backend.selected.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!("Selected backend {} ({selected} times)", backend.addr);
+            if let Some((stream, usage)) = backend.idle.checkout(&self.keepalive, Instant::now()) {
+                debug!("Reusing a connection to {}", backend.addr);
+                backend.usage = usage;
+                return Ok((backend, stream));
+            }
+            match self.open(&backend, Some(client)).await {
+                Ok(stream) => return Ok((backend, stream)),
+                Err(e) => {
+                    warn!("Failed to connect to backend {}: {e}", backend.addr);
+                    backend.report(false);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, net::Ipv4Addr};
+
+    use rstest::rstest;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::{Addrs, HashKey};
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn client() -> Client {
+        Client {
+            addr: SocketAddr::new(CLIENT, 56324),
+            local_addr: None,
+            tls: false,
+            cert: None,
+            trusted: false,
+            ip: CLIENT,
+        }
+    }
+
+    fn request(headers: &str) -> Request {
+        let head = format!("GET / HTTP/1.1\r\n{headers}\r\n");
+        Request::parse(head.as_bytes()).unwrap().0
+    }
+
+    /// An address nothing is listening on
+    async fn closed_addr() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn new_pool(addrs: Addrs, balance: Balance) -> Pool {
+        Pool::new(&Upstream {
+            addr: addrs,
+            balance,
+            ..Default::default()
+        })
+    }
+
+    fn addrs() -> Addrs {
+        Addrs(vec![
+            String::from("a:1"),
+            String::from("b:1"),
+            String::from("c:1"),
+        ])
+    }
+
+    fn order(backends: Vec<Arc<Backend>>) -> Vec<String> {
+        backends
+            .into_iter()
+            .map(|backend| backend.addr.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let pool = new_pool(addrs(), Balance::RoundRobin);
+        for expected in [
+            ["a:1", "b:1", "c:1"],
+            ["b:1", "c:1", "a:1"],
+            ["c:1", "a:1", "b:1"],
+            ["a:1", "b:1", "c:1"],
+        ] {
+            assert_eq!(expected.to_vec(), order(pool.round_robin().collect()));
+        }
+        assert_eq!(
+            0,
+            new_pool(Addrs::default(), Balance::RoundRobin)
+                .round_robin()
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let pool = new_pool(Addrs::default(), Balance::RoundRobin);
+        pool.set_discovered(vec![(String::from("a:1"), 2), (String::from("b:1"), 1)]);
+        let firsts: Vec<_> = (0..6)
+            .map(|_| pool.round_robin().next().unwrap().addr.clone())
+            .collect();
+        assert_eq!(vec!["a:1", "a:1", "b:1", "a:1", "a:1", "b:1"], firsts);
+    }
+
+    #[test]
+    fn test_least_conn() {
+        let pool = new_pool(addrs(), Balance::LeastConn);
+        let candidates = || order(pool.candidates(&request(""), CLIENT));
+        let backends = pool.backends();
+        let a = [
+            InFlight::new(&pool, &backends[0]),
+            InFlight::new(&pool, &backends[0]),
+        ];
+        let b = InFlight::new(&pool, &backends[1]);
+        assert_eq!(vec!["c:1", "b:1", "a:1"], candidates());
+
+        drop(b);
+        // ties take turns
+        assert_eq!(vec!["b:1", "c:1", "a:1"], candidates());
+        assert_eq!(vec!["c:1", "b:1", "a:1"], candidates());
+
+        drop(a);
+        assert_eq!(0, backends[0].in_flight.load(Ordering::Relaxed));
+        assert_eq!(vec!["a:1", "b:1", "c:1"], candidates());
+    }
+
+    #[test]
+    fn test_hash() {
+        let pool = new_pool(
+            addrs(),
+            Balance::Hash(HashKey::Header(String::from("x-user"))),
+        );
+        let user =
+            |id: &str| order(pool.candidates(&request(&format!("x-user: {id}\r\n")), CLIENT));
+
+        // the same key always goes to the same backend, and different keys to others
+        assert_eq!(user("1"), user("1"));
+        let firsts: HashSet<_> = (0..20)
+            .map(|id| user(&id.to_string()).swap_remove(0))
+            .collect();
+        assert_eq!(3, firsts.len());
+
+        // requests without the key take turns
+        let without = pool.candidates(&request(""), CLIENT);
+        assert_ne!(order(without), order(pool.candidates(&request(""), CLIENT)));
+    }
+
+    #[test]
+    fn test_discovered_backends() {
+        let pool = new_pool(
+            Addrs(vec![
+                String::from("a:1"),
+                String::from("srv://_api._tcp.example.internal"),
+            ]),
+            Balance::RoundRobin,
+        );
+        assert_eq!(vec!["_api._tcp.example.internal"], pool.srv);
+        assert_eq!(vec!["a:1"], order(pool.backends()));
+
+        pool.set_discovered(vec![(String::from("b:1"), 1), (String::from("c:1"), 1)]);
+        assert_eq!(vec!["a:1", "b:1", "c:1"], order(pool.backends()));
+
+        // backends still found keep their state
+        pool.backends()[1].record_latency(Duration::from_millis(100));
+        pool.set_discovered(vec![(String::from("b:1"), 2)]);
+        assert_eq!(vec!["a:1", "b:1"], order(pool.backends()));
+        assert_eq!(
+            Some(Duration::from_millis(100)),
+            pool.backends()[1].latency()
+        );
+        assert_eq!(2, pool.backends()[1].weight());
+    }
+
+    #[rstest]
+    #[case("a:1", false, "a:1")]
+    #[case("https://api.internal", true, "api.internal:443")]
+    #[case("https://api.internal:8443/", true, "api.internal:8443")]
+    #[case("http://[::1]", false, "[::1]:80")]
+    fn test_scheme(#[case] addr: &str, #[case] tls: bool, #[case] authority: &str) {
+        let backend = Backend::new(addr.to_string(), 1);
+        assert_eq!(tls, backend.tls);
+        assert_eq!(authority, backend.authority);
+    }
+
+    #[rstest]
+    #[case("api.internal:443", "api.internal")]
+    #[case("10.0.0.1:443", "10.0.0.1")]
+    #[case("[fd00::1]:443", "fd00::1")]
+    fn test_host(#[case] authority: &str, #[case] expected: &str) {
+        assert_eq!(expected, host(authority));
+    }
+
+    #[test]
+    fn test_record_latency() {
+        let pool = new_pool(addrs(), Balance::P2c);
+        let backend = &pool.backends()[0];
+        assert_eq!(None, backend.latency());
+
+        backend.record_latency(Duration::from_millis(100));
+        assert_eq!(Some(Duration::from_millis(100)), backend.latency());
+        backend.record_latency(Duration::from_millis(200));
+        assert_eq!(Some(Duration::from_millis(130)), backend.latency());
+    }
+
+    #[test]
+    fn test_p2c() {
+        let pool = new_pool(addrs(), Balance::P2c);
+        let backends = pool.backends();
+        backends[0].record_latency(Duration::from_millis(500));
+        backends[1].record_latency(Duration::from_millis(5));
+        backends[2].record_latency(Duration::from_millis(5));
+
+        // the slow backend loses against either of the others
+        for _ in 0..100 {
+            let candidates = order(pool.candidates(&request(""), CLIENT));
+            assert_ne!("a:1", candidates[0]);
+            assert_eq!(3, candidates.len());
+        }
+
+        // busy backends lose against slow ones too
+        let _busy: Vec<_> = (0..200)
+            .map(|_| InFlight::new(&pool, &backends[1]))
+            .collect();
+        for _ in 0..100 {
+            assert_ne!("b:1", order(pool.candidates(&request(""), CLIENT))[0]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_skips_unreachable_backends() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().to_string();
+        let pool = new_pool(
+            Addrs(vec![closed_addr().await, open.clone()]),
+            Balance::RoundRobin,
+        );
+
+        let (backend, _) = pool.connect(&request(""), &client()).await.unwrap();
+        assert_eq!(open, backend.addr);
+    }
+
+    #[tokio::test]
+    async fn test_connect_skips_unhealthy_backends() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let pool = new_pool(Addrs(vec![addr.clone(), addr]), Balance::RoundRobin);
+        pool.backends()[0].set_healthy(false);
+
+        for _ in 0..2 {
+            let (backend, _) = pool.connect(&request(""), &client()).await.unwrap();
+            assert!(Arc::ptr_eq(&pool.backends()[1], &backend.backend));
+        }
+        pool.backends()[1].set_healthy(false);
+        let error = pool.connect(&request(""), &client()).await.unwrap_err();
+        assert_eq!("no healthy backends", error.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_connect_skips_ejected_backends() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().to_string();
+        let pool = Pool::new(&Upstream {
+            addr: Addrs(vec![closed_addr().await, open.clone()]),
+            outlier_detection: Some(OutlierDetection {
+                consecutive_failures: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        // the first connection ejects the closed backend, so the next ones skip it
+        for _ in 0..3 {
+            let (backend, _) = pool.connect(&request(""), &client()).await.unwrap();
+            assert_eq!(open, backend.addr);
+        }
+        assert_eq!(1, pool.backends()[0].selected.load(Ordering::Relaxed));
+
+        // with every backend ejected, they are all tried anyway
+        pool.connect(&request(""), &client())
+            .await
+            .unwrap()
+            .0
+            .report(false);
+        assert_eq!(2, pool.available(pool.backends()).len());
+    }
+
+    #[tokio::test]
+    async fn test_connect_fails_without_reachable_backends() {
+        let pool = new_pool(Addrs(vec![closed_addr().await]), Balance::LeastConn);
+        assert!(pool.connect(&request(""), &client()).await.is_err());
+        assert!(
+            new_pool(Addrs::default(), Balance::RoundRobin)
+                .connect(&request(""), &client())
+                .await
+                .is_err()
+        );
+    }
+}
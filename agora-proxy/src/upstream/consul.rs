@@ -0,0 +1,225 @@
+//! Discovering the backends of a pool from the Consul catalog.
+//!
+//! The HTTP API of the agent is asked for the instances of the service passing their
+//! health checks, with blocking queries which respond as soon as the instances change,
+//! or otherwise once the wait time is up. When a query fails, the backends found last
+//! are kept until one succeeds.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Weak,
+    time::Duration,
+};
+
+use agora_http_parser::Response;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{sleep, timeout},
+};
+use tracing::warn;
+
+use super::Pool;
+use crate::config::Consul;
+
+/// How long the agent holds a query while the instances don't change
+const WAIT: Duration = Duration::from_secs(60);
+
+/// Time allowed for a query on top of the wait, since the agent adds some jitter to it
+const MARGIN: Duration = Duration::from_secs(10);
+
+/// How long to wait before querying again after a query failed
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// The most of a response read, far more than the instances of a service take
+const MAX_RESPONSE_SIZE: usize = 8 * 1024 * 1024;
+
+/// An instance of the service, as listed by the health endpoint
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Entry {
+    node: Node,
+    service: Service,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Node {
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Service {
+    /// Empty when the service is reached at the address of its node
+    address: String,
+    port: u16,
+}
+
+/// Query the catalog for the service of the pool until the pool is dropped, which
+/// happens once the config it is part of is replaced and its last request is done
+pub(super) fn spawn(pool: Weak<Pool>) {
+    tokio::spawn(async move {
+        let Some(consul) = pool.upgrade().and_then(|pool| pool.consul.clone()) else {
+            return;
+        };
+        let mut index = 0;
+        loop {
+            let result = timeout(WAIT + MARGIN, instances(&consul, index))
+                .await
+                .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")));
+            let Some(pool) = pool.upgrade() else {
+                return;
+            };
+            let failed = match result {
+                Ok((next, addrs)) => {
+                    // the index going backwards means the agent started over
+                    index = if next < index { 0 } else { next };
+                    pool.set_discovered(addrs.into_iter().map(|addr| (addr, 1)).collect());
+                    false
+                }
+                Err(e) => {
+                    warn!("Failed to query Consul for {}: {e}", consul.service);
+                    index = 0;
+                    true
+                }
+            };
+            pool.discovered.send_replace(true);
+            drop(pool);
+            if failed {
+                sleep(RETRY_AFTER).await;
+            }
+        }
+    });
+}
+
+/// The addresses of the passing instances of the service once they differ from the
+/// ones at the index, along with the index they are at
+async fn instances(consul: &Consul, index: u64) -> io::Result<(u64, Vec<String>)> {
+    let mut query = vec![
+        ("passing", String::from("true")),
+        ("index", index.to_string()),
+        ("wait", format!("{}s", WAIT.as_secs())),
+    ];
+    if let Some(datacenter) = &consul.datacenter {
+        query.push(("dc", datacenter.clone()));
+    }
+    query.extend(consul.tags.iter().map(|tag| ("tag", tag.clone())));
+    let query = serde_urlencoded::to_string(query).map_err(io::Error::other)?;
+
+    // HTTP/1.0 keeps the agent from chunking the response, which ends with the connection
+    let mut request = format!(
+        "GET /v1/health/service/{}?{query} HTTP/1.0\r\nhost: {}\r\nuser-agent: agora\r\n",
+        consul.service, consul.addr
+    );
+    if let Some(token) = &consul.token {
+        request.push_str(&format!("x-consul-token: {token}\r\n"));
+    }
+    request.push_str("\r\n");
+    let mut stream = TcpStream::connect(&consul.addr).await?;
+    stream.write_all(request.as_bytes()).await?;
+    let mut received = Vec::new();
+    (&mut stream)
+        .take(MAX_RESPONSE_SIZE as u64 + 1)
+        .read_to_end(&mut received)
+        .await?;
+    if received.len() > MAX_RESPONSE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "response too large",
+        ));
+    }
+
+    let (response, body) = Response::parse(&received)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(io::Error::other(format!(
+            "responded with {}",
+            response.status()
+        )));
+    }
+    let index = response
+        .get_header("x-consul-index")
+        .and_then(|index| index.to_str().ok()?.parse().ok())
+        .unwrap_or(0);
+    let entries: Vec<Entry> = serde_json::from_slice(body)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let mut addrs: Vec<_> = entries.into_iter().map(addr).collect();
+    addrs.sort_unstable();
+    addrs.dedup();
+    Ok((index, addrs))
+}
+
+/// The address of an instance, bracketing IPv6 addresses
+fn addr(entry: Entry) -> String {
+    let host = if entry.service.address.is_empty() {
+        entry.node.address
+    } else {
+        entry.service.address
+    };
+    match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, entry.service.port).to_string(),
+        Err(_) => format!("{host}:{}", entry.service.port),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// An agent responding once with the body, sending back the request it got
+    async fn agent(body: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = [0; 1024];
+            let n = stream.read(&mut received).await.unwrap();
+            let response = format!(
+                "HTTP/1.0 200 OK\r\ncontent-type: application/json\r\nx-consul-index: 42\r\n\r\n{body}"
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&received[..n]).into_owned()
+        });
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn test_instances() {
+        let (addr, request) = agent(
+            r#"[
+                { "Node": { "Address": "10.0.0.1" }, "Service": { "Address": "", "Port": 8080 } },
+                { "Node": { "Address": "10.0.0.2" }, "Service": { "Address": "fd00::2", "Port": 8080 } },
+                { "Node": { "Address": "10.0.0.3" }, "Service": { "Address": "api.internal", "Port": 9090 } }
+            ]"#,
+        )
+        .await;
+        let consul = Consul {
+            addr,
+            service: String::from("api"),
+            tags: vec![String::from("v2")],
+            token: Some(String::from("secret")),
+            ..Default::default()
+        };
+
+        let (index, addrs) = instances(&consul, 7).await.unwrap();
+        assert_eq!(42, index);
+        assert_eq!(
+            vec!["10.0.0.1:8080", "[fd00::2]:8080", "api.internal:9090"],
+            addrs
+        );
+
+        let request = request.await.unwrap();
+        assert!(
+            request.starts_with(
+                "GET /v1/health/service/api?passing=true&index=7&wait=60s&tag=v2 HTTP/1.0\r\n"
+            ),
+            "{request}"
+        );
+        assert!(request.contains("x-consul-token: secret\r\n"), "{request}");
+    }
+}
@@ -0,0 +1,155 @@
+//! Resolving the host names of backends without blocking, keeping the addresses for as
+//! long as their records live.
+//!
+//! Each backend resolves its host name again once the addresses it has expire, so pools
+//! pick up backends added to or removed from the records of a DNS based load balancer.
+//! Backends given as IP addresses are never resolved.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use hickory_resolver::TokioResolver;
+use tracing::{debug, warn};
+
+/// How long addresses from the system resolver are kept, since it doesn't tell their TTL
+const SYSTEM_TTL: Duration = Duration::from_secs(30);
+
+/// The resolver shared by every pool, read from the system DNS config the first time a
+/// host name is resolved. Without a readable config, host names are resolved by the
+/// system resolver on the blocking thread pool instead.
+pub(super) fn resolver() -> Option<&'static TokioResolver> {
+    static RESOLVER: OnceLock<Option<TokioResolver>> = OnceLock::new();
+    RESOLVER
+        .get_or_init(|| match TokioResolver::builder_tokio() {
+            Ok(builder) => Some(builder.build()),
+            Err(e) => {
+                warn!("Couldn't read the DNS config, using the system resolver instead: {e}");
+                None
+            }
+        })
+        .as_ref()
+}
+
+/// The addresses of a backend, resolved when first needed and again once they expire
+#[derive(Debug, Default)]
+pub(super) struct Resolved {
+    cached: Mutex<Option<Cached>>,
+}
+
+#[derive(Debug, Clone)]
+struct Cached {
+    addrs: Vec<SocketAddr>,
+    valid_until: Instant,
+}
+
+impl Resolved {
+    /// The addresses to connect to for the address of a backend. When resolving it again
+    /// fails, the addresses it last resolved to are used until it succeeds.
+    pub(super) async fn addrs(&self, addr: &str) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(addr) = addr.parse() {
+            return Ok(vec![addr]);
+        }
+
+        let stale = {
+            let cached = self.cached.lock().unwrap();
+            match &*cached {
+                Some(cached) if Instant::now() < cached.valid_until => {
+                    return Ok(cached.addrs.clone());
+                }
+                cached => cached.clone(),
+            }
+        };
+
+        match lookup(addr).await {
+            Ok(cached) => {
+                debug!("Resolved {addr} to {:?}", cached.addrs);
+                let addrs = cached.addrs.clone();
+                *self.cached.lock().unwrap() = Some(cached);
+                Ok(addrs)
+            }
+            Err(e) => match stale {
+                Some(stale) => {
+                    warn!("Failed to resolve {addr}, using the addresses it last had: {e}");
+                    Ok(stale.addrs)
+                }
+                None => Err(e),
+            },
+        }
+    }
+}
+
+async fn lookup(addr: &str) -> io::Result<Cached> {
+    let Some(resolver) = resolver() else {
+        return Ok(Cached {
+            addrs: tokio::net::lookup_host(addr).await?.collect(),
+            valid_until: Instant::now() + SYSTEM_TTL,
+        });
+    };
+
+    let (host, port) = addr
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{addr} isn't a host and port"),
+            )
+        })?;
+    let lookup = resolver
+        .lookup_ip(host)
+        .await
+        .map_err(|e| io::Error::other(format!("failed to resolve {host}: {e}")))?;
+    let addrs: Vec<_> = lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{host} has no addresses"),
+        ));
+    }
+    Ok(Cached {
+        addrs,
+        valid_until: lookup.valid_until(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ip_addresses_are_not_resolved() {
+        let resolved = Resolved::default();
+        let addrs = resolved.addrs("127.0.0.1:3000").await.unwrap();
+        assert_eq!(vec![SocketAddr::from(([127, 0, 0, 1], 3000))], addrs);
+        assert!(resolved.cached.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_addresses_are_kept_until_they_expire() {
+        let addr = SocketAddr::from(([10, 0, 0, 1], 3000));
+        let resolved = Resolved {
+            cached: Mutex::new(Some(Cached {
+                addrs: vec![addr],
+                valid_until: Instant::now() + Duration::from_secs(60),
+            })),
+        };
+        assert_eq!(vec![addr], resolved.addrs("api.test:3000").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_localhost() {
+        let resolved = Resolved::default();
+        let addrs = resolved.addrs("localhost:3000").await.unwrap();
+        assert!(
+            addrs
+                .iter()
+                .all(|addr| addr.ip().is_loopback() && addr.port() == 3000),
+            "{addrs:?}"
+        );
+        assert!(resolved.cached.lock().unwrap().is_some());
+    }
+}
@@ -0,0 +1,198 @@
+//! Backends listed in a file of their own, which deploy tools can rewrite to swap the
+//! backends of a pool without touching the config.
+//!
+//! Each line has the address of a backend, optionally followed by its weight, which is
+//! 1 if not given. Blank lines and lines starting with `#` are skipped. The directory of
+//! the file is watched, and the file read again whenever something in it changes, so
+//! replacing the file by renaming another over it works too. A file that fails to read
+//! or parse is logged, and the pool keeps the backends it has.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Weak,
+    time::Duration,
+};
+
+use notify::{RecursiveMode, Watcher};
+use tokio::{sync::mpsc, time::sleep};
+use tracing::{error, warn};
+
+use super::Pool;
+
+/// The highest weight of a backend
+pub(super) const MAX_WEIGHT: u32 = 100;
+
+/// How long to wait for more changes after the file changes, since it may be written in
+/// several steps
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// How often to check whether the pool is still in use while the file doesn't change
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The backends listed in the file, with their weights
+pub(super) fn read(path: &Path) -> Result<Vec<(String, u32)>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse(&contents)
+}
+
+fn parse(contents: &str) -> Result<Vec<(String, u32)>, String> {
+    let mut backends = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(addr), weight, None) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(format!(
+                "line {}: expected an address and an optional weight",
+                i + 1
+            ));
+        };
+        let weight = match weight {
+            None => 1,
+            Some(weight) => weight
+                .parse()
+                .ok()
+                .filter(|weight| (1..=MAX_WEIGHT).contains(weight))
+                .ok_or_else(|| {
+                    format!(
+                        "line {}: invalid weight `{weight}`, expected a number from 1 to {MAX_WEIGHT}",
+                        i + 1
+                    )
+                })?,
+        };
+        backends.push((addr.to_string(), weight));
+    }
+    Ok(backends)
+}
+
+/// Read the file again whenever it changes, until the pool is dropped, which happens
+/// once the config it is part of is replaced and its last request is done
+pub(super) fn spawn(pool: Weak<Pool>, path: PathBuf) {
+    let (changes_tx, mut changes) = mpsc::unbounded_channel();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if event.kind.is_access() => {}
+            Ok(_) => {
+                let _ = changes_tx.send(());
+            }
+            Err(e) => warn!("Error watching backend files: {e}"),
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+    let watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("Failed to watch {}: {e}", path.display());
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        // dropping the watcher stops it, so it has to live as long as the loop
+        let _watcher = watcher;
+        loop {
+            tokio::select! {
+                Some(()) = changes.recv() => {}
+                () = sleep(CHECK_INTERVAL) => {
+                    if pool.strong_count() == 0 {
+                        return;
+                    }
+                    continue;
+                }
+            }
+            sleep(SETTLE_TIME).await;
+            while changes.try_recv().is_ok() {}
+
+            let Some(pool) = pool.upgrade() else {
+                return;
+            };
+            match read(&path) {
+                Ok(backends) => pool.set_discovered(backends),
+                Err(e) => warn!(
+                    "Failed to read backends from {}, keeping the current ones: {e}",
+                    path.display()
+                ),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::config::Upstream;
+
+    #[rstest]
+    #[case("", Ok(vec![]))]
+    #[case(
+        "# api\n10.0.0.1:4000 3\n\n  10.0.0.2:4000\n",
+        Ok(vec![(String::from("10.0.0.1:4000"), 3), (String::from("10.0.0.2:4000"), 1)])
+    )]
+    #[case(
+        "10.0.0.1:4000 0",
+        Err(String::from("line 1: invalid weight `0`, expected a number from 1 to 100"))
+    )]
+    #[case(
+        "10.0.0.1:4000\n10.0.0.2:4000 heavy",
+        Err(String::from("line 2: invalid weight `heavy`, expected a number from 1 to 100"))
+    )]
+    #[case(
+        "10.0.0.1:4000 1 2",
+        Err(String::from("line 1: expected an address and an optional weight"))
+    )]
+    fn test_parse(#[case] contents: &str, #[case] expected: Result<Vec<(String, u32)>, String>) {
+        assert_eq!(expected, parse(contents));
+    }
+
+    #[tokio::test]
+    async fn test_file_changes_are_picked_up() {
+        let dir = std::env::temp_dir().join(format!("agora-backends-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("api.txt");
+        fs::write(&path, "a:1\nb:1 2\n").unwrap();
+
+        let pool = Arc::new(Pool::new(&Upstream {
+            file: Some(path.clone()),
+            ..Default::default()
+        }));
+        let backends = |pool: &Pool| -> Vec<_> {
+            pool.backends()
+                .iter()
+                .map(|backend| (backend.addr.clone(), backend.weight()))
+                .collect()
+        };
+        assert_eq!(
+            vec![(String::from("a:1"), 1), (String::from("b:1"), 2)],
+            backends(&pool)
+        );
+
+        pool.discover();
+        // replace the file like deploy tools do, by renaming a new one over it
+        let new = dir.join("api.txt.new");
+        fs::write(&new, "b:1\nc:1 3\n").unwrap();
+        fs::rename(&new, &path).unwrap();
+        let expected = vec![(String::from("b:1"), 1), (String::from("c:1"), 3)];
+        for _ in 0..50 {
+            if backends(&pool) == expected {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(expected, backends(&pool));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
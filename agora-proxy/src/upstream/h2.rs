@@ -0,0 +1,867 @@
+//! HTTP/2 connections to backends, over cleartext with prior knowledge (RFC 9113 section
+//! 3.3), which carry the requests of many clients at once.
+//!
+//! Each request gets a stream that looks like a connection of its own to the rest of the
+//! proxy: the request is written in HTTP/1.1 to one end of an in-memory pipe, turned
+//! into frames at the other end, and the response comes back through the pipe the same
+//! way. Nothing is kept idle per request, the connection is what's reused.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use agora_http_parser::{
+    Body, HTTPMethod, HTTPVersion, HeaderName, HeaderValue, Headers, Request, Response,
+    chunked::ChunkedDecoder,
+    h2::{
+        CONNECTION_PREFACE, DEFAULT_MAX_FRAME_SIZE, DEFAULT_WINDOW_SIZE, ErrorCode, Frame,
+        FrameError, Setting, SettingId,
+    },
+    is_terminated_from,
+};
+use http::StatusCode;
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf},
+    net::{TcpStream, tcp::OwnedReadHalf},
+    sync::{self as async_sync, Notify, mpsc},
+    time::sleep,
+};
+use tracing::debug;
+
+use super::keepalive::Usage;
+use crate::{
+    config::KeepAlive,
+    http2::{
+        HeaderBlock, HeaderBlocks, MAX_HEADER_LIST_SIZE, PIPE_SIZE, Sender, Window, last_chunk,
+    },
+};
+
+/// The bytes of response body a backend may send ahead, on each stream and on the
+/// connection as a whole
+const WINDOW_SIZE: u32 = 1 << 20;
+
+/// The streams a connection is assumed to take until the backend says otherwise, the
+/// least it should allow (RFC 9113 section 6.5.2)
+const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// The streams a connection carries over its lifetime, keeping stream ids in range
+const MAX_STREAMS: u32 = 1 << 30;
+
+/// The largest request head taken from the pipe
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// The HTTP/2 connections to a backend
+#[derive(Debug, Default)]
+pub(super) struct Connections {
+    /// Only the reading side of a connection keeps it, so it is gone once closed and
+    /// done with its streams. Locked while connecting, so requests arriving together
+    /// share the new connection.
+    connections: async_sync::Mutex<Vec<Weak<Connection>>>,
+}
+
+impl Connections {
+    /// Open a stream on a connection with room for it, connecting with `connect` if
+    /// there is none, and return the end of the pipe the request is written to.
+    pub(super) async fn open_stream(
+        &self,
+        authority: &str,
+        keepalive: &KeepAlive,
+        connect: impl Future<Output = io::Result<TcpStream>>,
+    ) -> io::Result<DuplexStream> {
+        let mut connections = self.connections.lock().await;
+        connections.retain(|conn| conn.upgrade().is_some_and(|conn| !conn.is_closed()));
+        let reserved = connections
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|conn| conn.reserve(keepalive, Instant::now()));
+        let conn = match reserved {
+            Some(conn) => conn,
+            None => {
+                debug!("Opening an HTTP/2 connection to {authority}");
+                let conn = Connection::start(connect.await?, keepalive).await?;
+                connections.push(Arc::downgrade(&conn));
+                conn
+            }
+        };
+        drop(connections);
+
+        let (proxy_end, stream_end) = io::duplex(PIPE_SIZE);
+        let mut exchange = Exchange {
+            conn,
+            stream_id: None,
+            ended: false,
+        };
+        let authority = authority.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = exchange.run(stream_end, &authority).await {
+                debug!("HTTP/2 stream to {authority} failed: {e}");
+            }
+        });
+        Ok(proxy_end)
+    }
+}
+
+/// What the streams of a connection share with it
+#[derive(Debug)]
+struct Connection {
+    sender: Sender,
+    streams: Mutex<Streams>,
+    /// The id of the next stream, locked while a stream is opened since the backend
+    /// has to see them opened in the order of their ids
+    next_stream_id: async_sync::Mutex<u32>,
+    /// Bytes of response bodies passed on by the streams, which the backend may send again
+    consumed: mpsc::UnboundedSender<(u32, u32)>,
+    /// Told when the last active stream is done, to start counting the idle time
+    went_idle: Notify,
+}
+
+#[derive(Debug)]
+struct Streams {
+    open: HashMap<u32, Open>,
+    /// Streams handed out and not done yet, whether opened on the connection or not
+    active: u32,
+    /// The streams handed out so far and when the connection was opened, which retire it
+    usage: Usage,
+    max_concurrent_streams: u32,
+    /// The size the backend gives the send windows of new streams
+    initial_window_size: u32,
+    /// Set once no more streams may be opened
+    closed: bool,
+}
+
+/// A stream whose response is still coming
+#[derive(Debug)]
+struct Open {
+    events: mpsc::UnboundedSender<Event>,
+    /// The bytes of response body the backend may still send
+    recv_window: i64,
+    send_window: Arc<Window>,
+}
+
+/// What the backend sent on a stream
+#[derive(Debug)]
+enum Event {
+    Headers(Box<Headers>, bool),
+    Data(Vec<u8>, bool),
+    Reset(ErrorCode),
+}
+
+impl Connection {
+    /// Open a connection, with the first stream handed out already
+    async fn start(stream: TcpStream, keepalive: &KeepAlive) -> io::Result<Arc<Self>> {
+        stream.set_nodelay(true)?;
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all(CONNECTION_PREFACE).await?;
+        let (sender, _) = Sender::spawn(writer);
+        let (consumed, consumed_rx) = mpsc::unbounded_channel();
+        let conn = Arc::new(Connection {
+            sender,
+            streams: Mutex::new(Streams {
+                open: HashMap::new(),
+                active: 1,
+                usage: Usage::new(Instant::now()),
+                max_concurrent_streams: DEFAULT_MAX_CONCURRENT_STREAMS,
+                initial_window_size: DEFAULT_WINDOW_SIZE,
+                closed: false,
+            }),
+            next_stream_id: async_sync::Mutex::new(1),
+            consumed,
+            went_idle: Notify::new(),
+        });
+
+        let settings = vec![
+            Setting {
+                id: SettingId::EnablePush,
+                value: 0,
+            },
+            Setting {
+                id: SettingId::InitialWindowSize,
+                value: WINDOW_SIZE,
+            },
+            Setting {
+                id: SettingId::MaxHeaderListSize,
+                value: MAX_HEADER_LIST_SIZE,
+            },
+        ];
+        let broken = |_| io::Error::from(io::ErrorKind::BrokenPipe);
+        conn.sender
+            .send(Frame::Settings {
+                ack: false,
+                settings,
+            })
+            .await
+            .map_err(broken)?;
+        // the connection window can only be widened by WINDOW_UPDATE
+        conn.sender
+            .send(Frame::WindowUpdate {
+                stream_id: 0,
+                increment: WINDOW_SIZE - DEFAULT_WINDOW_SIZE,
+            })
+            .await
+            .map_err(broken)?;
+
+        let mut session = Session {
+            conn: conn.clone(),
+            idle_timeout: Duration::from_secs(keepalive.idle_timeout),
+            header_blocks: HeaderBlocks::default(),
+            consumed: consumed_rx,
+            recv_window: WINDOW_SIZE.into(),
+            going_away: false,
+        };
+        tokio::spawn(async move {
+            let mut reader = reader;
+            let result = session.run(&mut reader).await;
+            session.conn.close();
+            if let Err(code) = result {
+                debug!("Closing HTTP/2 connection to backend: {code:?}");
+                session.go_away(code).await;
+            }
+        });
+        Ok(conn)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.streams.lock().unwrap().closed
+    }
+
+    /// Hand out a stream if the connection has room for it and isn't due to be retired
+    fn reserve(&self, keepalive: &KeepAlive, now: Instant) -> bool {
+        let mut streams = self.streams.lock().unwrap();
+        if streams.closed
+            || streams.active >= streams.max_concurrent_streams
+            || streams.usage.requests >= MAX_STREAMS
+            || streams.usage.is_retired(keepalive, now)
+        {
+            return false;
+        }
+        streams.active += 1;
+        streams.usage.requests += 1;
+        true
+    }
+
+    /// Open a stream with the header fields of a request
+    async fn open(
+        &self,
+        headers: Headers,
+        end_stream: bool,
+    ) -> Result<(u32, mpsc::UnboundedReceiver<Event>, Arc<Window>), ErrorCode> {
+        let mut next_stream_id = self.next_stream_id.lock().await;
+        let stream_id = *next_stream_id;
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let send_window = {
+            let mut streams = self.streams.lock().unwrap();
+            if streams.closed {
+                return Err(ErrorCode::RefusedStream);
+            }
+            let send_window = Arc::new(Window::new(streams.initial_window_size));
+            streams.open.insert(
+                stream_id,
+                Open {
+                    events,
+                    recv_window: WINDOW_SIZE.into(),
+                    send_window: send_window.clone(),
+                },
+            );
+            send_window
+        };
+        *next_stream_id += 2;
+        self.sender
+            .send_headers(stream_id, headers, end_stream)
+            .await?;
+        Ok((stream_id, events_rx, send_window))
+    }
+
+    /// Open no more streams, and end those waiting for a response
+    fn close(&self) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.closed = true;
+        streams.open.clear();
+    }
+
+    /// Close the connection if no stream is active, for it was left idle
+    fn close_idle(&self) -> bool {
+        let mut streams = self.streams.lock().unwrap();
+        if streams.active > 0 {
+            return false;
+        }
+        streams.closed = true;
+        true
+    }
+}
+
+/// The reading side of a connection, which keeps it for as long as it is usable
+struct Session {
+    conn: Arc<Connection>,
+    /// How long the connection stays open without streams
+    idle_timeout: Duration,
+    header_blocks: HeaderBlocks,
+    consumed: mpsc::UnboundedReceiver<(u32, u32)>,
+    /// The bytes of DATA the backend may still send on the connection
+    recv_window: i64,
+    /// Whether the backend is closing the connection, taking no more streams
+    going_away: bool,
+}
+
+impl Session {
+    async fn run(&mut self, reader: &mut OwnedReadHalf) -> Result<(), ErrorCode> {
+        let mut buf = Vec::new();
+        loop {
+            let mut parsed = 0;
+            loop {
+                match Frame::parse(&buf[parsed..], DEFAULT_MAX_FRAME_SIZE) {
+                    Ok((frame, rest)) => {
+                        let len = buf.len() - parsed - rest.len();
+                        self.frame(frame).await?;
+                        parsed += len;
+                    }
+                    Err(FrameError::Incomplete) => break,
+                    Err(e) => return Err(e.error_code()),
+                }
+            }
+            buf.drain(..parsed);
+
+            let idle = self.conn.streams.lock().unwrap().active == 0;
+            if self.going_away && idle {
+                return Ok(());
+            }
+            tokio::select! {
+                read = read(reader, &mut buf) => {
+                    if read == 0 {
+                        return Ok(());
+                    }
+                }
+                Some((stream_id, len)) = self.consumed.recv() => {
+                    self.passed_on(stream_id, len).await;
+                }
+                _ = self.conn.went_idle.notified(), if !idle => {}
+                _ = sleep(self.idle_timeout), if idle => {
+                    if self.conn.close_idle() {
+                        self.go_away(ErrorCode::NoError).await;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    async fn frame(&mut self, frame: Frame<'_>) -> Result<(), ErrorCode> {
+        self.header_blocks.expect(&frame)?;
+        if self.conn.sender.answer(&frame).await? {
+            return Ok(());
+        }
+        match frame {
+            Frame::Settings {
+                ack: false,
+                settings,
+            } => {
+                self.apply_settings(&settings).await?;
+                self.send(Frame::Settings {
+                    ack: true,
+                    settings: Vec::new(),
+                })
+                .await;
+            }
+            Frame::GoAway { last_stream_id, .. } => {
+                self.going_away = true;
+                let mut streams = self.conn.streams.lock().unwrap();
+                streams.closed = true;
+                // streams the backend never got to are refused, and safe to send again
+                streams.open.retain(|&stream_id, open| {
+                    let refused = stream_id > last_stream_id;
+                    if refused {
+                        let _ = open.events.send(Event::Reset(ErrorCode::RefusedStream));
+                    }
+                    !refused
+                });
+            }
+            Frame::WindowUpdate {
+                stream_id,
+                increment,
+            } => {
+                let widened = self
+                    .conn
+                    .streams
+                    .lock()
+                    .unwrap()
+                    .open
+                    .get(&stream_id)
+                    .is_none_or(|open| open.send_window.widen(increment.into()));
+                if !widened {
+                    self.close(stream_id, ErrorCode::FlowControlError).await;
+                }
+            }
+            Frame::RstStream {
+                stream_id,
+                error_code,
+            } => {
+                if let Some(open) = self.remove(stream_id) {
+                    let _ = open.events.send(Event::Reset(error_code));
+                }
+            }
+            frame @ (Frame::Headers { .. } | Frame::Continuation { .. }) => {
+                if let Some(block) = self.header_blocks.take(frame)? {
+                    self.headers(block)?;
+                }
+            }
+            Frame::Data {
+                stream_id,
+                end_stream,
+                data,
+            } => self.data(stream_id, end_stream, data).await?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn apply_settings(&mut self, settings: &[Setting]) -> Result<(), ErrorCode> {
+        self.conn.sender.follow(settings).await;
+        let mut streams = self.conn.streams.lock().unwrap();
+        for setting in settings {
+            match setting.id {
+                SettingId::MaxConcurrentStreams => streams.max_concurrent_streams = setting.value,
+                SettingId::InitialWindowSize => {
+                    let change = i64::from(setting.value) - i64::from(streams.initial_window_size);
+                    streams.initial_window_size = setting.value;
+                    for open in streams.open.values() {
+                        if !open.send_window.widen(change) {
+                            return Err(ErrorCode::FlowControlError);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Take in a complete header block, the head of a response or its trailers
+    fn headers(&mut self, block: HeaderBlock) -> Result<(), ErrorCode> {
+        let HeaderBlock {
+            stream_id,
+            end_stream,
+            headers,
+        } = block;
+        let mut streams = self.conn.streams.lock().unwrap();
+        let Some(open) = streams.open.get(&stream_id) else {
+            return Ok(());
+        };
+        let _ = open
+            .events
+            .send(Event::Headers(Box::new(headers), end_stream));
+        if end_stream {
+            streams.open.remove(&stream_id);
+        }
+        Ok(())
+    }
+
+    async fn data(
+        &mut self,
+        stream_id: u32,
+        end_stream: bool,
+        data: &[u8],
+    ) -> Result<(), ErrorCode> {
+        let len = data.len() as i64;
+        self.recv_window -= len;
+        if self.recv_window < 0 {
+            return Err(ErrorCode::FlowControlError);
+        }
+        let passed = {
+            let mut streams = self.conn.streams.lock().unwrap();
+            match streams.open.get_mut(&stream_id) {
+                Some(open) => {
+                    open.recv_window -= len;
+                    if open.recv_window < 0 {
+                        None
+                    } else {
+                        let sent = open
+                            .events
+                            .send(Event::Data(data.to_vec(), end_stream))
+                            .is_ok();
+                        if end_stream {
+                            streams.open.remove(&stream_id);
+                        }
+                        Some(sent)
+                    }
+                }
+                // the stream is closed, but the bytes still count against the connection
+                None => Some(false),
+            }
+        };
+        match passed {
+            Some(true) => {}
+            Some(false) => self.credit(0, len as u32).await,
+            None => {
+                self.close(stream_id, ErrorCode::FlowControlError).await;
+                self.credit(0, len as u32).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Let the backend send again the bytes a stream passed on
+    async fn passed_on(&mut self, stream_id: u32, len: u32) {
+        self.credit(0, len).await;
+        let open = {
+            let mut streams = self.conn.streams.lock().unwrap();
+            streams
+                .open
+                .get_mut(&stream_id)
+                .map(|open| open.recv_window += i64::from(len))
+                .is_some()
+        };
+        if open {
+            self.credit(stream_id, len).await;
+        }
+    }
+
+    async fn credit(&mut self, stream_id: u32, increment: u32) {
+        if increment == 0 {
+            return;
+        }
+        if stream_id == 0 {
+            self.recv_window += i64::from(increment);
+        }
+        self.send(Frame::WindowUpdate {
+            stream_id,
+            increment,
+        })
+        .await;
+    }
+
+    fn remove(&self, stream_id: u32) -> Option<Open> {
+        self.conn.streams.lock().unwrap().open.remove(&stream_id)
+    }
+
+    /// Reset a stream still waiting for its response
+    async fn close(&mut self, stream_id: u32, error_code: ErrorCode) {
+        if let Some(open) = self.remove(stream_id) {
+            let _ = open.events.send(Event::Reset(error_code));
+            self.send(Frame::RstStream {
+                stream_id,
+                error_code,
+            })
+            .await;
+        }
+    }
+
+    async fn go_away(&self, error_code: ErrorCode) {
+        self.send(Frame::GoAway {
+            last_stream_id: 0,
+            error_code,
+            debug_data: &[],
+        })
+        .await;
+    }
+
+    /// Queue a frame for the backend. Frames are dropped once the connection is broken,
+    /// which the reading side finds out about as well.
+    async fn send(&self, frame: Frame<'_>) {
+        let _ = self.conn.sender.send(frame).await;
+    }
+}
+
+async fn read(reader: &mut OwnedReadHalf, buf: &mut Vec<u8>) -> usize {
+    buf.reserve(DEFAULT_MAX_FRAME_SIZE as usize);
+    reader.read_buf(buf).await.unwrap_or(0)
+}
+
+/// A stream handed out for a request, counted as active on its connection until dropped
+struct Exchange {
+    conn: Arc<Connection>,
+    /// Set once the stream is opened on the connection
+    stream_id: Option<u32>,
+    /// Whether the whole request was sent
+    ended: bool,
+}
+
+impl Exchange {
+    /// Send the request written to the pipe on a new stream, and write the response
+    /// back to it
+    async fn run(&mut self, pipe: DuplexStream, authority: &str) -> io::Result<()> {
+        let (mut reader, mut writer) = io::split(pipe);
+        let (request, pending) = read_request(&mut reader).await?;
+        let head = request.method == HTTPMethod::HEAD;
+        let version = request.version;
+        let body = request.body;
+        let end_stream = !matches!(body, Body::Sized(1..) | Body::Chunked);
+        let conn = self.conn.clone();
+        let (stream_id, events, window) = conn
+            .open(request_headers(request, authority), end_stream)
+            .await
+            .map_err(reset)?;
+        self.stream_id = Some(stream_id);
+        self.ended = end_stream;
+
+        {
+            let sending = send_body(&conn, stream_id, &window, &mut reader, body, pending);
+            let receiving = receive_response(&conn, stream_id, events, &mut writer, head, version);
+            tokio::pin!(receiving);
+            tokio::select! {
+                received = &mut receiving => received?,
+                sent = sending => {
+                    sent?;
+                    self.ended = true;
+                    receiving.await?;
+                }
+            }
+        }
+        // ends a response delimited by the connection closing
+        writer.shutdown().await
+    }
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        let mut streams = self.conn.streams.lock().unwrap();
+        streams.active -= 1;
+        if streams.active == 0 {
+            self.conn.went_idle.notify_one();
+        }
+        let Some(stream_id) = self.stream_id else {
+            return;
+        };
+        // a stream either side didn't end would stay open on the backend
+        if streams.open.remove(&stream_id).is_some() || !self.ended {
+            self.conn.sender.try_send(Frame::RstStream {
+                stream_id,
+                error_code: ErrorCode::Cancel,
+            });
+        }
+    }
+}
+
+fn reset(code: ErrorCode) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionReset,
+        format!("stream reset: {code:?}"),
+    )
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed response")
+}
+
+/// Read the request head the proxy wrote to the pipe, along with what came after it
+async fn read_request(reader: &mut ReadHalf<DuplexStream>) -> io::Result<(Request, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut scanned = 0;
+    while !is_terminated_from(&buf, scanned) {
+        scanned = buf.len();
+        if read_more(reader, &mut buf).await? == 0 || buf.len() > MAX_HEAD_SIZE {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+    }
+    let (request, rest) =
+        Request::parse(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok((request, rest.to_vec()))
+}
+
+async fn read_more(reader: &mut ReadHalf<DuplexStream>, buf: &mut Vec<u8>) -> io::Result<usize> {
+    buf.reserve(PIPE_SIZE);
+    reader.read_buf(buf).await
+}
+
+/// Translate an HTTP/1.1 request head into the header fields opening a stream
+fn request_headers(request: Request, authority: &str) -> Headers {
+    let mut fields = request.headers;
+    let host = fields.remove("host");
+    let accepts_trailers = fields.get("te").is_some_and(|te| {
+        te.as_bytes()
+            .split(|&b| b == b',')
+            .any(|coding| coding.trim_ascii().eq_ignore_ascii_case(b"trailers"))
+    });
+    // connection-specific fields have no place in HTTP/2 (RFC 9113 section 8.2.2)
+    fields.strip_hop_by_hop();
+
+    let mut headers = Headers::with_capacity(fields.len() + 5);
+    headers.insert(
+        HeaderName::from_static(":method"),
+        HeaderValue::from_static(request.method.as_str()),
+    );
+    headers.insert(
+        HeaderName::from_static(":scheme"),
+        HeaderValue::from_static("http"),
+    );
+    let authority = host.or_else(|| HeaderValue::try_from(authority).ok());
+    if let Some(authority) = authority {
+        headers.insert(HeaderName::from_static(":authority"), authority);
+    }
+    if let Ok(path) = HeaderValue::try_from(request.path) {
+        headers.insert(HeaderName::from_static(":path"), path);
+    }
+    for (name, value) in fields.iter() {
+        headers.append(name.clone(), value.clone());
+    }
+    if accepts_trailers {
+        headers.insert(
+            HeaderName::from_static("te"),
+            HeaderValue::from_static("trailers"),
+        );
+    }
+    headers
+}
+
+/// Translate the header fields of a response into an HTTP/1.1 response head, or `None`
+/// if they are malformed
+fn to_response(mut headers: Headers) -> Option<Response> {
+    let status = headers.remove(":status")?;
+    let status = StatusCode::from_bytes(status.as_bytes()).ok()?;
+    if headers.iter().any(|(name, _)| name.is_pseudo()) {
+        return None;
+    }
+    let mut response = Response::new(status);
+    for (name, value) in headers.iter() {
+        response
+            .get_headers_mut()
+            .append(name.clone(), value.clone());
+    }
+    Some(response)
+}
+
+/// Send the request body following the head in the pipe on the stream
+async fn send_body(
+    conn: &Connection,
+    stream_id: u32,
+    window: &Window,
+    reader: &mut ReadHalf<DuplexStream>,
+    body: Body,
+    mut pending: Vec<u8>,
+) -> io::Result<()> {
+    let cut_short = || io::Error::from(io::ErrorKind::UnexpectedEof);
+    match body {
+        Body::Sized(len) if len > 0 => {
+            let mut left = len;
+            loop {
+                let piece = &pending[..pending
+                    .len()
+                    .min(usize::try_from(left).unwrap_or(usize::MAX))];
+                left -= piece.len() as u64;
+                conn.sender
+                    .send_data(stream_id, window, piece)
+                    .await
+                    .map_err(reset)?;
+                if left == 0 {
+                    break;
+                }
+                pending.clear();
+                if read_more(reader, &mut pending).await? == 0 {
+                    return Err(cut_short());
+                }
+            }
+        }
+        Body::Chunked => {
+            let mut decoder = ChunkedDecoder::default();
+            let mut data = Vec::new();
+            loop {
+                decoder
+                    .decode_data(&pending, &mut data)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                conn.sender
+                    .send_data(stream_id, window, &data)
+                    .await
+                    .map_err(reset)?;
+                data.clear();
+                if decoder.is_done() {
+                    break;
+                }
+                pending.clear();
+                if read_more(reader, &mut pending).await? == 0 {
+                    return Err(cut_short());
+                }
+            }
+            if !decoder.trailers().is_empty() {
+                let mut trailers = decoder.trailers().clone();
+                trailers.strip_hop_by_hop();
+                return conn
+                    .sender
+                    .send_headers(stream_id, trailers, true)
+                    .await
+                    .map_err(reset);
+            }
+        }
+        // the stream was ended with the request head
+        _ => return Ok(()),
+    }
+    conn.sender.end_stream(stream_id).await.map_err(reset)
+}
+
+/// The next thing the backend sent on the stream, failing if it reset the stream or
+/// the connection closed
+async fn next_event(events: &mut mpsc::UnboundedReceiver<Event>) -> io::Result<Event> {
+    match events.recv().await {
+        Some(Event::Reset(code)) => Err(reset(code)),
+        Some(event) => Ok(event),
+        None => Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "HTTP/2 connection to backend closed",
+        )),
+    }
+}
+
+/// Write the response coming in on the stream to the pipe. Without a length, the body
+/// is sent chunked, which also carries trailers, unless the request was HTTP/1.0.
+async fn receive_response(
+    conn: &Connection,
+    stream_id: u32,
+    mut events: mpsc::UnboundedReceiver<Event>,
+    pipe: &mut WriteHalf<DuplexStream>,
+    head: bool,
+    version: HTTPVersion,
+) -> io::Result<()> {
+    let (chunked, mut ended) = loop {
+        let Event::Headers(headers, end_stream) = next_event(&mut events).await? else {
+            return Err(malformed());
+        };
+        let mut response = to_response(*headers).ok_or_else(malformed)?;
+        if response.is_interim() {
+            pipe.write_all(&response.into_bytes()).await?;
+            continue;
+        }
+        let mut chunked = false;
+        if !head && response.framing().map_err(|_| malformed())? == Body::UntilClose {
+            if end_stream {
+                response.header(HeaderName::from_static("content-length"), 0.into());
+            } else if version == HTTPVersion::HTTP1_1 {
+                response.header(
+                    HeaderName::from_static("transfer-encoding"),
+                    HeaderValue::from_static("chunked"),
+                );
+                chunked = true;
+            }
+        }
+        pipe.write_all(&response.into_bytes()).await?;
+        break (chunked, end_stream);
+    };
+
+    let mut trailers = None;
+    while !ended {
+        match next_event(&mut events).await? {
+            Event::Data(data, end_stream) => {
+                ended = end_stream;
+                if data.is_empty() {
+                    continue;
+                }
+                if chunked {
+                    pipe.write_all(format!("{:x}\r\n", data.len()).as_bytes())
+                        .await?;
+                    pipe.write_all(&data).await?;
+                    pipe.write_all(b"\r\n").await?;
+                } else if !head {
+                    pipe.write_all(&data).await?;
+                }
+                let _ = conn.consumed.send((stream_id, data.len() as u32));
+            }
+            Event::Headers(fields, _) => {
+                trailers = Some(*fields);
+                ended = true;
+            }
+            Event::Reset(code) => return Err(reset(code)),
+        }
+    }
+    if chunked {
+        pipe.write_all(&last_chunk(trailers.as_ref())).await?;
+    }
+    Ok(())
+}
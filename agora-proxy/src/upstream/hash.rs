@@ -0,0 +1,181 @@
+//! Consistent hashing of requests to backends.
+//!
+//! Each backend is placed at many points of a ring of hashes, and a key goes to the
+//! backend of the first point at or after its own hash. Adding or removing a backend
+//! only moves the keys between its points and the ones before them, instead of
+//! remapping nearly every key like hashing modulo the number of backends would.
+
+use std::net::IpAddr;
+
+use agora_http_parser::Request;
+
+use crate::config::HashKey;
+
+/// Points per unit of weight of a backend, enough to spread keys evenly over a handful
+/// of backends
+const POINTS: usize = 160;
+
+/// The points of the backends of a pool on the ring, by their index in the pool
+#[derive(Debug, Default)]
+pub(super) struct Ring {
+    points: Vec<(u64, usize)>,
+}
+
+impl Ring {
+    /// The ring of the backends at the addresses, each with points for its weight
+    pub(super) fn new<'a>(backends: impl Iterator<Item = (&'a str, u32)>) -> Self {
+        let mut points: Vec<_> = backends
+            .enumerate()
+            .flat_map(|(i, (addr, weight))| {
+                (0..POINTS * weight as usize)
+                    .map(move |point| (hash(format!("{addr}-{point}").as_bytes()), i))
+            })
+            .collect();
+        points.sort_unstable();
+        Self { points }
+    }
+
+    /// The indices of the backends in the order they come on the ring after the hash,
+    /// each only once, so the next ones can take over when the first is down
+    pub(super) fn walk(&self, hash: u64, backends: usize) -> Vec<usize> {
+        let start = self.points.partition_point(|&(point, _)| point < hash);
+        let mut seen = vec![false; backends];
+        let mut order = Vec::with_capacity(backends);
+        for &(_, i) in self.points[start..].iter().chain(&self.points[..start]) {
+            if !seen[i] {
+                seen[i] = true;
+                order.push(i);
+                if order.len() == backends {
+                    break;
+                }
+            }
+        }
+        order
+    }
+}
+
+/// The hash of the key of the request, if it has one
+pub(super) fn request_hash(key: &HashKey, request: &Request, client: IpAddr) -> Option<u64> {
+    match key {
+        HashKey::Ip => Some(hash(client.to_string().as_bytes())),
+        HashKey::Header(name) => request
+            .headers
+            .get(name)
+            .map(|value| hash(value.as_bytes())),
+        HashKey::Cookie(name) => cookie(request, name).map(|value| hash(value.as_bytes())),
+    }
+}
+
+/// The value of a cookie sent with the request. Several Cookie fields are combined
+/// with commas when parsed, so those separate cookies as well.
+fn cookie<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    let cookies = request.headers.get("cookie")?.to_str().ok()?;
+    cookies
+        .split([';', ','])
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value)
+}
+
+/// FNV-1a, whose output is the same across builds and platforms unlike the hasher of
+/// the standard library, followed by the finalizer of MurmurHash3 to spread the bits
+/// of similar inputs
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf29ce484222325_u64, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    const ADDRS: [&str; 3] = ["a:1", "b:1", "c:1"];
+
+    #[rstest]
+    #[case("session=abc", Some("abc"))]
+    #[case("theme=dark; session=abc; lang=en", Some("abc"))]
+    #[case("theme=dark, session=abc", Some("abc"))]
+    #[case("mysession=abc", None)]
+    #[case("session", None)]
+    fn test_cookie(#[case] cookies: &str, #[case] expected: Option<&str>) {
+        let head = format!("GET / HTTP/1.1\r\ncookie: {cookies}\r\n\r\n");
+        let (request, _) = Request::parse(head.as_bytes()).unwrap();
+        assert_eq!(expected, cookie(&request, "session"));
+    }
+
+    #[rstest]
+    #[case("x-session-id")]
+    #[case("X-Session-Id")]
+    fn test_header_hash(#[case] name: &str) {
+        let key = HashKey::Header(name.to_string());
+        let client = IpAddr::from([127, 0, 0, 1]);
+        let hash_of = |headers: &str| {
+            let head = format!("GET / HTTP/1.1\r\n{headers}\r\n");
+            let (request, _) = Request::parse(head.as_bytes()).unwrap();
+            request_hash(&key, &request, client)
+        };
+
+        let session = hash_of("X-Session-ID: abc\r\n");
+        assert!(session.is_some());
+        assert_eq!(session, hash_of("x-session-id: abc\r\naccept: */*\r\n"));
+        assert_ne!(session, hash_of("x-session-id: abd\r\n"));
+        assert_eq!(None, hash_of("authorization: Bearer abc\r\n"));
+    }
+
+    fn ring(addrs: &[&str]) -> Ring {
+        Ring::new(addrs.iter().map(|&addr| (addr, 1)))
+    }
+
+    #[test]
+    fn test_walk_visits_every_backend_once() {
+        let ring = ring(&ADDRS);
+        for key in ["a", "b", "c", "d"] {
+            let mut order = ring.walk(hash(key.as_bytes()), ADDRS.len());
+            order.sort();
+            assert_eq!(vec![0, 1, 2], order);
+        }
+        assert!(Ring::new(std::iter::empty()).walk(0, 0).is_empty());
+    }
+
+    #[test]
+    fn test_keys_spread_evenly() {
+        let ring = ring(&ADDRS);
+        let mut counts = [0; 3];
+        for key in 0..3000 {
+            counts[ring.walk(hash(format!("user-{key}").as_bytes()), 3)[0]] += 1;
+        }
+        assert!(counts.iter().all(|&count| count > 700), "{counts:?}");
+    }
+
+    #[test]
+    fn test_keys_spread_by_weight() {
+        let ring = Ring::new(ADDRS.into_iter().zip([1, 1, 2]));
+        let mut counts = [0; 3];
+        for key in 0..4000 {
+            counts[ring.walk(hash(format!("user-{key}").as_bytes()), 3)[0]] += 1;
+        }
+        assert!(counts[2] > 1600, "{counts:?}");
+        assert!(counts[..2].iter().all(|&count| count > 700), "{counts:?}");
+    }
+
+    #[test]
+    fn test_removing_a_backend_only_moves_its_keys() {
+        let before = ring(&ADDRS);
+        let after = ring(&ADDRS[..2]);
+        for key in 0..1000 {
+            let hash = hash(format!("user-{key}").as_bytes());
+            let backend = before.walk(hash, 3)[0];
+            if backend != 2 {
+                assert_eq!(backend, after.walk(hash, 2)[0]);
+            }
+        }
+    }
+}
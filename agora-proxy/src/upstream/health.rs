@@ -0,0 +1,220 @@
+//! Active health checks, requesting a path from each backend of a pool in the background
+//! and marking the backends up or down by their responses.
+
+use std::{collections::HashMap, io, sync::Weak, time::Duration};
+
+use agora_http_parser::{Response, is_terminated_from};
+use http::StatusCode;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinSet,
+    time::{MissedTickBehavior, interval, timeout},
+};
+use tracing::{info, warn};
+
+use super::{Backend, Pool};
+use crate::config::HealthCheck;
+
+/// The most of a response read to find its status
+const MAX_HEAD_SIZE: usize = 8192;
+
+/// Check the backends of the pool until it is dropped, which happens once the config it
+/// is part of is replaced and its last request is done
+pub(super) fn spawn(pool: Weak<Pool>, check: HealthCheck) {
+    tokio::spawn(async move {
+        let mut ticks = interval(Duration::from_secs(check.interval));
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut trackers = HashMap::new();
+
+        loop {
+            ticks.tick().await;
+            let Some(pool) = pool.upgrade() else {
+                return;
+            };
+            // forget the backends discovery removed
+            let backends = pool.backends();
+            trackers
+                .retain(|addr: &String, _| backends.iter().any(|backend| &backend.addr == addr));
+
+            let mut probes = JoinSet::new();
+            for backend in backends {
+                let check = check.clone();
+                let pool = pool.clone();
+                probes.spawn(async move {
+                    let result = probe(&pool, &backend, &check).await;
+                    (backend, result)
+                });
+            }
+
+            while let Some(Ok((backend, result))) = probes.join_next().await {
+                if let Err(e) = &result {
+                    warn!("Health check of {} failed: {e}", backend.addr);
+                }
+                let tracker: &mut Tracker = trackers.entry(backend.addr.clone()).or_default();
+                let healthy = backend.is_healthy();
+                if let Some(healthy) = tracker.record(healthy, result.is_ok(), &check) {
+                    backend.set_healthy(healthy);
+                    if healthy {
+                        info!("Backend {} is healthy again", backend.addr);
+                    } else {
+                        warn!(
+                            "Backend {} is unhealthy, no longer picking it",
+                            backend.addr
+                        );
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// The results of the last checks of a backend
+#[derive(Debug, Default)]
+struct Tracker {
+    /// Checks passed in a row
+    successes: u32,
+    /// Checks failed in a row
+    failures: u32,
+}
+
+impl Tracker {
+    /// Count the result of a check, returning whether the backend is healthy if that
+    /// changed
+    fn record(&mut self, healthy: bool, passed: bool, check: &HealthCheck) -> Option<bool> {
+        if passed {
+            self.successes += 1;
+            self.failures = 0;
+            (!healthy && self.successes >= check.healthy_threshold).then_some(true)
+        } else {
+            self.failures += 1;
+            self.successes = 0;
+            (healthy && self.failures >= check.unhealthy_threshold).then_some(false)
+        }
+    }
+}
+
+/// Request the path of the check from the backend, succeeding if it responds in time
+/// with a `2xx` or `3xx` status
+async fn probe(pool: &Pool, backend: &Backend, check: &HealthCheck) -> Result<(), String> {
+    let status = timeout(
+        Duration::from_secs(check.timeout),
+        request_status(pool, backend, check),
+    )
+    .await
+    .map_err(|_| String::from("timed out"))?
+    .map_err(|e| e.to_string())?;
+    if status.is_success() || status.is_redirection() {
+        Ok(())
+    } else {
+        Err(format!("responded with {status}"))
+    }
+}
+
+async fn request_status(
+    pool: &Pool,
+    backend: &Backend,
+    check: &HealthCheck,
+) -> io::Result<StatusCode> {
+    let mut stream = pool.open(backend, None).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nhost: {}\r\nuser-agent: agora-health-check\r\nconnection: close\r\n\r\n",
+        check.path, backend.authority
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    let mut scanned = 0;
+    loop {
+        while !is_terminated_from(&head, scanned) {
+            scanned = head.len();
+            let n = stream.read(&mut buf).await?;
+            if n == 0 || head.len() + n > MAX_HEAD_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "incomplete response",
+                ));
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        let (response, remaining) = Response::parse(&head)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if !response.is_interim() {
+            return Ok(response.status());
+        }
+        // the status is that of the final response following interim ones
+        head.drain(..head.len() - remaining.len());
+        scanned = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::{Addrs, Upstream};
+
+    async fn backend(response: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut received = [0; 1024];
+                let _ = stream.read(&mut received).await.unwrap();
+                stream.write_all(response).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let check = HealthCheck::default();
+        let ok = backend(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").await;
+        let redirect = backend(b"HTTP/1.1 302 Found\r\nlocation: /\r\n\r\n").await;
+        let down = backend(b"HTTP/1.1 503 Service Unavailable\r\n\r\n").await;
+        let garbage = backend(b"hello\r\n\r\n").await;
+        let hints =
+            backend(b"HTTP/1.1 103 Early Hints\r\nlink: </app.css>\r\n\r\nHTTP/1.1 200 OK\r\n\r\n")
+                .await;
+        let pool = Pool::new(&Upstream::from(Addrs(vec![
+            ok, redirect, down, garbage, hints,
+        ])));
+        let backends = pool.backends();
+
+        assert_eq!(Ok(()), probe(&pool, &backends[0], &check).await);
+        assert_eq!(Ok(()), probe(&pool, &backends[1], &check).await);
+        assert_eq!(
+            Err(String::from("responded with 503 Service Unavailable")),
+            probe(&pool, &backends[2], &check).await
+        );
+        assert!(probe(&pool, &backends[3], &check).await.is_err());
+        assert_eq!(Ok(()), probe(&pool, &backends[4], &check).await);
+    }
+
+    #[rstest]
+    // a healthy backend goes down after the failures in a row
+    #[case(true, &[false, false, true, false, false, false], &[None, None, None, None, None, Some(false)])]
+    // and comes back up after the successes in a row
+    #[case(false, &[true, false, true, true], &[None, None, None, Some(true)])]
+    fn test_tracker(
+        #[case] mut healthy: bool,
+        #[case] results: &[bool],
+        #[case] expected: &[Option<bool>],
+    ) {
+        let check = HealthCheck::default();
+        let mut tracker = Tracker::default();
+        let changes: Vec<_> = results
+            .iter()
+            .map(|&passed| {
+                let change = tracker.record(healthy, passed, &check);
+                healthy = change.unwrap_or(healthy);
+                change
+            })
+            .collect();
+        assert_eq!(expected, changes);
+    }
+}
@@ -0,0 +1,236 @@
+//! Idle connections to a backend, kept open after a response to send later requests on
+//! instead of connecting again.
+
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::Stream;
+use crate::config::KeepAlive;
+
+/// The idle connections of a backend, the most recently used last
+#[derive(Debug, Default)]
+pub(super) struct Idle {
+    connections: Mutex<VecDeque<Kept>>,
+}
+
+/// An idle connection
+#[derive(Debug)]
+struct Kept {
+    stream: Stream,
+    usage: Usage,
+    released: Instant,
+}
+
+/// What a connection is retired by
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Usage {
+    opened: Instant,
+    /// Requests sent on the connection, including the one it is used for
+    pub(super) requests: u32,
+}
+
+impl Usage {
+    pub(super) fn new(now: Instant) -> Self {
+        Self {
+            opened: now,
+            requests: 1,
+        }
+    }
+
+    /// Whether the connection has served enough requests or been open long enough to
+    /// be closed instead of used again
+    pub(super) fn is_retired(&self, keepalive: &KeepAlive, now: Instant) -> bool {
+        keepalive
+            .max_requests
+            .is_some_and(|max_requests| self.requests >= max_requests)
+            || keepalive.max_lifetime.is_some_and(|max_lifetime| {
+                now.duration_since(self.opened) >= Duration::from_secs(max_lifetime)
+            })
+    }
+}
+
+impl Idle {
+    /// Take the most recently used connection which is still usable, closing the ones
+    /// idle or open for too long along the way
+    pub(super) fn checkout(&self, keepalive: &KeepAlive, now: Instant) -> Option<(Stream, Usage)> {
+        let idle_timeout = Duration::from_secs(keepalive.idle_timeout);
+        let mut connections = self.connections.lock().unwrap();
+        // the oldest connections are the first to go stale
+        while let Some(kept) = connections.front()
+            && now.duration_since(kept.released) >= idle_timeout
+        {
+            connections.pop_front();
+        }
+        while let Some(kept) = connections.pop_back() {
+            if !kept.usage.is_retired(keepalive, now) && kept.stream.is_open() {
+                let usage = Usage {
+                    requests: kept.usage.requests + 1,
+                    ..kept.usage
+                };
+                return Some((kept.stream, usage));
+            }
+        }
+        None
+    }
+
+    /// Keep the connection for a later request, closing the oldest one when there are
+    /// already as many as allowed. Connections which are retired are closed instead.
+    pub(super) fn release(
+        &self,
+        stream: Stream,
+        usage: Usage,
+        keepalive: &KeepAlive,
+        now: Instant,
+    ) {
+        if keepalive.max_idle == 0 || usage.is_retired(keepalive, now) {
+            return;
+        }
+        let mut connections = self.connections.lock().unwrap();
+        if connections.len() >= keepalive.max_idle {
+            connections.pop_front();
+        }
+        connections.push_back(Kept {
+            stream,
+            usage,
+            released: now,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    /// A connection and the end of it the backend has
+    async fn connection(listener: &TcpListener) -> (Stream, TcpStream) {
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        (Stream::Plain(stream), accepted)
+    }
+
+    fn local_addr(stream: &Stream) -> SocketAddr {
+        let Stream::Plain(stream) = stream else {
+            unreachable!("only plain connections are kept in the tests");
+        };
+        stream.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_checkout_takes_the_latest_open_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let keepalive = KeepAlive::default();
+        let idle = Idle::default();
+        let now = Instant::now();
+
+        let (first, _first) = connection(&listener).await;
+        let (second, _second) = connection(&listener).await;
+        let (closed, accepted) = connection(&listener).await;
+        let first_addr = local_addr(&first);
+        let second_addr = local_addr(&second);
+        idle.release(first, Usage::new(now), &keepalive, now);
+        idle.release(second, Usage::new(now), &keepalive, now);
+        idle.release(closed, Usage::new(now), &keepalive, now);
+
+        // the backend closing a connection is noticed once the close arrives
+        drop(accepted);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let (stream, usage) = idle.checkout(&keepalive, now).unwrap();
+        assert_eq!(second_addr, local_addr(&stream));
+        assert_eq!(2, usage.requests);
+        let (stream, _) = idle.checkout(&keepalive, now).unwrap();
+        assert_eq!(first_addr, local_addr(&stream));
+        assert!(idle.checkout(&keepalive, now).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_checkout_skips_connections_out_of_step() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let keepalive = KeepAlive::default();
+        let idle = Idle::default();
+
+        let (stream, mut accepted) = connection(&listener).await;
+        idle.release(
+            stream,
+            Usage::new(Instant::now()),
+            &keepalive,
+            Instant::now(),
+        );
+        accepted
+            .write_all(b"HTTP/1.1 200 OK\r\n\r\n")
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(idle.checkout(&keepalive, Instant::now()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_idle_limits() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let keepalive = KeepAlive {
+            max_idle: 1,
+            idle_timeout: 10,
+            ..Default::default()
+        };
+        let idle = Idle::default();
+        let now = Instant::now();
+
+        let (first, _first) = connection(&listener).await;
+        let (second, _second) = connection(&listener).await;
+        let second_addr = local_addr(&second);
+        idle.release(first, Usage::new(now), &keepalive, now);
+        idle.release(second, Usage::new(now), &keepalive, now);
+
+        // only the latest connection is kept
+        let (stream, usage) = idle
+            .checkout(&keepalive, now + Duration::from_secs(9))
+            .unwrap();
+        assert_eq!(second_addr, local_addr(&stream));
+        assert!(idle.checkout(&keepalive, now).is_none());
+
+        // and only until it has been idle for too long
+        idle.release(stream, usage, &keepalive, now);
+        assert!(
+            idle.checkout(&keepalive, now + Duration::from_secs(10))
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retired_connections_are_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let keepalive = KeepAlive {
+            max_requests: Some(2),
+            max_lifetime: Some(60),
+            ..Default::default()
+        };
+        let idle = Idle::default();
+        let now = Instant::now();
+
+        // a connection is used for as many requests as allowed
+        let (stream, _accepted) = connection(&listener).await;
+        idle.release(stream, Usage::new(now), &keepalive, now);
+        let (stream, usage) = idle.checkout(&keepalive, now).unwrap();
+        idle.release(stream, usage, &keepalive, now);
+        assert!(idle.checkout(&keepalive, now).is_none());
+
+        // and for as long as allowed, even while idle
+        let (stream, _accepted) = connection(&listener).await;
+        idle.release(stream, Usage::new(now), &keepalive, now);
+        let later = now + Duration::from_secs(60);
+        assert!(idle.checkout(&keepalive, later).is_none());
+    }
+}
@@ -0,0 +1,122 @@
+//! Passive health checks, ejecting backends for a while after requests to them fail.
+//!
+//! A backend is ejected once enough requests to it fail in a row. Each ejection in a
+//! row lasts twice as long as the one before, and a request succeeding after the
+//! backend is readmitted starts over from the base time.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::OutlierDetection;
+
+/// The recent failures of a backend
+#[derive(Debug, Default)]
+pub(super) struct Outliers {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Requests failed in a row
+    failures: u32,
+    /// Times ejected without a request succeeding in between
+    ejections: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl Outliers {
+    pub(super) fn is_ejected(&self, now: Instant) -> bool {
+        let state = self.state.lock().unwrap();
+        state.ejected_until.is_some_and(|until| now < until)
+    }
+
+    /// Count the result of a request, returning how long the backend is ejected for if
+    /// it just was
+    pub(super) fn record(
+        &self,
+        passed: bool,
+        detection: &OutlierDetection,
+        now: Instant,
+    ) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let ejected = state.ejected_until.is_some_and(|until| now < until);
+        if passed {
+            state.failures = 0;
+            if !ejected {
+                state.ejections = 0;
+            }
+            return None;
+        }
+
+        state.failures += 1;
+        if ejected || state.failures < detection.consecutive_failures {
+            return None;
+        }
+        state.failures = 0;
+        state.ejections += 1;
+        let doublings = (state.ejections - 1).min(31);
+        let time = Duration::from_secs(
+            detection
+                .base_ejection_time
+                .saturating_mul(1 << doublings)
+                .min(detection.max_ejection_time),
+        );
+        state.ejected_until = Some(now + time);
+        Some(time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection() -> OutlierDetection {
+        OutlierDetection {
+            consecutive_failures: 2,
+            base_ejection_time: 10,
+            max_ejection_time: 30,
+        }
+    }
+
+    #[test]
+    fn test_ejects_after_failures_in_a_row() {
+        let outliers = Outliers::default();
+        let detection = detection();
+        let now = Instant::now();
+
+        assert_eq!(None, outliers.record(false, &detection, now));
+        assert_eq!(None, outliers.record(true, &detection, now));
+        assert_eq!(None, outliers.record(false, &detection, now));
+        assert!(!outliers.is_ejected(now));
+        assert_eq!(
+            Some(Duration::from_secs(10)),
+            outliers.record(false, &detection, now)
+        );
+        assert!(outliers.is_ejected(now + Duration::from_secs(9)));
+        assert!(!outliers.is_ejected(now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_ejections_in_a_row_back_off() {
+        let outliers = Outliers::default();
+        let detection = detection();
+        let mut now = Instant::now();
+
+        for expected in [10, 20, 30, 30] {
+            outliers.record(false, &detection, now);
+            let time = outliers.record(false, &detection, now).unwrap();
+            assert_eq!(Duration::from_secs(expected), time);
+            now += time;
+        }
+
+        // a request succeeding after the backend is readmitted starts over
+        outliers.record(true, &detection, now);
+        outliers.record(false, &detection, now);
+        assert_eq!(
+            Some(Duration::from_secs(10)),
+            outliers.record(false, &detection, now)
+        );
+    }
+}
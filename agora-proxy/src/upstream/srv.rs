@@ -0,0 +1,184 @@
+//! Discovering the backends of a pool from DNS SRV records, like the ones Consul DNS or
+//! Kubernetes headless services serve.
+//!
+//! An address like `srv://_api._tcp.example.internal` stands for the targets of the SRV
+//! records of the name, at the ports they give, weighted like the records are. Only the
+//! records with the lowest priority are used, the others being there for when those
+//! are gone. The records are looked up again once their TTL runs out, and when a lookup
+//! fails the backends found last are kept until one succeeds.
+
+use std::{
+    io,
+    sync::Weak,
+    time::{Duration, Instant},
+};
+
+use hickory_resolver::proto::rr::rdata::SRV;
+use tokio::time::sleep_until;
+use tracing::warn;
+
+use super::{Pool, dns, file::MAX_WEIGHT};
+
+/// The scheme of the addresses standing for SRV records
+pub(super) const SCHEME: &str = "srv://";
+
+/// How long to wait before looking up the names again after a lookup failed
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// The least time between lookups, for records with a TTL of zero
+const MIN_REFRESH: Duration = Duration::from_secs(1);
+
+/// Look up the SRV names of the pool until it is dropped, which happens once the config
+/// it is part of is replaced and its last request is done
+pub(super) fn spawn(pool: Weak<Pool>) {
+    tokio::spawn(async move {
+        loop {
+            let Some(pool) = pool.upgrade() else {
+                return;
+            };
+            let next = match lookup(&pool.srv).await {
+                Ok((addrs, valid_until)) => {
+                    pool.set_discovered(addrs);
+                    valid_until.max(Instant::now() + MIN_REFRESH)
+                }
+                Err(e) => {
+                    warn!("Failed to look up {}: {e}", pool.srv.join(", "));
+                    Instant::now() + RETRY_AFTER
+                }
+            };
+            pool.discovered.send_replace(true);
+            drop(pool);
+            sleep_until(next.into()).await;
+        }
+    });
+}
+
+/// The addresses of the targets of the SRV records of the names with their weights, and
+/// when the first of the records expires
+async fn lookup(names: &[String]) -> io::Result<(Vec<(String, u32)>, Instant)> {
+    let resolver = dns::resolver().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "no DNS config to look up SRV records with",
+        )
+    })?;
+    let mut addrs = Vec::new();
+    let mut valid_until = None::<Instant>;
+    for name in names {
+        let lookup = resolver
+            .srv_lookup(name.as_str())
+            .await
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        addrs.extend(targets(lookup.iter()));
+        let until = lookup.as_lookup().valid_until();
+        valid_until = Some(valid_until.map_or(until, |valid_until| valid_until.min(until)));
+    }
+    addrs.sort_unstable();
+    addrs.dedup_by(|(addr, _), (kept, _)| addr == kept);
+    Ok((addrs, valid_until.unwrap_or_else(Instant::now)))
+}
+
+/// The addresses of the targets of the records with the lowest priority, with their
+/// weights. A target of `.` means the service isn't available at the name. A weight of
+/// 0 counts as 1, so those targets get a small share of the requests next to weighted
+/// ones, and an even share when none are weighted (RFC 2782). Weights above
+/// [`MAX_WEIGHT`] are scaled down with the others, keeping their proportions.
+fn targets<'a>(records: impl Iterator<Item = &'a SRV>) -> Vec<(String, u32)> {
+    let records: Vec<_> = records
+        .filter(|record| !record.target().is_root())
+        .collect();
+    let Some(priority) = records.iter().map(|record| record.priority()).min() else {
+        return Vec::new();
+    };
+    let records: Vec<_> = records
+        .into_iter()
+        .filter(|record| record.priority() == priority)
+        .collect();
+    let max_weight = records
+        .iter()
+        .map(|record| u32::from(record.weight()))
+        .max()
+        .unwrap_or_default();
+    records
+        .into_iter()
+        .map(|record| {
+            let target = record.target().to_utf8();
+            let addr = format!("{}:{}", target.trim_end_matches('.'), record.port());
+            (addr, scale(u32::from(record.weight()), max_weight))
+        })
+        .collect()
+}
+
+/// The weight of a record for the pool, which takes weights of up to [`MAX_WEIGHT`]
+/// while SRV records can have up to 65535
+fn scale(weight: u32, max_weight: u32) -> u32 {
+    let weight = if max_weight > MAX_WEIGHT {
+        (weight * MAX_WEIGHT + max_weight / 2) / max_weight
+    } else {
+        weight
+    };
+    weight.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_resolver::Name;
+
+    use super::*;
+
+    fn record(priority: u16, target: &str, port: u16) -> SRV {
+        weighted(priority, 10, target, port)
+    }
+
+    fn weighted(priority: u16, weight: u16, target: &str, port: u16) -> SRV {
+        SRV::new(priority, weight, port, Name::from_ascii(target).unwrap())
+    }
+
+    #[test]
+    fn test_targets() {
+        let records = [
+            record(10, "a.example.internal.", 8080),
+            record(20, "backup.example.internal.", 8080),
+            record(10, "b.example.internal.", 9090),
+        ];
+        assert_eq!(
+            vec![
+                (String::from("a.example.internal:8080"), 10),
+                (String::from("b.example.internal:9090"), 10)
+            ],
+            targets(records.iter())
+        );
+        assert!(targets([record(0, ".", 0)].iter()).is_empty());
+    }
+
+    #[test]
+    fn test_targets_weights() {
+        let records = [
+            weighted(10, 60, "a.example.internal.", 8080),
+            weighted(10, 20, "b.example.internal.", 8080),
+            weighted(10, 0, "c.example.internal.", 8080),
+        ];
+        assert_eq!(
+            vec![
+                (String::from("a.example.internal:8080"), 60),
+                (String::from("b.example.internal:8080"), 20),
+                (String::from("c.example.internal:8080"), 1)
+            ],
+            targets(records.iter())
+        );
+
+        let records = [
+            weighted(10, 65535, "a.example.internal.", 8080),
+            weighted(10, 32768, "b.example.internal.", 8080),
+            weighted(10, 1, "c.example.internal.", 8080),
+        ];
+        assert_eq!(
+            vec![
+                (String::from("a.example.internal:8080"), MAX_WEIGHT),
+                (String::from("b.example.internal:8080"), 50),
+                (String::from("c.example.internal:8080"), 1)
+            ],
+            targets(records.iter())
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! Connections to backends through a parent proxy, for networks where traffic leaving
+//! them has to go through one.
+
+use std::{io, net::IpAddr};
+
+use agora_http_parser::{Response, is_terminated_from};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::config::{ParentProxy, ProxyServer};
+
+/// The most of the response of a proxy read to find its status
+const MAX_HEAD_SIZE: usize = 8192;
+
+/// The version byte starting SOCKS5 messages
+const SOCKS_VERSION: u8 = 5;
+
+/// The SOCKS5 authentication methods, from RFC 1928 and RFC 1929
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+
+/// Open a connection to the host and port through the proxy
+pub(super) async fn connect(proxy: &ParentProxy, authority: &str) -> io::Result<TcpStream> {
+    match proxy {
+        ParentProxy::Http(server) => http_connect(server, authority).await,
+        ParentProxy::Socks5(server) => socks5_connect(server, authority).await,
+    }
+}
+
+/// Ask an HTTP proxy to open a tunnel to the host and port with `CONNECT`
+async fn http_connect(server: &ProxyServer, authority: &str) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(&server.addr).await?;
+    let mut request = format!("CONNECT {authority} HTTP/1.1\r\nhost: {authority}\r\n");
+    if let Some(username) = &server.username {
+        let credentials = format!(
+            "{username}:{}",
+            server.password.as_deref().unwrap_or_default()
+        );
+        request.push_str(&format!(
+            "proxy-authorization: Basic {}\r\n",
+            BASE64_STANDARD.encode(credentials)
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // nothing follows the head until a request is sent through the tunnel, so reading
+    // past it never takes bytes of the backend
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    let mut scanned = 0;
+    while !is_terminated_from(&head, scanned) {
+        scanned = head.len();
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || head.len() + n > MAX_HEAD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "incomplete response from the proxy",
+            ));
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let (response, rest) = Response::parse(&head)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(io::Error::other(format!(
+            "proxy {} responded with {}",
+            server.addr,
+            response.status()
+        )));
+    }
+    if !rest.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "proxy sent data before the backend",
+        ));
+    }
+    Ok(stream)
+}
+
+/// Ask a SOCKS5 proxy to connect to the host and port, sending host names as they are
+/// for the proxy to resolve
+async fn socks5_connect(server: &ProxyServer, authority: &str) -> io::Result<TcpStream> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+    let (host, port) = authority
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        .ok_or_else(|| invalid("the address has no port"))?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    let mut request = vec![SOCKS_VERSION, 1, 0];
+    match host.parse() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len()).map_err(|_| invalid("the host name is too long"))?;
+            request.extend_from_slice(&[3, len]);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+
+    let mut stream = TcpStream::connect(&server.addr).await?;
+    let method = match server.username {
+        Some(_) => USERNAME_PASSWORD,
+        None => NO_AUTHENTICATION,
+    };
+    stream.write_all(&[SOCKS_VERSION, 1, method]).await?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(invalid("not a SOCKS5 proxy"));
+    }
+    if reply[1] != method {
+        return Err(io::Error::other(format!(
+            "proxy {} accepts none of the authentication methods offered",
+            server.addr
+        )));
+    }
+    if let Some(username) = &server.username {
+        // the lengths were checked along with the config
+        let password = server.password.as_deref().unwrap_or_default();
+        let mut credentials = vec![1, username.len() as u8];
+        credentials.extend_from_slice(username.as_bytes());
+        credentials.push(password.len() as u8);
+        credentials.extend_from_slice(password.as_bytes());
+        stream.write_all(&credentials).await?;
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(io::Error::other(format!(
+                "proxy {} rejected the credentials",
+                server.addr
+            )));
+        }
+    }
+
+    stream.write_all(&request).await?;
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(invalid("not a SOCKS5 proxy"));
+    }
+    if reply[1] != 0 {
+        let reason = match reply[1] {
+            2 => "connection not allowed by ruleset",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            7 => "command not supported",
+            8 => "address type not supported",
+            _ => "general failure",
+        };
+        return Err(io::Error::other(format!(
+            "proxy {} failed to connect: {reason}",
+            server.addr
+        )));
+    }
+    // the address the proxy connected from, which isn't needed
+    let bound_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => usize::from(stream.read_u8().await?),
+        _ => return Err(invalid("invalid address type in the reply of the proxy")),
+    };
+    let mut bound = vec![0; bound_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// A proxy responding with the head and echoing what comes after, sending back the
+    /// request it got
+    async fn proxy(response: &'static [u8]) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = [0; 1024];
+            let n = stream.read(&mut received).await.unwrap();
+            stream.write_all(response).await.unwrap();
+            let mut echoed = [0; 1024];
+            if let Ok(echo @ 1..) = stream.read(&mut echoed).await {
+                stream.write_all(&echoed[..echo]).await.unwrap();
+            }
+            String::from_utf8_lossy(&received[..n]).into_owned()
+        });
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn test_http_connect() {
+        let (addr, request) = proxy(b"HTTP/1.1 200 Connection established\r\n\r\n").await;
+        let server = ProxyServer {
+            addr,
+            username: Some(String::from("agora")),
+            password: Some(String::from("secret")),
+        };
+
+        let mut stream = connect(&ParentProxy::Http(server), "api.internal:443")
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut echoed = [0; 5];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(b"hello", &echoed);
+
+        assert_eq!(
+            "CONNECT api.internal:443 HTTP/1.1\r\nhost: api.internal:443\r\n\
+             proxy-authorization: Basic YWdvcmE6c2VjcmV0\r\n\r\n",
+            request.await.unwrap()
+        );
+    }
+
+    /// A SOCKS5 proxy sending the replies after reading as much of the handshake as
+    /// they answer, then echoing what comes after. Sends back the handshake it got.
+    async fn socks5_proxy(
+        replies: &'static [(usize, &'static [u8])],
+    ) -> (String, tokio::task::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            for (len, reply) in replies {
+                let mut message = vec![0; *len];
+                stream.read_exact(&mut message).await.unwrap();
+                received.extend(message);
+                stream.write_all(reply).await.unwrap();
+            }
+            let mut echoed = [0; 1024];
+            if let Ok(echo @ 1..) = stream.read(&mut echoed).await {
+                stream.write_all(&echoed[..echo]).await.unwrap();
+            }
+            received
+        });
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect() {
+        let (addr, handshake) = socks5_proxy(&[
+            (3, &[5, 2]),
+            (14, &[1, 0]),
+            (19, &[5, 0, 0, 1, 127, 0, 0, 1, 0x1f, 0x90]),
+        ])
+        .await;
+        let server = ProxyServer {
+            addr,
+            username: Some(String::from("agora")),
+            password: Some(String::from("secret")),
+        };
+
+        let mut stream = connect(&ParentProxy::Socks5(server), "api.internal:443")
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut echoed = [0; 5];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(b"hello", &echoed);
+
+        let mut expected = vec![5, 1, 2];
+        expected.extend(b"\x01\x05agora\x06secret");
+        expected.extend(b"\x05\x01\x00\x03\x0capi.internal\x01\xbb");
+        assert_eq!(expected, handshake.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect_refused() {
+        let (addr, handshake) = socks5_proxy(&[(3, &[5, 0]), (10, &[5, 5, 0, 1])]).await;
+        let server = ProxyServer {
+            addr: addr.clone(),
+            username: None,
+            password: None,
+        };
+
+        let e = connect(&ParentProxy::Socks5(server), "10.0.0.1:4000")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            format!("proxy {addr} failed to connect: connection refused"),
+            e.to_string()
+        );
+        assert_eq!(
+            vec![5, 1, 0, 5, 1, 0, 1, 10, 0, 0, 1, 0x0f, 0xa0],
+            handshake.await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_connect_refused() {
+        let (addr, _) = proxy(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await;
+        let server = ProxyServer {
+            addr: addr.clone(),
+            username: None,
+            password: None,
+        };
+
+        let e = connect(&ParentProxy::Http(server), "api.internal:443")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            format!("proxy {addr} responded with 407 Proxy Authentication Required"),
+            e.to_string()
+        );
+    }
+}
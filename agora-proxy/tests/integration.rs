@@ -1,9 +1,40 @@
-use agora_http_parser::{Request, Response};
-use agora_proxy::server::{ProxyEntry, Server, ServerConfig};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use agora_http_parser::{
+    HTTPMethod, HTTPVersion, Headers, Request, Response,
+    chunked::chunked_body_len,
+    h2::{CONNECTION_PREFACE, ErrorCode, Frame, FrameError, MAX_ALLOWED_FRAME_SIZE, SettingId},
+    hpack::{Decoder, Encoder},
+};
+use agora_proxy::{
+    config::{
+        Addrs, Alpn, ClientAuth, ExpectContinue, ForwardProxy, HealthCheck, HttpsRedirect,
+        Listener, ListenerTls, OutlierDetection, ProxyEntry, Retry, RetryBudget, Route,
+        ServerConfig, TlsPassthrough, TlsVersion, Upstream, UpstreamTls,
+    },
+    proxy_protocol::{self, Header},
+    routing::PathPattern,
+    server::Server,
+};
+use http::StatusCode;
+use rustls::{
+    pki_types::{PrivateKeyDer, pem::PemObject},
+    server::WebPkiClientVerifier,
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpSocket},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpSocket, TcpStream},
 };
+use tokio_rustls::{TlsAcceptor, TlsConnector};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
 async fn test_reverse_proxy_transfer() {
@@ -21,8 +52,9 @@ async fn test_reverse_proxy_transfer() {
         let mut received = [0; 1024];
         let bytes_read = stream.read(&mut received).await.unwrap();
         let expected = &format!(
-            "GET / HTTP/1.1\r\nx-forwarded-for: {}\r\n\r\nHello World",
-            client_addr
+            "GET / HTTP/1.1\r\ncontent-length: 11\r\nvia: 1.1 agora\r\nx-forwarded-for: {}\r\n\
+             x-forwarded-proto: http\r\nx-forwarded-port: 8080\r\n\r\nHello World",
+            client_addr.ip()
         )
         .into_bytes();
         let expected_request = Request::parse(expected);
@@ -41,20 +73,22 @@ async fn test_reverse_proxy_transfer() {
     let proxy_addr = "127.0.0.1:8080";
     let proxy = tokio::spawn(async move {
         let mut config = ServerConfig::default();
-        config.reverse_proxy_mapping.insert(
-            String::from("/"),
+        config.routes.push(Route::new(
+            PathPattern::prefix("/"),
             ProxyEntry {
-                addr: server_addr.to_string(),
+                addr: server_addr.to_string().into(),
                 strip_prefix: false,
+                ..Default::default()
             },
-        );
+        ));
         let server = Server::new(config);
 
         server.listen(proxy_addr).await.unwrap();
     });
 
     let client = tokio::spawn(async move {
-        let request = b"GET / HTTP/1.1\r\n\r\nHello World";
+        let request =
+            b"GET / HTTP/1.1\r\nconnection: close\r\ncontent-length: 11\r\n\r\nHello World";
 
         let mut stream = client_socket
             .connect(proxy_addr.parse().unwrap())
@@ -65,8 +99,10 @@ async fn test_reverse_proxy_transfer() {
         let mut received = [0; 1024];
         let bytes_read = stream.read(&mut received).await.unwrap();
 
+        let (mut expected, _) = Response::parse(response).unwrap();
+        expected.append_via("agora");
         assert_eq!(
-            Response::parse(response),
+            Ok((expected, b"Test Success".as_slice())),
             Response::parse(&received[..bytes_read]),
             "response does not match expected"
         );
@@ -78,3 +114,2996 @@ async fn test_reverse_proxy_transfer() {
     client.await.unwrap();
     proxy.abort();
 }
+
+#[tokio::test]
+async fn test_reverse_proxy_interim_response() {
+    let response = b"HTTP/1.1 100 Continue\r\n\r\n\
+        HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 12\r\n\r\nTest Success";
+
+    let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let server_handle = tokio::spawn(async move {
+        let (mut stream, _) = server.accept().await.unwrap();
+        let mut received = [0; 1024];
+        let _ = stream.read(&mut received).await.unwrap();
+        stream.write_all(response).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let mut config = ServerConfig::default();
+        config.routes.push(Route::new(
+            PathPattern::prefix("/"),
+            ProxyEntry {
+                addr: server_addr.to_string().into(),
+                strip_prefix: false,
+                ..Default::default()
+            },
+        ));
+        Server::new(config).serve(listener).await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(b"POST / HTTP/1.1\r\nexpect: 100-continue\r\ncontent-length: 5\r\n\r\nHello")
+        .await
+        .unwrap();
+    // sending nothing more ends the connection after the response
+    stream.shutdown().await.unwrap();
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+
+    let (interim, final_response, body) = Response::parse_final(&received).unwrap();
+    let (_, mut expected, expected_body) = Response::parse_final(response).unwrap();
+    // the connection of the upstream is closed, not the one of the client
+    expected.get_headers_mut().remove("connection");
+    expected.append_via("agora");
+    assert_eq!(vec![Response::continue_()], interim);
+    assert_eq!(expected, final_response);
+    assert_eq!(expected_body, body);
+
+    server_handle.await.unwrap();
+    proxy.abort();
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_strips_hop_by_hop() {
+    let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let server_handle = tokio::spawn(async move {
+        let (mut stream, _) = server.accept().await.unwrap();
+        let mut received = [0; 1024];
+        let bytes_read = stream.read(&mut received).await.unwrap();
+        let (request, _) = Request::parse(&received[..bytes_read]).unwrap();
+        assert_eq!(None, request.headers.get("connection"));
+        assert_eq!(None, request.headers.get("x-secret"));
+        assert_eq!(None, request.headers.get("keep-alive"));
+        assert!(request.headers.contains_key("x-forwarded-for"));
+        assert!(request.headers.contains_key("x-kept"));
+
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nkeep-alive: timeout=5\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let mut config = ServerConfig::default();
+        config.routes.push(Route::new(
+            PathPattern::prefix("/"),
+            ProxyEntry {
+                addr: server_addr.to_string().into(),
+                strip_prefix: false,
+                ..Default::default()
+            },
+        ));
+        Server::new(config).serve(listener).await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nconnection: keep-alive, x-secret\r\nx-secret: 1\r\nkeep-alive: timeout=5\r\nx-kept: 1\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(None, response.get_header("keep-alive"));
+    assert_eq!(None, response.get_header("connection"));
+
+    server_handle.await.unwrap();
+    proxy.abort();
+}
+
+/// Start an upstream answering every connection with the response
+async fn spawn_upstream(response: &'static [u8]) -> SocketAddr {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut received = [0; 1024];
+                let _ = stream.read(&mut received).await.unwrap();
+                stream.write_all(response).await.unwrap();
+                stream.shutdown().await.unwrap();
+            });
+        }
+    });
+    upstream_addr
+}
+
+/// Start a proxy with the config, returning its address
+async fn spawn_proxy(config: ServerConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { Server::new(config).serve(listener).await.unwrap() });
+    proxy_addr
+}
+
+/// Send a request through the proxy and read everything it sends back, until the proxy
+/// closes the connection once it sees no more requests are coming
+async fn send(proxy_addr: SocketAddr, request: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    received
+}
+
+fn route(prefix: &str, addr: SocketAddr) -> Route {
+    Route::new(
+        PathPattern::prefix(prefix),
+        ProxyEntry {
+            addr: addr.to_string().into(),
+            strip_prefix: false,
+            ..Default::default()
+        },
+    )
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_virtual_hosts() {
+    let exact = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nexact").await;
+    let wildcard = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 8\r\n\r\nwildcard").await;
+    let fallback = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 8\r\n\r\nfallback").await;
+
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", fallback));
+    config
+        .virtual_hosts
+        .insert(String::from("api.example.com"), vec![route("/", exact)]);
+    config
+        .virtual_hosts
+        .insert(String::from("*.example.com"), vec![route("/", wildcard)]);
+    config.virtual_hosts.insert(
+        String::from("static.example.com"),
+        vec![route("/assets", exact)],
+    );
+    let proxy_addr = spawn_proxy(config).await;
+
+    for (host, expected) in [
+        ("api.example.com", b"exact".as_slice()),
+        ("API.Example.com:8080", b"exact"),
+        ("www.example.com", b"wildcard"),
+        ("a.b.example.com", b"wildcard"),
+        ("example.com", b"fallback"),
+        ("other.org", b"fallback"),
+    ] {
+        let request = format!("GET / HTTP/1.1\r\nhost: {host}\r\n\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status(), "{host}");
+        assert_eq!(expected, body, "{host}");
+    }
+
+    // a virtual host without a matching route doesn't fall back to the default mapping
+    let received = send(
+        proxy_addr,
+        b"GET /index.html HTTP/1.1\r\nhost: static.example.com\r\n\r\n",
+    )
+    .await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_regex_route() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut received = [0; 1024];
+        let bytes_read = stream.read(&mut received).await.unwrap();
+        let (request, _) = Request::parse(&received[..bytes_read]).unwrap();
+        assert_eq!("/posts", request.path);
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let config: ServerConfig = serde_json::from_str(&format!(
+        r#"{{
+            "routes": {{
+                "~^/users/(?<id>[0-9]+)": {{ "addr": "{upstream_addr}", "strip_prefix": true }}
+            }}
+        }}"#
+    ))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+
+    let received = send(proxy_addr, b"GET /users/me HTTP/1.1\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+    let received = send(proxy_addr, b"GET /users/42/posts HTTP/1.1\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    upstream_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_predicates() {
+    let tenant = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 6\r\n\r\ntenant").await;
+    let debug = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\ndebug").await;
+    let fallback = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 8\r\n\r\nfallback").await;
+
+    let config: ServerConfig = serde_json::from_str(&format!(
+        r#"{{
+            "routes": [
+                {{
+                    "path": "/api/v2",
+                    "addr": "{tenant}",
+                    "strip_prefix": false,
+                    "headers": [{{ "name": "x-tenant", "equals": "acme" }}]
+                }},
+                {{
+                    "path": "~^/api",
+                    "addr": "{debug}",
+                    "strip_prefix": false,
+                    "query": [{{ "name": "debug", "exists": true }}]
+                }},
+                {{ "path": "/api", "addr": "{fallback}", "strip_prefix": false }}
+            ]
+        }}"#
+    ))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+
+    for (request, expected) in [
+        (
+            "GET /api/v2/users HTTP/1.1\r\nx-tenant: acme\r\n\r\n",
+            b"tenant".as_slice(),
+        ),
+        (
+            "GET /api/v2/users HTTP/1.1\r\nx-tenant: other\r\n\r\n",
+            b"fallback",
+        ),
+        ("GET /api/v2/users?debug HTTP/1.1\r\n\r\n", b"debug"),
+        ("GET /api/v1?a=1&debug=1 HTTP/1.1\r\n\r\n", b"debug"),
+        ("GET /api/v1?a=1 HTTP/1.1\r\n\r\n", b"fallback"),
+    ] {
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status(), "{request}");
+        assert_eq!(expected, body, "{request}");
+    }
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_route_priority() {
+    let low = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 3\r\n\r\nlow").await;
+    let high = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nhigh").await;
+
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/api/v2", low));
+    config.routes.push(Route {
+        priority: 1,
+        ..route("/api", high)
+    });
+    let proxy_addr = spawn_proxy(config).await;
+
+    // the higher priority wins over the longer prefix
+    let received = send(proxy_addr, b"GET /api/v2/users HTTP/1.1\r\n\r\n").await;
+    let (_, body) = Response::parse(&received).unwrap();
+    assert_eq!(b"high", body);
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_rewrite() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut received = [0; 1024];
+        let bytes_read = stream.read(&mut received).await.unwrap();
+        let (request, _) = Request::parse(&received[..bytes_read]).unwrap();
+        assert_eq!("/api/users/v1", request.path);
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let config: ServerConfig = serde_json::from_str(&format!(
+        r#"{{
+            "routes": [
+                {{
+                    "path": "/v1",
+                    "addr": "{upstream_addr}",
+                    "strip_prefix": false,
+                    "rewrite": {{ "pattern": "^/v1/(.*)", "replacement": "/api/$1" }}
+                }}
+            ]
+        }}"#
+    ))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+
+    let received = send(proxy_addr, b"GET /v1/users/v1 HTTP/1.1\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    upstream_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_host_rewrite() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        for expected_host in [upstream_addr.to_string(), String::from("backend.internal")] {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut received = [0; 1024];
+            let bytes_read = stream.read(&mut received).await.unwrap();
+            let (request, _) = Request::parse(&received[..bytes_read]).unwrap();
+            assert_eq!(
+                Some(expected_host.as_str()),
+                request
+                    .headers
+                    .get("host")
+                    .map(|host| host.to_str().unwrap())
+            );
+            assert_eq!(
+                Some("example.com"),
+                request
+                    .headers
+                    .get("x-forwarded-host")
+                    .map(|host| host.to_str().unwrap())
+            );
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let config: ServerConfig = serde_json::from_str(&format!(
+        r#"{{
+            "routes": [
+                {{ "path": "/a", "addr": "{upstream_addr}", "strip_prefix": false, "host": "upstream" }},
+                {{
+                    "path": "/b",
+                    "addr": "{upstream_addr}",
+                    "strip_prefix": false,
+                    "host": {{ "value": "backend.internal" }}
+                }}
+            ]
+        }}"#
+    ))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+
+    for path in ["/a", "/b"] {
+        let request = format!("GET {path} HTTP/1.1\r\nhost: example.com\r\n\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, _) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    upstream_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_header_transforms() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut received = [0; 1024];
+        let bytes_read = stream.read(&mut received).await.unwrap();
+        let (request, _) = Request::parse(&received[..bytes_read]).unwrap();
+        assert_eq!(None, request.headers.get("authorization"));
+        assert_eq!(
+            Some("internal example.com"),
+            request
+                .headers
+                .get("x-token")
+                .map(|token| token.to_str().unwrap())
+        );
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nserver: backend\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let config: ServerConfig = serde_json::from_str(&format!(
+        r#"{{
+            "routes": [
+                {{
+                    "path": "/",
+                    "addr": "{upstream_addr}",
+                    "strip_prefix": false,
+                    "request_headers": {{
+                        "remove": ["authorization"],
+                        "set": {{ "x-token": "internal $host" }}
+                    }},
+                    "response_headers": {{ "remove": ["server"] }}
+                }}
+            ]
+        }}"#
+    ))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+
+    let received = send(
+        proxy_addr,
+        b"GET / HTTP/1.1\r\nhost: example.com\r\nauthorization: Basic YTpi\r\n\r\n",
+    )
+    .await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(None, response.get_header("server"));
+
+    upstream_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_mirror() {
+    let primary = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 7\r\n\r\nprimary").await;
+
+    let mirror = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mirror_addr = mirror.local_addr().unwrap();
+    let mirror_handle = tokio::spawn(async move {
+        let (mut stream, _) = mirror.accept().await.unwrap();
+        let mut received = [0; 1024];
+        let bytes_read = stream.read(&mut received).await.unwrap();
+        let (request, body) = Request::parse(&received[..bytes_read]).unwrap();
+        assert_eq!("/users", request.path);
+        assert_eq!(b"hello", body);
+        stream
+            .write_all(b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let mut config = ServerConfig::default();
+    config.routes.push(Route {
+        entry: ProxyEntry {
+            mirror: Some(mirror_addr.to_string()),
+            ..route("/", primary).entry
+        },
+        ..route("/", primary)
+    });
+    let proxy_addr = spawn_proxy(config).await;
+
+    let received = send(
+        proxy_addr,
+        b"POST /users HTTP/1.1\r\ncontent-length: 5\r\n\r\nhello",
+    )
+    .await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"primary", body);
+
+    mirror_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_static_files() {
+    let root = std::env::temp_dir().join(format!("agora-integration-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("assets")).unwrap();
+    std::fs::write(root.join("index.html"), "<h1>app</h1>").unwrap();
+    std::fs::write(root.join("assets/app.js"), "app()").unwrap();
+
+    let config: ServerConfig = serde_json::from_value(serde_json::json!({
+        "routes": [{
+            "path": "/",
+            "strip_prefix": false,
+            "static_files": { "root": root, "index": "index.html", "fallback": "index.html" }
+        }]
+    }))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+
+    for (path, content_type, expected) in [
+        ("/", "text/html; charset=utf-8", b"<h1>app</h1>".as_slice()),
+        ("/assets/app.js", "text/javascript; charset=utf-8", b"app()"),
+        ("/users/42", "text/html; charset=utf-8", b"<h1>app</h1>"),
+    ] {
+        let request = format!("GET {path} HTTP/1.1\r\n\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status(), "{path}");
+        assert_eq!(
+            Some(content_type),
+            response
+                .get_header("content-type")
+                .map(|value| value.to_str().unwrap()),
+            "{path}"
+        );
+        assert_eq!(expected, body, "{path}");
+    }
+
+    let received = send(proxy_addr, b"DELETE / HTTP/1.1\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::METHOD_NOT_ALLOWED, response.status());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_static_files_precompressed() {
+    let root = std::env::temp_dir().join(format!("agora-precompressed-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("assets")).unwrap();
+    std::fs::write(root.join("assets/app.js"), "plain").unwrap();
+    std::fs::write(root.join("assets/app.js.gz"), "gzip").unwrap();
+    std::fs::write(root.join("assets/app.js.br"), "br").unwrap();
+
+    let config: ServerConfig = serde_json::from_value(serde_json::json!({
+        "routes": [{
+            "path": "/",
+            "strip_prefix": false,
+            "static_files": { "root": root, "directory_listing": true }
+        }]
+    }))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+
+    for (accept_encoding, content_encoding, expected) in [
+        ("", None, b"plain".as_slice()),
+        ("accept-encoding: gzip\r\n", Some("gzip"), b"gzip"),
+        ("accept-encoding: gzip, br\r\n", Some("br"), b"br"),
+        ("accept-encoding: br;q=0.5, gzip\r\n", Some("gzip"), b"gzip"),
+    ] {
+        let request = format!("GET /assets/app.js HTTP/1.1\r\n{accept_encoding}\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            content_encoding,
+            response
+                .get_header("content-encoding")
+                .map(|value| value.to_str().unwrap())
+        );
+        assert_eq!(
+            Some("text/javascript; charset=utf-8"),
+            response
+                .get_header("content-type")
+                .map(|value| value.to_str().unwrap())
+        );
+        assert!(response.get_header("vary").is_some());
+        assert_eq!(expected, body);
+    }
+
+    let received = send(proxy_addr, b"GET /assets/ HTTP/1.1\r\n\r\n").await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    let body = str::from_utf8(body).unwrap();
+    assert!(body.contains("<a href=\"app.js\">app.js</a>"));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_maintenance() {
+    // nothing listens on the upstream, so any attempt to proxy would be a 502
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    drop(upstream);
+
+    let config: ServerConfig = serde_json::from_str(&format!(
+        r#"{{
+            "routes": [
+                {{
+                    "path": "/",
+                    "addr": "{upstream_addr}",
+                    "strip_prefix": false,
+                    "maintenance": {{ "retry_after": 120, "message": "maintenance" }}
+                }}
+            ]
+        }}"#
+    ))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+
+    let received = send(proxy_addr, b"GET / HTTP/1.1\r\n\r\n").await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+    assert_eq!(
+        Some("120"),
+        response
+            .get_header("retry-after")
+            .map(|value| value.to_str().unwrap())
+    );
+    assert_eq!(b"maintenance", body);
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_maintenance_file() {
+    let upstream_addr = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok").await;
+    let file = std::env::temp_dir().join(format!("agora-maintenance-{}", std::process::id()));
+    let config: ServerConfig = serde_json::from_value(serde_json::json!({
+        "routes": [{
+            "path": "/",
+            "addr": upstream_addr.to_string(),
+            "strip_prefix": false,
+            "maintenance": { "file": file }
+        }]
+    }))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+    let status = || async {
+        let received = send(proxy_addr, b"GET / HTTP/1.1\r\nconnection: close\r\n\r\n").await;
+        Response::parse(&received).unwrap().0.status()
+    };
+
+    // the route is taken down and brought back up without reloading the config
+    assert_eq!(StatusCode::OK, status().await);
+    std::fs::write(&file, "").unwrap();
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status().await);
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(StatusCode::OK, status().await);
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_reload() {
+    let before = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 6\r\n\r\nbefore").await;
+    let after = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nafter").await;
+
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", before));
+    let server = Arc::new(Server::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let serving = server.clone();
+    tokio::spawn(async move { serving.serve(listener).await.unwrap() });
+
+    let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let received = send(proxy_addr, request).await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"before", body);
+
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", after));
+    config.routes.push(route("/new", after));
+    server.reload(config);
+
+    for path in ["/", "/new"] {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(b"after", body);
+    }
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_pool() {
+    let upstream = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok").await;
+    let down = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let mut config = ServerConfig::default();
+    config.upstreams.insert(
+        String::from("app"),
+        Upstream {
+            addr: Addrs(vec![down.to_string(), upstream.to_string()]),
+            ..Default::default()
+        },
+    );
+    config.routes.push(Route::new(
+        PathPattern::prefix("/"),
+        ProxyEntry {
+            upstream: Some(String::from("app")),
+            strip_prefix: false,
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+
+    // the backend that is down is skipped
+    let received = send(proxy_addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"ok", body);
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_round_robin() {
+    let a = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 1\r\n\r\na").await;
+    let b = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 1\r\n\r\nb").await;
+
+    let mut config = ServerConfig::default();
+    config.routes.push(Route::new(
+        PathPattern::prefix("/"),
+        ProxyEntry {
+            addr: Addrs(vec![a.to_string(), b.to_string()]),
+            strip_prefix: false,
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let mut bodies = Vec::new();
+    for _ in 0..4 {
+        let received = send(proxy_addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        bodies.push(String::from_utf8(body.to_vec()).unwrap());
+    }
+    assert_eq!(vec!["a", "b", "a", "b"], bodies);
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_health_checks() {
+    let down =
+        spawn_upstream(b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n").await;
+    let up = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nup").await;
+
+    let mut config = ServerConfig::default();
+    config.upstreams.insert(
+        String::from("app"),
+        Upstream {
+            addr: Addrs(vec![down.to_string(), up.to_string()]),
+            health_check: Some(HealthCheck {
+                unhealthy_threshold: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+    config.routes.push(Route::new(
+        PathPattern::prefix("/"),
+        ProxyEntry {
+            upstream: Some(String::from("app")),
+            strip_prefix: false,
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+    // the first checks run right away
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    for _ in 0..4 {
+        let received = send(proxy_addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(b"up", body);
+    }
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_outlier_detection() {
+    let failing =
+        spawn_upstream(b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n").await;
+    let working = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok").await;
+
+    let mut config = ServerConfig::default();
+    config.upstreams.insert(
+        String::from("app"),
+        Upstream {
+            addr: Addrs(vec![failing.to_string(), working.to_string()]),
+            outlier_detection: Some(OutlierDetection {
+                consecutive_failures: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+    config.routes.push(Route::new(
+        PathPattern::prefix("/"),
+        ProxyEntry {
+            upstream: Some(String::from("app")),
+            strip_prefix: false,
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let mut statuses = Vec::new();
+    for _ in 0..8 {
+        let received = send(proxy_addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (response, _) = Response::parse(&received).unwrap();
+        statuses.push(response.status().as_u16());
+    }
+    // the failing backend takes its turn twice before it is ejected
+    assert_eq!(vec![500, 200, 500, 200, 200, 200, 200, 200], statuses);
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_retry() {
+    let failing =
+        spawn_upstream(b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n").await;
+    let working = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok").await;
+
+    let mut config = ServerConfig::default();
+    config.routes.push(Route::new(
+        PathPattern::prefix("/"),
+        ProxyEntry {
+            addr: Addrs(vec![failing.to_string(), working.to_string()]),
+            strip_prefix: false,
+            retry: Some(Retry::default()),
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+
+    // each request taking its turn on the failing backend is tried again on the other
+    for _ in 0..4 {
+        let received = send(proxy_addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(b"ok", body);
+    }
+
+    // but a POST may have been acted on, so its failure goes to the client
+    let received = send(
+        proxy_addr,
+        b"POST / HTTP/1.1\r\nHost: localhost\r\ncontent-length: 2\r\n\r\nhi",
+    )
+    .await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_hedging() {
+    let slow = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let slow_addr = slow.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = slow.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut received = [0; 1024];
+                let _ = stream.read(&mut received).await.unwrap();
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nslow")
+                    .await;
+            });
+        }
+    });
+    let fast = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nfast").await;
+
+    let mut config = ServerConfig::default();
+    config.routes.push(Route::new(
+        PathPattern::prefix("/"),
+        ProxyEntry {
+            addr: Addrs(vec![slow_addr.to_string(), fast.to_string()]),
+            strip_prefix: false,
+            retry: Some(Retry {
+                hedge_after_ms: Some(100),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+
+    // the request goes to the slow backend first, and the hedge to the fast one wins
+    let started = Instant::now();
+    let received = send(proxy_addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"fast", body);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_retry_budget() {
+    let failing =
+        spawn_upstream(b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n").await;
+    let working = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok").await;
+
+    let mut config = ServerConfig::default();
+    config.routes.push(Route::new(
+        PathPattern::prefix("/"),
+        ProxyEntry {
+            addr: Addrs(vec![failing.to_string(), working.to_string()]),
+            strip_prefix: false,
+            retry: Some(Retry {
+                budget: Some(RetryBudget {
+                    percent: 0,
+                    min_retries: 0,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+
+    // without any budget, the failure goes to the client instead of being retried
+    let received = send(proxy_addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_backup() {
+    let failing = spawn_upstream(b"HTTP/1.1 502 Bad Gateway\r\ncontent-length: 0\r\n\r\n").await;
+    let unreachable = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let sorry = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nsorry").await;
+
+    let mut config = ServerConfig::default();
+    config.upstreams.insert(
+        String::from("sorry"),
+        Upstream::from(Addrs::from(sorry.to_string())),
+    );
+    for (prefix, addr) in [("/failing", failing), ("/unreachable", unreachable)] {
+        let mut route = route(prefix, addr);
+        route.entry.backup = Some(String::from("sorry"));
+        config.routes.push(route);
+    }
+    let proxy_addr = spawn_proxy(config).await;
+
+    for path in ["/failing", "/unreachable"] {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status(), "{path}");
+        assert_eq!(b"sorry", body);
+    }
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_keepalive() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            accepted.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(async move {
+                let mut received = [0; 1024];
+                while let Ok(n) = stream.read(&mut received).await
+                    && n > 0
+                {
+                    let response: &[u8] = if received.starts_with(b"HEAD") {
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n"
+                    } else {
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello"
+                    };
+                    stream.write_all(response).await.unwrap();
+                }
+            });
+        }
+    });
+
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+
+    for method in ["GET", "HEAD", "GET"] {
+        let request = format!("{method} / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let expected: &[u8] = if method == "HEAD" { b"" } else { b"hello" };
+        assert_eq!(expected, body);
+    }
+    // the requests were all sent on the connection kept open after the first
+    assert_eq!(1, connections.load(Ordering::Relaxed));
+}
+
+/// An upstream responding over TLS with a certificate for `localhost`, along with the
+/// connections it accepted. The directory gets the certificate as `ca.pem`, and with
+/// `client_auth` the certificate and key the upstream requires clients to have as
+/// `client.pem` and `client-key.pem`.
+async fn spawn_tls_upstream(
+    name: &str,
+    client_auth: bool,
+) -> (SocketAddr, PathBuf, Arc<AtomicUsize>) {
+    let key = rcgen::KeyPair::generate().unwrap();
+    let cert = rcgen::CertificateParams::new(vec![String::from("localhost")])
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("agora-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("ca.pem"), cert.pem()).unwrap();
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap();
+    let builder = if client_auth {
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let client_cert = rcgen::CertificateParams::new(vec![String::from("proxy")])
+            .unwrap()
+            .self_signed(&client_key)
+            .unwrap();
+        std::fs::write(dir.join("client.pem"), client_cert.pem()).unwrap();
+        std::fs::write(dir.join("client-key.pem"), client_key.serialize_pem()).unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(client_cert.der().clone()).unwrap();
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .unwrap();
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    let config = builder
+        .with_single_cert(
+            vec![cert.der().clone()],
+            PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+        )
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let accepted = accepted.clone();
+            tokio::spawn(async move {
+                let Ok(mut stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                accepted.fetch_add(1, Ordering::Relaxed);
+                let mut received = [0; 1024];
+                while let Ok(n) = stream.read(&mut received).await
+                    && n > 0
+                {
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 6\r\n\r\nsecret")
+                        .await
+                        .unwrap();
+                }
+            });
+        }
+    });
+    (addr, dir, connections)
+}
+
+/// A route to the upstream over TLS with the settings
+fn tls_route(prefix: &str, upstream_addr: SocketAddr, tls: UpstreamTls) -> Route {
+    let mut route = route(prefix, upstream_addr);
+    route.entry.addr = Addrs(vec![format!("https://{upstream_addr}")]);
+    route.entry.tls = Some(tls);
+    route
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_tls_upstream() {
+    let (upstream_addr, dir, connections) = spawn_tls_upstream("tls-upstream", false).await;
+    let ca = dir.join("ca.pem");
+    let https_route = |prefix: &str, tls: UpstreamTls| tls_route(prefix, upstream_addr, tls);
+
+    let mut config = ServerConfig::default();
+    config.routes.push(https_route(
+        "/verified",
+        UpstreamTls {
+            ca: Some(ca.clone()),
+            server_name: Some(String::from("localhost")),
+            ..Default::default()
+        },
+    ));
+    config.routes.push(https_route(
+        "/unverified",
+        UpstreamTls {
+            verify: false,
+            ..Default::default()
+        },
+    ));
+    // the certificate isn't for the IP address the backend is connected to
+    config.routes.push(https_route(
+        "/mismatched",
+        UpstreamTls {
+            ca: Some(ca.clone()),
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+
+    for path in ["/verified", "/verified", "/unverified"] {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status(), "{path}");
+        assert_eq!(b"secret", body);
+    }
+    // the second request reused the connection of the first
+    assert_eq!(2, connections.load(Ordering::Relaxed));
+
+    let received = send(
+        proxy_addr,
+        b"GET /mismatched HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    )
+    .await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::BAD_GATEWAY, response.status());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_tls_client_cert() {
+    let (upstream_addr, dir, _) = spawn_tls_upstream("tls-client-cert", true).await;
+    let tls = UpstreamTls {
+        ca: Some(dir.join("ca.pem")),
+        server_name: Some(String::from("localhost")),
+        ..Default::default()
+    };
+
+    let mut config = ServerConfig::default();
+    config.routes.push(tls_route(
+        "/authenticated",
+        upstream_addr,
+        UpstreamTls {
+            cert: Some(dir.join("client.pem")),
+            key: Some(dir.join("client-key.pem")),
+            ..tls.clone()
+        },
+    ));
+    config
+        .routes
+        .push(tls_route("/anonymous", upstream_addr, tls));
+    let proxy_addr = spawn_proxy(config).await;
+
+    for (path, status) in [
+        ("/authenticated", StatusCode::OK),
+        ("/anonymous", StatusCode::BAD_GATEWAY),
+    ] {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, _) = Response::parse(&received).unwrap();
+        assert_eq!(status, response.status(), "{path}");
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_client_keepalive() {
+    let upstream_addr = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello").await;
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    config.limits.keepalive_requests = 3;
+    let proxy_addr = spawn_proxy(config).await;
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(b"GET /first HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    while !received.ends_with(b"hello") {
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed after the first response");
+        received.extend_from_slice(&buf[..n]);
+    }
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(None, response.get_header("connection"));
+    assert_eq!(b"hello", body);
+
+    // the next two are sent together, the last one reaching the limit of the connection
+    stream
+        .write_all(
+            b"POST /second HTTP/1.1\r\nHost: localhost\r\ncontent-length: 2\r\n\r\nhi\
+              GET /third HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    let (second, rest) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, second.status());
+    assert_eq!(b"hello", &rest[..5]);
+    let (third, body) = Response::parse(&rest[5..]).unwrap();
+    assert_eq!(StatusCode::OK, third.status());
+    assert_eq!(
+        Some("close"),
+        third.get_header("connection").map(|v| v.to_str().unwrap())
+    );
+    assert_eq!(b"hello", body);
+}
+
+#[tokio::test]
+async fn test_client_keepalive_local_responses() {
+    let root = std::env::temp_dir().join(format!("agora-keepalive-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("index.html"), "app").unwrap();
+    let config: ServerConfig = serde_json::from_value(serde_json::json!({
+        "routes": [
+            { "path": "/down", "strip_prefix": false, "maintenance": { "message": "down" } },
+            { "path": "/", "strip_prefix": false, "static_files": { "root": root, "index": "index.html" } }
+        ]
+    }))
+    .unwrap();
+    let proxy_addr = spawn_proxy(config).await;
+
+    // files and maintenance pages are answered on the same connection, the body of a
+    // request being skipped, until the client asks to close it
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              POST /down HTTP/1.1\r\nHost: localhost\r\ncontent-length: 2\r\n\r\nhi\
+              GET /missing HTTP/1.1\r\nHost: localhost\r\nconnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    let (first, rest) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, first.status());
+    assert_eq!(None, first.get_header("connection"));
+    assert_eq!(b"app", &rest[..3]);
+    let (second, rest) = Response::parse(&rest[3..]).unwrap();
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, second.status());
+    assert_eq!(None, second.get_header("connection"));
+    assert_eq!(b"down", &rest[..4]);
+    let (third, body) = Response::parse(&rest[4..]).unwrap();
+    assert_eq!(StatusCode::NOT_FOUND, third.status());
+    assert_eq!(
+        Some("close"),
+        third.get_header("connection").map(|v| v.to_str().unwrap())
+    );
+    assert!(body.is_empty());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_http_1_0_client() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        for _ in 0..2 {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut received = [0; 1024];
+            let bytes_read = stream.read(&mut received).await.unwrap();
+            let (request, _) = Request::parse(&received[..bytes_read]).unwrap();
+            assert_eq!(HTTPVersion::HTTP1_0, request.version);
+            stream
+                .write_all(b"HTTP/1.0 200 OK\r\ncontent-length: 5\r\n\r\nhello")
+                .await
+                .unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+
+    // an HTTP/1.0 client only keeps the connection open when it asks to, and needs to
+    // be told it stays open
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(b"GET /first HTTP/1.0\r\nconnection: keep-alive\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    while !received.ends_with(b"hello") {
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed after the first response");
+        received.extend_from_slice(&buf[..n]);
+    }
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(
+        Some("keep-alive"),
+        response
+            .get_header("connection")
+            .map(|v| v.to_str().unwrap())
+    );
+    assert_eq!(b"hello", body);
+
+    stream
+        .write_all(b"GET /second HTTP/1.0\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(
+        Some("close"),
+        response
+            .get_header("connection")
+            .map(|v| v.to_str().unwrap())
+    );
+    assert_eq!(b"hello", body);
+
+    upstream_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_chunked_bodies() {
+    // chunk data can look like the end of a body, and is sent over separate writes so
+    // the proxy can only find the end by following the chunk sizes
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut received = Vec::new();
+        let body = loop {
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "request body not terminated");
+            received.extend_from_slice(&buf[..n]);
+            if let Ok((_, body)) = Request::parse(&received)
+                && let Ok(Some(_)) = chunked_body_len(body)
+            {
+                break body.to_vec();
+            }
+        };
+        assert_eq!(b"4\r\n\r\n\r\n\r\n0\r\n\r\n".as_slice(), body);
+
+        for piece in [
+            b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n".as_slice(),
+            b"6\r\nab\r\n\r\n",
+            b"\r\n3\r\nxyz\r\n0\r\n",
+            b"\r\n",
+        ] {
+            stream.write_all(piece).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        // the proxy is done with the connection once the body ends
+        assert_eq!(0, stream.read(&mut [0; 16]).await.unwrap());
+    });
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    for piece in [
+        b"POST / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\ntransfer-encoding: chunked\r\n\r\n4\r\n\r\n"
+            .as_slice(),
+        b"\r\n\r\n",
+        b"0\r\n\r\n",
+    ] {
+        stream.write_all(piece).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"6\r\nab\r\n\r\n\r\n3\r\nxyz\r\n0\r\n\r\n".as_slice(), body);
+
+    upstream_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_trailers() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut received = [0; 1024];
+        let bytes_read = stream.read(&mut received).await.unwrap();
+        let (request, _) = Request::parse(&received[..bytes_read]).unwrap();
+        assert_eq!(
+            Some("trailers"),
+            request.headers.get("te").map(|te| te.to_str().unwrap())
+        );
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\ntrailer: grpc-status\r\n\r\n\
+                  2\r\nok\r\n0\r\ngrpc-status: 0\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let received = send(
+        proxy_addr,
+        b"POST / HTTP/1.1\r\nhost: localhost\r\nte: trailers\r\nconnection: te\r\n\r\n",
+    )
+    .await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(
+        Some("grpc-status"),
+        response.get_header("trailer").map(|v| v.to_str().unwrap())
+    );
+    assert_eq!(b"2\r\nok\r\n0\r\ngrpc-status: 0\r\n\r\n".as_slice(), body);
+
+    upstream_handle.await.unwrap();
+}
+
+/// Read from the stream until what was received ends with `end`
+async fn read_until(stream: &mut TcpStream, end: &[u8]) -> Vec<u8> {
+    let mut received = Vec::new();
+    while !received.ends_with(end) {
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed before {end:?}");
+        received.extend_from_slice(&buf[..n]);
+    }
+    received
+}
+
+#[tokio::test]
+async fn test_expect_continue() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut received = Vec::new();
+        while !received.ends_with(b"hello") {
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "request body not sent");
+            received.extend_from_slice(&buf[..n]);
+        }
+        let (request, _) = Request::parse(&received).unwrap();
+        assert_eq!(None, request.headers.get("expect"));
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+
+    // the proxy tells the client to go ahead itself
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(
+            b"PUT / HTTP/1.1\r\nhost: localhost\r\nexpect: 100-continue\r\ncontent-length: 5\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let received = read_until(&mut stream, b"\r\n\r\n").await;
+    assert_eq!(b"HTTP/1.1 100 Continue\r\n\r\n".as_slice(), received);
+    stream.write_all(b"hello").await.unwrap();
+    let received = read_until(&mut stream, b"\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    upstream_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_expect_continue_forwarded() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        // the first request is let through, the second turned down before its body
+        for accepted in [true, false] {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut received = [0; 1024];
+            let bytes_read = stream.read(&mut received).await.unwrap();
+            let (request, body) = Request::parse(&received[..bytes_read]).unwrap();
+            assert!(request.expects_continue());
+            assert!(body.is_empty());
+            if accepted {
+                stream
+                    .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                    .await
+                    .unwrap();
+                let bytes_read = stream.read(&mut received).await.unwrap();
+                assert_eq!(b"hello", &received[..bytes_read]);
+                stream
+                    .write_all(b"HTTP/1.1 201 Created\r\ncontent-length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+            } else {
+                stream
+                    .write_all(b"HTTP/1.1 413 Payload Too Large\r\ncontent-length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+            }
+            stream.shutdown().await.unwrap();
+        }
+    });
+    let mut config = ServerConfig::default();
+    config.routes.push(Route::new(
+        PathPattern::prefix("/"),
+        ProxyEntry {
+            addr: upstream_addr.to_string().into(),
+            strip_prefix: false,
+            expect_continue: ExpectContinue::Forward,
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+    let request =
+        b"PUT / HTTP/1.1\r\nhost: localhost\r\nexpect: 100-continue\r\ncontent-length: 5\r\n\r\n";
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let received = read_until(&mut stream, b"\r\n\r\n").await;
+    assert_eq!(b"HTTP/1.1 100 Continue\r\n\r\n".as_slice(), received);
+    stream.write_all(b"hello").await.unwrap();
+    let received = read_until(&mut stream, b"\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::CREATED, response.status());
+
+    // the body never sent, the connection can't be used for another request
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+    assert_eq!(
+        Some("close"),
+        response
+            .get_header("connection")
+            .map(|v| v.to_str().unwrap())
+    );
+
+    upstream_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_bodyless_responses() {
+    // the upstream keeps its connections open, so a body waited for never comes
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut received = [0; 1024];
+                while let Ok(n) = stream.read(&mut received).await
+                    && n > 0
+                {
+                    let response: &[u8] = match Request::parse(&received[..n]).unwrap().0.method {
+                        HTTPMethod::HEAD => b"HTTP/1.1 200 OK\r\ncontent-length: 1000\r\n\r\n",
+                        _ => b"HTTP/1.1 204 No Content\r\ncontent-length: 5\r\n\r\n",
+                    };
+                    stream.write_all(response).await.unwrap();
+                }
+            });
+        }
+    });
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    for (request, status) in [
+        (
+            b"HEAD / HTTP/1.1\r\nhost: localhost\r\n\r\n".as_slice(),
+            StatusCode::OK,
+        ),
+        (
+            b"DELETE / HTTP/1.1\r\nhost: localhost\r\n\r\n",
+            StatusCode::NO_CONTENT,
+        ),
+        (
+            b"HEAD / HTTP/1.1\r\nhost: localhost\r\n\r\n",
+            StatusCode::OK,
+        ),
+    ] {
+        stream.write_all(request).await.unwrap();
+        let received =
+            tokio::time::timeout(Duration::from_secs(5), read_until(&mut stream, b"\r\n\r\n"))
+                .await
+                .expect("response stalled");
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(status, response.status());
+        assert!(body.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_reverse_proxy_loop() {
+    // a route sending requests back to the proxy itself
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", proxy_addr));
+    tokio::spawn(async move { Server::new(config).serve(listener).await.unwrap() });
+
+    let received = send(proxy_addr, b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::LOOP_DETECTED, response.status());
+    assert_eq!(
+        Some("1.1 agora"),
+        response.get_header("via").map(|v| v.to_str().unwrap())
+    );
+}
+
+#[tokio::test]
+async fn test_websocket() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let received = read_until(&mut stream, b"\r\n\r\n").await;
+        let (request, _) = Request::parse(&received).unwrap();
+        let header = |name| request.headers.get(name).map(|v| v.to_str().unwrap());
+        assert_eq!(Some("websocket"), header("upgrade"));
+        assert_eq!(Some("upgrade"), header("connection"));
+        assert_eq!(
+            Some("dGhlIHNhbXBsZSBub25jZQ=="),
+            header("sec-websocket-key")
+        );
+        stream
+            .write_all(
+                b"HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\nconnection: Upgrade\r\n\
+                  sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\nhello",
+            )
+            .await
+            .unwrap();
+        // echo whatever comes, the client sending it after the proxy timed out on requests
+        let mut buf = [0; 1024];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            stream.write_all(&buf[..n]).await.unwrap();
+        }
+        stream.shutdown().await.unwrap();
+    });
+
+    let mut config = ServerConfig::default();
+    config.limits.upstream_timeout = 1;
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET /chat HTTP/1.1\r\nhost: localhost\r\nupgrade: websocket\r\n\
+              connection: keep-alive, Upgrade\r\nsec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+              sec-websocket-version: 13\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let received = read_until(&mut stream, b"hello").await;
+    let (response, rest) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::SWITCHING_PROTOCOLS, response.status());
+    assert_eq!(
+        Some("websocket"),
+        response.get_header("upgrade").map(|v| v.to_str().unwrap())
+    );
+    assert_eq!(
+        Some("upgrade"),
+        response
+            .get_header("connection")
+            .map(|v| v.to_str().unwrap())
+    );
+    assert_eq!(b"hello", rest);
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    stream.write_all(b"still there?").await.unwrap();
+    read_until(&mut stream, b"still there?").await;
+    stream.shutdown().await.unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    assert!(received.is_empty());
+    upstream_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_unrequested_upgrade() {
+    let upstream =
+        spawn_upstream(b"HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\n\r\n").await;
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let received = send(proxy_addr, b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::BAD_GATEWAY, response.status());
+}
+
+#[tokio::test]
+async fn test_server_sent_events() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let (sent_tx, mut sent_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        read_until(&mut stream, b"\r\n\r\n").await;
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n")
+            .await
+            .unwrap();
+        // longer than the upstream timeout in all, though never quiet for that long
+        for event in 0..4 {
+            stream
+                .write_all(format!("data: {event}\n\n").as_bytes())
+                .await
+                .unwrap();
+            sent_tx.send(event).unwrap();
+            tokio::time::sleep(Duration::from_millis(400)).await;
+        }
+        stream.shutdown().await.unwrap();
+    });
+
+    let mut config = ServerConfig::default();
+    config.limits.upstream_timeout = 1;
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nhost: localhost\r\naccept: text/event-stream\r\n\r\n")
+        .await
+        .unwrap();
+    // each event arrives before the next one is sent
+    let mut received = Vec::new();
+    for event in 0..4 {
+        received.extend(read_until(&mut stream, format!("data: {event}\n\n").as_bytes()).await);
+        assert_eq!(Some(event), sent_rx.recv().await);
+        assert!(sent_rx.try_recv().is_err(), "event {event} was held back");
+    }
+    stream.read_to_end(&mut received).await.unwrap();
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"data: 0\n\ndata: 1\n\ndata: 2\n\ndata: 3\n\n", body);
+}
+
+#[tokio::test]
+async fn test_grpc() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let upstream_handle = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let received = read_until(&mut stream, b"\0\0\0\0\0").await;
+        let (request, _) = Request::parse(&received).unwrap();
+        // asked for on behalf of the client, which didn't
+        assert_eq!(
+            Some("trailers"),
+            request.headers.get("te").map(|te| te.to_str().unwrap())
+        );
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\ncontent-type: application/grpc\r\n\
+                  transfer-encoding: chunked\r\ntrailer: grpc-status\r\n\r\n\
+                  5\r\n\0\0\0\0\0\r\n0\r\ngrpc-status: 0\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+    // a backend that is down
+    let down = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let down_addr = down.local_addr().unwrap();
+    drop(down);
+
+    let mut config = ServerConfig::default();
+    for (prefix, addr) in [("/helloworld.Greeter/", upstream_addr), ("/", down_addr)] {
+        let mut route = route(prefix, addr);
+        route.entry.grpc = true;
+        config.routes.push(route);
+    }
+    let proxy_addr = spawn_proxy(config).await;
+
+    let call = |path| {
+        format!(
+            "POST {path} HTTP/1.1\r\nhost: localhost\r\ncontent-type: application/grpc\r\n\
+             content-length: 5\r\n\r\n\0\0\0\0\0"
+        )
+    };
+    let received = send(proxy_addr, call("/helloworld.Greeter/SayHello").as_bytes()).await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(
+        b"5\r\n\0\0\0\0\0\r\n0\r\ngrpc-status: 0\r\n\r\n".as_slice(),
+        body
+    );
+    upstream_handle.await.unwrap();
+
+    let received = send(
+        proxy_addr,
+        call("/routeguide.RouteGuide/GetFeature").as_bytes(),
+    )
+    .await;
+    let (response, _) = Response::parse(&received).unwrap();
+    let header = |name| response.get_header(name).map(|v| v.to_str().unwrap());
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(Some("14"), header("grpc-status"));
+    assert_eq!(Some("Bad Gateway"), header("grpc-message"));
+}
+
+/// A client speaking just enough HTTP/2 to send requests through the proxy
+struct Http2Client<S = TcpStream> {
+    stream: S,
+    received: Vec<u8>,
+    encoder: Encoder,
+    decoder: Decoder,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Http2Client<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream,
+            received: Vec::new(),
+            encoder: Encoder::default(),
+            decoder: Decoder::default(),
+        }
+    }
+
+    /// Open the connection with the preface and empty settings
+    async fn start(&mut self) {
+        let mut bytes = CONNECTION_PREFACE.to_vec();
+        Frame::Settings {
+            ack: false,
+            settings: Vec::new(),
+        }
+        .encode(&mut bytes);
+        self.stream.write_all(&bytes).await.unwrap();
+    }
+
+    /// Read the head of the response to an HTTP/1.1 request
+    async fn read_head(&mut self) -> Response {
+        while !self.received.windows(4).any(|w| w == b"\r\n\r\n") {
+            self.read().await;
+        }
+        let (response, rest) = Response::parse(&self.received).unwrap();
+        self.received = rest.to_vec();
+        response
+    }
+
+    async fn request(&mut self, stream_id: u32, fields: &[(&str, &str)], body: Option<&[u8]>) {
+        let mut header_block = Vec::new();
+        self.encoder
+            .encode(fields.iter().copied(), &mut header_block);
+        let mut bytes = Vec::new();
+        Frame::Headers {
+            stream_id,
+            end_stream: body.is_none(),
+            end_headers: true,
+            priority: None,
+            header_block: &header_block,
+        }
+        .encode(&mut bytes);
+        if let Some(data) = body {
+            Frame::Data {
+                stream_id,
+                end_stream: true,
+                data,
+            }
+            .encode(&mut bytes);
+        }
+        self.stream.write_all(&bytes).await.unwrap();
+    }
+
+    /// Read the response on the stream: its header blocks, the trailers being the last
+    /// if there are any, and its body
+    async fn response(&mut self, stream_id: u32) -> (Vec<Headers>, Vec<u8>) {
+        let mut headers = Vec::new();
+        let mut body = Vec::new();
+        loop {
+            let (frame, rest) = match Frame::parse(&self.received, MAX_ALLOWED_FRAME_SIZE) {
+                Ok(parsed) => parsed,
+                Err(FrameError::Incomplete) => {
+                    self.read().await;
+                    continue;
+                }
+                Err(e) => panic!("invalid frame: {e}"),
+            };
+            let mut done = false;
+            match frame {
+                Frame::Headers {
+                    stream_id: id,
+                    end_stream,
+                    header_block,
+                    ..
+                } => {
+                    // decoded whatever the stream, to keep up with the HPACK table
+                    let fields = self.decoder.decode_headers(header_block).unwrap();
+                    if id == stream_id {
+                        headers.push(fields);
+                        done = end_stream;
+                    }
+                }
+                Frame::Data {
+                    stream_id: id,
+                    end_stream,
+                    data,
+                } if id == stream_id => {
+                    body.extend_from_slice(data);
+                    done = end_stream;
+                }
+                Frame::RstStream {
+                    stream_id: id,
+                    error_code,
+                } if id == stream_id => panic!("stream reset: {error_code:?}"),
+                Frame::GoAway { error_code, .. } => panic!("connection closed: {error_code:?}"),
+                _ => {}
+            }
+            self.received = rest.to_vec();
+            if done {
+                return (headers, body);
+            }
+        }
+    }
+
+    async fn read(&mut self) {
+        let mut buf = [0; 4096];
+        let n = self.stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed");
+        self.received.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[tokio::test]
+async fn test_connect_tunnel() {
+    let destination = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let destination_addr = destination.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = destination.accept().await.unwrap();
+        let received = read_until(&mut stream, b"ping").await;
+        assert_eq!(b"early ping", received.as_slice());
+        stream.write_all(b"pong").await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let config = ServerConfig {
+        forward_proxy: Some(ForwardProxy {
+            allow: vec![String::from("127.0.0.0/8")],
+            ports: vec![destination_addr.port()],
+        }),
+        ..Default::default()
+    };
+    let proxy_addr = spawn_proxy(config).await;
+
+    // bytes sent along with the head go through once the tunnel is open
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let connect =
+        format!("CONNECT {destination_addr} HTTP/1.1\r\nhost: {destination_addr}\r\n\r\nearly");
+    stream.write_all(connect.as_bytes()).await.unwrap();
+    let received = read_until(&mut stream, b"\r\n\r\n").await;
+    assert_eq!(
+        b"HTTP/1.1 200 Connection Established\r\n\r\n",
+        received.as_slice()
+    );
+    stream.write_all(b" ping").await.unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    assert_eq!(b"pong", received.as_slice());
+
+    for (destination, status) in [
+        ("127.0.0.1:22", StatusCode::FORBIDDEN),
+        ("example.com:443", StatusCode::FORBIDDEN),
+        ("127.0.0.1", StatusCode::BAD_REQUEST),
+    ] {
+        let request = format!("CONNECT {destination} HTTP/1.1\r\nhost: {destination}\r\n\r\n");
+        let received = send(proxy_addr, request.as_bytes()).await;
+        let (response, _) = Response::parse(&received).unwrap();
+        assert_eq!(status, response.status(), "{destination}");
+    }
+}
+
+#[tokio::test]
+async fn test_tls_passthrough() {
+    let (upstream_addr, dir, connections) = spawn_tls_upstream("tls-passthrough", false).await;
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls::pki_types::CertificateDer::pem_file_iter(dir.join("ca.pem")).unwrap() {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let passthrough = Listener {
+        tls_passthrough: Some(TlsPassthrough {
+            servers: HashMap::from([(String::from("localhost"), upstream_addr.to_string())]),
+            default: None,
+        }),
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        Server::new(ServerConfig::default())
+            .accept(listener, &passthrough)
+            .await
+            .unwrap()
+    });
+
+    // the client verifies the certificate of the upstream, the proxy having no keys
+    let stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+    let mut stream = connector.connect(name, stream).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = [0; 1024];
+    let n = stream.read(&mut received).await.unwrap();
+    let (response, body) = Response::parse(&received[..n]).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"secret", body);
+    assert_eq!(1, connections.load(Ordering::Relaxed));
+
+    // no server has the name, and the client is told so
+    let stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let name = rustls::pki_types::ServerName::try_from("example.com").unwrap();
+    let e = connector.connect(name, stream).await.unwrap_err();
+    assert!(e.to_string().contains("UnrecognisedName"), "{e}");
+    assert_eq!(1, connections.load(Ordering::Relaxed));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_proxy_protocol() {
+    // an upstream answering with the client it was told about
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let received = read_until(&mut stream, b"\r\n\r\n").await;
+            let (request, _) = Request::parse(&received).unwrap();
+            let client = request.headers.get("x-forwarded-for").unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{}",
+                client.len(),
+                client.to_str().unwrap()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let behind_balancer = Listener {
+        proxy_protocol: true,
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        Server::new(config)
+            .accept(listener, &behind_balancer)
+            .await
+            .unwrap()
+    });
+
+    let request = b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n";
+    let mut v2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x21\x00\x24".to_vec();
+    v2.extend_from_slice(&[0x20, 1, 0xd, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    v2.extend_from_slice(&[0; 16]);
+    v2.extend_from_slice(&[0xdc, 0x04, 1, 0xbb]);
+    for (header, client) in [
+        (
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n".to_vec(),
+            "192.0.2.1",
+        ),
+        (v2, "2001:db8::1"),
+        // health checks of the balancer come from itself
+        (b"PROXY UNKNOWN\r\n".to_vec(), "127.0.0.1"),
+    ] {
+        let received = send(proxy_addr, &[header, request.to_vec()].concat()).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(client.as_bytes(), body);
+    }
+
+    // connections without a header aren't served
+    let received = send(proxy_addr, request).await;
+    assert!(received.is_empty());
+}
+
+#[tokio::test]
+async fn test_proxy_protocol_upstream() {
+    // a backend answering with the client the header told, counting its connections
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            accepted.fetch_add(1, Ordering::Relaxed);
+            let received = read_until(&mut stream, b"\r\n\r\n").await;
+            let Header::Parsed { source, len } = proxy_protocol::parse(&received) else {
+                panic!("no PROXY protocol header in {received:?}");
+            };
+            Request::parse(&received[len..]).unwrap();
+            let client = source.unwrap().to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{client}",
+                client.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+
+    let mut config = ServerConfig::default();
+    config.upstreams.insert(
+        String::from("api"),
+        Upstream {
+            addr: Addrs(vec![upstream_addr.to_string()]),
+            proxy_protocol: true,
+            ..Default::default()
+        },
+    );
+    let mut api = route("/", upstream_addr);
+    api.entry.addr = Addrs::default();
+    api.entry.upstream = Some(String::from("api"));
+    config.routes.push(api);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let behind_balancer = Listener {
+        proxy_protocol: true,
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        Server::new(config)
+            .accept(listener, &behind_balancer)
+            .await
+            .unwrap()
+    });
+
+    // the client the balancer told the proxy about is passed on, on a connection of its
+    // own each time
+    let request =
+        b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\nhost: localhost\r\n\r\n";
+    for connection in 1..=2 {
+        let received = send(proxy_addr, request).await;
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(b"192.0.2.1:56324", body);
+        assert_eq!(connection, connections.load(Ordering::Relaxed));
+    }
+}
+
+/// A certificate and key for `localhost` written to a directory of their own as
+/// `cert.pem` and `key.pem`, and the certificate itself
+fn localhost_cert(name: &str) -> (PathBuf, rustls::pki_types::CertificateDer<'static>) {
+    let key = rcgen::KeyPair::generate().unwrap();
+    let cert = rcgen::CertificateParams::new(vec![String::from("localhost")])
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("agora-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("cert.pem"), cert.pem()).unwrap();
+    std::fs::write(dir.join("key.pem"), key.serialize_pem()).unwrap();
+    (dir, cert.der().clone())
+}
+
+/// A client trusting the certificate, of the TLS versions, offering the protocols in
+/// ALPN
+fn tls_connector(
+    cert: &rustls::pki_types::CertificateDer<'static>,
+    versions: &[&'static rustls::SupportedProtocolVersion],
+    alpn: &[&[u8]],
+) -> TlsConnector {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert.clone()).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Start a proxy with the config on a listener serving HTTPS
+async fn spawn_https_proxy(config: ServerConfig, settings: ListenerTls) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let https = Listener {
+        tls: Some(settings),
+        ..Default::default()
+    };
+    tokio::spawn(async move { Server::new(config).accept(listener, &https).await });
+    proxy_addr
+}
+
+async fn connect_tls(
+    proxy_addr: SocketAddr,
+    connector: &TlsConnector,
+) -> std::io::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+    connector.connect(name, stream).await
+}
+
+#[tokio::test]
+async fn test_tls_listener() {
+    let upstream_addr = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok").await;
+    let (dir, cert) = localhost_cert("tls-listener");
+    let connector = tls_connector(&cert, rustls::ALL_VERSIONS, &[]);
+    let settings = ListenerTls {
+        cert: dir.join("cert.pem"),
+        key: dir.join("key.pem"),
+        ..Default::default()
+    };
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+
+    // requests are served over TLS, keeping the connection open between them
+    let proxy_addr = spawn_https_proxy(config.clone(), settings.clone()).await;
+    let mut stream = connect_tls(proxy_addr, &connector).await.unwrap();
+    assert_eq!(
+        Some(rustls::ProtocolVersion::TLSv1_3),
+        stream.get_ref().1.protocol_version()
+    );
+    for _ in 0..2 {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = [0; 1024];
+        let n = stream.read(&mut received).await.unwrap();
+        let (response, body) = Response::parse(&received[..n]).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(b"ok", body);
+    }
+
+    // the cipher suites allowed are the only ones negotiated
+    let tls12 = ListenerTls {
+        max_version: TlsVersion::Tls12,
+        cipher_suites: vec![String::from(
+            "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+        )],
+        ..settings.clone()
+    };
+    let proxy_addr = spawn_https_proxy(config.clone(), tls12).await;
+    let stream = connect_tls(proxy_addr, &connector).await.unwrap();
+    let suite = stream.get_ref().1.negotiated_cipher_suite().unwrap();
+    assert_eq!(
+        rustls::CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+        suite.suite()
+    );
+
+    // clients of older versions than allowed are refused
+    let tls13 = ListenerTls {
+        min_version: TlsVersion::Tls13,
+        ..settings
+    };
+    let proxy_addr = spawn_https_proxy(config, tls13).await;
+    let tls12_connector = tls_connector(&cert, &[&rustls::version::TLS12], &[]);
+    let e = connect_tls(proxy_addr, &tls12_connector).await.unwrap_err();
+    assert!(e.to_string().contains("ProtocolVersion"), "{e}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_tls_alpn() {
+    let upstream_addr = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok").await;
+    let (dir, cert) = localhost_cert("tls-alpn");
+    let settings = ListenerTls {
+        cert: dir.join("cert.pem"),
+        key: dir.join("key.pem"),
+        ..Default::default()
+    };
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_https_proxy(config.clone(), settings.clone()).await;
+    let connect = |alpn: &[&[u8]]| {
+        let connector = tls_connector(&cert, rustls::ALL_VERSIONS, alpn);
+        async move { connect_tls(proxy_addr, &connector).await }
+    };
+
+    // clients agreeing on h2 speak HTTP/2 right away
+    let stream = connect(&[b"h2", b"http/1.1"]).await.unwrap();
+    assert_eq!(Some(&b"h2"[..]), stream.get_ref().1.alpn_protocol());
+    let mut client = Http2Client::new(stream);
+    client.start().await;
+    let get = [
+        (":method", "GET"),
+        (":scheme", "https"),
+        (":authority", "localhost"),
+        (":path", "/"),
+    ];
+    client.request(1, &get, None).await;
+    let (headers, body) = client.response(1).await;
+    assert_eq!(
+        Some("200"),
+        headers[0].get(":status").map(|v| v.to_str().unwrap())
+    );
+    assert_eq!(b"ok", body.as_slice());
+
+    // and those agreeing on HTTP/1.1 speak that
+    let mut stream = connect(&[b"http/1.1"]).await.unwrap();
+    assert_eq!(Some(&b"http/1.1"[..]), stream.get_ref().1.alpn_protocol());
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"ok", body);
+
+    // protocols the listener doesn't offer are refused
+    let proxy_addr = spawn_https_proxy(
+        config,
+        ListenerTls {
+            alpn: vec![Alpn::AcmeTls, Alpn::Http1],
+            ..settings
+        },
+    )
+    .await;
+    let connect = |alpn: &[&[u8]]| {
+        let connector = tls_connector(&cert, rustls::ALL_VERSIONS, alpn);
+        async move { connect_tls(proxy_addr, &connector).await }
+    };
+    let e = connect(&[b"h2"]).await.unwrap_err();
+    assert!(e.to_string().contains("NoApplicationProtocol"), "{e}");
+
+    // ACME challenges are done with the handshake, the connection being closed after
+    let mut stream = connect(&[b"acme-tls/1"]).await.unwrap();
+    assert_eq!(Some(&b"acme-tls/1"[..]), stream.get_ref().1.alpn_protocol());
+    let mut received = Vec::new();
+    let _ = stream.read_to_end(&mut received).await;
+    assert!(received.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_tls_client_auth() {
+    // an upstream answering with what it was told about the certificate of the client
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let received = read_until(&mut stream, b"\r\n\r\n").await;
+            let (request, _) = Request::parse(&received).unwrap();
+            let header = |name| {
+                request
+                    .headers
+                    .get(name)
+                    .map_or("", |v| v.to_str().unwrap())
+            };
+            let body = format!(
+                "{}|{}",
+                header("x-client-cert-subject"),
+                header("x-client-cert-fingerprint")
+            );
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let (dir, cert) = localhost_cert("tls-client-auth");
+    let client_key = rcgen::KeyPair::generate().unwrap();
+    let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "client");
+    let client_cert = params.self_signed(&client_key).unwrap();
+    std::fs::write(dir.join("client-ca.pem"), client_cert.pem()).unwrap();
+
+    let mut config = ServerConfig::default();
+    let mut route = route("/", upstream_addr);
+    route.entry.forwarded.x_client_cert_subject = true;
+    route.entry.forwarded.x_client_cert_fingerprint = true;
+    config.routes.push(route);
+    let settings = ListenerTls {
+        cert: dir.join("cert.pem"),
+        key: dir.join("key.pem"),
+        client_auth: Some(ClientAuth {
+            ca: dir.join("client-ca.pem"),
+            required: true,
+        }),
+        ..Default::default()
+    };
+    let request = b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\nx-client-cert-subject: CN=admin\r\n\r\n";
+    let get = |proxy_addr, connector: TlsConnector| async move {
+        let mut stream = connect_tls(proxy_addr, &connector).await?;
+        stream.write_all(request).await?;
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await?;
+        Ok::<_, std::io::Error>(received)
+    };
+
+    // clients with a certificate the CA issued are told apart upstream
+    let proxy_addr = spawn_https_proxy(config.clone(), settings.clone()).await;
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert.clone()).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let with_cert = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_client_auth_cert(
+            vec![client_cert.der().clone()],
+            PrivateKeyDer::Pkcs8(client_key.serialize_der().into()),
+        )
+        .unwrap();
+    let received = get(proxy_addr, TlsConnector::from(Arc::new(with_cert)))
+        .await
+        .unwrap();
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    let body = std::str::from_utf8(body).unwrap();
+    let (subject, fingerprint) = body.split_once('|').unwrap();
+    assert_eq!("CN=client", subject);
+    assert_eq!(64, fingerprint.len());
+    assert!(fingerprint.bytes().all(|b| b.is_ascii_hexdigit()));
+
+    // clients without one are refused
+    let without_cert = tls_connector(&cert, rustls::ALL_VERSIONS, &[]);
+    let received = get(proxy_addr, without_cert.clone()).await;
+    assert!(received.is_err(), "{received:?}");
+
+    // unless certificates are optional, clients without one not being able to claim any
+    let optional = ListenerTls {
+        client_auth: Some(ClientAuth {
+            ca: dir.join("client-ca.pem"),
+            required: false,
+        }),
+        ..settings
+    };
+    let proxy_addr = spawn_https_proxy(config, optional).await;
+    let received = get(proxy_addr, without_cert).await.unwrap();
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"|", body);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Start a backend speaking HTTP/2 over cleartext, answering each stream with what it
+/// asked for, and counting the connections it gets
+async fn spawn_http2_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = upstream.accept().await.unwrap();
+            accepted.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(serve_http2(stream));
+        }
+    });
+    (upstream_addr, connections)
+}
+
+async fn serve_http2(mut stream: TcpStream) {
+    let mut received = Vec::new();
+    while received.len() < CONNECTION_PREFACE.len() {
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed before the preface");
+        received.extend_from_slice(&buf[..n]);
+    }
+    assert!(received.starts_with(CONNECTION_PREFACE));
+    received.drain(..CONNECTION_PREFACE.len());
+    let mut bytes = Vec::new();
+    Frame::Settings {
+        ack: false,
+        settings: Vec::new(),
+    }
+    .encode(&mut bytes);
+    stream.write_all(&bytes).await.unwrap();
+
+    let mut decoder = Decoder::default();
+    let mut encoder = Encoder::default();
+    // what each open stream asked for so far: its request line and body
+    let mut requests: HashMap<u32, (String, Vec<u8>)> = HashMap::new();
+    loop {
+        let (frame, rest) = match Frame::parse(&received, MAX_ALLOWED_FRAME_SIZE) {
+            Ok(parsed) => parsed,
+            Err(FrameError::Incomplete) => {
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    return;
+                }
+                received.extend_from_slice(&buf[..n]);
+                continue;
+            }
+            Err(e) => panic!("invalid frame: {e}"),
+        };
+        let ended = match frame {
+            Frame::Headers {
+                stream_id,
+                end_stream,
+                header_block,
+                ..
+            } => {
+                let headers = decoder.decode_headers(header_block).unwrap();
+                let field = |name| headers.get(name).unwrap().to_str().unwrap().to_string();
+                assert_eq!("http", field(":scheme"));
+                assert_eq!(None, headers.get("connection"));
+                let line = format!(
+                    "{} {} {}",
+                    field(":method"),
+                    field(":path"),
+                    field(":authority")
+                );
+                requests.insert(stream_id, (line, Vec::new()));
+                end_stream.then_some(stream_id)
+            }
+            Frame::Data {
+                stream_id,
+                end_stream,
+                data,
+            } => {
+                requests
+                    .get_mut(&stream_id)
+                    .unwrap()
+                    .1
+                    .extend_from_slice(data);
+                end_stream.then_some(stream_id)
+            }
+            _ => None,
+        };
+        received = rest.to_vec();
+        let Some(stream_id) = ended else {
+            continue;
+        };
+        let (line, body) = requests.remove(&stream_id).unwrap();
+        let mut header_block = Vec::new();
+        encoder.encode(
+            [(":status", "200"), ("content-type", "text/plain")],
+            &mut header_block,
+        );
+        let data = format!("{line}: {}", String::from_utf8(body).unwrap());
+        let mut bytes = Vec::new();
+        Frame::Headers {
+            stream_id,
+            end_stream: false,
+            end_headers: true,
+            priority: None,
+            header_block: &header_block,
+        }
+        .encode(&mut bytes);
+        Frame::Data {
+            stream_id,
+            end_stream: true,
+            data: data.as_bytes(),
+        }
+        .encode(&mut bytes);
+        stream.write_all(&bytes).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_http2_upstream() {
+    let (upstream_addr, connections) = spawn_http2_upstream().await;
+    let mut config = ServerConfig::default();
+    config.upstreams.insert(
+        String::from("api"),
+        Upstream {
+            addr: Addrs(vec![upstream_addr.to_string()]),
+            http2: true,
+            ..Default::default()
+        },
+    );
+    config.routes.push(Route::new(
+        PathPattern::prefix("/"),
+        ProxyEntry {
+            upstream: Some(String::from("api")),
+            strip_prefix: false,
+            ..Default::default()
+        },
+    ));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let (get, post) = tokio::join!(
+        send(proxy_addr, b"GET /a HTTP/1.1\r\nhost: example.com\r\n\r\n"),
+        send(
+            proxy_addr,
+            b"POST /b HTTP/1.1\r\nhost: example.com\r\ncontent-length: 5\r\n\r\nhello"
+        ),
+    );
+    for (received, expected) in [
+        (get, "GET /a example.com: "),
+        (post, "POST /b example.com: hello"),
+    ] {
+        let (response, body) = Response::parse(&received).unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        // the backend sent no length, so the body is passed on chunked
+        assert_eq!(
+            Some("chunked"),
+            response
+                .get_header("transfer-encoding")
+                .map(|v| v.to_str().unwrap())
+        );
+        let expected = format!("{:x}\r\n{expected}\r\n0\r\n\r\n", expected.len());
+        assert_eq!(expected.as_bytes(), body);
+    }
+    // both requests went over the same connection
+    assert_eq!(1, connections.load(Ordering::Relaxed));
+}
+
+#[tokio::test]
+async fn test_http2() {
+    let upstream_addr = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello").await;
+    let grpc_upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let grpc_addr = grpc_upstream.local_addr().unwrap();
+    let grpc_handle = tokio::spawn(async move {
+        let (mut stream, _) = grpc_upstream.accept().await.unwrap();
+        let received = read_until(&mut stream, b"0\r\n\r\n").await;
+        let (request, body) = Request::parse(&received).unwrap();
+        assert_eq!(HTTPVersion::HTTP1_1, request.version);
+        assert_eq!(
+            Some("localhost"),
+            request.headers.get("host").map(|v| v.to_str().unwrap())
+        );
+        // without a content-length, the body is sent on chunked
+        assert_eq!(b"5\r\n\0\0\0\0\0\r\n0\r\n\r\n", body);
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\ncontent-type: application/grpc\r\n\
+                  transfer-encoding: chunked\r\n\r\n\
+                  5\r\n\0\0\0\0\0\r\n0\r\ngrpc-status: 0\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let mut config = ServerConfig::default();
+    let mut grpc = route("/helloworld.Greeter/", grpc_addr);
+    grpc.entry.grpc = true;
+    config.routes.push(grpc);
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+    let header =
+        |headers: &Headers, name| headers.get(name).map(|v| v.to_str().unwrap().to_string());
+
+    // with prior knowledge, starting right away with the preface
+    let mut client = Http2Client::new(TcpStream::connect(proxy_addr).await.unwrap());
+    client.start().await;
+    let get = [
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":authority", "localhost"),
+        (":path", "/"),
+    ];
+    client.request(1, &get, None).await;
+    let (headers, body) = client.response(1).await;
+    assert_eq!(1, headers.len());
+    assert_eq!(Some("200".into()), header(&headers[0], ":status"));
+    // connection-specific fields have no place in HTTP/2
+    assert_eq!(None, header(&headers[0], "connection"));
+    assert_eq!(b"hello", body.as_slice());
+
+    let call = [
+        (":method", "POST"),
+        (":scheme", "http"),
+        (":authority", "localhost"),
+        (":path", "/helloworld.Greeter/SayHello"),
+        ("content-type", "application/grpc"),
+        ("te", "trailers"),
+    ];
+    client.request(3, &call, Some(b"\0\0\0\0\0")).await;
+    let (headers, body) = client.response(3).await;
+    assert_eq!(2, headers.len());
+    assert_eq!(Some("200".into()), header(&headers[0], ":status"));
+    assert_eq!(b"\0\0\0\0\0", body.as_slice());
+    assert_eq!(Some("0".into()), header(&headers[1], "grpc-status"));
+    grpc_handle.await.unwrap();
+
+    // upgrading from HTTP/1.1, the request being answered on the first stream
+    let mut client = Http2Client::new(TcpStream::connect(proxy_addr).await.unwrap());
+    client
+        .stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: upgrade, http2-settings\r\n\
+              upgrade: h2c\r\nhttp2-settings: AAMAAABkAAQAAP__\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let response = client.read_head().await;
+    assert_eq!(StatusCode::SWITCHING_PROTOCOLS, response.status());
+    client.start().await;
+    let (headers, body) = client.response(1).await;
+    assert_eq!(Some("200".into()), header(&headers[0], ":status"));
+    assert_eq!(b"hello", body.as_slice());
+    client.request(3, &get, None).await;
+    let (_, body) = client.response(3).await;
+    assert_eq!(b"hello", body.as_slice());
+}
+
+#[tokio::test]
+async fn test_http2_max_header_list_size() {
+    let upstream_addr = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello").await;
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let proxy_addr = spawn_proxy(config).await;
+
+    let mut client = Http2Client::new(TcpStream::connect(proxy_addr).await.unwrap());
+    client.start().await;
+    let value = "a".repeat(4000);
+    let fields = [
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":authority", "localhost"),
+        (":path", "/"),
+        ("x-large", value.as_str()),
+    ];
+    let mut header_block = Vec::new();
+    client.encoder.encode(fields, &mut header_block);
+    // each byte stands for the large field again, the newest entry of the HPACK table
+    header_block.extend_from_slice(&[0xbe; 20]);
+    let mut bytes = Vec::new();
+    Frame::Headers {
+        stream_id: 1,
+        end_stream: true,
+        end_headers: true,
+        priority: None,
+        header_block: &header_block,
+    }
+    .encode(&mut bytes);
+    client.stream.write_all(&bytes).await.unwrap();
+
+    let mut max_header_list_size = None;
+    let error_code = loop {
+        let (frame, rest) = match Frame::parse(&client.received, MAX_ALLOWED_FRAME_SIZE) {
+            Ok(parsed) => parsed,
+            Err(FrameError::Incomplete) => {
+                client.read().await;
+                continue;
+            }
+            Err(e) => panic!("invalid frame: {e}"),
+        };
+        match frame {
+            Frame::Settings {
+                ack: false,
+                settings,
+            } => {
+                max_header_list_size = settings
+                    .iter()
+                    .find(|setting| setting.id == SettingId::MaxHeaderListSize)
+                    .map(|setting| setting.value);
+            }
+            Frame::GoAway { error_code, .. } => break error_code,
+            _ => {}
+        }
+        client.received = rest.to_vec();
+    };
+    assert_eq!(Some(64 * 1024), max_header_list_size);
+    assert_eq!(ErrorCode::EnhanceYourCalm, error_code);
+}
+
+#[tokio::test]
+async fn test_https_redirect() {
+    let upstream_addr = spawn_upstream(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\ntoken").await;
+    let mut config = ServerConfig::default();
+    config.routes.push(route("/", upstream_addr));
+    let spawn_redirect = |redirect: HttpsRedirect| {
+        let config = config.clone();
+        async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy_addr = listener.local_addr().unwrap();
+            let cleartext = Listener {
+                https_redirect: Some(redirect),
+                ..Default::default()
+            };
+            tokio::spawn(async move { Server::new(config).accept(listener, &cleartext).await });
+            proxy_addr
+        }
+    };
+
+    let proxy_addr = spawn_redirect(HttpsRedirect::default()).await;
+    let received = send(
+        proxy_addr,
+        b"GET /search?q=agora HTTP/1.1\r\nhost: example.com\r\n\r\n",
+    )
+    .await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::MOVED_PERMANENTLY, response.status());
+    assert_eq!(
+        Some("https://example.com/search?q=agora"),
+        response
+            .get_header("location")
+            .map(|value| value.to_str().unwrap())
+    );
+    // ACME challenges are redirected like the rest unless excluded
+    let challenge = b"GET /.well-known/acme-challenge/abc HTTP/1.1\r\nhost: example.com:80\r\n\r\n";
+    let (response, _) = Response::parse(&send(proxy_addr, challenge).await).unwrap();
+    assert_eq!(
+        Some("https://example.com/.well-known/acme-challenge/abc"),
+        response
+            .get_header("location")
+            .map(|value| value.to_str().unwrap())
+    );
+    // without a host there is nowhere to send the client
+    let (response, _) =
+        Response::parse(&send(proxy_addr, b"GET / HTTP/1.0\r\n\r\n").await).unwrap();
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+
+    let proxy_addr = spawn_redirect(HttpsRedirect {
+        status: 308,
+        port: Some(8443),
+        exclude_acme_challenges: true,
+    })
+    .await;
+    let received = send(
+        proxy_addr,
+        b"POST /upload HTTP/1.1\r\nhost: example.com\r\ncontent-length: 0\r\n\r\n",
+    )
+    .await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::PERMANENT_REDIRECT, response.status());
+    assert_eq!(
+        Some("https://example.com:8443/upload"),
+        response
+            .get_header("location")
+            .map(|value| value.to_str().unwrap())
+    );
+    let received = send(proxy_addr, challenge).await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(b"token", body);
+}
+
+#[tokio::test]
+async fn test_security_headers() {
+    let upstream_addr = spawn_upstream(
+        b"HTTP/1.1 200 OK\r\nx-frame-options: DENY\r\nconnection: close\r\ncontent-length: 2\r\n\r\nok",
+    )
+    .await;
+    let root = std::env::temp_dir().join(format!("agora-security-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("index.html"), "<h1>app</h1>").unwrap();
+    let config: ServerConfig = serde_json::from_value(serde_json::json!({
+        "security_headers": {
+            "strict_transport_security": { "include_subdomains": true },
+            "x_content_type_options": true,
+            "x_frame_options": "same_origin",
+            "referrer_policy": "no-referrer"
+        },
+        "routes": [
+            { "path": "/api", "addr": upstream_addr.to_string(), "strip_prefix": false },
+            {
+                "path": "/admin",
+                "addr": upstream_addr.to_string(),
+                "strip_prefix": false,
+                "security_headers": { "content_security_policy": "default-src 'self'", "replace": true }
+            },
+            {
+                "path": "/down",
+                "strip_prefix": false,
+                "maintenance": { "message": "down" }
+            },
+            {
+                "path": "/",
+                "strip_prefix": false,
+                "static_files": { "root": root, "index": "index.html" }
+            }
+        ]
+    }))
+    .unwrap();
+    let header = |response: &Response, name: &str| {
+        response
+            .get_header(name)
+            .map(|value| value.to_str().unwrap().to_string())
+    };
+
+    let proxy_addr = spawn_proxy(config.clone()).await;
+    let received = send(proxy_addr, b"GET /api HTTP/1.1\r\nhost: localhost\r\n\r\n").await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(b"ok", body);
+    // the header the upstream sent is kept
+    assert_eq!(
+        Some("DENY"),
+        header(&response, "x-frame-options").as_deref()
+    );
+    assert_eq!(
+        Some("nosniff"),
+        header(&response, "x-content-type-options").as_deref()
+    );
+    assert_eq!(
+        Some("no-referrer"),
+        header(&response, "referrer-policy").as_deref()
+    );
+    // HSTS means nothing over cleartext HTTP
+    assert_eq!(None, header(&response, "strict-transport-security"));
+
+    // routes with their own headers get those instead
+    let received = send(
+        proxy_addr,
+        b"GET /admin HTTP/1.1\r\nhost: localhost\r\n\r\n",
+    )
+    .await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(
+        Some("default-src 'self'"),
+        header(&response, "content-security-policy").as_deref()
+    );
+    assert_eq!(None, header(&response, "x-content-type-options"));
+
+    // the responses the proxy makes itself get them too, for maintenance and files
+    let received = send(proxy_addr, b"GET /down HTTP/1.1\r\nhost: localhost\r\n\r\n").await;
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+    assert_eq!(
+        Some("no-referrer"),
+        header(&response, "referrer-policy").as_deref()
+    );
+    let received = send(proxy_addr, b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n").await;
+    let (response, body) = Response::parse(&received).unwrap();
+    assert_eq!(b"<h1>app</h1>", body);
+    assert_eq!(
+        Some("SAMEORIGIN"),
+        header(&response, "x-frame-options").as_deref()
+    );
+
+    // over TLS, browsers are told to stick to HTTPS
+    let (dir, cert) = localhost_cert("security-headers");
+    let connector = tls_connector(&cert, rustls::ALL_VERSIONS, &[]);
+    let settings = ListenerTls {
+        cert: dir.join("cert.pem"),
+        key: dir.join("key.pem"),
+        ..Default::default()
+    };
+    let proxy_addr = spawn_https_proxy(config, settings).await;
+    let mut stream = connect_tls(proxy_addr, &connector).await.unwrap();
+    stream
+        .write_all(b"GET /api HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    let (response, _) = Response::parse(&received).unwrap();
+    assert_eq!(
+        Some("max-age=31536000; includeSubDomains"),
+        header(&response, "strict-transport-security").as_deref()
+    );
+
+    std::fs::remove_dir_all(root).unwrap();
+}